@@ -0,0 +1,188 @@
+//! Reading the members of archives, so their contents can be served as
+//! read-only virtual files.
+use std::{fmt::Debug, fs::File, io, path::Path};
+
+use zip::{result::ZipError, ZipArchive};
+
+use crate::tagger::ResourceLimits;
+
+/// A regular file stored inside an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    /// Path of the member within the archive
+    pub name: String,
+    /// Uncompressed size in bytes
+    pub size: u64,
+    /// Whether reading the member needs a password
+    pub encrypted: bool,
+}
+
+/// Lists and extracts archive members, in the way the libc wrapper fronts
+/// plain source files.
+pub trait ArchiveReader: Debug + Send + Sync {
+    /// The regular file members of `archive`; directories are omitted.
+    fn members(&self, archive: &Path) -> io::Result<Vec<ArchiveMember>>;
+    /// The uncompressed content of member `name`.
+    fn read_member(&self, archive: &Path, name: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Reads zip archives, decompressing members within [`ResourceLimits`].
+#[derive(Debug, Default)]
+pub struct ZipReader {
+    limits: ResourceLimits,
+}
+impl ZipReader {
+    pub fn with_limits(limits: ResourceLimits) -> Self {
+        Self { limits }
+    }
+}
+
+fn to_io_error(e: ZipError) -> io::Error {
+    match e {
+        ZipError::Io(e) => e,
+        ZipError::FileNotFound => io::Error::from(io::ErrorKind::NotFound),
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+            io::Error::new(io::ErrorKind::PermissionDenied, e)
+        }
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+impl ArchiveReader for ZipReader {
+    fn members(&self, archive: &Path) -> io::Result<Vec<ArchiveMember>> {
+        let mut zip = ZipArchive::new(File::open(archive)?).map_err(to_io_error)?;
+        let mut members = Vec::new();
+        for i in 0..zip.len() {
+            // Raw, so encrypted members are listed rather than refused
+            let file = zip.by_index_raw(i).map_err(to_io_error)?;
+            if file.is_file() {
+                members.push(ArchiveMember {
+                    name: file.name().to_string(),
+                    size: file.size(),
+                    encrypted: file.encrypted(),
+                });
+            }
+        }
+        Ok(members)
+    }
+
+    fn read_member(&self, archive: &Path, name: &str) -> io::Result<Vec<u8>> {
+        let mut zip = ZipArchive::new(File::open(archive)?).map_err(to_io_error)?;
+        let file = zip.by_name(name).map_err(to_io_error)?;
+        let declared = file.size();
+        self.limits.read_member(file, declared)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{fs::File, io::Write as _, path::Path};
+
+    use zip::{unstable::write::FileOptionsExt as _, write::SimpleFileOptions, ZipWriter};
+
+    use crate::tagger::ResourceLimits;
+
+    use super::{ArchiveMember, ArchiveReader, ZipReader};
+
+    /// Write a zip at `path` holding `members`, plus a directory entry.
+    pub(crate) fn write_zip(path: &Path, members: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        zip.add_directory("dir/", SimpleFileOptions::default())
+            .unwrap();
+        for (name, content) in members {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Write a zip at `path` holding `members`, each encrypted with
+    /// `password`.
+    pub(crate) fn write_encrypted_zip(path: &Path, members: &[(&str, &[u8])], password: &str) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let options = SimpleFileOptions::default().with_deprecated_encryption(password.as_bytes());
+        for (name, content) in members {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn zip_members() {
+        let path = std::env::temp_dir().join("archive_zip_members.zip");
+        write_zip(&path, &[("a.txt", b"alpha"), ("dir/b.txt", b"bravo!")]);
+
+        let reader = ZipReader::default();
+        assert_eq!(
+            vec![
+                ArchiveMember {
+                    name: "a.txt".into(),
+                    size: 5,
+                    encrypted: false
+                },
+                ArchiveMember {
+                    name: "dir/b.txt".into(),
+                    size: 6,
+                    encrypted: false
+                },
+            ],
+            reader.members(&path).unwrap()
+        );
+        assert_eq!(
+            b"bravo!".to_vec(),
+            reader.read_member(&path, "dir/b.txt").unwrap()
+        );
+        assert_eq!(
+            std::io::ErrorKind::NotFound,
+            reader.read_member(&path, "missing").unwrap_err().kind()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_members_listed() {
+        let path = std::env::temp_dir().join("archive_encrypted_members_listed.zip");
+        write_encrypted_zip(&path, &[("secret.txt", b"hush")], "hunter2");
+
+        let reader = ZipReader::default();
+        assert_eq!(
+            vec![ArchiveMember {
+                name: "secret.txt".into(),
+                size: 4,
+                encrypted: true
+            }],
+            reader.members(&path).unwrap()
+        );
+        assert_eq!(
+            std::io::ErrorKind::PermissionDenied,
+            reader.read_member(&path, "secret.txt").unwrap_err().kind()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn member_reads_bounded() {
+        let path = std::env::temp_dir().join("archive_member_reads_bounded.zip");
+        write_zip(&path, &[("big.bin", &[7u8; 4096]), ("small.txt", b"tiny")]);
+
+        let reader = ZipReader::with_limits(ResourceLimits {
+            max_member_bytes: 1024,
+            ..ResourceLimits::default()
+        });
+        assert_eq!(
+            b"tiny".to_vec(),
+            reader.read_member(&path, "small.txt").unwrap()
+        );
+        assert_eq!(
+            std::io::ErrorKind::InvalidData,
+            reader.read_member(&path, "big.bin").unwrap_err().kind()
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // A header understating the size doesn't let more through
+        let limits = ResourceLimits::default();
+        assert_eq!(b"abc".to_vec(), limits.read_member(&b"abc"[..], 3).unwrap());
+        assert!(limits.read_member(&b"abcdef"[..], 3).is_err());
+    }
+}
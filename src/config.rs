@@ -0,0 +1,147 @@
+//! Settings loaded from a TOML config file, so long-lived choices don't have
+//! to be repeated on every command line.
+//!
+//! Each key names a long option, with `_` or `-` separators, e.g.
+//! `read_cache_mb = 64` or `depth_view = ["1=files"]`. The settings are turned
+//! into arguments placed before the real command line, so with
+//! `args_override_self` set on the parser the command line wins.
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+use toml::{Table, Value};
+
+/// Option naming an explicit config file, replacing the discovered one.
+pub const CONFIG_OPTION: &str = "--config";
+
+/// `$XDG_CONFIG_HOME/tagfs/config.toml`, falling back to `~/.config` when
+/// the variable is unset or not absolute.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("tagfs").join("config.toml"))
+}
+
+/// Command line with the config file's settings inserted after the program
+/// name. An explicit `--config` file must exist; the default one is optional.
+pub fn with_config_args(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let args = args.into_iter().collect::<Vec<_>>();
+    let config = match explicit_config_path(&args) {
+        Some(path) => {
+            Some(fs::read_to_string(&path).with_context(|| format!("read config {path:?}"))?)
+        }
+        None => default_config_path().and_then(|path| fs::read_to_string(path).ok()),
+    };
+    let config_args = match config {
+        Some(config) => config_args(&config)?,
+        None => Vec::new(),
+    };
+    let mut args = args.into_iter();
+    Ok(args
+        .next()
+        .into_iter()
+        .chain(config_args)
+        .chain(args)
+        .collect())
+}
+
+fn explicit_config_path(args: &[OsString]) -> Option<PathBuf> {
+    let prefix = format!("{CONFIG_OPTION}=");
+    args.iter().enumerate().find_map(|(idx, arg)| {
+        let arg = arg.to_str()?;
+        match arg.strip_prefix(&prefix) {
+            Some(path) => Some(PathBuf::from(path)),
+            None if arg == CONFIG_OPTION => args.get(idx + 1).map(PathBuf::from),
+            None => None,
+        }
+    })
+}
+
+/// Long options equivalent to a TOML config table.
+pub fn config_args(config: &str) -> Result<Vec<OsString>> {
+    let table: Table = config.parse().context("parse config")?;
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let option = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) => args.push(OsString::from(&option)),
+                Value::Boolean(false) => {}
+                Value::String(s) => args.push(format!("{option}={s}").into()),
+                Value::Integer(i) => args.push(format!("{option}={i}").into()),
+                Value::Float(f) => args.push(format!("{option}={f}").into()),
+                value => bail!("unsupported config value for {key}: {value}"),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, ffi::OsString, fs};
+
+    use clap::Parser;
+
+    use super::{config_args, with_config_args};
+
+    #[derive(Parser, Debug)]
+    #[command(args_override_self = true)]
+    struct Args {
+        source: String,
+        #[arg(long)]
+        config: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        read_cache_mb: usize,
+        #[arg(long)]
+        mime_namespace: Option<String>,
+        #[arg(long)]
+        relative_sources: bool,
+    }
+
+    fn parse(config: &str, cli: &[&str]) -> Args {
+        let path = env::temp_dir().join(format!("tagfs-config-{}.toml", cli.len()));
+        fs::write(&path, config).unwrap();
+        let mut args = vec![OsString::from("tagfs"), OsString::from("--config")];
+        args.push(path.clone().into());
+        args.extend(cli.iter().map(OsString::from));
+        let args = Args::parse_from(with_config_args(args).unwrap());
+        fs::remove_file(&path).unwrap();
+        args
+    }
+
+    #[test]
+    fn config_applied_and_overridden() {
+        let config = "read_cache_mb = 64\nmime-namespace = \"mime\"\nrelative_sources = true\n";
+        let args = parse(config, &["src"]);
+        assert_eq!(64, args.read_cache_mb);
+        assert_eq!(Some("mime".to_string()), args.mime_namespace);
+        assert!(args.relative_sources);
+
+        let args = parse(config, &["--read-cache-mb", "8", "src"]);
+        assert_eq!(8, args.read_cache_mb);
+        assert_eq!("src", args.source);
+    }
+
+    #[test]
+    fn rejects_tables() {
+        assert!(config_args("[section]\nkey = 1\n").is_err());
+        assert_eq!(
+            vec![
+                OsString::from("--depth-view=1=files"),
+                OsString::from("--depth-view=2=tags")
+            ],
+            config_args("depth_view = [\"1=files\", \"2=tags\"]\nflag = false\n").unwrap()
+        );
+    }
+}
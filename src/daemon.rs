@@ -0,0 +1,61 @@
+//! Detaching from the terminal, so a mount can be scripted: the command
+//! returns once the filesystem is mounted, leaving a child process serving it.
+use std::{
+    fs::File,
+    io::{self, Read as _, Write as _},
+    os::fd::FromRawFd as _,
+    process,
+};
+
+use anyhow::{Context as _, Result};
+
+/// The background child's side of [`detach`].
+#[derive(Debug)]
+pub struct Detached {
+    ready: File,
+}
+impl Detached {
+    /// Tell the waiting parent the mount is established, so it can print this
+    /// process's PID and exit.
+    pub fn ready(mut self) -> Result<()> {
+        self.ready.write_all(b"\n").context("signal parent")
+    }
+}
+
+/// Fork into a new session. The child continues with a [`Detached`] handle;
+/// the parent never returns, instead waiting for [`Detached::ready`] then
+/// printing the child's PID and exiting successfully, or exiting with a
+/// failure if the child exits first.
+///
+/// Must be called before any threads are spawned. Logging carries on to the
+/// inherited stdout and stderr.
+pub fn detach() -> Result<Detached> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).context("create ready pipe");
+    }
+    let (mut read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    // Only safe while single-threaded, see above
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("fork"),
+        0 => {
+            drop(read);
+            unsafe { libc::setsid() };
+            Ok(Detached { ready: write })
+        }
+        child => {
+            drop(write);
+            let mut buf = [0; 1];
+            match read.read(&mut buf) {
+                Ok(1) => {
+                    println!("{child}");
+                    process::exit(0)
+                }
+                _ => {
+                    eprintln!("background mount failed, see log of process {child}");
+                    process::exit(1)
+                }
+            }
+        }
+    }
+}
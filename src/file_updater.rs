@@ -0,0 +1,628 @@
+use std::{
+    cell::OnceCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    ffi::OsString,
+    fmt, fs,
+    path::Path,
+};
+
+use itertools::Itertools as _;
+use tracing::{debug, info, warn};
+
+use crate::{
+    scan_cache::CachedFile,
+    tagger::{NamespacedTagger, Normalizers, Sniff, Tag, Tagger, DEFAULT_SNIFF_BYTES},
+};
+
+/// What to do with tags that aren't valid UTF-8 (or contain NUL bytes), and
+/// so can't safely be used as path components.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NonUtf8Policy {
+    /// Drop the offending tags, keeping the file's other tags
+    Skip,
+    /// Replace the offending bytes with U+FFFD
+    #[default]
+    Lossy,
+    /// Leave the whole file untagged
+    Error,
+}
+
+/// Tags of a file, each with the names of the taggers that emitted it.
+pub type Provenance = HashMap<Tag, BTreeSet<String>>;
+
+/// The tag already in `tags` holding a different value for `tag`'s singleton
+/// label, if any.
+fn conflicting_singleton<'t>(tags: &'t Provenance, tag: &Tag) -> Option<&'t Tag> {
+    if !tag.is_singleton() {
+        return None;
+    }
+    tags.keys().find(|kept| {
+        kept.is_singleton() && kept.label() == tag.label() && kept.as_os_str() != tag.as_os_str()
+    })
+}
+
+/// Too many files failed tagging for the scan to be worth mounting.
+#[derive(Debug, PartialEq)]
+pub struct BudgetExceeded {
+    pub failed: usize,
+    pub scanned: usize,
+}
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} files failed tagging, exceeding the error budget",
+            self.failed, self.scanned
+        )
+    }
+}
+impl std::error::Error for BudgetExceeded {}
+
+/// Limit on how many files may fail tagging during a scan, so a mostly
+/// unreadable source aborts instead of mounting a near-empty index.
+///
+/// The absolute limit is checked as files are recorded; the percentage only
+/// means something over the whole scan, so is checked by
+/// [`ErrorBudget::finish`].
+#[derive(Debug, Default)]
+pub struct ErrorBudget {
+    max_failed: Option<usize>,
+    max_percent: Option<f64>,
+    failed: usize,
+    scanned: usize,
+}
+impl ErrorBudget {
+    pub fn new(max_failed: Option<usize>, max_percent: Option<f64>) -> Self {
+        Self {
+            max_failed,
+            max_percent,
+            ..Self::default()
+        }
+    }
+
+    /// Count one scanned file, erroring once more than the allowed number
+    /// have failed.
+    pub fn record(&mut self, failed: bool) -> Result<(), BudgetExceeded> {
+        self.scanned += 1;
+        if failed {
+            self.failed += 1;
+        }
+        match self.max_failed {
+            Some(max_failed) if self.failed > max_failed => Err(self.exceeded()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the failure percentage once every file has been recorded.
+    pub fn finish(&self) -> Result<(), BudgetExceeded> {
+        match self.max_percent {
+            Some(max_percent)
+                if self.scanned > 0
+                    && self.failed as f64 * 100.0 / self.scanned as f64 > max_percent =>
+            {
+                Err(self.exceeded())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exceeded(&self) -> BudgetExceeded {
+        BudgetExceeded {
+            failed: self.failed,
+            scanned: self.scanned,
+        }
+    }
+}
+
+/// Runs every registered tagger over a file, combining and normalizing the
+/// tags they emit.
+///
+/// A file has at most one value per singleton label. When taggers disagree,
+/// the earliest registered tagger's value wins, and within one tagger the
+/// value sorting first; the losing values are dropped with a warning.
+#[derive(Debug)]
+pub struct FileUpdater {
+    taggers: Vec<Box<dyn Tagger>>,
+    normalizers: Normalizers,
+    non_utf8: NonUtf8Policy,
+    /// Labels whose tags are dropped rather than indexed
+    suppressed_labels: HashSet<OsString>,
+    /// Leading bytes read once per file and shared by sniffing taggers
+    sniff_bytes: u64,
+}
+impl Default for FileUpdater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl FileUpdater {
+    pub fn new() -> Self {
+        Self {
+            taggers: Vec::new(),
+            normalizers: Normalizers::with_defaults(),
+            non_utf8: NonUtf8Policy::default(),
+            suppressed_labels: HashSet::new(),
+            sniff_bytes: DEFAULT_SNIFF_BYTES,
+        }
+    }
+
+    /// Most bytes read from the start of each file for taggers that only
+    /// look at a prefix.
+    pub fn set_sniff_bytes(&mut self, sniff_bytes: u64) {
+        self.sniff_bytes = sniff_bytes;
+    }
+
+    /// Drop every tag labelled `label`, as seen after namespacing, while
+    /// keeping the other tags from the same tagger.
+    pub fn suppress_label(&mut self, label: impl Into<OsString>) {
+        self.suppressed_labels.insert(label.into());
+    }
+
+    pub fn set_non_utf8_policy(&mut self, policy: NonUtf8Policy) {
+        self.non_utf8 = policy;
+    }
+
+    pub fn add_tagger(&mut self, tagger: impl Tagger + 'static) {
+        self.add_boxed_tagger(Box::new(tagger));
+    }
+
+    pub fn add_boxed_tagger(&mut self, tagger: Box<dyn Tagger>) {
+        self.taggers.push(tagger);
+    }
+
+    pub fn add_tagger_with_namespace(
+        &mut self,
+        namespace: Option<&str>,
+        tagger: impl Tagger + 'static,
+    ) {
+        match namespace {
+            Some(namespace) => self.add_tagger(NamespacedTagger::new(namespace, tagger)),
+            None => self.add_tagger(tagger),
+        }
+    }
+
+    pub fn normalizers_mut(&mut self) -> &mut Normalizers {
+        &mut self.normalizers
+    }
+
+    pub fn tagger_names(&self) -> impl Iterator<Item = &str> {
+        self.taggers.iter().map(|tagger| tagger.name())
+    }
+
+    pub fn tag(&self, path: &Path) -> HashSet<Tag> {
+        self.tag_with_provenance(path).into_keys().collect()
+    }
+
+    /// Tags for `path`, recording which taggers emitted each one.
+    pub fn tag_with_provenance(&self, path: &Path) -> Provenance {
+        self.tag_counting_failures(path).0
+    }
+
+    /// Tag every non-directory under `source`, each stat'ed just before it's
+    /// tagged. Aborts once more files have failed than `budget` allows.
+    pub fn scan(
+        &self,
+        source: &Path,
+        mut budget: ErrorBudget,
+    ) -> Result<Vec<CachedFile>, BudgetExceeded> {
+        let mut files = Vec::new();
+        for e in walkdir::WalkDir::new(source)
+            .same_file_system(true)
+            .into_iter()
+            .flatten()
+        {
+            debug!(entry = debug(&e), "walkdir");
+            if !e.file_type().is_dir() {
+                let mut file = CachedFile::stat(e.path().to_path_buf());
+                let (tags, failures) = self.tag_counting_failures(e.path());
+                budget.record(failures > 0)?;
+                file.provenance = tags;
+                files.push(file);
+                info!(filename = ?e.path(), "file");
+            }
+        }
+        budget.finish()?;
+        Ok(files)
+    }
+
+    /// Tags for `path` as [`FileUpdater::tag_with_provenance`], along with how
+    /// many taggers failed on it. Failing taggers contribute no tags.
+    pub fn tag_counting_failures(&self, path: &Path) -> (Provenance, usize) {
+        let special = fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_file());
+        let mut failures = 0;
+        // Read the prefix once, when the first sniffing tagger runs so
+        // earlier taggers see the file unread; if it can't be read, they
+        // fall back to reading the file themselves.
+        let sniff = OnceCell::new();
+        let tags = self
+            .taggers
+            .iter()
+            .filter(|tagger| !special || tagger.tags_special_files())
+            .fold(Provenance::new(), |mut acc, tagger| {
+                // Opening a fifo to sniff it would block
+                let sniffed = (tagger.sniffs() && !special)
+                    .then(|| sniff.get_or_init(|| Sniff::read(path, self.sniff_bytes).ok()))
+                    .and_then(Option::as_ref);
+                let tagged = match sniffed {
+                    Some(sniff) => tagger.tag_sniffed(path, sniff),
+                    None => tagger.tag(path),
+                };
+                match tagged {
+                    Ok(tags) => {
+                        let tags = tags
+                            .into_iter()
+                            .filter(|tag| {
+                                !(tag.has_label() && self.suppressed_labels.contains(tag.label()))
+                            })
+                            .map(|tag| self.normalizers.apply(tag))
+                            .sorted_by(|a, b| a.as_os_str().cmp(b.as_os_str()));
+                        for tag in tags {
+                            if let Some(kept) = conflicting_singleton(&acc, &tag) {
+                                warn!(
+                                    ?path,
+                                    tagger = tagger.name(),
+                                    ?tag,
+                                    ?kept,
+                                    "conflicting singleton value, dropping"
+                                );
+                                continue;
+                            }
+                            acc.entry(tag)
+                                .or_default()
+                                .insert(tagger.name().to_string());
+                        }
+                    }
+                    Err(error) => {
+                        warn!(?path, tagger = tagger.name(), ?error, "tagging failed");
+                        failures += 1;
+                    }
+                }
+                acc
+            });
+        (self.apply_non_utf8_policy(path, tags), failures)
+    }
+
+    fn apply_non_utf8_policy(&self, path: &Path, tags: Provenance) -> Provenance {
+        if tags.keys().all(Tag::is_representable) {
+            return tags;
+        }
+        match self.non_utf8 {
+            NonUtf8Policy::Skip => tags
+                .into_iter()
+                .filter(|(tag, _taggers)| {
+                    let keep = tag.is_representable();
+                    if !keep {
+                        warn!(?path, ?tag, "skipping non-UTF-8 tag");
+                    }
+                    keep
+                })
+                .collect(),
+            NonUtf8Policy::Lossy => {
+                tags.into_iter()
+                    .fold(Provenance::new(), |mut acc, (tag, taggers)| {
+                        let tag = match tag.is_representable() {
+                            true => tag,
+                            false => tag.to_lossy(),
+                        };
+                        acc.entry(tag).or_default().extend(taggers);
+                        acc
+                    })
+            }
+            NonUtf8Policy::Error => {
+                warn!(?path, "non-UTF-8 tag, leaving file untagged");
+                Provenance::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeSet, HashSet},
+        env,
+        ffi::{OsStr, OsString},
+        fs,
+        path::Path,
+    };
+
+    use crate::tagger::{Error, Sniff, Tag, Tagger};
+
+    use super::{BudgetExceeded, ErrorBudget, FileUpdater, NonUtf8Policy};
+
+    #[derive(Debug)]
+    struct StubTagger(Vec<(&'static str, &'static str)>);
+    impl Tagger for StubTagger {
+        fn name(&self) -> &str {
+            "stub"
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(label, value)| Tag::new(*label, true, *value))
+                .collect())
+        }
+    }
+
+    #[derive(Debug)]
+    struct NonUtf8Tagger;
+    impl Tagger for NonUtf8Tagger {
+        fn name(&self) -> &str {
+            "non-utf8"
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            use std::os::unix::ffi::OsStringExt as _;
+            Ok(HashSet::from([
+                Tag::new("folder", false, OsString::from_vec(vec![b'x', 0xff])),
+                Tag::new("folder", false, "ok"),
+            ]))
+        }
+    }
+
+    fn tag_with_policy(policy: NonUtf8Policy) -> HashSet<Tag> {
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(NonUtf8Tagger);
+        file_updater.set_non_utf8_policy(policy);
+        file_updater.tag(Path::new("any"))
+    }
+
+    #[test]
+    fn non_utf8_skip() {
+        assert_eq!(
+            HashSet::from([Tag::new("folder", false, "ok")]),
+            tag_with_policy(NonUtf8Policy::Skip)
+        );
+    }
+
+    #[test]
+    fn non_utf8_lossy() {
+        assert_eq!(
+            HashSet::from([
+                Tag::new("folder", false, "x\u{FFFD}"),
+                Tag::new("folder", false, "ok")
+            ]),
+            tag_with_policy(NonUtf8Policy::Lossy)
+        );
+    }
+
+    #[test]
+    fn non_utf8_error() {
+        assert!(tag_with_policy(NonUtf8Policy::Error).is_empty());
+    }
+
+    #[test]
+    fn normalizes_before_indexing() {
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(StubTagger(vec![
+            ("modified", "1970-01-02 00:00:00"),
+            ("size", "2048"),
+            ("camera", "ACME"),
+        ]));
+        file_updater
+            .normalizers_mut()
+            .register("camera", |_label: &OsStr, value: &OsStr| {
+                value.to_ascii_lowercase()
+            });
+        assert_eq!(
+            HashSet::from([
+                Tag::new("modified", true, "1970-01-02T00:00:00Z"),
+                Tag::new("size", true, "2.0 KiB"),
+                Tag::new("camera", true, OsString::from("acme")),
+            ]),
+            file_updater.tag(Path::new("any"))
+        );
+    }
+
+    #[test]
+    fn provenance_names_tagger() {
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(StubTagger(vec![("colour", "red")]));
+        file_updater.add_tagger(NonUtf8Tagger);
+        let provenance = file_updater.tag_with_provenance(Path::new("any"));
+        assert_eq!(
+            Some(&BTreeSet::from(["stub".to_string()])),
+            provenance.get(&Tag::new("colour", true, "red"))
+        );
+        assert_eq!(
+            Some(&BTreeSet::from(["non-utf8".to_string()])),
+            provenance.get(&Tag::new("folder", false, "ok"))
+        );
+    }
+
+    #[test]
+    fn special_files_only_reach_willing_taggers() {
+        #[derive(Debug)]
+        struct SpecialTagger;
+        impl Tagger for SpecialTagger {
+            fn name(&self) -> &str {
+                "special"
+            }
+            fn tags_special_files(&self) -> bool {
+                true
+            }
+            fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+                Ok(HashSet::from([Tag::from("special")]))
+            }
+        }
+
+        let path = env::temp_dir().join("file_updater_special_files_link");
+        let _ = fs::remove_file(&path);
+        std::os::unix::fs::symlink("/nonexistent/target", &path).unwrap();
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(StubTagger(vec![("colour", "red")]));
+        file_updater.add_tagger(SpecialTagger);
+        let tags = file_updater.tag(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(HashSet::from([Tag::from("special")]), tags);
+    }
+
+    #[test]
+    fn scan_aborts_over_error_budget() {
+        #[derive(Debug)]
+        struct MostlyFailingTagger;
+        impl Tagger for MostlyFailingTagger {
+            fn name(&self) -> &str {
+                "mostly-failing"
+            }
+            fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+                match path.ends_with("ok") {
+                    true => Ok(HashSet::from([Tag::from("ok")])),
+                    false => Err(Error::Illegible),
+                }
+            }
+        }
+
+        let dir = env::temp_dir().join("file_updater_scan_aborts");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        for name in [
+            "ok",
+            "bad1",
+            "sub/ok",
+            "bad2",
+            "sub/bad3",
+            "bad4",
+            "sub/deeper/ok",
+        ] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(MostlyFailingTagger);
+
+        // Walk order varies, so only the failures are certain
+        let Err(aborted) = file_updater.scan(&dir, ErrorBudget::new(Some(2), None)) else {
+            panic!("scan within budget");
+        };
+        assert_eq!(3, aborted.failed);
+        assert!((3..=6).contains(&aborted.scanned));
+        assert_eq!(
+            Err(BudgetExceeded {
+                failed: 4,
+                scanned: 7
+            }),
+            file_updater.scan(&dir, ErrorBudget::new(None, Some(50.0)))
+        );
+        let files = file_updater
+            .scan(&dir, ErrorBudget::new(Some(4), Some(60.0)))
+            .unwrap();
+        assert_eq!(7, files.len());
+        assert_eq!(
+            3,
+            files
+                .iter()
+                .filter(|file| file.provenance.contains_key(&Tag::from("ok")))
+                .count()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn suppressed_labels_dropped() {
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(StubTagger(vec![
+            ("size", "2048"),
+            ("size-range", "small"),
+            ("colour", "red"),
+        ]));
+        file_updater.add_tagger(NonUtf8Tagger);
+        file_updater.suppress_label("size");
+        file_updater.suppress_label("folder");
+        assert_eq!(
+            HashSet::from([
+                Tag::new("size-range", true, "small"),
+                Tag::new("colour", true, "red"),
+            ]),
+            file_updater.tag(Path::new("any"))
+        );
+    }
+
+    /// Records the prefix it was handed, and panics if made to read itself.
+    #[derive(Debug)]
+    struct PrefixTagger(&'static str);
+    impl Tagger for PrefixTagger {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            panic!("{} read the file itself", self.0);
+        }
+        fn sniffs(&self) -> bool {
+            true
+        }
+        fn tag_sniffed(&self, _path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+            Ok(HashSet::from([Tag::new(
+                self.0,
+                true,
+                String::from_utf8_lossy(&sniff.prefix).into_owned(),
+            )]))
+        }
+    }
+
+    #[test]
+    fn sniff_shared_between_taggers() {
+        let path = env::temp_dir().join("file_updater_sniff_shared");
+        fs::write(&path, "0123456789").unwrap();
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(PrefixTagger("first"));
+        file_updater.add_tagger(PrefixTagger("second"));
+        file_updater.set_sniff_bytes(4);
+        let tags = file_updater.tag(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            HashSet::from([
+                Tag::new("first", true, "0123"),
+                Tag::new("second", true, "0123"),
+            ]),
+            tags
+        );
+    }
+
+    #[derive(Debug)]
+    struct NamedStub(&'static str, Vec<(&'static str, &'static str)>);
+    impl Tagger for NamedStub {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            Ok(self
+                .1
+                .iter()
+                .map(|(label, value)| Tag::new(*label, true, *value))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn conflicting_singletons_resolved_by_order() {
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(NamedStub("first", vec![("title", "A"), ("kind", "text")]));
+        file_updater.add_tagger(NamedStub(
+            "second",
+            vec![
+                ("title", "B"),
+                ("kind", "text"),
+                ("author", "Z"),
+                ("author", "Y"),
+            ],
+        ));
+        let tags = file_updater.tag_with_provenance(Path::new("any"));
+        assert_eq!(
+            HashSet::from([
+                Tag::new("title", true, "A"),
+                Tag::new("kind", true, "text"),
+                Tag::new("author", true, "Y"),
+            ]),
+            tags.keys().cloned().collect::<HashSet<_>>()
+        );
+        // Agreeing taggers both keep their provenance
+        assert_eq!(
+            &BTreeSet::from(["first".to_string(), "second".to_string()]),
+            &tags[&Tag::new("kind", true, "text")]
+        );
+        assert_eq!(
+            &BTreeSet::from(["first".to_string()]),
+            &tags[&Tag::new("title", true, "A")]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Orderings for directory listings.
+use std::{cmp::Ordering, ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+use crate::tagger::split_display;
+
+/// How entries of a directory listing are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Collation {
+    /// Byte-wise, so `size:10` sorts before `size:2`
+    #[default]
+    Lexical,
+    /// Runs of digits compare by value wherever they appear, so `file9`
+    /// sorts before `file10`
+    Natural,
+    /// Tags sharing a label compare by value when it is a number, with
+    /// non-numeric values after the numbers
+    Numeric,
+}
+impl Collation {
+    pub fn compare(self, a: &OsStr, b: &OsStr) -> Ordering {
+        match self {
+            Collation::Lexical => a.cmp(b),
+            Collation::Natural => natural(a.as_bytes(), b.as_bytes()).then_with(|| a.cmp(b)),
+            Collation::Numeric => numeric(a, b).then_with(|| a.cmp(b)),
+        }
+    }
+}
+
+/// Split into alternating runs of digits and non-digits.
+fn runs(s: &[u8]) -> impl Iterator<Item = &[u8]> {
+    s.chunk_by(|a, b| a.is_ascii_digit() == b.is_ascii_digit())
+}
+
+fn natural(a: &[u8], b: &[u8]) -> Ordering {
+    for (a, b) in runs(a).zip(runs(b)) {
+        let ordering = match (a[0].is_ascii_digit(), b[0].is_ascii_digit()) {
+            (true, true) => {
+                let (a, b) = (trim_zeros(a), trim_zeros(b));
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            }
+            _ => a.cmp(b),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    runs(a).count().cmp(&runs(b).count())
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|b| **b == b'0').count();
+    &digits[zeros..]
+}
+
+fn numeric(a: &OsStr, b: &OsStr) -> Ordering {
+    let (a_label, a_value) = split_display(a);
+    let (b_label, b_value) = split_display(b);
+    let number = |value: &OsStr| value.to_str().and_then(|v| v.parse::<f64>().ok());
+    a_label
+        .cmp(&b_label)
+        .then_with(|| match (number(a_value), number(b_value)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::Collation;
+
+    fn sorted(collation: Collation) -> Vec<&'static str> {
+        let mut names = vec![
+            "size:10", "size:2", "size:abc", "size:1.5", "a10b", "a9b", "size:02",
+        ];
+        names.sort_by(|a, b| collation.compare(OsStr::new(a), OsStr::new(b)));
+        names
+    }
+
+    #[test]
+    fn orderings() {
+        assert_eq!(
+            vec!["a10b", "a9b", "size:02", "size:1.5", "size:10", "size:2", "size:abc"],
+            sorted(Collation::Lexical)
+        );
+        assert_eq!(
+            vec!["a9b", "a10b", "size:1.5", "size:02", "size:2", "size:10", "size:abc"],
+            sorted(Collation::Natural)
+        );
+        assert_eq!(
+            vec!["a10b", "a9b", "size:1.5", "size:02", "size:2", "size:10", "size:abc"],
+            sorted(Collation::Numeric)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Names telling apart files listed in one directory under the same name.
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    os::unix::ffi::{OsStrExt as _, OsStringExt as _},
+    path::Path,
+};
+
+/// How files sharing a name within a listing are renamed. The first keeps
+/// the name, the rest get a suffix that parses back to it, so a lookup of
+/// the listed name finds the right file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CollisionFormat {
+    /// `name (2).ext`, counting from the first file
+    #[default]
+    Numbered,
+    /// `name.ext.2`
+    Dotted,
+    /// `name~1a2b3c4d.ext`, after a hash of the source directory
+    DirHash,
+}
+impl CollisionFormat {
+    /// The name listed for the file ranked `rank` among those named `name`,
+    /// counting from 0, whose source lies in `dir`.
+    pub fn disambiguate<'n>(self, name: &'n OsStr, rank: usize, dir: &Path) -> Cow<'n, OsStr> {
+        if rank == 0 {
+            return Cow::Borrowed(name);
+        }
+        let (stem, extension) = split_extension(name.as_bytes());
+        let mut disambiguated = Vec::with_capacity(name.len() + 12);
+        match self {
+            CollisionFormat::Numbered => {
+                disambiguated.extend_from_slice(stem);
+                disambiguated.extend_from_slice(format!(" ({})", rank + 1).as_bytes());
+                disambiguated.extend_from_slice(extension);
+            }
+            CollisionFormat::Dotted => {
+                disambiguated.extend_from_slice(name.as_bytes());
+                disambiguated.extend_from_slice(format!(".{}", rank + 1).as_bytes());
+            }
+            CollisionFormat::DirHash => {
+                disambiguated.extend_from_slice(stem);
+                disambiguated.extend_from_slice(format!("~{:08x}", dir_hash(dir)).as_bytes());
+                disambiguated.extend_from_slice(extension);
+            }
+        }
+        Cow::Owned(OsString::from_vec(disambiguated))
+    }
+
+    /// The shared name `name` was disambiguated from, if it has this
+    /// format's suffix.
+    pub fn base_name(self, name: &OsStr) -> Option<OsString> {
+        let (stem, extension) = match self {
+            CollisionFormat::Dotted => (name.as_bytes(), &[][..]),
+            _ => split_extension(name.as_bytes()),
+        };
+        let base = match self {
+            CollisionFormat::Numbered => {
+                let (base, count) = rsplit_once(stem.strip_suffix(b")")?, b" (")?;
+                is_count(count).then_some(base)?
+            }
+            CollisionFormat::Dotted => {
+                let (base, count) = rsplit_once(stem, b".")?;
+                is_count(count).then_some(base)?
+            }
+            CollisionFormat::DirHash => {
+                let (base, hash) = rsplit_once(stem, b"~")?;
+                (hash.len() == 8 && hash.iter().all(u8::is_ascii_hexdigit)).then_some(base)?
+            }
+        };
+        if base.is_empty() {
+            return None;
+        }
+        Some(OsString::from_vec([base, extension].concat()))
+    }
+}
+
+/// Split around the last `separator`.
+fn rsplit_once<'b>(bytes: &'b [u8], separator: &[u8]) -> Option<(&'b [u8], &'b [u8])> {
+    let idx = bytes
+        .windows(separator.len())
+        .rposition(|window| window == separator)?;
+    Some((&bytes[..idx], &bytes[idx + separator.len()..]))
+}
+
+/// A count of at least 2, written as [`CollisionFormat::disambiguate`] does.
+fn is_count(digits: &[u8]) -> bool {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .is_some_and(|count| count >= 2 && count.to_string().as_bytes() == digits)
+}
+
+/// Split off the extension, dot included, as [`Path::extension`] would.
+fn split_extension(name: &[u8]) -> (&[u8], &[u8]) {
+    match name.iter().rposition(|b| *b == b'.') {
+        Some(0) | None => (name, &[]),
+        Some(dot) => name.split_at(dot),
+    }
+}
+
+/// FNV-1a, which unlike the std hashers is stable across builds, so names
+/// survive upgrades.
+fn dir_hash(dir: &Path) -> u32 {
+    dir.as_os_str()
+        .as_bytes()
+        .iter()
+        .fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsStr, path::Path};
+
+    use clap::ValueEnum as _;
+
+    use super::CollisionFormat;
+
+    #[test]
+    fn formats_round_trip() {
+        let name = OsStr::new("report.tar.gz");
+        for format in CollisionFormat::value_variants() {
+            let first = format.disambiguate(name, 0, Path::new("a"));
+            assert_eq!(name, first);
+            assert_eq!(None, format.base_name(&first));
+
+            let second = format.disambiguate(name, 1, Path::new("b"));
+            assert_ne!(name, second);
+            assert_eq!(Some(name), format.base_name(&second).as_deref());
+        }
+        assert_eq!(
+            OsStr::new("report.tar (2).gz"),
+            CollisionFormat::Numbered.disambiguate(name, 1, Path::new("b"))
+        );
+        assert_eq!(
+            OsStr::new("report.tar.gz.3"),
+            CollisionFormat::Dotted.disambiguate(name, 2, Path::new("b"))
+        );
+        let hashed = CollisionFormat::DirHash.disambiguate(name, 1, Path::new("b"));
+        assert!(hashed.to_str().unwrap().starts_with("report.tar~"));
+        assert_ne!(
+            hashed,
+            CollisionFormat::DirHash.disambiguate(name, 1, Path::new("c"))
+        );
+    }
+
+    #[test]
+    fn plain_names_not_parsed() {
+        for name in ["Makefile", ".bashrc", "notes (1).txt", "v.02", "~cafe.txt"] {
+            for format in CollisionFormat::value_variants() {
+                assert_eq!(
+                    None,
+                    format.base_name(OsStr::new(name)),
+                    "{format:?} {name}"
+                );
+            }
+        }
+        assert_eq!(
+            Some("Makefile".into()),
+            CollisionFormat::Numbered.base_name(OsStr::new("Makefile (10)"))
+        );
+    }
+}
@@ -1,10 +1,16 @@
 #![allow(dead_code)]
 use std::{
+    collections::HashMap,
     ffi::CString,
     io,
     mem::MaybeUninit,
     os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, RwLock,
+    },
+    time::SystemTime,
 };
 
 use fuse_mt::FileType;
@@ -12,6 +18,16 @@ use libc::c_void;
 use mockall::automock;
 use tracing::error;
 
+/// Largest buffer a single read will allocate, whatever count is requested.
+pub(crate) const MAX_READ_SIZE: usize = 16 * 1024 * 1024;
+
+/// Number of bytes to buffer for a read of `count` bytes.
+pub(crate) fn read_len(count: u32) -> usize {
+    usize::try_from(count)
+        .unwrap_or(MAX_READ_SIZE)
+        .min(MAX_READ_SIZE)
+}
+
 pub(crate) fn mode_to_filetype(mode: libc::mode_t) -> FileType {
     match mode & libc::S_IFMT {
         libc::S_IFDIR => FileType::Directory,
@@ -36,7 +52,18 @@ pub trait LibcWrapper: std::fmt::Debug {
     fn open(&self, path: &Path, flags: i32) -> io::Result<i32>;
     fn close(&self, fd: i32) -> io::Result<()>;
     fn read(&self, fd: i32, offset: i64, count: u32) -> io::Result<Vec<u8>>;
+    /// Write `data` at `offset`, returning how much was written.
+    fn pwrite(&self, fd: i32, offset: i64, data: &[u8]) -> io::Result<usize>;
+    /// Write `data` at the file position, which for an fd opened with
+    /// `O_APPEND` is always the end of file.
+    fn write(&self, fd: i32, data: &[u8]) -> io::Result<usize>;
     fn unlink(&self, path: &Path) -> io::Result<()>;
+    fn fallocate(&self, fd: i32, offset: i64, len: i64, mode: i32) -> io::Result<()>;
+    /// Cut the file open as `fd` to `len` bytes, or extend it with zeros.
+    fn ftruncate(&self, fd: i32, len: i64) -> io::Result<()>;
+    fn chmod(&self, path: &Path, mode: u32) -> io::Result<()>;
+    /// Change the owner and group; `None` leaves that id unchanged.
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()>;
 }
 
 #[derive(Debug)]
@@ -114,29 +141,76 @@ impl LibcWrapper for LibcWrapperReal {
     }
 
     fn read(&self, fd: i32, offset: i64, count: u32) -> io::Result<Vec<u8>> {
-        let result = unsafe { libc::lseek64(fd, offset, libc::SEEK_SET) };
-        if -1 == result {
-            let e = io::Error::last_os_error();
-            error!("read({:?}): {}", fd, e);
-            return Err(e);
-        }
-        let mut buf = vec![0; count.try_into().unwrap()];
+        let len = read_len(count);
+        let mut buf = vec![0; len];
 
-        let result = unsafe {
-            libc::read(
-                fd,
-                buf.as_mut_ptr() as *mut c_void,
-                count.try_into().unwrap(),
-            )
-        };
-        if -1 == result {
-            let e = io::Error::last_os_error();
-            error!("read({:?}): {}", fd, e);
-            return Err(e);
+        // A single read may return fewer bytes than asked for, so keep going
+        // until the buffer is full or end of file is reached. pread leaves the
+        // shared file position alone, so concurrent reads on one fd can't race.
+        let mut filled = 0;
+        while filled < len {
+            let Some(position) = i64::try_from(filled)
+                .ok()
+                .and_then(|filled| offset.checked_add(filled))
+            else {
+                return Err(io::Error::from_raw_os_error(libc::EOVERFLOW));
+            };
+            let result = unsafe {
+                libc::pread64(
+                    fd,
+                    buf[filled..].as_mut_ptr() as *mut c_void,
+                    len - filled,
+                    position,
+                )
+            };
+            if -1 == result {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("read({:?}): {}", fd, e);
+                return Err(e);
+            }
+            if 0 == result {
+                break;
+            }
+            filled += result as usize;
         }
+        buf.truncate(filled);
         Ok(buf)
     }
 
+    fn pwrite(&self, fd: i32, offset: i64, data: &[u8]) -> io::Result<usize> {
+        loop {
+            let result =
+                unsafe { libc::pwrite64(fd, data.as_ptr() as *const c_void, data.len(), offset) };
+            if -1 == result {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("pwrite({:?}): {}", fd, e);
+                return Err(e);
+            }
+            return Ok(result as usize);
+        }
+    }
+
+    fn write(&self, fd: i32, data: &[u8]) -> io::Result<usize> {
+        loop {
+            let result = unsafe { libc::write(fd, data.as_ptr() as *const c_void, data.len()) };
+            if -1 == result {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("write({:?}): {}", fd, e);
+                return Err(e);
+            }
+            return Ok(result as usize);
+        }
+    }
+
     fn unlink(&self, path: &Path) -> io::Result<()> {
         let cstr = CString::new(path.to_path_buf().into_os_string().as_bytes())?;
         let result = unsafe { libc::unlink(cstr.as_ptr()) };
@@ -148,4 +222,381 @@ impl LibcWrapper for LibcWrapperReal {
             Ok(())
         }
     }
+
+    fn fallocate(&self, fd: i32, offset: i64, len: i64, mode: i32) -> io::Result<()> {
+        loop {
+            let result = unsafe { libc::fallocate64(fd, mode, offset, len) };
+            if -1 == result {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("fallocate({:?}): {}", fd, e);
+                return Err(e);
+            }
+            return Ok(());
+        }
+    }
+
+    fn ftruncate(&self, fd: i32, len: i64) -> io::Result<()> {
+        loop {
+            let result = unsafe { libc::ftruncate64(fd, len) };
+            if -1 == result {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("ftruncate({:?}): {}", fd, e);
+                return Err(e);
+            }
+            return Ok(());
+        }
+    }
+
+    fn chmod(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let cstr = CString::new(path.as_os_str().as_bytes())?;
+        let result = unsafe { libc::chmod(cstr.as_ptr(), mode as libc::mode_t) };
+        if -1 == result {
+            let e = io::Error::last_os_error();
+            error!("chmod({:?}): {}", path, e);
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let cstr = CString::new(path.as_os_str().as_bytes())?;
+        // -1 asks chown(2) to leave the id alone
+        let result = unsafe {
+            libc::chown(
+                cstr.as_ptr(),
+                uid.unwrap_or(libc::uid_t::MAX),
+                gid.unwrap_or(libc::gid_t::MAX),
+            )
+        };
+        if -1 == result {
+            let e = io::Error::last_os_error();
+            error!("chown({:?}): {}", path, e);
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A regular file held by [`InMemoryLibcWrapper`].
+#[derive(Debug)]
+struct MemoryFile {
+    content: Vec<u8>,
+    mode: libc::mode_t,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+}
+
+impl MemoryFile {
+    fn stat(&self) -> libc::stat {
+        let mut stat = unsafe { MaybeUninit::<libc::stat>::zeroed().assume_init() };
+        stat.st_mode = self.mode;
+        stat.st_size = self.content.len() as i64;
+        stat.st_blocks = self.content.len().div_ceil(512) as i64;
+        stat.st_nlink = 1;
+        stat.st_uid = self.uid;
+        stat.st_gid = self.gid;
+        stat.st_atime = self.mtime;
+        stat.st_mtime = self.mtime;
+        stat.st_ctime = self.mtime;
+        stat
+    }
+}
+
+/// An fd open on an [`InMemoryLibcWrapper`] file.
+#[derive(Debug)]
+struct MemoryFd {
+    file: Arc<RwLock<MemoryFile>>,
+    append: bool,
+    /// Where [`LibcWrapper::write`] writes when not appending
+    position: usize,
+}
+
+fn errno(code: i32) -> io::Error {
+    io::Error::from_raw_os_error(code)
+}
+
+/// Serves regular files from memory rather than the disk, for tests and
+/// small datasets. Files are keyed by the source path the index stores, and
+/// as with real files, one unlinked while open stays readable until closed.
+#[derive(Debug)]
+pub struct InMemoryLibcWrapper {
+    files: RwLock<HashMap<PathBuf, Arc<RwLock<MemoryFile>>>>,
+    fds: RwLock<HashMap<i32, MemoryFd>>,
+    next_fd: AtomicI32,
+}
+
+impl Default for InMemoryLibcWrapper {
+    fn default() -> Self {
+        Self {
+            files: RwLock::new(HashMap::new()),
+            fds: RwLock::new(HashMap::new()),
+            // Clear of the standard streams, as real fds would be
+            next_fd: AtomicI32::new(3),
+        }
+    }
+}
+
+impl InMemoryLibcWrapper {
+    /// Add or replace the file at `path`, owned by the current user.
+    pub fn insert(&self, path: impl Into<PathBuf>, content: impl Into<Vec<u8>>) {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        let file = MemoryFile {
+            content: content.into(),
+            mode: libc::S_IFREG | 0o644,
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+            mtime,
+        };
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.into(), Arc::new(RwLock::new(file)));
+    }
+
+    /// Copy every regular file under `source` into memory.
+    pub fn from_dir(source: &Path) -> io::Result<Self> {
+        let wrapper = Self::default();
+        for entry in walkdir::WalkDir::new(source).same_file_system(true) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                wrapper.insert(entry.path(), std::fs::read(entry.path())?);
+            }
+        }
+        Ok(wrapper)
+    }
+
+    fn by_path(&self, path: &Path) -> io::Result<Arc<RwLock<MemoryFile>>> {
+        let files = self.files.read().unwrap();
+        files.get(path).cloned().ok_or_else(|| errno(libc::ENOENT))
+    }
+
+    fn by_fd(&self, fd: i32) -> io::Result<Arc<RwLock<MemoryFile>>> {
+        let fds = self.fds.read().unwrap();
+        fds.get(&fd)
+            .map(|fd| fd.file.clone())
+            .ok_or_else(|| errno(libc::EBADF))
+    }
+}
+
+/// Overwrite `content` from `offset` with `data`, zero-filling any gap.
+fn write_at(content: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    let end = offset + data.len();
+    if end > content.len() {
+        content.resize(end, 0);
+    }
+    content[offset..end].copy_from_slice(data);
+}
+
+impl LibcWrapper for InMemoryLibcWrapper {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn statfs(&self, _path: PathBuf) -> io::Result<libc::statfs> {
+        Ok(unsafe { MaybeUninit::<libc::statfs>::zeroed().assume_init() })
+    }
+
+    fn fstat(&self, fh: u64) -> io::Result<libc::stat> {
+        let fd = i32::try_from(fh).map_err(|_| errno(libc::EBADF))?;
+        Ok(self.by_fd(fd)?.read().unwrap().stat())
+    }
+
+    fn lstat(&self, path: &Path) -> io::Result<libc::stat> {
+        Ok(self.by_path(path)?.read().unwrap().stat())
+    }
+
+    fn open(&self, path: &Path, flags: i32) -> io::Result<i32> {
+        let file = self.by_path(path)?;
+        if flags & libc::O_TRUNC != 0 && flags & libc::O_ACCMODE != libc::O_RDONLY {
+            file.write().unwrap().content.clear();
+        }
+        let fd = self.next_fd.fetch_add(1, Ordering::Relaxed);
+        let memory_fd = MemoryFd {
+            file,
+            append: flags & libc::O_APPEND != 0,
+            position: 0,
+        };
+        self.fds.write().unwrap().insert(fd, memory_fd);
+        Ok(fd)
+    }
+
+    fn close(&self, fd: i32) -> io::Result<()> {
+        match self.fds.write().unwrap().remove(&fd) {
+            Some(_) => Ok(()),
+            None => Err(errno(libc::EBADF)),
+        }
+    }
+
+    fn read(&self, fd: i32, offset: i64, count: u32) -> io::Result<Vec<u8>> {
+        let offset = usize::try_from(offset).map_err(|_| errno(libc::EINVAL))?;
+        let file = self.by_fd(fd)?;
+        let file = file.read().unwrap();
+        let start = offset.min(file.content.len());
+        let end = start
+            .saturating_add(read_len(count))
+            .min(file.content.len());
+        Ok(file.content[start..end].to_vec())
+    }
+
+    fn pwrite(&self, fd: i32, offset: i64, data: &[u8]) -> io::Result<usize> {
+        let offset = usize::try_from(offset).map_err(|_| errno(libc::EINVAL))?;
+        let file = self.by_fd(fd)?;
+        write_at(&mut file.write().unwrap().content, offset, data);
+        Ok(data.len())
+    }
+
+    fn write(&self, fd: i32, data: &[u8]) -> io::Result<usize> {
+        let mut fds = self.fds.write().unwrap();
+        let memory_fd = fds.get_mut(&fd).ok_or_else(|| errno(libc::EBADF))?;
+        let mut file = memory_fd.file.write().unwrap();
+        if memory_fd.append {
+            memory_fd.position = file.content.len();
+        }
+        write_at(&mut file.content, memory_fd.position, data);
+        memory_fd.position += data.len();
+        Ok(data.len())
+    }
+
+    fn unlink(&self, path: &Path) -> io::Result<()> {
+        match self.files.write().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(errno(libc::ENOENT)),
+        }
+    }
+
+    fn fallocate(&self, fd: i32, offset: i64, len: i64, mode: i32) -> io::Result<()> {
+        let end = offset
+            .checked_add(len)
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or_else(|| errno(libc::EINVAL))?;
+        let file = self.by_fd(fd)?;
+        let mut file = file.write().unwrap();
+        if mode & libc::FALLOC_FL_KEEP_SIZE == 0 && end > file.content.len() {
+            file.content.resize(end, 0);
+        }
+        Ok(())
+    }
+
+    fn ftruncate(&self, fd: i32, len: i64) -> io::Result<()> {
+        let len = usize::try_from(len).map_err(|_| errno(libc::EINVAL))?;
+        self.by_fd(fd)?.write().unwrap().content.resize(len, 0);
+        Ok(())
+    }
+
+    fn chmod(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let file = self.by_path(path)?;
+        let mut file = file.write().unwrap();
+        file.mode = (file.mode & libc::S_IFMT) | (mode & 0o7777);
+        Ok(())
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let file = self.by_path(path)?;
+        let mut file = file.write().unwrap();
+        file.uid = uid.unwrap_or(file.uid);
+        file.gid = gid.unwrap_or(file.gid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io, path::PathBuf, thread};
+
+    use super::{read_len, InMemoryLibcWrapper, LibcWrapper, LibcWrapperReal, MAX_READ_SIZE};
+
+    #[test]
+    fn read_len_clamps() {
+        assert_eq!(0, read_len(0));
+        assert_eq!(4096, read_len(4096));
+        assert_eq!(MAX_READ_SIZE, read_len(u32::MAX));
+    }
+
+    #[test]
+    fn read_large_count() -> io::Result<()> {
+        let path: PathBuf = env::temp_dir().join("libc_wrappers_read_large_count");
+        fs::write(&path, "0123456789")?;
+
+        let wrapper = LibcWrapperReal::new();
+        let fd = wrapper.open(&path, libc::O_RDONLY)?;
+        let whole = wrapper.read(fd, 0, u32::MAX);
+        let tail = wrapper.read(fd, 4, u32::MAX);
+        let past_end = wrapper.read(fd, 20, 16);
+        wrapper.close(fd)?;
+        fs::remove_file(&path)?;
+
+        // Short reads are truncated to what was actually read, not zero-padded
+        assert_eq!(b"0123456789".to_vec(), whole?);
+        assert_eq!(b"456789".to_vec(), tail?);
+        assert!(past_end?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn read_concurrent_offsets() -> io::Result<()> {
+        let path: PathBuf = env::temp_dir().join("libc_wrappers_read_concurrent_offsets");
+        let content = (0..=255u8).cycle().take(64 * 1024).collect::<Vec<_>>();
+        fs::write(&path, &content)?;
+
+        let wrapper = LibcWrapperReal::new();
+        let fd = wrapper.open(&path, libc::O_RDONLY)?;
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let (wrapper, content) = (&wrapper, &content);
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        let offset = (t * 997 + i * 131) % (content.len() - 64);
+                        let data = wrapper.read(fd, offset as i64, 64).unwrap();
+                        assert_eq!(&content[offset..offset + 64], data.as_slice());
+                    }
+                });
+            }
+        });
+        // Reads never moved the fd's own position
+        let position = unsafe { libc::lseek64(fd, 0, libc::SEEK_CUR) };
+        wrapper.close(fd)?;
+        fs::remove_file(&path)?;
+        assert_eq!(0, position);
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory_serves_files() -> io::Result<()> {
+        let wrapper = InMemoryLibcWrapper::new();
+        let path = PathBuf::from("/mem/notes.txt");
+        wrapper.insert(&path, "0123456789");
+
+        let stat = wrapper.lstat(&path)?;
+        assert_eq!(10, stat.st_size);
+        assert_eq!(libc::S_IFREG | 0o644, stat.st_mode);
+        let fd = wrapper.open(&path, libc::O_RDONLY)?;
+        assert_eq!(10, wrapper.fstat(fd as u64)?.st_size);
+        assert_eq!(b"456789".to_vec(), wrapper.read(fd, 4, u32::MAX)?);
+        assert!(wrapper.read(fd, 20, 16)?.is_empty());
+
+        // Unlinked but open, the content stays readable
+        wrapper.unlink(&path)?;
+        assert_eq!(
+            Some(libc::ENOENT),
+            wrapper.lstat(&path).unwrap_err().raw_os_error()
+        );
+        assert_eq!(b"0123".to_vec(), wrapper.read(fd, 0, 4)?);
+        wrapper.close(fd)?;
+        assert_eq!(
+            Some(libc::EBADF),
+            wrapper.read(fd, 0, 4).unwrap_err().raw_os_error()
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,189 @@
+//! Operation counters kept by [`TagFS`](super::tagfs::TagFS), and a minimal
+//! HTTP endpoint exposing them in the Prometheus text format.
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead as _, BufReader, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+/// How long a metrics client may stall reading or writing before it's
+/// dropped, since requests are answered one at a time.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    getattr_calls: AtomicU64,
+    readdir_calls: AtomicU64,
+    read_calls: AtomicU64,
+    read_bytes: AtomicU64,
+    /// Files in the index, not counting deleted ones
+    index_files: AtomicU64,
+}
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn count_getattr(&self) {
+        self.getattr_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_readdir(&self) {
+        self.readdir_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a read call serving `bytes`.
+    pub(crate) fn count_read(&self, bytes: usize) {
+        self.read_calls.fetch_add(1, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_index_files(&self, files: usize) {
+        self.index_files.store(files as u64, Ordering::Relaxed);
+    }
+
+    pub fn getattr_calls(&self) -> u64 {
+        self.getattr_calls.load(Ordering::Relaxed)
+    }
+
+    pub fn readdir_calls(&self) -> u64 {
+        self.readdir_calls.load(Ordering::Relaxed)
+    }
+
+    pub fn read_calls(&self) -> u64 {
+        self.read_calls.load(Ordering::Relaxed)
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn index_files(&self) -> u64 {
+        self.index_files.load(Ordering::Relaxed)
+    }
+
+    /// The counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, value) in [
+            (
+                "tagfs_getattr_calls_total",
+                "counter",
+                "getattr calls",
+                self.getattr_calls(),
+            ),
+            (
+                "tagfs_readdir_calls_total",
+                "counter",
+                "readdir calls",
+                self.readdir_calls(),
+            ),
+            (
+                "tagfs_read_calls_total",
+                "counter",
+                "read calls",
+                self.read_calls(),
+            ),
+            (
+                "tagfs_read_bytes_total",
+                "counter",
+                "Bytes served by read calls",
+                self.read_bytes(),
+            ),
+            (
+                "tagfs_index_files",
+                "gauge",
+                "Files in the index",
+                self.index_files(),
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        }
+        out
+    }
+
+    /// Answer every HTTP request on `addr` with the rendered counters, from
+    /// a background thread.
+    pub fn serve(self: Arc<Self>, addr: SocketAddr) -> io::Result<SocketAddr> {
+        self.serve_with_timeout(addr, CLIENT_TIMEOUT)
+    }
+
+    /// [`Self::serve`], dropping clients that stall for `timeout`.
+    pub(crate) fn serve_with_timeout(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        info!(addr = %local_addr, "serving metrics");
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let responded = stream.and_then(|stream| {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    self.respond(stream)
+                });
+                if let Err(error) = responded {
+                    warn!(?error, "metrics request");
+                }
+            }
+        });
+        Ok(local_addr)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        // Only the request line matters; read headers up to the blank line
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+            line.clear();
+        }
+        let body = self.render();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read as _, Write as _},
+        net::{SocketAddr, TcpStream},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use super::Metrics;
+
+    #[test]
+    fn idle_client_times_out() {
+        let addr = Arc::new(Metrics::new())
+            .serve_with_timeout(
+                SocketAddr::from(([127, 0, 0, 1], 0)),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        // Connects but never sends a request
+        let _idle = TcpStream::connect(addr).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("tagfs_index_files 0\n"));
+    }
+}
@@ -1,2 +1,10 @@
+pub mod collation;
+pub mod collision;
 mod libc_wrappers;
+pub mod metrics;
+mod read_budget;
+mod read_cache;
+pub mod search;
 pub mod tagfs;
+
+pub use libc_wrappers::{InMemoryLibcWrapper, LibcWrapper, LibcWrapperReal};
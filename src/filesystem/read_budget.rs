@@ -0,0 +1,91 @@
+//! Bound on the bytes buffered by reads in flight at once.
+use std::sync::{Condvar, Mutex};
+
+use tracing::debug;
+
+/// Bytes reserved by the reads in flight, against a fixed budget. Reads
+/// reserve their buffer before filling it, waiting while the budget is spent,
+/// so a burst of parallel reads can't spike memory.
+#[derive(Debug)]
+pub(crate) struct ReadBudget {
+    max_bytes: u64,
+    in_flight: Mutex<u64>,
+    released: Condvar,
+}
+
+impl ReadBudget {
+    pub(crate) fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Reserve `bytes` until the permit is dropped, waiting for other reads
+    /// to release enough. A read asking for more than the whole budget
+    /// reserves all of it, so runs alone rather than never.
+    pub(crate) fn acquire(&self, bytes: u64) -> ReadPermit<'_> {
+        let bytes = bytes.min(self.max_bytes);
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if *in_flight + bytes > self.max_bytes {
+            debug!(bytes, in_flight = *in_flight, "read waiting for memory");
+            in_flight = self
+                .released
+                .wait_while(in_flight, |in_flight| *in_flight + bytes > self.max_bytes)
+                .unwrap();
+        }
+        *in_flight += bytes;
+        ReadPermit {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+/// Bytes reserved from a [`ReadBudget`], released on drop.
+#[derive(Debug)]
+pub(crate) struct ReadPermit<'b> {
+    budget: &'b ReadBudget,
+    bytes: u64,
+}
+
+impl Drop for ReadPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_flight.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::ReadBudget;
+
+    #[test]
+    fn throttles_concurrent_reads() {
+        let budget = ReadBudget::new(100);
+        let (buffered, peak) = (AtomicU64::new(0), AtomicU64::new(0));
+        thread::scope(|scope| {
+            for i in 0..16 {
+                let (budget, buffered, peak) = (&budget, &buffered, &peak);
+                scope.spawn(move || {
+                    // Every fourth read wants more than the whole budget
+                    let size = if i % 4 == 0 { 250 } else { 40 };
+                    let permit = budget.acquire(size);
+                    let now = buffered.fetch_add(permit.bytes, Ordering::SeqCst) + permit.bytes;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    buffered.fetch_sub(permit.bytes, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 100);
+        assert_eq!(0, *budget.in_flight.lock().unwrap());
+    }
+}
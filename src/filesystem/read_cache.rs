@@ -0,0 +1,143 @@
+//! Byte-bounded LRU cache of recently read file slices.
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Slices larger than this fraction of the cache are never stored, so one
+/// big read can't flush everything else.
+const MAX_SLICE_FRACTION: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    source: PathBuf,
+    /// File size when opened, so a changed file misses rather than serving
+    /// stale content
+    file_size: u64,
+    offset: u64,
+    size: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ReadCache {
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<Key, (u64, Vec<u8>)>,
+    /// Keys by last use, oldest first
+    recency: BTreeMap<u64, Key>,
+}
+
+impl ReadCache {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn get(
+        &mut self,
+        source: &Path,
+        file_size: u64,
+        offset: u64,
+        size: u32,
+    ) -> Option<Vec<u8>> {
+        let key = Key {
+            source: source.to_path_buf(),
+            file_size,
+            offset,
+            size,
+        };
+        self.tick += 1;
+        let (last_used, content) = self.entries.get_mut(&key)?;
+        self.recency.remove(last_used);
+        *last_used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(content.clone())
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        source: &Path,
+        file_size: u64,
+        offset: u64,
+        size: u32,
+        content: &[u8],
+    ) {
+        if content.len() > self.max_bytes / MAX_SLICE_FRACTION {
+            return;
+        }
+        let key = Key {
+            source: source.to_path_buf(),
+            file_size,
+            offset,
+            size,
+        };
+        self.remove(&key);
+        while self.bytes + content.len() > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((_, evicted)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+        self.tick += 1;
+        self.bytes += content.len();
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (self.tick, content.to_vec()));
+    }
+
+    /// Drop every slice of `source`.
+    pub(crate) fn invalidate(&mut self, source: &Path) {
+        let keys = self
+            .entries
+            .keys()
+            .filter(|key| key.source == source)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some((last_used, content)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+            self.bytes -= content.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::ReadCache;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ReadCache::new(64);
+        let (a, b, c) = (Path::new("/a"), Path::new("/b"), Path::new("/c"));
+        cache.insert(a, 100, 0, 4, b"aaaa");
+        cache.insert(b, 100, 0, 4, b"bbbb");
+        assert_eq!(Some(b"aaaa".to_vec()), cache.get(a, 100, 0, 4));
+        // Too big for a single slice
+        cache.insert(c, 100, 0, 5, b"ccccc");
+        assert_eq!(None, cache.get(c, 100, 0, 5));
+
+        for offset in 0..15 {
+            cache.insert(c, 100, offset, 4, b"cccc");
+        }
+        // `b` was least recently used, so went first
+        assert_eq!(None, cache.get(b, 100, 0, 4));
+        assert_eq!(Some(b"aaaa".to_vec()), cache.get(a, 100, 0, 4));
+        assert!(cache.bytes <= 64);
+
+        // A different size at open is a different file
+        assert_eq!(None, cache.get(a, 101, 0, 4));
+        cache.invalidate(a);
+        assert_eq!(None, cache.get(a, 100, 0, 4));
+    }
+}
@@ -0,0 +1,223 @@
+//! Boolean expressions over tags, such as
+//! `folder:Invoices AND (doctype:invoice OR NOT reviewed)`, for saved
+//! searches.
+//!
+//! `NOT` binds tightest, then `AND`, then `OR`; parentheses group. Tags are
+//! written in their display form, and double-quoted when they contain
+//! spaces or parentheses or are one of the keywords.
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt,
+    str::FromStr,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// Files carrying the tag with this display form
+    Tag(OsString),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The expression ended where a tag or `(` was expected
+    UnexpectedEnd,
+    /// A token that can't appear where it was found
+    Unexpected(String),
+    UnclosedQuote,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "expression ends unexpectedly"),
+            ParseError::Unexpected(token) => write!(f, "unexpected {token:?}"),
+            ParseError::UnclosedQuote => write!(f, "unclosed quote"),
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Tag(String),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(ParseError::UnclosedQuote),
+                    }
+                }
+                tokens.push(Token::Tag(tag));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()\"".contains(*c)) {
+                    word.push(c);
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Tag(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, one function per precedence level.
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+impl Parser {
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    Some(token) => Err(ParseError::Unexpected(format!("{token:?}"))),
+                    None => Err(ParseError::UnexpectedEnd),
+                }
+            }
+            Some(Token::Tag(tag)) => Ok(Expr::Tag(tag.into())),
+            Some(token) => Err(ParseError::Unexpected(format!("{token:?}"))),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?.into_iter().peekable(),
+        };
+        let expr = parser.or()?;
+        match parser.tokens.next() {
+            None => Ok(expr),
+            Some(token) => Err(ParseError::Unexpected(format!("{token:?}"))),
+        }
+    }
+}
+
+impl Expr {
+    /// Ids of the files matching the expression, out of `all`, with
+    /// `files_for` giving the ids of the files carrying a tag.
+    pub fn eval(
+        &self,
+        files_for: &impl Fn(&OsStr) -> HashSet<usize>,
+        all: &HashSet<usize>,
+    ) -> HashSet<usize> {
+        match self {
+            Expr::Tag(tag) => files_for(tag).intersection(all).copied().collect(),
+            Expr::And(a, b) => {
+                let a = a.eval(files_for, all);
+                match a.is_empty() {
+                    true => a,
+                    false => b.eval(files_for, &a),
+                }
+            }
+            Expr::Or(a, b) => {
+                let mut a = a.eval(files_for, all);
+                a.extend(b.eval(files_for, all));
+                a
+            }
+            Expr::Not(a) => all.difference(&a.eval(files_for, all)).copied().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        ffi::{OsStr, OsString},
+    };
+
+    use super::{Expr, ParseError};
+
+    fn tag(name: &str) -> Box<Expr> {
+        Box::new(Expr::Tag(name.into()))
+    }
+
+    #[test]
+    fn parses_with_precedence() {
+        assert_eq!(
+            Ok(Expr::Or(
+                Box::new(Expr::And(tag("folder:Invoices"), tag("doctype:invoice"))),
+                Box::new(Expr::Not(tag("reviewed"))),
+            )),
+            "folder:Invoices AND doctype:invoice OR NOT reviewed".parse()
+        );
+        assert_eq!(
+            Ok(Expr::And(
+                tag("a"),
+                Box::new(Expr::Or(tag("b c"), tag("AND"))),
+            )),
+            "a AND (\"b c\" OR \"AND\")".parse()
+        );
+        assert_eq!(Err(ParseError::UnexpectedEnd), "a AND".parse::<Expr>());
+        assert_eq!(Err(ParseError::UnexpectedEnd), "(a OR b".parse::<Expr>());
+        assert_eq!(Err(ParseError::UnclosedQuote), "\"a".parse::<Expr>());
+        assert!(matches!(
+            "a b".parse::<Expr>(),
+            Err(ParseError::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn evaluates_over_tag_sets() {
+        let tags = HashMap::from([
+            (OsString::from("red"), HashSet::from([0, 1])),
+            (OsString::from("round"), HashSet::from([1, 2])),
+        ]);
+        let files_for = |tag: &OsStr| tags.get(tag).cloned().unwrap_or_default();
+        let all = HashSet::from([0, 1, 2, 3]);
+        let eval = |expression: &str| expression.parse::<Expr>().unwrap().eval(&files_for, &all);
+        assert_eq!(HashSet::from([1]), eval("red AND round"));
+        assert_eq!(HashSet::from([0, 1, 2]), eval("red OR round"));
+        assert_eq!(HashSet::from([0, 3]), eval("NOT round"));
+        assert_eq!(HashSet::from([0]), eval("red AND NOT round"));
+        assert!(eval("missing").is_empty());
+    }
+}
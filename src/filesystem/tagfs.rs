@@ -1,24 +1,90 @@
 use std::{
-    collections::{HashMap, HashSet},
-    ffi::OsStr,
+    borrow::Cow,
+    cell::Cell,
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    mem,
+    os::unix::ffi::OsStrExt,
     path::{Component, Path, PathBuf},
-    sync::Mutex,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use fuse_mt::{
     DirectoryEntry, FileAttr, FileType, FilesystemMT, RequestInfo, ResultOpen, ResultReaddir,
+    ResultWrite, ResultXattr, Xattr,
 };
 use itertools::Itertools as _;
-use libc::ENOENT;
-use tracing::{debug, info, instrument};
+use libc::{
+    EBADF, EINVAL, EIO, EISDIR, ENODATA, ENOENT, ENOSYS, ENOTDIR, ENOTSUP, EPERM, ERANGE, EROFS,
+    O_ACCMODE, O_APPEND, O_RDONLY,
+};
+use tracing::{debug, info, instrument, warn};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization as _};
 
-use crate::tagger::Tag;
+use crate::{
+    archive::{ArchiveMember, ArchiveReader, ZipReader},
+    file_updater::{FileUpdater, Provenance},
+    tagger::{Tag, TAG_SEPARATOR},
+};
 
-use super::libc_wrappers::{mode_to_filetype, LibcWrapper, LibcWrapperReal};
+use super::{
+    collation::Collation,
+    collision::CollisionFormat,
+    libc_wrappers::{mode_to_filetype, LibcWrapper, LibcWrapperReal},
+    metrics::Metrics,
+    read_budget::ReadBudget,
+    read_cache::ReadCache,
+    search::Expr,
+};
 
 const TTL: Duration = Duration::from_secs(1);
 
+/// Name of the read-only pseudo-file at the mount root reporting mount stats.
+/// A tag with this name is shadowed by the pseudo-file, so is never indexed.
+pub(crate) const INFO_FILE: &str = ".tagfs-info";
+
+/// Synthetic tag on the most recently modified files.
+pub(crate) const RECENT_TAG: &str = "recent";
+
+/// Label of the synthetic tag giving the scan generation a file was first
+/// indexed in, once there has been more than one.
+pub(crate) const GENERATION_LABEL: &str = "generation";
+/// Synthetic tag on the files first indexed in the latest generation.
+pub(crate) const NEW_TAG: &str = "new";
+const DEFAULT_RECENT_LIMIT: usize = 100;
+
+/// Synthetic tag on files whose size stands out from their siblings'.
+pub(crate) const SIZE_OUTLIER_TAG: &str = "size-outlier";
+/// Standard deviations from its directory's mean size making a file an
+/// outlier.
+const OUTLIER_DEVIATIONS: f64 = 3.0;
+/// Directories with fewer files have no outliers: among n files one can be
+/// at most (n - 1) / sqrt(n) deviations out, under 3 until n reaches 11.
+const OUTLIER_MIN_FILES: usize = 11;
+
+/// Extended attribute exposing the absolute source path of a file entry.
+pub(crate) const SOURCE_XATTR: &str = "user.tagfs.source";
+
+/// Extended attribute listing, one `tag<TAB>tagger,...` line per tag, which
+/// taggers produced each of a file's tags.
+pub(crate) const PROVENANCE_XATTR: &str = "user.tagfs.provenance";
+
+/// Write-only extended attribute editing a file's tags: newline-separated
+/// `+tag` and `-tag` commands, tags in their display form. Edits are
+/// replayed when the file is retagged or the source rescanned, but are held
+/// in memory only, so are lost on unmount.
+pub(crate) const TAG_CONTROL_XATTR: &str = "user.tagfs.tags";
+/// Prefix of the path component `+latest:<label>`, which narrows the files
+/// listed to the most recently modified carrying each value of `<label>`
+pub(crate) const LATEST_PREFIX: &str = "+latest:";
+/// Provenance recorded for tags added through [`TAG_CONTROL_XATTR`].
+const TAG_CONTROL_TAGGER: &str = "manual";
+
 trait ToFileAttr {
     fn to_file_attr(&self) -> FileAttr;
 }
@@ -75,13 +141,54 @@ impl ToFileAttr for libc::stat {
 
 #[derive(Debug)]
 struct Entry {
+    /// Source path, relative to [`Index::root`] when under it
     source: PathBuf,
+    /// For archive members, the member within the archive at `source`
+    member: Option<ArchiveMember>,
+    /// Size in bytes when indexed, if it could be read; for archive
+    /// members, the uncompressed size
+    size: Option<u64>,
+    /// Modification time of the source when indexed, if it could be read
+    mtime: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Name the entry is listed under.
+    fn file_name(&self) -> Option<&OsStr> {
+        match &self.member {
+            Some(member) => Path::new(&member.name).file_name(),
+            None => self.source.file_name(),
+        }
+    }
+
+    /// Cache the size and modification time of the file the entry stands
+    /// for from its `stat`, so listings needn't stat it again. Both are
+    /// cleared if it couldn't be read.
+    fn set_stat(&mut self, stat: Option<&libc::stat>) {
+        self.size = stat.and_then(|stat| u64::try_from(stat.st_size).ok());
+        self.mtime = stat.and_then(|stat| {
+            let secs = u64::try_from(stat.st_mtime).ok()?;
+            let nanos = u32::try_from(stat.st_mtime_nsec).ok()?;
+            SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+        });
+    }
+
+    /// Source path as stored, extended by the member for archive members.
+    fn listed_path(&self) -> Cow<'_, Path> {
+        match &self.member {
+            Some(member) => Cow::Owned(self.source.join(&member.name)),
+            None => Cow::Borrowed(&self.source),
+        }
+    }
 }
 
 impl From<&str> for Entry {
     fn from(value: &str) -> Self {
         Self {
             source: PathBuf::from(value),
+            member: None,
+            size: None,
+            mtime: None,
         }
     }
 }
@@ -90,613 +197,5349 @@ impl From<&Path> for Entry {
     fn from(value: &Path) -> Self {
         Self {
             source: value.to_path_buf(),
+            member: None,
+            size: None,
+            mtime: None,
+        }
+    }
+}
+
+/// Which kinds of entry a directory listing shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum View {
+    /// Only tag directories
+    Tags,
+    /// Only files
+    Files,
+    /// Tag directories and files
+    Both,
+}
+impl View {
+    fn shows_tags(self) -> bool {
+        self != View::Files
+    }
+
+    fn shows_files(self) -> bool {
+        self != View::Tags
+    }
+}
+
+/// The [`View`] used for listings at each depth of tag directories: the root
+/// is depth 0. Without overrides the root shows only tags and deeper
+/// directories show both.
+#[derive(Clone, Debug)]
+pub struct ListingView {
+    root: View,
+    depths: HashMap<usize, View>,
+    max_tag_depth: Option<usize>,
+}
+impl Default for ListingView {
+    fn default() -> Self {
+        Self::new(View::Tags)
+    }
+}
+impl ListingView {
+    pub fn new(root: View) -> Self {
+        Self {
+            root,
+            depths: HashMap::new(),
+            max_tag_depth: None,
+        }
+    }
+
+    /// List only files from `max_tag_depth` tags deep, so recursive walks of
+    /// the mount stay finite.
+    pub fn with_max_tag_depth(mut self, max_tag_depth: usize) -> Self {
+        self.max_tag_depth = Some(max_tag_depth);
+        self
+    }
+
+    /// Use `view` for listings exactly `depth` tags deep.
+    pub fn with_depth(mut self, depth: usize, view: View) -> Self {
+        self.depths.insert(depth, view);
+        self
+    }
+
+    fn at_depth(&self, depth: usize) -> View {
+        if self
+            .max_tag_depth
+            .is_some_and(|max_tag_depth| depth >= max_tag_depth)
+        {
+            return View::Files;
+        }
+        match self.depths.get(&depth) {
+            Some(view) => *view,
+            None if depth == 0 => self.root,
+            None => View::Both,
         }
     }
 }
 
+/// State held for each file opened through the filesystem, keyed by the
+/// handle returned to fuse.
 #[derive(Debug)]
-pub struct TagFS<T> {
+struct OpenFile {
+    fd: i32,
+    source: PathBuf,
+    size: u64,
+    /// Opened with `O_APPEND`, so writes go to the end of file whatever
+    /// offset they're given
+    append: bool,
+    /// Where the last read ended, to spot sequential access
+    next_offset: Option<u64>,
+    /// Chunk read ahead of a sequential reader, and the offset it starts at
+    readahead: Option<(u64, Vec<u8>)>,
+}
+impl OpenFile {
+    fn new(fd: i32, source: PathBuf, size: u64, append: bool) -> Self {
+        Self {
+            fd,
+            source,
+            size,
+            append,
+            next_offset: None,
+            readahead: None,
+        }
+    }
+
+    /// `size` bytes from `offset` out of the read-ahead chunk, if it holds
+    /// all of them.
+    fn buffered(&self, offset: u64, size: u32) -> Option<Vec<u8>> {
+        let (start, chunk) = self.readahead.as_ref()?;
+        let from = usize::try_from(offset.checked_sub(*start)?).ok()?;
+        let to = from.checked_add(size as usize)?;
+        chunk.get(from..to).map(<[u8]>::to_vec)
+    }
+}
+
+/// The searchable state: file entries, the files carrying each tag, and the
+/// reverse mapping used to replace a file's tags in place and report their
+/// provenance.
+#[derive(Debug)]
+struct Index {
+    /// Common prefix stripped from the stored source paths
+    root: Option<PathBuf>,
     files: Vec<Entry>,
-    deleted: Mutex<HashSet<usize>>,
     tags: HashMap<Tag, HashSet<usize>>,
-    libc_wrapper: T, //Box<dyn LibcWrapper + Send + Sync>,
+    /// Tags carried by each file with the taggers that emitted them, indexed
+    /// by file id
+    file_tags: Vec<Provenance>,
+    /// Ids of files unlinked or vanished from the source; their entries stay
+    /// so ids remain stable
+    deleted: HashSet<usize>,
+    /// Generation each file was first indexed in, indexed by file id
+    generations: Vec<u64>,
+    /// Generation files indexed now belong to, counting from 1
+    generation: u64,
+    /// Tags by their names with case and accents folded away, kept only
+    /// while tag names match ignoring them
+    folded: Option<HashMap<String, HashSet<Tag>>>,
+    /// Tag control commands applied to each file, by display path, replayed
+    /// over its tags whenever they're replaced
+    manual: HashMap<PathBuf, Vec<TagCommand>>,
+    /// Files listed in the directories most recently looked up in, by
+    /// listed name, so looking up every entry of a listing doesn't recompute
+    /// it each time. Emptied whenever files or their tags change.
+    dir_names: Mutex<DirNamesCache>,
 }
 
-pub fn new() -> TagFS<LibcWrapperReal> {
-    TagFS::<LibcWrapperReal>::new()
+/// The files listed in a directory, by the name each is listed as.
+type DirNames = Arc<HashMap<OsString, usize>>;
+
+/// Most directories whose names [`DirNamesCache`] holds at once.
+const DIR_NAMES_CACHED: usize = 32;
+
+/// [`DirNames`] of the [`DIR_NAMES_CACHED`] directories most recently
+/// looked up in, so lookups over many paths can't grow it without bound.
+#[derive(Debug, Default)]
+struct DirNamesCache {
+    tick: u64,
+    entries: HashMap<PathBuf, (u64, DirNames)>,
+    /// Directories by last use, oldest first
+    recency: BTreeMap<u64, PathBuf>,
 }
+impl DirNamesCache {
+    fn get(&mut self, dir: &Path) -> Option<DirNames> {
+        self.tick += 1;
+        let (last_used, names) = self.entries.get_mut(dir)?;
+        self.recency.remove(last_used);
+        *last_used = self.tick;
+        self.recency.insert(self.tick, dir.to_path_buf());
+        Some(names.clone())
+    }
 
-impl<'a, T> TagFS<T>
-where
-    T: LibcWrapper,
-{
-    fn new() -> Self {
-        let libc_wrapper = T::new();
+    fn insert(&mut self, dir: &Path, names: DirNames) {
+        self.tick += 1;
+        if let Some((last_used, _)) = self.entries.remove(dir) {
+            self.recency.remove(&last_used);
+        }
+        while self.entries.len() >= DIR_NAMES_CACHED {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(dir.to_path_buf(), (self.tick, names));
+        self.recency.insert(self.tick, dir.to_path_buf());
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+impl Default for Index {
+    fn default() -> Self {
         Self {
+            root: None,
             files: Vec::new(),
-            deleted: Mutex::new(HashSet::new()),
             tags: HashMap::new(),
-            libc_wrapper,
+            file_tags: Vec::new(),
+            deleted: HashSet::new(),
+            generations: Vec::new(),
+            generation: 1,
+            folded: None,
+            manual: HashMap::new(),
+            dir_names: Mutex::default(),
         }
     }
+}
+
+impl Index {
+    fn insert(&mut self, source: &Path, stat: Option<&libc::stat>, tags: Provenance) -> usize {
+        let mut entry = Entry::from(self.intern(source));
+        entry.set_stat(stat);
+        self.insert_entry(entry, tags)
+    }
 
-    pub fn add_file(&mut self, source: &'a Path, tags: HashSet<Tag>) {
-        info!(file = ?source, ?tags, "add_file");
-        self.files.push(Entry::from(source));
+    fn insert_entry(&mut self, entry: Entry, tags: Provenance) -> usize {
+        self.files.push(entry);
+        self.file_tags.push(Provenance::new());
+        self.generations.push(self.generation);
         let file_id = self.files.len() - 1;
-        for tag in tags {
-            self.tags.entry(tag).or_default().insert(file_id);
-        }
+        self.set_tags(file_id, tags);
+        self.stamp_generation(file_id);
+        file_id
     }
 
-    pub fn is_deleted(&self, file_id: usize) -> bool {
-        let deleted = self.deleted.lock().unwrap();
-        deleted.contains(&file_id)
+    /// Give `file_id` the tags of the generation it was first indexed in,
+    /// once there is more than one generation to tell apart.
+    fn stamp_generation(&mut self, file_id: usize) {
+        if self.generation == 1 {
+            return;
+        }
+        self.changed();
+        let generation = self.generations[file_id];
+        let mut stamps = vec![Tag::new(GENERATION_LABEL, true, generation.to_string())];
+        if generation == self.generation {
+            stamps.push(Tag::from(NEW_TAG));
+        }
+        for tag in stamps {
+            self.add_member(&tag, file_id);
+            self.file_tags[file_id].insert(tag, BTreeSet::from([GENERATION_LABEL.to_string()]));
+        }
     }
 
-    pub fn delete_file(&self, file_id: usize) {
-        //self.files.get_mut(file_id).unwrap().deleted = true;
-        let mut deleted = self.deleted.lock().unwrap();
-        deleted.insert(file_id);
+    /// Start a new generation, which files indexed from now on belong to.
+    fn next_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.set_tag_members(Tag::from(NEW_TAG), HashSet::new(), GENERATION_LABEL);
+        // The first generation is only tagged once there's a second
+        if self.generation == 2 {
+            for file_id in 0..self.files.len() {
+                self.stamp_generation(file_id);
+            }
+        }
+        self.generation
     }
 
-    fn contains_tag(&self, tag: &OsStr) -> bool {
-        self.get_tag(tag).is_some()
+    /// Make this fresh rebuild of `old` the generation after it: files it
+    /// already indexed keep their generation, and the rest are new.
+    fn succeed(&mut self, old: &Index) {
+        let known = old
+            .files
+            .iter()
+            .zip(&old.generations)
+            .enumerate()
+            .filter(|(file_id, _)| !old.is_deleted(*file_id))
+            .map(|(_, (entry, generation))| (old.display_path(entry), *generation))
+            .collect::<HashMap<_, _>>();
+        self.generation = old.generation + 1;
+        self.manual = old.manual.clone();
+        for file_id in 0..self.files.len() {
+            let path = self.display_path(&self.files[file_id]);
+            self.generations[file_id] = known.get(&path).copied().unwrap_or(self.generation);
+            self.stamp_generation(file_id);
+            self.replay_tag_commands(file_id);
+        }
     }
 
-    fn get_tag(&self, tag: &OsStr) -> Option<(&Tag, &HashSet<usize>)> {
-        self.tags.iter().find(|(t, _file_ids)| t.as_os_str() == tag)
+    fn is_deleted(&self, file_id: usize) -> bool {
+        self.deleted.contains(&file_id)
     }
-}
 
-impl<T> FilesystemMT for TagFS<T>
-where
-    T: LibcWrapper,
-{
-    fn getattr(
-        &self,
-        _req: fuse_mt::RequestInfo,
-        path: &std::path::Path,
-        fh: Option<u64>,
-    ) -> fuse_mt::ResultEntry {
-        info!(path = debug(path), fh = debug(fh), "getattr");
+    fn set_deleted(&mut self, file_id: usize, deleted: bool) {
+        self.changed();
+        match deleted {
+            true => self.deleted.insert(file_id),
+            false => self.deleted.remove(&file_id),
+        };
+    }
 
-        if let Some(fh) = fh {
-            match self.libc_wrapper.fstat(fh) {
-                Ok(stat) => Ok((TTL, stat.to_file_attr())),
-                Err(e) => Err(e.raw_os_error().unwrap_or(libc::ENOENT)),
-            }
-        } else {
-            match self.lookup(path) {
-                LookupResult::Directory => Ok((TTL, fh.to_file_attr())),
-                LookupResult::Missing => Err(ENOENT),
-                LookupResult::File(e, ..) => match self.libc_wrapper.lstat(&e.source) {
-                    Ok(stat) => Ok((TTL, stat.to_file_attr())),
-                    Err(e) => Err(e.raw_os_error().unwrap_or(libc::ENOENT)),
-                },
-            }
-        }
+    /// Forget what was worked out from the files and their tags, as they've
+    /// changed.
+    fn changed(&mut self) {
+        self.dir_names.get_mut().unwrap().clear();
     }
 
-    fn opendir(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
-        info!(
-            path = debug(path),
-            flags = format!("{:#o}", flags),
-            "opendir"
-        );
-        if path.components().all(|c| match c {
-            Component::Prefix(_prefix_component) => todo!(),
-            Component::RootDir => true,
-            Component::CurDir => false,
-            Component::ParentDir => false,
-            Component::Normal(tag) => self.contains_tag(tag),
-        }) {
-            Ok((0, 0))
-        } else {
-            info!(path = debug(path), "TODO: lookup");
-            Err(ENOENT)
+    /// The files listed in the directory at `dir` by listed name, from the
+    /// `file_ids` it lists when not cached. Directories listing nothing,
+    /// including missing ones, aren't cached.
+    fn dir_names(
+        &self,
+        dir: &Path,
+        file_ids: impl FnOnce() -> HashSet<usize>,
+        collision: CollisionFormat,
+    ) -> DirNames {
+        if let Some(names) = self.dir_names.lock().unwrap().get(dir) {
+            return names;
         }
+        let names = file_names(&self.files, file_ids(), collision)
+            .into_iter()
+            .map(|(file_id, name)| (name.into_owned(), file_id))
+            .collect::<HashMap<_, _>>();
+        let names = Arc::new(names);
+        if !names.is_empty() {
+            self.dir_names.lock().unwrap().insert(dir, names.clone());
+        }
+        names
     }
 
-    fn readdir(&self, _req: RequestInfo, path: &Path, fh: u64) -> ResultReaddir {
-        info!(path = debug(path), fh = debug(fh), "readdir");
-        let tags = path
-            .components()
-            .filter_map(|c| match c {
-                Component::Normal(p) => Some(p.to_os_string()),
-                _ => None,
-            })
+    /// Ids of the files listed by `dir`, in the order they were added.
+    fn flat_dir_files(&self, dir: FlatDir) -> Vec<usize> {
+        let live = (0..self.files.len())
+            .filter(|file_id| !self.is_deleted(*file_id))
             .collect::<HashSet<_>>();
-        info!(?tags, ?path, "lookup");
-        let mut entries = vec![
-            DirectoryEntry {
-                name: ".".into(),
-                kind: FileType::Directory,
-            },
-            DirectoryEntry {
-                name: "..".into(),
-                kind: FileType::Directory,
-            },
-        ];
-
-        for (child_type, child_name) in get_children(path, &self.tags, &self.files, |file_id| {
-            self.is_deleted(file_id)
-        }) {
-            info!(?child_type, name = ?child_name, "children");
-            entries.push(DirectoryEntry {
-                name: child_name.into(),
-                kind: child_type,
-            });
-        }
+        let file_ids = match dir {
+            FlatDir::All => live,
+            FlatDir::Search(expr) => {
+                // As in tag directories, a name covers every tag shown so
+                let files_for = |tag: &OsStr| files_displayed_as(&self.tags, self.unfold(tag));
+                expr.eval(&files_for, &live)
+            }
+        };
+        file_ids.into_iter().sorted().collect()
+    }
 
-        Ok(entries)
+    /// Number of files not deleted.
+    fn live_files(&self) -> usize {
+        self.files.len() - self.deleted.len()
     }
 
-    fn open(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
-        info!(?path, flags = format!("{:o}", flags), "open");
+    /// Replace the tags of `file_id`, dropping tags left with no files.
+    fn set_tags(&mut self, file_id: usize, tags: Provenance) {
+        self.changed();
+        for tag in std::mem::take(&mut self.file_tags[file_id]).into_keys() {
+            if let Some(file_ids) = self.tags.get_mut(&tag) {
+                file_ids.remove(&file_id);
+                if file_ids.is_empty() {
+                    self.remove_tag(&tag);
+                }
+            }
+        }
+        for (tag, taggers) in tags {
+            if tag.as_os_str() == INFO_FILE {
+                warn!(?tag, "tag shadowed by info file, skipping");
+                continue;
+            }
+            self.add_member(&tag, file_id);
+            self.file_tags[file_id].insert(tag, taggers);
+        }
+    }
 
-        match self.lookup(path) {
-            LookupResult::Directory => Err(ENOENT),
-            LookupResult::File(e, ..) => self
-                .libc_wrapper
-                .open(&e.source, flags as i32)
-                .map(|fh| (fh as u64, flags))
-                .map_err(|e| e.raw_os_error().unwrap_or(ENOENT)),
-            LookupResult::Missing => Err(ENOENT),
+    /// Add `file_id` to the files carrying `tag`.
+    fn add_member(&mut self, tag: &Tag, file_id: usize) {
+        if !self.tags.contains_key(tag) {
+            self.fold_tag(tag);
         }
+        self.tags.entry(tag.clone()).or_default().insert(file_id);
     }
 
-    fn release(
-        &self,
-        _req: RequestInfo,
-        path: &Path,
-        fh: u64,
-        flags: u32,
-        lock_owner: u64,
-        flush: bool,
-    ) -> fuse_mt::ResultEmpty {
-        info!(
-            ?path,
-            fh,
-            flags = format!("{:o}", flags),
-            lock_owner,
-            flush,
-            "release"
-        );
-        self.libc_wrapper
-            .close(fh as i32)
-            .map_err(|e| e.raw_os_error().unwrap_or(ENOENT))
+    /// Drop `tag` from every file set, returning the files it had.
+    fn remove_tag(&mut self, tag: &Tag) -> Option<HashSet<usize>> {
+        let file_ids = self.tags.remove(tag)?;
+        let folded = self.folded.as_mut().zip(fold(tag.as_os_str()));
+        if let Some((folded, name)) = folded {
+            if let Some(tags) = folded.get_mut(&name) {
+                tags.remove(tag);
+                if tags.is_empty() {
+                    folded.remove(&name);
+                }
+            }
+        }
+        Some(file_ids)
     }
 
-    fn read(
-        &self,
-        _req: RequestInfo,
-        path: &Path,
-        fh: u64,
-        offset: u64,
-        size: u32,
-        callback: impl FnOnce(fuse_mt::ResultSlice<'_>) -> fuse_mt::CallbackResult,
-    ) -> fuse_mt::CallbackResult {
-        info!(?path, fh, offset, size, "read");
+    /// Record `tag` under its folded name, while folding.
+    fn fold_tag(&mut self, tag: &Tag) {
+        let folded = self.folded.as_mut().zip(fold(tag.as_os_str()));
+        if let Some((folded, name)) = folded {
+            folded.entry(name).or_default().insert(tag.clone());
+        }
+    }
 
-        match self.libc_wrapper.read(fh as i32, offset as i64, size) {
-            Ok(content) => callback(Ok(content.as_slice())),
-            Err(e) => callback(Err(e.raw_os_error().unwrap_or(ENOENT))),
+    /// Match tag names ignoring case and accents, or not.
+    fn set_fold_accents(&mut self, fold_accents: bool) {
+        self.folded = fold_accents.then(HashMap::new);
+        for tag in self.tags.keys().cloned().collect::<Vec<_>>() {
+            self.fold_tag(&tag);
         }
     }
 
-    fn unlink(&self, _req: RequestInfo, parent: &Path, name: &OsStr) -> fuse_mt::ResultEmpty {
-        let path: PathBuf = parent.join(name);
-        info!(?parent, ?name, ?path, "unlink");
-        // TODO Mark self.files entry as deleted, if unlink successfully
-        match self.lookup(&path) {
-            LookupResult::Directory | LookupResult::Missing => Err(ENOENT),
-            LookupResult::File(e, i) => match self.libc_wrapper.unlink(&e.source) {
-                Ok(_) => {
-                    self.delete_file(i);
-                    Ok(())
+    /// Make exactly `file_ids` carry `tag` on behalf of `tagger`. Files
+    /// given the tag by anything else, such as a tagger or a manual edit,
+    /// keep it.
+    fn set_tag_members(&mut self, tag: Tag, file_ids: HashSet<usize>, tagger: &str) {
+        self.changed();
+        let carriers = self.tags.get(&tag).cloned().unwrap_or_default();
+        for file_id in carriers.difference(&file_ids) {
+            let Some(taggers) = self.file_tags[*file_id].get_mut(&tag) else {
+                continue;
+            };
+            if taggers.remove(tagger) && taggers.is_empty() {
+                self.file_tags[*file_id].remove(&tag);
+                if let Some(carriers) = self.tags.get_mut(&tag) {
+                    carriers.remove(file_id);
                 }
-                Err(e) => Err(e.raw_os_error().unwrap_or(ENOENT)),
-            },
+            }
+        }
+        for file_id in file_ids {
+            self.file_tags[file_id]
+                .entry(tag.clone())
+                .or_default()
+                .insert(tagger.to_string());
+            self.add_member(&tag, file_id);
+        }
+        if self.tags.get(&tag).is_some_and(HashSet::is_empty) {
+            self.remove_tag(&tag);
         }
     }
-}
 
-#[derive(Debug)]
-enum LookupResult<'a> {
-    Directory,
-    File(&'a Entry, usize),
-    Missing,
-}
-impl<'a, T> TagFS<T>
-where
-    T: LibcWrapper,
-{
-    #[instrument(skip(self))]
-    fn lookup(&'a self, path: &Path) -> LookupResult<'a> {
-        use LookupResult::*;
-        info!(?path, "lookup");
+    /// Apply tag control commands to `file_id`, returning the tags added.
+    /// They're kept to replay should the file's tags be replaced.
+    fn apply_tag_commands(&mut self, file_id: usize, commands: Vec<TagCommand>) -> Provenance {
+        let added = self.run_tag_commands(file_id, &commands);
+        let path = self.display_path(&self.files[file_id]);
+        self.manual.entry(path).or_default().extend(commands);
+        added
+    }
 
-        // TODO Skip deleted files
+    /// Apply again the tag control commands `file_id` was given.
+    fn replay_tag_commands(&mut self, file_id: usize) {
+        let path = self.display_path(&self.files[file_id]);
+        if let Some(commands) = self.manual.get(&path).cloned() {
+            debug!(?path, commands = commands.len(), "replay tag commands");
+            self.run_tag_commands(file_id, &commands);
+        }
+    }
 
-        if path.components().all(|c| match c {
-            Component::Prefix(_prefix_component) => todo!(),
-            Component::RootDir => true,
-            Component::CurDir => false,
-            Component::ParentDir => false,
-            Component::Normal(tag) => self.contains_tag(tag),
-        }) {
-            debug!(?path, "tag dir");
-            Directory
-        } else {
-            let mut valid_files = None;
-            let empty = PathBuf::new();
-            let empty = empty.as_path();
-
-            for component in path.parent().unwrap_or(empty).components() {
-                info!(?path, ?component);
-                if let Component::Normal(tag) = component {
-                    if let Some(files) = self.get_tag(tag) {
-                        let files = files.1;
-                        if valid_files.is_none() {
-                            valid_files = Some(files.clone());
-                        } else {
-                            valid_files =
-                                Some(valid_files.unwrap().intersection(files).cloned().collect());
+    /// A labelled tag added where its label is already a singleton label
+    /// replaces the file's tags with that label, keeping it single-valued.
+    fn run_tag_commands(&mut self, file_id: usize, commands: &[TagCommand]) -> Provenance {
+        let mut tags = self.file_tags[file_id].clone();
+        let mut added = Provenance::new();
+        for command in commands {
+            match command.clone() {
+                TagCommand::Add(tag) => {
+                    let singleton = tag.has_label() && self.is_singleton_label(tag.label());
+                    let tag = match singleton {
+                        true => {
+                            tags.retain(|t, _| !t.has_label() || t.label() != tag.label());
+                            Tag::new(tag.label(), true, tag.value())
                         }
-                        info!(?tag, ?valid_files, "found");
-                    } else {
-                        info!(?component, "missing");
-                    }
+                        false => tag,
+                    };
+                    let taggers = BTreeSet::from([TAG_CONTROL_TAGGER.to_string()]);
+                    tags.insert(tag.clone(), taggers.clone());
+                    added.insert(tag, taggers);
                 }
-            }
-            if let Some(files) = valid_files {
-                let entry = files
-                    .iter()
-                    .flat_map(|idx| self.files.get(*idx).map(|e| (*idx, e)))
-                    .filter(|(_idx, entry)| entry.source.file_name() == path.file_name())
-                    .take(1)
-                    .next();
-                match entry {
-                    None => Missing,
-                    Some((idx, e)) => File(e, idx),
+                TagCommand::Remove(display) => {
+                    tags.retain(|t, _| t.as_os_str() != display);
+                    added.retain(|t, _| t.as_os_str() != display);
                 }
-            } else {
-                info!(path = debug(path), "failed lookup");
-                Missing
             }
         }
+        self.set_tags(file_id, tags);
+        added
     }
-}
 
-#[instrument(skip_all)]
-fn get_children<'a, 'b, 'c, F>(
-    root: &Path,
-    tags: &'a HashMap<Tag, HashSet<usize>>,
-    files: &'b [Entry],
-    is_deleted: F,
-) -> impl Iterator<Item = (FileType, &'c OsStr)>
-where
-    'a: 'c,
-    'b: 'c,
-    F: Fn(usize) -> bool + 'c,
-{
-    // TODO Filter out intrinsic tags NOT represented by residual files
-    // TODO Skip deleted files
+    fn find(&self, source: &Path) -> Option<usize> {
+        let source = self.intern(source);
+        self.files
+            .iter()
+            .position(|entry| entry.member.is_none() && entry.source == source)
+    }
 
-    let root_tags = root
-        .components()
-        .filter_map(|c| match c {
-            Component::Normal(p) => Some(p.to_os_string()),
-            _ => None,
-        })
-        .collect::<HashSet<_>>();
+    /// `source` as stored: relative to the root if it lies under it.
+    fn intern<'p>(&self, source: &'p Path) -> &'p Path {
+        match &self.root {
+            Some(root) => match source.strip_prefix(root) {
+                Ok(relative) if relative != Path::new("") => relative,
+                _ => source,
+            },
+            None => source,
+        }
+    }
+
+    /// The full source path of `entry`; for archive members, the archive.
+    fn source(&self, entry: &Entry) -> PathBuf {
+        match &self.root {
+            // Joining an absolute path replaces the root
+            Some(root) => root.join(&entry.source),
+            None => entry.source.clone(),
+        }
+    }
+
+    /// The full path reported for `entry`: archive members appear beneath
+    /// their archive.
+    fn display_path(&self, entry: &Entry) -> PathBuf {
+        match &entry.member {
+            Some(member) => self.source(entry).join(&member.name),
+            None => self.source(entry),
+        }
+    }
+
+    fn contains_tag(&self, tag: &OsStr) -> bool {
+        self.get_tag(tag).is_some()
+    }
+
+    /// Whether `name` is the label of singleton tags, and not itself a tag.
+    fn is_singleton_label(&self, name: &OsStr) -> bool {
+        !self.contains_tag(name)
+            && self
+                .tags
+                .keys()
+                .any(|tag| tag.is_singleton() && tag.label() == name)
+    }
+
+    /// Whether `name` is a `+latest:<label>` modifier of a label some tag
+    /// has.
+    fn is_modifier(&self, name: &OsStr) -> bool {
+        latest_label(name).is_some_and(|label| {
+            self.tags
+                .keys()
+                .any(|tag| tag.has_label() && tag.label() == label)
+        })
+    }
+
+    /// The tag named `tag`. With accent folding, a tag matching only once
+    /// folded is found too; the least such name wins should several match.
+    fn get_tag(&self, tag: &OsStr) -> Option<(&Tag, &HashSet<usize>)> {
+        let exact = self.tags.iter().find(|(t, _file_ids)| t.as_os_str() == tag);
+        if exact.is_some() {
+            return exact;
+        }
+        let folded = self.folded.as_ref()?.get(&fold(tag)?)?;
+        let tag = folded.iter().min_by_key(|t| t.as_os_str())?;
+        self.tags.get_key_value(tag)
+    }
+
+    /// Whether `name` matches a tag only once folded.
+    fn is_folded_tag(&self, name: &OsStr) -> bool {
+        self.folded.is_some()
+            && self.contains_tag(name)
+            && !self.tags.keys().any(|tag| tag.as_os_str() == name)
+    }
+
+    /// The name of the tag `name` resolves to, folding if enabled, or `name`
+    /// itself when none does.
+    fn unfold<'n>(&'n self, name: &'n OsStr) -> &'n OsStr {
+        match self.get_tag(name) {
+            Some((tag, _)) => tag.as_os_str(),
+            None => name,
+        }
+    }
+
+    /// Ids of the files carrying every tag in `tags`, or `None` when no tags
+    /// are given. Unknown tags match no files.
+    fn intersect<'t>(&self, tags: impl IntoIterator<Item = &'t OsStr>) -> Option<HashSet<usize>> {
+        let mut valid_files: Option<HashSet<usize>> = None;
+        for tag in tags {
+            let files = files_displayed_as(&self.tags, tag);
+            if files.is_empty() {
+                info!(?tag, "missing");
+            }
+            let files = match valid_files {
+                None => files,
+                Some(valid_files) => valid_files.intersection(&files).cloned().collect(),
+            };
+            info!(?tag, ?files, "found");
+            // No later tag can add files back to an empty intersection
+            let empty = files.is_empty();
+            valid_files = Some(files);
+            if empty {
+                debug!(?tag, "empty intersection");
+                break;
+            }
+        }
+        valid_files
+    }
+}
+
+#[derive(Debug)]
+pub struct TagFS<T> {
+    /// Everything mutated after mounting. Read paths (lookup, getattr,
+    /// readdir) take the read lock and mutation paths the write lock. It is
+    /// taken before any of the handle maps or the read cache, and never
+    /// while already held: helpers taking it mustn't be called with a guard
+    /// in scope, so use the guard's [`Index`] methods instead. Shared with
+    /// any [`IndexHandle`], which may replace it wholesale.
+    index: Arc<RwLock<Index>>,
+    handles: RwLock<HashMap<u64, OpenFile>>,
+    directories: RwLock<HashMap<u64, Vec<DirectoryEntry>>>,
+    virtual_handles: RwLock<HashMap<u64, Vec<u8>>>,
+    /// Recently read slices, when enabled
+    read_cache: Option<Mutex<ReadCache>>,
+    /// Bytes read at once once a handle is read sequentially; 0 disables
+    readahead_bytes: u32,
+    /// Bound on the bytes buffered by reads in flight, when enabled
+    read_budget: Option<ReadBudget>,
+    archive_reader: Box<dyn ArchiveReader>,
+    /// Whether [`TagFS::add_files`] orders files by source path
+    deterministic_ids: bool,
+    listing_view: ListingView,
+    /// Whether singleton labels are browsed as `label/value` directories
+    flatten_singletons: bool,
+    /// Labels of the tags listed at the root, when not all of them
+    root_labels: Option<HashSet<OsString>>,
+    collation: Collation,
+    /// How files listed together under the same name are told apart
+    collision_format: CollisionFormat,
+    /// Size of the [`RECENT_TAG`] set; 0 disables it
+    recent_limit: usize,
+    /// Name of the top-level directory listing every file, when enabled
+    all_dir: Option<OsString>,
+    /// Top-level directories listing the files matching an expression
+    saved_searches: BTreeMap<OsString, Expr>,
+    /// Suffix of the companion file listing each file's tags, when enabled
+    tags_suffix: Option<OsString>,
+    /// Whether to log an event per tag given to a file
+    audit_tags: bool,
+    /// Whether files whose source has vanished are dropped from the index
+    /// when first noticed
+    prune_stale: bool,
+    /// Operation counters, shared with any metrics endpoint
+    metrics: Arc<Metrics>,
+    next_handle: AtomicU64,
+    started: Instant,
+    taggers: Vec<String>,
+    libc_wrapper: T, //Box<dyn LibcWrapper + Send + Sync>,
+}
+
+/// Shared access to the index of a mounted [`TagFS`].
+#[derive(Clone, Debug)]
+pub struct IndexHandle {
+    index: Arc<RwLock<Index>>,
+    metrics: Arc<Metrics>,
+}
+impl IndexHandle {
+    /// Replace the index with the one `rebuilt` was populated with, in a
+    /// single step: requests already holding the old index finish against
+    /// it, and later ones see only the new. Open files are unaffected.
+    ///
+    /// The rebuild is the next generation: files not in the old index are
+    /// tagged [`NEW_TAG`].
+    pub fn swap_from<U: LibcWrapper>(&self, rebuilt: TagFS<U>) {
+        let mut index = mem::take(&mut *rebuilt.index.write().unwrap());
+        index.succeed(&self.index.read().unwrap());
+        let live_files = index.live_files();
+        let old = mem::replace(&mut *self.index.write().unwrap(), index);
+        self.metrics.set_index_files(live_files);
+        info!(
+            files = live_files,
+            replaced = old.files.len(),
+            "index swapped"
+        );
+    }
+}
+
+/// Every tag given to a file, from [`TagFS::associations`], for streaming
+/// into an external store. The index stays read-locked while this is held,
+/// so retags and rescans wait for it to be dropped.
+pub struct Associations<'f> {
+    index: RwLockReadGuard<'f, Index>,
+}
+impl Associations<'_> {
+    /// Each tag paired with the source path of every file carrying it,
+    /// borrowed as stored: relative to [`Associations::root`] when under it.
+    /// Archive members are skipped, having no source file of their own.
+    pub fn iter(&self) -> impl Iterator<Item = (&Tag, &Path)> {
+        let index = &*self.index;
+        index.tags.iter().flat_map(move |(tag, file_ids)| {
+            file_ids
+                .iter()
+                .filter(|file_id| !index.is_deleted(**file_id))
+                .map(|file_id| &index.files[*file_id])
+                .filter(|entry| entry.member.is_none())
+                .map(move |entry| (tag, entry.source.as_path()))
+        })
+    }
+
+    /// Prefix stripped from the source paths under it, if one was set.
+    pub fn root(&self) -> Option<&Path> {
+        self.index.root.as_deref()
+    }
+}
+
+pub fn new() -> TagFS<LibcWrapperReal> {
+    TagFS::<LibcWrapperReal>::new()
+}
+
+thread_local! {
+    /// Whether this thread serves the mount: runs its session, or has
+    /// handled a filesystem request
+    static SERVING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Mark the calling thread as serving the mount, for [`is_serving_thread`].
+pub fn mark_serving_thread() {
+    SERVING.with(|serving| serving.set(true));
+}
+
+/// Whether the calling thread serves the mount, so that a panic on it means
+/// the mount has failed rather than some unrelated thread.
+pub fn is_serving_thread() -> bool {
+    SERVING.with(Cell::get)
+}
+
+impl<'a, T> TagFS<T>
+where
+    T: LibcWrapper,
+{
+    fn new() -> Self {
+        Self::with_libc_wrapper(T::new())
+    }
+
+    /// Filesystem serving sources through `libc_wrapper`, e.g. an
+    /// [`InMemoryLibcWrapper`](super::InMemoryLibcWrapper) holding them.
+    pub fn with_libc_wrapper(libc_wrapper: T) -> Self {
+        Self {
+            index: Arc::new(RwLock::new(Index::default())),
+            handles: RwLock::new(HashMap::new()),
+            directories: RwLock::new(HashMap::new()),
+            virtual_handles: RwLock::new(HashMap::new()),
+            read_cache: None,
+            readahead_bytes: 0,
+            read_budget: None,
+            archive_reader: Box::new(ZipReader::default()),
+            deterministic_ids: false,
+            listing_view: ListingView::default(),
+            flatten_singletons: false,
+            root_labels: None,
+            collation: Collation::default(),
+            collision_format: CollisionFormat::default(),
+            recent_limit: DEFAULT_RECENT_LIMIT,
+            all_dir: None,
+            saved_searches: BTreeMap::new(),
+            tags_suffix: None,
+            audit_tags: false,
+            prune_stale: false,
+            metrics: Arc::new(Metrics::new()),
+            next_handle: AtomicU64::new(1),
+            started: Instant::now(),
+            taggers: Vec::new(),
+            libc_wrapper,
+        }
+    }
+
+    /// Store source paths under `root` relative to it, shrinking the index
+    /// when every file shares a long prefix. Must be set before any file is
+    /// added.
+    pub fn set_source_root(&mut self, root: impl Into<PathBuf>) {
+        let mut index = self.index.write().unwrap();
+        assert!(index.files.is_empty(), "source root set after adding files");
+        index.root = Some(root.into());
+    }
+
+    /// Cache up to `max_bytes` of recently read small slices, serving
+    /// repeated reads of the same range without touching the source.
+    pub fn set_read_cache_bytes(&mut self, max_bytes: usize) {
+        self.read_cache = (max_bytes > 0).then(|| Mutex::new(ReadCache::new(max_bytes)));
+    }
+
+    /// Read `bytes` at a time from handles read sequentially, serving the
+    /// reads that follow from the chunk; 0 disables read-ahead.
+    pub fn set_readahead_bytes(&mut self, bytes: u32) {
+        self.readahead_bytes = bytes;
+    }
+
+    /// Make reads wait while those in flight already buffer `max_bytes`
+    /// between them; 0 leaves them unbounded.
+    pub fn set_max_read_bytes(&mut self, max_bytes: u64) {
+        self.read_budget = (max_bytes > 0).then(|| ReadBudget::new(max_bytes));
+    }
+
+    pub fn set_archive_reader(&mut self, archive_reader: impl ArchiveReader + 'static) {
+        self.archive_reader = Box::new(archive_reader);
+    }
+
+    /// Add every member of `archive` as a read-only virtual file tagged
+    /// `archive-member-of:<archive name>`, returning how many were added.
+    /// Encrypted members are skipped, as they can't be read.
+    pub fn add_archive_members(&mut self, archive: &'a Path) -> std::io::Result<usize> {
+        let members = self
+            .archive_reader
+            .members(archive)?
+            .into_iter()
+            .filter(|member| {
+                if member.encrypted {
+                    debug!(?archive, ?member, "skipping encrypted member");
+                }
+                !member.encrypted
+            })
+            .collect::<Vec<_>>();
+        let count = members.len();
+        let archive_name = archive.file_name().unwrap_or(archive.as_os_str());
+        let tag = Tag::new("archive-member-of", false, archive_name);
+        let mut index = self.index.write().unwrap();
+        for member in members {
+            info!(?archive, ?member, "add_archive_member");
+            let entry = Entry {
+                source: index.intern(archive).to_path_buf(),
+                size: Some(member.size),
+                member: Some(member),
+                mtime: None,
+            };
+            index.insert_entry(entry, Provenance::from([(tag.clone(), BTreeSet::new())]));
+        }
+        self.metrics.set_index_files(index.live_files());
+        Ok(count)
+    }
+
+    /// Choose which kinds of entry directory listings show at each depth.
+    pub fn set_listing_view(&mut self, listing_view: ListingView) {
+        self.listing_view = listing_view;
+    }
+
+    /// Browse singleton tags as a directory per label holding a directory per
+    /// value, so `/mime/text|x-c/` stands for `/mime:text|x-c/`.
+    pub fn set_flatten_singletons(&mut self, flatten_singletons: bool) {
+        self.flatten_singletons = flatten_singletons;
+    }
+
+    /// Match tag names ignoring case and accents, so `/cafe` resolves a
+    /// `Café` tag. Listings still show the names as tagged.
+    pub fn set_fold_accents(&mut self, fold_accents: bool) {
+        self.index.write().unwrap().set_fold_accents(fold_accents);
+    }
+
+    /// With singleton flattening, `path` with each `label/value` pair of
+    /// components rewritten to its `label:value` tag, plus any trailing bare
+    /// label. Otherwise `path` unchanged, but for components naming a tag
+    /// only once folded, which are given its name: lookups check the final
+    /// component against file names as given.
+    fn flatten_path(&self, path: &Path) -> (PathBuf, Option<OsString>) {
+        let index = self.index.read().unwrap();
+        let path = match index.folded.is_some() {
+            true => path
+                .components()
+                .map(|c| match c {
+                    Component::Normal(name) => index.unfold(name),
+                    c => c.as_os_str(),
+                })
+                .collect(),
+            false => path.to_path_buf(),
+        };
+        if !self.flatten_singletons {
+            return (path, None);
+        }
+        let mut flat = PathBuf::from("/");
+        let mut label: Option<OsString> = None;
+        for component in path.components() {
+            let Component::Normal(component) = component else {
+                continue;
+            };
+            match label.take() {
+                Some(mut tag) => {
+                    tag.push(TAG_SEPARATOR);
+                    tag.push(component);
+                    flat.push(index.unfold(&tag));
+                }
+                None if index.is_singleton_label(component) => {
+                    label = Some(component.to_os_string());
+                }
+                None => flat.push(component),
+            }
+        }
+        (flat, label)
+    }
+
+    /// Order of entries within directory listings.
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.collation = collation;
+    }
+
+    /// How files listed in one directory under the same name are renamed.
+    pub fn set_collision_format(&mut self, collision_format: CollisionFormat) {
+        self.collision_format = collision_format;
+        self.index.write().unwrap().changed();
+    }
+
+    /// How many files [`TagFS::refresh_recent`] tags as recent; 0 disables
+    /// the tag.
+    pub fn set_recent_limit(&mut self, recent_limit: usize) {
+        self.recent_limit = recent_limit;
+    }
+
+    /// Point the `recent` tag at the most recently modified files, by the
+    /// modification time cached when each was indexed. Run after the scan;
+    /// [`TagFS::retag`] runs it again so the set follows changes.
+    pub fn refresh_recent(&self) {
+        if self.recent_limit == 0 {
+            return;
+        }
+        let mut by_mtime = {
+            let index = self.index.read().unwrap();
+            index
+                .files
+                .iter()
+                .enumerate()
+                .filter(|(file_id, entry)| entry.member.is_none() && !index.is_deleted(*file_id))
+                .filter_map(|(file_id, entry)| Some((entry.mtime?, file_id)))
+                .collect::<Vec<_>>()
+        };
+        // Newest first
+        by_mtime.sort_by(|a, b| b.cmp(a));
+        let recent = by_mtime
+            .into_iter()
+            .take(self.recent_limit)
+            .map(|(_mtime, file_id)| file_id)
+            .collect::<HashSet<_>>();
+        debug!(?recent, "refresh recent");
+        self.index
+            .write()
+            .unwrap()
+            .set_tag_members(Tag::from(RECENT_TAG), recent, RECENT_TAG);
+    }
+
+    /// Point the `size-outlier` tag at files more than
+    /// [`OUTLIER_DEVIATIONS`] standard deviations from the mean size of the
+    /// files in their source directory, by the sizes cached when each was
+    /// indexed. Run after the scan; [`TagFS::retag`] runs it again so the
+    /// set follows changes.
+    pub fn refresh_size_outliers(&self) {
+        let mut by_directory: HashMap<PathBuf, Vec<(usize, f64)>> = HashMap::new();
+        {
+            let index = self.index.read().unwrap();
+            let sized = index
+                .files
+                .iter()
+                .enumerate()
+                .filter(|(file_id, entry)| entry.member.is_none() && !index.is_deleted(*file_id))
+                .filter_map(|(file_id, entry)| Some((file_id, entry, entry.size?)));
+            for (file_id, entry, size) in sized {
+                let directory = entry.source.parent().unwrap_or(Path::new("")).to_path_buf();
+                by_directory
+                    .entry(directory)
+                    .or_default()
+                    .push((file_id, size as f64));
+            }
+        }
+        let mut outliers = HashSet::new();
+        for sizes in by_directory.values() {
+            if sizes.len() < OUTLIER_MIN_FILES {
+                continue;
+            }
+            let count = sizes.len() as f64;
+            let mean = sizes.iter().map(|(_, size)| size).sum::<f64>() / count;
+            let variance = sizes
+                .iter()
+                .map(|(_, size)| (size - mean).powi(2))
+                .sum::<f64>()
+                / count;
+            let deviation = variance.sqrt();
+            outliers.extend(
+                sizes
+                    .iter()
+                    .filter(|(_, size)| (size - mean).abs() > OUTLIER_DEVIATIONS * deviation)
+                    .map(|(file_id, _)| *file_id),
+            );
+        }
+        debug!(?outliers, "refresh size outliers");
+        self.index.write().unwrap().set_tag_members(
+            Tag::from(SIZE_OUTLIER_TAG),
+            outliers,
+            SIZE_OUTLIER_TAG,
+        );
+    }
+
+    /// Make [`TagFS::add_files`] assign ids in source path order, so the
+    /// index doesn't depend on the order files were discovered in.
+    pub fn set_deterministic_ids(&mut self, deterministic_ids: bool) {
+        self.deterministic_ids = deterministic_ids;
+    }
+
+    /// Add a batch of files with their tags and provenance.
+    pub fn add_files(&mut self, files: impl IntoIterator<Item = (PathBuf, Provenance)>) {
+        let mut files = files.into_iter().collect::<Vec<_>>();
+        if self.deterministic_ids {
+            files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        for (source, tags) in files {
+            self.add_file_with_provenance(&source, tags);
+        }
+    }
+
+    pub fn add_file(&mut self, source: &'a Path, tags: HashSet<Tag>) {
+        let tags = tags.into_iter().map(|tag| (tag, BTreeSet::new())).collect();
+        self.add_file_with_provenance(source, tags);
+    }
+
+    /// Like [`TagFS::add_file`], also recording which tagger produced each
+    /// tag.
+    pub fn add_file_with_provenance(&mut self, source: &'a Path, tags: Provenance) {
+        info!(file = ?source, ?tags, "add_file");
+        self.audit(source, &tags);
+        let stat = self.libc_wrapper.lstat(source).ok();
+        let mut index = self.index.write().unwrap();
+        index.insert(source, stat.as_ref(), tags);
+        self.metrics.set_index_files(index.live_files());
+    }
+
+    /// Start a new scan generation, returning it. Files added or retagged
+    /// into the index for the first time from now on belong to it, and are
+    /// tagged [`NEW_TAG`] until the next; every file is tagged with its
+    /// generation, as `generation:N`, once there's more than one.
+    pub fn next_generation(&self) -> u64 {
+        self.index.write().unwrap().next_generation()
+    }
+
+    /// Drop a file from the index when its source turns out to have been
+    /// deleted since the scan, rather than listing it until rescanned.
+    pub fn set_prune_stale(&mut self, prune_stale: bool) {
+        self.prune_stale = prune_stale;
+    }
+
+    /// Errno for a failure `e` reaching the source of `file_id`, pruning
+    /// every entry read from that source if enabled when it no longer exists.
+    /// Failures without an OS error are reported as EIO, never pruning.
+    fn source_error(&self, file_id: usize, source: &Path, e: std::io::Error) -> libc::c_int {
+        let Some(errno) = e.raw_os_error() else {
+            warn!(?source, file_id, error = ?e, "source failed");
+            return EIO;
+        };
+        if errno == ENOENT && self.prune_stale {
+            warn!(?source, file_id, "source vanished, pruning");
+            if let Some(cache) = &self.read_cache {
+                cache.lock().unwrap().invalidate(source);
+            }
+            self.delete_source(file_id);
+        }
+        errno
+    }
+
+    /// Mark `file_id` deleted along with every other entry sharing its
+    /// source, as the members of one archive do.
+    fn delete_source(&self, file_id: usize) {
+        let mut index = self.index.write().unwrap();
+        let Some(source) = index.files.get(file_id).map(|entry| entry.source.clone()) else {
+            return;
+        };
+        let sharing = index
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.source == source)
+            .map(|(file_id, _)| file_id)
+            .collect::<Vec<_>>();
+        for file_id in sharing {
+            index.set_deleted(file_id, true);
+        }
+        self.metrics.set_index_files(index.live_files());
+    }
+
+    /// Handle for replacing the index once the filesystem has been handed to
+    /// fuse, e.g. with a rescan of the source.
+    pub fn index_handle(&self) -> IndexHandle {
+        IndexHandle {
+            index: self.index.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Serve the index behind `handle` in place of this filesystem's own,
+    /// e.g. when remounting one already populated.
+    pub fn use_index(&mut self, handle: &IndexHandle) {
+        self.index = handle.index.clone();
+        self.metrics = handle.metrics.clone();
+    }
+
+    /// Every tag-to-file association, borrowed from the index without
+    /// copying it.
+    pub fn associations(&self) -> Associations<'_> {
+        Associations {
+            index: self.index.read().unwrap(),
+        }
+    }
+
+    /// Counters of the operations served, for exposing as metrics.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// List only tags with one of `labels` at the root, or unlabelled tags
+    /// named in it; deeper directories still list every tag, and the others
+    /// can still be visited by name. No labels lists every tag.
+    pub fn set_root_labels(&mut self, labels: impl IntoIterator<Item = impl Into<OsString>>) {
+        let labels = labels.into_iter().map(Into::into).collect::<HashSet<_>>();
+        self.root_labels = (!labels.is_empty()).then_some(labels);
+    }
+
+    /// Serve every file, whatever its tags, in a top-level directory called
+    /// `name`, shadowing any tag of that name. An empty name disables it.
+    pub fn set_all_dir(&mut self, name: impl Into<OsString>) {
+        let name = name.into();
+        self.all_dir = (!name.is_empty()).then_some(name);
+        self.index.write().unwrap().changed();
+    }
+
+    /// Serve the files matching `expr` in a top-level directory called
+    /// `name`, shadowing any tag of that name. The expression is evaluated
+    /// afresh on each visit, so follows retagging.
+    pub fn add_saved_search(&mut self, name: impl Into<OsString>, expr: Expr) {
+        self.saved_searches.insert(name.into(), expr);
+        self.index.write().unwrap().changed();
+    }
+
+    /// Beside each listed file `name`, serve a read-only `name` + `suffix`
+    /// listing its tags one per line. A real file of that name takes
+    /// precedence. An empty suffix disables it.
+    pub fn set_tags_suffix(&mut self, suffix: impl Into<OsString>) {
+        let suffix = suffix.into();
+        self.tags_suffix = (!suffix.is_empty()).then_some(suffix);
+    }
+
+    /// The path of the file whose companion `path` would be, if its name
+    /// carries the companion suffix.
+    fn companion_of(&self, path: &Path) -> Option<PathBuf> {
+        let suffix = self.tags_suffix.as_ref()?.as_bytes();
+        let name = path.file_name()?.as_bytes();
+        let base = name.strip_suffix(suffix).filter(|base| !base.is_empty())?;
+        Some(path.with_file_name(OsStr::from_bytes(base)))
+    }
+
+    /// The flat directory `path` lies in, if any, with the rest of `path`
+    /// relative to it.
+    fn flat_dir_relative<'p>(&self, path: &'p Path) -> Option<(FlatDir<'_>, &'p Path)> {
+        let mut components = path.strip_prefix("/").ok()?.components();
+        let Some(Component::Normal(name)) = components.next() else {
+            return None;
+        };
+        let relative = components.as_path();
+        if self.all_dir.as_deref() == Some(name) {
+            return Some((FlatDir::All, relative));
+        }
+        let expr = self.saved_searches.get(name)?;
+        Some((FlatDir::Search(expr), relative))
+    }
+
+    /// Log a debug event, with `file`, `label`, `value` and `tagger` fields,
+    /// for each tagger giving each tag to a file as it is added or retagged.
+    pub fn set_audit_tags(&mut self, audit_tags: bool) {
+        self.audit_tags = audit_tags;
+    }
+
+    fn audit(&self, source: &Path, tags: &Provenance) {
+        if !self.audit_tags {
+            return;
+        }
+        for (tag, taggers) in tags {
+            let label = match tag.has_label() {
+                true => tag.label(),
+                false => OsStr::new(""),
+            };
+            // Tags added without provenance have no tagger to report
+            let taggers = match taggers.is_empty() {
+                true => vec![""],
+                false => taggers.iter().map(String::as_str).collect(),
+            };
+            for tagger in taggers {
+                debug!(
+                    file = %source.display(),
+                    label = %label.to_string_lossy(),
+                    value = %tag.value().to_string_lossy(),
+                    tagger,
+                    "tag assigned"
+                );
+            }
+        }
+    }
+
+    /// Recompute the tags of `source` with `updater` and swap them into the
+    /// index, adding the file if it isn't indexed yet. Any non-directory is
+    /// tagged, as in a scan, while one that no longer exists is removed from
+    /// the index entirely. Safe to call while the filesystem is serving
+    /// requests.
+    pub fn retag(&self, source: &Path, updater: &FileUpdater) {
+        let stat = match self.libc_wrapper.lstat(source) {
+            Ok(stat) if mode_to_filetype(stat.st_mode) != FileType::Directory => Some(stat),
+            Ok(_) => None,
+            Err(e) if matches!(e.raw_os_error(), Some(ENOENT | ENOTDIR)) => None,
+            Err(error) => {
+                warn!(?source, ?error, "retag: can't stat, keeping tags");
+                return;
+            }
+        };
+        let Some(stat) = stat else {
+            let mut index = self.index.write().unwrap();
+            if let Some(file_id) = index.find(source) {
+                info!(?source, file_id, "retag: removed");
+                index.set_tags(file_id, Provenance::new());
+                index.set_deleted(file_id, true);
+                self.metrics.set_index_files(index.live_files());
+            }
+            return;
+        };
+
+        // Tag outside the lock, as taggers may be slow
+        let tags = updater.tag_with_provenance(source);
+        info!(?source, ?tags, "retag");
+        self.audit(source, &tags);
+        {
+            let mut index = self.index.write().unwrap();
+            match index.find(source) {
+                Some(file_id) => {
+                    index.set_tags(file_id, tags);
+                    index.stamp_generation(file_id);
+                    index.replay_tag_commands(file_id);
+                    index.set_deleted(file_id, false);
+                    index.files[file_id].set_stat(Some(&stat));
+                }
+                None => {
+                    let file_id = index.insert(source, Some(&stat), tags);
+                    index.replay_tag_commands(file_id);
+                }
+            }
+            self.metrics.set_index_files(index.live_files());
+        }
+        self.refresh_recent();
+        self.refresh_size_outliers();
+    }
+
+    /// Source paths of the files carrying every one of `tags`, in the order
+    /// they were added. With no tags, every file matches.
+    pub fn files_for_tags(&self, tags: &[Tag]) -> Vec<PathBuf> {
+        let tags = tags.iter().map(Tag::as_os_str).collect::<Vec<_>>();
+        self.query(&tags)
+    }
+
+    /// Source paths of the files carrying every one of the tags named by
+    /// their display strings, in the order they were added. With no tags,
+    /// every file matches; unknown tags match nothing.
+    pub fn query(&self, tags: &[&OsStr]) -> Vec<PathBuf> {
+        let index = self.index.read().unwrap();
+        let file_ids = match index.intersect(tags.iter().copied()) {
+            Some(file_ids) => file_ids.into_iter().sorted().collect(),
+            None => (0..index.files.len()).collect::<Vec<_>>(),
+        };
+        file_ids
+            .into_iter()
+            .filter(|file_id| !index.is_deleted(*file_id))
+            .filter_map(|file_id| index.files.get(file_id))
+            .map(|entry| index.display_path(entry))
+            .collect()
+    }
+
+    /// Source paths of the files under the virtual `path`, in the order they
+    /// were added: every file matching a tag directory's intersection, every
+    /// file for the root or all-files directory, the matches of a saved
+    /// search, or the one file a file path names. Archive members are
+    /// skipped, having no source file of their own. `None` when `path` names
+    /// neither a directory nor a file with a source.
+    pub fn collect_matches(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        match self.lookup(path) {
+            LookupResult::File(source, _) => return Some(vec![source]),
+            LookupResult::Directory => {}
+            LookupResult::Member(..)
+            | LookupResult::Info
+            | LookupResult::Tags(_)
+            | LookupResult::Missing => return None,
+        }
+        let flat_dir = self.flat_dir_relative(path).map(|(dir, _)| dir);
+        let (path, label) = self.flatten_path(path);
+        let index = self.index.read().unwrap();
+        let file_ids = match flat_dir {
+            Some(dir) => Some(index.flat_dir_files(dir).into_iter().collect()),
+            None => index.intersect(path.components().filter_map(|c| match c {
+                Component::Normal(tag) => Some(tag),
+                _ => None,
+            })),
+        };
+        // A bare singleton label stands for every one of its values
+        let labelled = |file_id: usize| match &label {
+            Some(label) => index.file_tags[file_id]
+                .keys()
+                .any(|tag| tag.has_label() && tag.label() == label),
+            None => true,
+        };
+        let file_ids = match file_ids {
+            Some(file_ids) => file_ids.into_iter().sorted().collect(),
+            None => (0..index.files.len()).collect::<Vec<_>>(),
+        };
+        let matches = file_ids
+            .into_iter()
+            .filter(|file_id| !index.is_deleted(*file_id) && labelled(*file_id))
+            .filter(|file_id| index.files[*file_id].member.is_none())
+            .map(|file_id| index.source(&index.files[file_id]))
+            .collect();
+        Some(matches)
+    }
+
+    /// Record the names of the taggers used to build the index, for reporting.
+    pub fn set_taggers(&mut self, taggers: impl IntoIterator<Item = impl Into<String>>) {
+        self.taggers = taggers.into_iter().map(Into::into).collect();
+    }
+
+    pub fn is_deleted(&self, file_id: usize) -> bool {
+        self.index.read().unwrap().is_deleted(file_id)
+    }
+
+    pub fn delete_file(&self, file_id: usize) {
+        let mut index = self.index.write().unwrap();
+        index.set_deleted(file_id, true);
+        self.metrics.set_index_files(index.live_files());
+    }
+
+    /// `tag<TAB>tagger,...` lines for each tag of `file_id`, sorted by tag.
+    fn provenance_content(&self, file_id: usize) -> Vec<u8> {
+        let index = self.index.read().unwrap();
+        let Some(tags) = index.file_tags.get(file_id) else {
+            return Vec::new();
+        };
+        let mut content = Vec::new();
+        for (tag, taggers) in tags
+            .iter()
+            .sorted_by(|(a, _), (b, _)| a.as_os_str().cmp(b.as_os_str()))
+        {
+            content.extend_from_slice(tag.as_os_str().as_bytes());
+            content.push(b'\t');
+            content.extend_from_slice(taggers.iter().join(",").as_bytes());
+            content.push(b'\n');
+        }
+        content
+    }
+
+    /// The display form of each tag of `file_id`, one per line, sorted.
+    fn tags_content(&self, file_id: usize) -> Vec<u8> {
+        let index = self.index.read().unwrap();
+        let Some(tags) = index.file_tags.get(file_id) else {
+            return Vec::new();
+        };
+        let mut content = Vec::new();
+        for tag in tags.keys().map(Tag::as_os_str).sorted() {
+            content.extend_from_slice(tag.as_bytes());
+            content.push(b'\n');
+        }
+        content
+    }
+
+    /// Register an open source fd, returning the handle to give to fuse.
+    fn insert_handle(&self, open_file: OpenFile) -> u64 {
+        let fh = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        let mut handles = self.handles.write().unwrap();
+        handles.insert(fh, open_file);
+        fh
+    }
+
+    fn with_handle<R>(&self, fh: u64, f: impl FnOnce(&OpenFile) -> R) -> Option<R> {
+        let handles = self.handles.read().unwrap();
+        handles.get(&fh).map(f)
+    }
+
+    /// Directory listing for `path`: `.` and `..` followed by the children
+    /// sorted by name, in the configured [`Collation`].
+    fn list_directory(&self, path: &Path) -> Vec<DirectoryEntry> {
+        let flat_dir = self.flat_dir_relative(path);
+        let (path, label) = self.flatten_path(path);
+        let path = path.as_path();
+        let tags = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(p) => Some(p.to_os_string()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        info!(?tags, ?path, "lookup");
+        let mut children = Vec::new();
+        if path == Path::new("/") {
+            children.push(DirectoryEntry {
+                name: INFO_FILE.into(),
+                kind: FileType::RegularFile,
+            });
+            for name in self.all_dir.iter().chain(self.saved_searches.keys()) {
+                children.push(DirectoryEntry {
+                    name: name.clone(),
+                    kind: FileType::Directory,
+                });
+            }
+        }
+
+        let index = self.index.read().unwrap();
+        if let Some((dir, relative)) = flat_dir {
+            // Files named as in tag directories
+            if relative == Path::new("") {
+                children.extend(
+                    file_names(
+                        &index.files,
+                        index.flat_dir_files(dir),
+                        self.collision_format,
+                    )
+                    .into_iter()
+                    .map(|(_, file_name)| DirectoryEntry {
+                        name: file_name.into_owned(),
+                        kind: FileType::RegularFile,
+                    }),
+                );
+            }
+        } else if let Some(label) = label {
+            // A bare singleton label lists the values of the matching files
+            let file_ids = index.intersect(
+                tags.iter()
+                    .map(OsString::as_os_str)
+                    .filter(|tag| latest_label(tag).is_none()),
+            );
+            let matches = |file_id: &usize| {
+                !index.is_deleted(*file_id)
+                    && file_ids.as_ref().is_none_or(|ids| ids.contains(file_id))
+            };
+            children.extend(
+                index
+                    .tags
+                    .iter()
+                    .filter(|(tag, _)| tag.is_singleton() && tag.label() == label)
+                    .filter(|(_, ids)| ids.iter().any(matches))
+                    .map(|(tag, _)| DirectoryEntry {
+                        name: tag.value().into(),
+                        kind: FileType::Directory,
+                    }),
+            );
+        } else {
+            let depth = path
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count();
+            let singleton_labels = match self.flatten_singletons {
+                true => index
+                    .tags
+                    .keys()
+                    .filter(|tag| tag.is_singleton())
+                    .map(|tag| (tag.as_os_str(), tag.label()))
+                    .collect(),
+                false => HashMap::new(),
+            };
+            for (child_type, child_name) in get_children(
+                path,
+                &index.tags,
+                &index.files,
+                |file_id| index.is_deleted(file_id),
+                self.root_labels.as_ref().filter(|_| depth == 0),
+                self.listing_view.at_depth(depth),
+                self.collision_format,
+            ) {
+                info!(?child_type, name = ?child_name, "children");
+                // Singleton tags are offered through their label
+                let name = match child_type {
+                    FileType::Directory => singleton_labels
+                        .get(&*child_name)
+                        .map_or(child_name, |label| Cow::Borrowed(label)),
+                    _ => child_name,
+                };
+                children.push(DirectoryEntry {
+                    name: name.into_owned(),
+                    kind: child_type,
+                });
+            }
+        }
+        // Companions of the files listed, unless a real file has the name
+        if let Some(suffix) = &self.tags_suffix {
+            let names = children
+                .iter()
+                .map(|child| child.name.clone())
+                .collect::<HashSet<_>>();
+            let companions = children
+                .iter()
+                .filter(|child| child.kind == FileType::RegularFile)
+                .filter(|child| path != Path::new("/") || child.name != INFO_FILE)
+                .map(|child| {
+                    let mut name = child.name.clone();
+                    name.push(suffix);
+                    name
+                })
+                .filter(|name| !names.contains(name))
+                .map(|name| DirectoryEntry {
+                    name,
+                    kind: FileType::RegularFile,
+                })
+                .collect::<Vec<_>>();
+            children.extend(companions);
+        }
+        // One unusable name mustn't take the rest of the listing with it
+        children.retain(|child| {
+            let valid = is_valid_entry_name(&child.name);
+            if !valid {
+                warn!(?path, name = ?child.name, "skipping unlistable entry");
+            }
+            valid
+        });
+        children.sort_by(|a, b| self.collation.compare(&a.name, &b.name));
+        children.dedup_by(|a, b| a.name == b.name && a.kind == b.kind);
+
+        let mut entries = vec![
+            DirectoryEntry {
+                name: ".".into(),
+                kind: FileType::Directory,
+            },
+            DirectoryEntry {
+                name: "..".into(),
+                kind: FileType::Directory,
+            },
+        ];
+        entries.append(&mut children);
+        entries
+    }
+
+    /// Register a snapshot of synthesized file content, returning its handle.
+    fn insert_virtual_handle(&self, content: Vec<u8>) -> u64 {
+        let fh = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        let mut virtual_handles = self.virtual_handles.write().unwrap();
+        virtual_handles.insert(fh, content);
+        fh
+    }
+
+    /// JSON summary of the mount, served as the content of [`INFO_FILE`].
+    fn info_content(&self) -> Vec<u8> {
+        let index = self.index.read().unwrap();
+        let files = index.files.len() - index.deleted.len();
+        let taggers = self
+            .taggers
+            .iter()
+            .map(|name| format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\"")))
+            .join(",");
+        format!(
+            "{{\"files\":{},\"tags\":{},\"uptime_secs\":{},\"taggers\":[{}]}}\n",
+            files,
+            index.tags.len(),
+            self.started.elapsed().as_secs(),
+            taggers
+        )
+        .into_bytes()
+    }
+
+    /// Attributes of a read-only synthesized file of `size` bytes.
+    fn virtual_attr(size: usize) -> FileAttr {
+        let now = SystemTime::now();
+        FileAttr {
+            size: size as u64,
+            blocks: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::RegularFile,
+            perm: 0o0444,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    /// Read from `fh` as [`TagFS::read_source`], counting the call.
+    fn read_handle(&self, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>, libc::c_int> {
+        let content = self.read_source(fh, offset, size);
+        self.metrics
+            .count_read(content.as_ref().map_or(0, |content| content.len()));
+        content
+    }
+
+    /// Read from an open handle, clamped to the end of file.
+    fn read_source(&self, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>, libc::c_int> {
+        if let Some(content) = self.virtual_handles.read().unwrap().get(&fh) {
+            let start = usize::try_from(offset).map_or(content.len(), |o| o.min(content.len()));
+            let end = content.len().min(start.saturating_add(size as usize));
+            return Ok(content[start..end].to_vec());
+        }
+
+        // Never ask for more than remains before the end of file as of open;
+        // the remainder is below `size` whenever it's picked, so fits a u32
+        let clamp = |file_size: u64, size: u32| {
+            let remaining = file_size.saturating_sub(offset);
+            u32::try_from(remaining).map_or(size, |remaining| remaining.min(size))
+        };
+        if self.readahead_bytes == 0 {
+            // Nothing per handle to track, so reads share the lock
+            let (fd, file_size, source) = {
+                let handles = self.handles.read().unwrap();
+                let Some(open_file) = handles.get(&fh) else {
+                    return Err(EBADF);
+                };
+                (
+                    open_file.fd,
+                    open_file.size,
+                    self.read_cache.as_ref().map(|_| open_file.source.clone()),
+                )
+            };
+            let size = clamp(file_size, size);
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+            return self.read_range(fd, source.as_deref(), file_size, offset, size);
+        }
+        let (fd, file_size, source, size, sequential) = {
+            let mut handles = self.handles.write().unwrap();
+            let Some(open_file) = handles.get_mut(&fh) else {
+                return Err(EBADF);
+            };
+            let size = clamp(open_file.size, size);
+            let sequential = open_file.next_offset == Some(offset);
+            open_file.next_offset = Some(offset + size as u64);
+            if let Some(content) = open_file.buffered(offset, size) {
+                debug!(fh, offset, size, "readahead hit");
+                return Ok(content);
+            }
+            (
+                open_file.fd,
+                open_file.size,
+                self.read_cache.as_ref().map(|_| open_file.source.clone()),
+                size,
+                sequential,
+            )
+        };
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        if !sequential || size >= self.readahead_bytes {
+            return self.read_range(fd, source.as_deref(), file_size, offset, size);
+        }
+        let chunk = self.read_range(
+            fd,
+            source.as_deref(),
+            file_size,
+            offset,
+            clamp(file_size, self.readahead_bytes),
+        )?;
+        let content = chunk[..chunk.len().min(size as usize)].to_vec();
+        debug!(fh, offset, len = chunk.len(), "read ahead");
+        if let Some(open_file) = self.handles.write().unwrap().get_mut(&fh) {
+            open_file.readahead = Some((offset, chunk));
+        }
+        Ok(content)
+    }
+
+    /// `size` bytes from `offset` of the open `fd`, through the read cache
+    /// when enabled and `source` is given.
+    fn read_range(
+        &self,
+        fd: i32,
+        source: Option<&Path>,
+        file_size: u64,
+        offset: u64,
+        size: u32,
+    ) -> Result<Vec<u8>, libc::c_int> {
+        // pread takes a signed offset; st_size never exceeds it, so neither
+        // can an offset before the end of file
+        let Ok(read_offset) = i64::try_from(offset) else {
+            return Err(EINVAL);
+        };
+        let cache = self.read_cache.as_ref().zip(source);
+        if let Some((cache, source)) = &cache {
+            if let Some(content) = cache.lock().unwrap().get(source, file_size, offset, size) {
+                debug!(fd, offset, size, "read cache hit");
+                return Ok(content);
+            }
+        }
+        let content = self
+            .libc_wrapper
+            .read(fd, read_offset, size)
+            .map_err(|e| e.raw_os_error().unwrap_or(ENOENT))?;
+        if let Some((cache, source)) = &cache {
+            cache
+                .lock()
+                .unwrap()
+                .insert(source, file_size, offset, size, &content);
+        }
+        Ok(content)
+    }
+
+    /// Resize the file open as `fh` to `size`. Growing preallocates the new
+    /// tail, so writes into it can't run out of space; shrinking cuts the
+    /// file off at `size`.
+    fn resize(&self, path: &Path, fh: u64, size: u64) -> fuse_mt::ResultEmpty {
+        match self.lookup(path) {
+            LookupResult::Directory => return Err(EISDIR),
+            LookupResult::Member(..) | LookupResult::Info | LookupResult::Tags(_) => {
+                return Err(EROFS)
+            }
+            LookupResult::Missing => return Err(ENOENT),
+            LookupResult::File(..) => {}
+        }
+        let mut handles = self.handles.write().unwrap();
+        let open_file = handles.get_mut(&fh).ok_or(EBADF)?;
+        let current = open_file.size;
+        let offset = |n: u64| i64::try_from(n).map_err(|_| EINVAL);
+        let resized = match size.cmp(&current) {
+            std::cmp::Ordering::Equal => return Ok(()),
+            std::cmp::Ordering::Less => self.libc_wrapper.ftruncate(open_file.fd, offset(size)?),
+            std::cmp::Ordering::Greater => self.libc_wrapper.fallocate(
+                open_file.fd,
+                offset(current)?,
+                offset(size - current)?,
+                0,
+            ),
+        };
+        resized.map_err(|e| e.raw_os_error().unwrap_or(EIO))?;
+        open_file.size = size;
+        if let Some(cache) = &self.read_cache {
+            cache.lock().unwrap().invalidate(&open_file.source);
+        }
+        let source = open_file.source.clone();
+        drop_readahead(&mut handles, &source);
+        Ok(())
+    }
+}
+
+/// Forget the chunks read ahead by every handle open on `source`, once it
+/// has been written through any of them.
+fn drop_readahead(handles: &mut HashMap<u64, OpenFile>, source: &Path) {
+    for open_file in handles.values_mut() {
+        if open_file.source == source {
+            open_file.readahead = None;
+        }
+    }
+}
+
+/// Whether `name` can be returned as a single directory entry.
+fn is_valid_entry_name(name: &OsStr) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.as_bytes().iter().any(|b| *b == b'/' || *b == 0)
+}
+
+/// One line written to [`TAG_CONTROL_XATTR`].
+#[derive(Clone, Debug, PartialEq)]
+enum TagCommand {
+    Add(Tag),
+    /// Display form of the tag to remove
+    Remove(OsString),
+}
+
+/// Parse a [`TAG_CONTROL_XATTR`] value, failing with EINVAL unless it is
+/// UTF-8 and every non-blank line is `+` or `-` followed by a tag usable as a
+/// path component.
+fn parse_tag_commands(value: &[u8]) -> Result<Vec<TagCommand>, libc::c_int> {
+    let value = std::str::from_utf8(value).map_err(|_| EINVAL)?;
+    let commands = value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (op, display) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+            let valid = !display.is_empty()
+                && !display.contains(['/', '\0'])
+                && display != "."
+                && display != ".."
+                && display != INFO_FILE;
+            match (op, valid) {
+                ("+", true) => Ok(TagCommand::Add(Tag::from_display(display))),
+                ("-", true) => Ok(TagCommand::Remove(display.into())),
+                _ => {
+                    warn!(?line, "malformed tag command");
+                    Err(EINVAL)
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match commands.is_empty() {
+        true => Err(EINVAL),
+        false => Ok(commands),
+    }
+}
+
+/// Answer an xattr request: the value's size when `size` is 0, otherwise the
+/// value itself, or ERANGE if it doesn't fit.
+fn xattr_reply(value: Vec<u8>, size: u32) -> ResultXattr {
+    if size == 0 {
+        Ok(Xattr::Size(value.len() as u32))
+    } else if value.len() > size as usize {
+        Err(ERANGE)
+    } else {
+        Ok(Xattr::Data(value))
+    }
+}
+
+impl<T> FilesystemMT for TagFS<T>
+where
+    T: LibcWrapper,
+{
+    fn getattr(
+        &self,
+        _req: fuse_mt::RequestInfo,
+        path: &std::path::Path,
+        fh: Option<u64>,
+    ) -> fuse_mt::ResultEntry {
+        mark_serving_thread();
+        info!(path = debug(path), fh = debug(fh), "getattr");
+        self.metrics.count_getattr();
+
+        if let Some(fd) = fh.and_then(|fh| self.with_handle(fh, |open_file| open_file.fd)) {
+            match self.libc_wrapper.fstat(fd as u64) {
+                Ok(stat) => Ok((TTL, stat.to_file_attr())),
+                Err(e) => Err(e.raw_os_error().unwrap_or(libc::ENOENT)),
+            }
+        } else {
+            match self.lookup(path) {
+                LookupResult::Directory => Ok((TTL, fh.to_file_attr())),
+                LookupResult::Info => Ok((TTL, Self::virtual_attr(self.info_content().len()))),
+                LookupResult::Tags(file_id) => {
+                    Ok((TTL, Self::virtual_attr(self.tags_content(file_id).len())))
+                }
+                LookupResult::Missing => Err(ENOENT),
+                LookupResult::File(source, file_id) => match self.libc_wrapper.lstat(&source) {
+                    Ok(stat) => Ok((TTL, stat.to_file_attr())),
+                    Err(e) => Err(self.source_error(file_id, &source, e)),
+                },
+                // Times and ownership come from the archive itself
+                LookupResult::Member(archive, member, file_id) => {
+                    match self.libc_wrapper.lstat(&archive) {
+                        Ok(stat) => Ok((
+                            TTL,
+                            FileAttr {
+                                size: member.size,
+                                blocks: member.size.div_ceil(512),
+                                kind: FileType::RegularFile,
+                                perm: 0o444,
+                                nlink: 1,
+                                ..stat.to_file_attr()
+                            },
+                        )),
+                        Err(e) => Err(self.source_error(file_id, &archive, e)),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Only open files can be resized, through their handle.
+    fn truncate(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: Option<u64>,
+        size: u64,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(?path, ?fh, size, "truncate");
+        let Some(fh) = fh else {
+            return Err(ENOSYS);
+        };
+        self.resize(path, fh, size)
+    }
+
+    fn chmod(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: Option<u64>,
+        mode: u32,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(?path, ?fh, mode = format!("{:o}", mode), "chmod");
+        match self.lookup(path) {
+            LookupResult::File(source, _) => self
+                .libc_wrapper
+                .chmod(&source, mode)
+                .map_err(|e| e.raw_os_error().unwrap_or(EIO)),
+            LookupResult::Directory | LookupResult::Info | LookupResult::Tags(_) => Err(EPERM),
+            LookupResult::Member(..) => Err(EROFS),
+            LookupResult::Missing => Err(ENOENT),
+        }
+    }
+
+    fn chown(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: Option<u64>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(?path, ?fh, ?uid, ?gid, "chown");
+        match self.lookup(path) {
+            LookupResult::File(source, _) => self
+                .libc_wrapper
+                .chown(&source, uid, gid)
+                .map_err(|e| e.raw_os_error().unwrap_or(EIO)),
+            LookupResult::Directory | LookupResult::Info | LookupResult::Tags(_) => Err(EPERM),
+            LookupResult::Member(..) => Err(EROFS),
+            LookupResult::Missing => Err(ENOENT),
+        }
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+        mark_serving_thread();
+        info!(
+            path = debug(path),
+            flags = format!("{:#o}", flags),
+            "opendir"
+        );
+        match self.lookup(path) {
+            LookupResult::Directory => {
+                // Snapshot the listing so every readdir page on this handle agrees
+                let entries = self.list_directory(path);
+                let fh = self.next_handle.fetch_add(1, Ordering::Relaxed);
+                self.directories.write().unwrap().insert(fh, entries);
+                Ok((fh, 0))
+            }
+            LookupResult::File(..)
+            | LookupResult::Member(..)
+            | LookupResult::Info
+            | LookupResult::Tags(_) => Err(ENOTDIR),
+            LookupResult::Missing => Err(ENOENT),
+        }
+    }
+
+    fn readdir(&self, _req: RequestInfo, path: &Path, fh: u64) -> ResultReaddir {
+        mark_serving_thread();
+        info!(path = debug(path), fh = debug(fh), "readdir");
+        self.metrics.count_readdir();
+        match self.directories.read().unwrap().get(&fh) {
+            Some(entries) => Ok(entries.clone()),
+            None => Ok(self.list_directory(path)),
+        }
+    }
+
+    fn releasedir(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        flags: u32,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(?path, fh, flags = format!("{:o}", flags), "releasedir");
+        match self.directories.write().unwrap().remove(&fh) {
+            Some(_) => Ok(()),
+            None => Err(EBADF),
+        }
+    }
+
+    fn open(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+        mark_serving_thread();
+        info!(?path, flags = format!("{:o}", flags), "open");
+
+        match self.lookup(path) {
+            LookupResult::Directory => Err(ENOENT),
+            LookupResult::File(source, file_id) => {
+                let fd = self
+                    .libc_wrapper
+                    .open(&source, flags as i32)
+                    .map_err(|e| self.source_error(file_id, &source, e))?;
+                let size = match self.libc_wrapper.fstat(fd as u64) {
+                    Ok(stat) => stat.st_size as u64,
+                    Err(err) => {
+                        let _ = self.libc_wrapper.close(fd);
+                        return Err(err.raw_os_error().unwrap_or(ENOENT));
+                    }
+                };
+                let fh = self.insert_handle(OpenFile::new(
+                    fd,
+                    source,
+                    size,
+                    flags as i32 & O_APPEND != 0,
+                ));
+                debug!(fh, fd, size, "opened");
+                Ok((fh, flags))
+            }
+            LookupResult::Info => Ok((self.insert_virtual_handle(self.info_content()), flags)),
+            LookupResult::Tags(file_id) => Ok((
+                self.insert_virtual_handle(self.tags_content(file_id)),
+                flags,
+            )),
+            LookupResult::Member(..) if flags as i32 & O_ACCMODE != O_RDONLY => Err(EROFS),
+            // Extract the whole member up front, then serve reads from memory
+            LookupResult::Member(archive, member, _) => {
+                match self.archive_reader.read_member(&archive, &member.name) {
+                    Ok(content) => Ok((self.insert_virtual_handle(content), flags)),
+                    Err(e) => {
+                        warn!(?archive, ?member, error = ?e, "read archive member");
+                        Err(e.raw_os_error().unwrap_or(EIO))
+                    }
+                }
+            }
+            LookupResult::Missing => Err(ENOENT),
+        }
+    }
+
+    fn release(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(
+            ?path,
+            fh,
+            flags = format!("{:o}", flags),
+            lock_owner,
+            flush,
+            "release"
+        );
+        if self.virtual_handles.write().unwrap().remove(&fh).is_some() {
+            return Ok(());
+        }
+        let open_file = {
+            let mut handles = self.handles.write().unwrap();
+            handles.remove(&fh)
+        };
+        match open_file {
+            Some(open_file) => {
+                debug!(fh, fd = open_file.fd, source = ?open_file.source, "close");
+                match self.libc_wrapper.close(open_file.fd) {
+                    Ok(()) => Ok(()),
+                    // Already closed: the handle is gone either way
+                    Err(e) if e.raw_os_error() == Some(EBADF) => {
+                        warn!(fh, fd = open_file.fd, "fd already closed");
+                        Ok(())
+                    }
+                    Err(e) => Err(e.raw_os_error().unwrap_or(ENOENT)),
+                }
+            }
+            None => Err(EBADF),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(fuse_mt::ResultSlice<'_>) -> fuse_mt::CallbackResult,
+    ) -> fuse_mt::CallbackResult {
+        mark_serving_thread();
+        info!(?path, fh, offset, size, "read");
+
+        // Held until the reply is sent; a read may fill a whole read-ahead
+        // chunk, so reserves that much
+        let _permit = self
+            .read_budget
+            .as_ref()
+            .map(|budget| budget.acquire(size.max(self.readahead_bytes).into()));
+        match self.read_handle(fh, offset, size) {
+            Ok(content) => callback(Ok(content.as_slice())),
+            Err(e) => callback(Err(e)),
+        }
+    }
+
+    /// Writes to handles opened with `O_APPEND` ignore `offset` and land at
+    /// the end of file, as the kernel would place them.
+    fn write(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        offset: u64,
+        data: Vec<u8>,
+        flags: u32,
+    ) -> ResultWrite {
+        mark_serving_thread();
+        info!(?path, fh, offset, len = data.len(), flags, "write");
+        if self.virtual_handles.read().unwrap().contains_key(&fh) {
+            return Err(EROFS);
+        }
+        let mut handles = self.handles.write().unwrap();
+        let open_file = handles.get_mut(&fh).ok_or(EBADF)?;
+        let written = match open_file.append {
+            true => self.libc_wrapper.write(open_file.fd, &data),
+            false => {
+                let offset = i64::try_from(offset).map_err(|_| EINVAL)?;
+                self.libc_wrapper.pwrite(open_file.fd, offset, &data)
+            }
+        }
+        .map_err(|e| e.raw_os_error().unwrap_or(EIO))?;
+        let end = match open_file.append {
+            true => open_file.size.saturating_add(written as u64),
+            false => offset.saturating_add(written as u64),
+        };
+        open_file.size = open_file.size.max(end);
+        if let Some(cache) = &self.read_cache {
+            cache.lock().unwrap().invalidate(&open_file.source);
+        }
+        let source = open_file.source.clone();
+        drop_readahead(&mut handles, &source);
+        u32::try_from(written).map_err(|_| EIO)
+    }
+
+    fn getxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, size: u32) -> ResultXattr {
+        mark_serving_thread();
+        info!(?path, ?name, size, "getxattr");
+        match self.lookup(path) {
+            LookupResult::Missing => Err(ENOENT),
+            LookupResult::File(source, ..) if name == SOURCE_XATTR => {
+                let source = std::path::absolute(&source).unwrap_or(source);
+                xattr_reply(source.as_os_str().as_bytes().to_vec(), size)
+            }
+            LookupResult::File(_, file_id) | LookupResult::Member(_, _, file_id)
+                if name == PROVENANCE_XATTR =>
+            {
+                xattr_reply(self.provenance_content(file_id), size)
+            }
+            _ => Err(ENODATA),
+        }
+    }
+
+    /// Edit a file's tags through [`TAG_CONTROL_XATTR`]. Other attribute
+    /// names are ENOTSUP, tag directories and the info file are EPERM, and
+    /// malformed commands are EINVAL, leaving the tags untouched.
+    fn setxattr(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        name: &OsStr,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        info!(?path, ?name, flags, position, "setxattr");
+        if name != TAG_CONTROL_XATTR {
+            return Err(ENOTSUP);
+        }
+        let file_id = match self.lookup(path) {
+            LookupResult::Missing => return Err(ENOENT),
+            LookupResult::Directory | LookupResult::Info | LookupResult::Tags(_) => {
+                return Err(EPERM)
+            }
+            LookupResult::File(_, file_id) | LookupResult::Member(_, _, file_id) => file_id,
+        };
+        let commands = parse_tag_commands(value)?;
+        let (source, added) = {
+            let mut index = self.index.write().unwrap();
+            let added = index.apply_tag_commands(file_id, commands);
+            (index.display_path(&index.files[file_id]), added)
+        };
+        self.audit(&source, &added);
+        Ok(())
+    }
+
+    fn listxattr(&self, _req: RequestInfo, path: &Path, size: u32) -> ResultXattr {
+        mark_serving_thread();
+        info!(?path, size, "listxattr");
+        match self.lookup(path) {
+            LookupResult::Missing => Err(ENOENT),
+            LookupResult::File(..) => xattr_reply(
+                format!("{SOURCE_XATTR}\0{PROVENANCE_XATTR}\0").into_bytes(),
+                size,
+            ),
+            LookupResult::Member(..) => {
+                xattr_reply(format!("{PROVENANCE_XATTR}\0").into_bytes(), size)
+            }
+            LookupResult::Directory | LookupResult::Info | LookupResult::Tags(_) => {
+                xattr_reply(Vec::new(), size)
+            }
+        }
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &Path, name: &OsStr) -> fuse_mt::ResultEmpty {
+        mark_serving_thread();
+        let path: PathBuf = parent.join(name);
+        info!(?parent, ?name, ?path, "unlink");
+        match self.lookup(&path) {
+            LookupResult::Directory | LookupResult::Missing => Err(ENOENT),
+            LookupResult::Info | LookupResult::Tags(_) => Err(EPERM),
+            LookupResult::Member(..) => Err(EROFS),
+            LookupResult::File(source, i) => match self.libc_wrapper.unlink(&source) {
+                Ok(_) => {
+                    if let Some(cache) = &self.read_cache {
+                        cache.lock().unwrap().invalidate(&source);
+                    }
+                    self.delete_file(i);
+                    Ok(())
+                }
+                Err(e) => Err(e.raw_os_error().unwrap_or(ENOENT)),
+            },
+        }
+    }
+}
+
+/// A top-level directory listing files directly rather than by tag.
+#[derive(Clone, Copy, Debug)]
+enum FlatDir<'s> {
+    /// Every file
+    All,
+    /// The files matching a saved search
+    Search(&'s Expr),
+}
+
+#[derive(Debug)]
+enum LookupResult {
+    Directory,
+    File(PathBuf, usize),
+    /// Archive path and the member within it
+    Member(PathBuf, ArchiveMember, usize),
+    Info,
+    /// Companion listing the tags of a file
+    Tags(usize),
+    Missing,
+}
+impl<T> TagFS<T>
+where
+    T: LibcWrapper,
+{
+    /// What `path` names, falling back to the tags companion of a file when
+    /// nothing else has the name.
+    fn lookup(&self, path: &Path) -> LookupResult {
+        match self.lookup_entry(path) {
+            LookupResult::Missing => {
+                let Some(base) = self.companion_of(path) else {
+                    return LookupResult::Missing;
+                };
+                match self.lookup_entry(&base) {
+                    LookupResult::File(_, file_id) | LookupResult::Member(_, _, file_id) => {
+                        debug!(?path, file_id, "tags companion");
+                        LookupResult::Tags(file_id)
+                    }
+                    _ => LookupResult::Missing,
+                }
+            }
+            found => found,
+        }
+    }
+
+    #[instrument(skip(self))]
+    fn lookup_entry(&self, path: &Path) -> LookupResult {
+        use LookupResult::*;
+        info!(?path, "lookup");
+
+        if path == Path::new("/").join(INFO_FILE) {
+            debug!(?path, "info file");
+            return Info;
+        }
+
+        if let Some((dir, relative)) = self.flat_dir_relative(path) {
+            let mut components = relative.components();
+            return match (components.next(), components.next()) {
+                (None, _) => Directory,
+                (Some(Component::Normal(name)), None) => {
+                    let index = self.index.read().unwrap();
+                    let names = index.dir_names(
+                        path.parent().unwrap_or(path),
+                        || index.flat_dir_files(dir).into_iter().collect(),
+                        self.collision_format,
+                    );
+                    let entry = names.get(name).map(|idx| (*idx, &index.files[*idx]));
+                    match entry {
+                        None => Missing,
+                        Some((idx, e)) => match &e.member {
+                            Some(member) => Member(index.source(e), member.clone(), idx),
+                            None => File(index.source(e), idx),
+                        },
+                    }
+                }
+                _ => Missing,
+            };
+        }
+
+        let name = path.file_name().map(OsStr::to_os_string);
+        let (path, label) = self.flatten_path(path);
+        let path = path.as_path();
+        let index = self.index.read().unwrap();
+        let tag_dir = path.components().all(|c| match c {
+            Component::Prefix(_prefix_component) => todo!(),
+            Component::RootDir => true,
+            Component::CurDir => false,
+            Component::ParentDir => false,
+            Component::Normal(tag) => index.contains_tag(tag) || index.is_modifier(tag),
+        });
+        if label.is_some() {
+            // Only under a tag directory
+            if !tag_dir {
+                debug!(?path, ?label, "singleton label under missing dir");
+                return Missing;
+            }
+            debug!(?path, ?label, "singleton label dir");
+            return Directory;
+        }
+        // A name matching a tag only once folded doesn't hide a file named
+        // exactly so, which is looked for by the name as given
+        let folded_name = name.filter(|name| index.is_folded_tag(name));
+        let found = if tag_dir && folded_name.is_none() {
+            debug!(?path, "tag dir");
+            Directory
+        } else {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let listed = || {
+                let (latest, tags): (Vec<_>, Vec<_>) = dir
+                    .components()
+                    .filter_map(|c| match c {
+                        Component::Normal(tag) => Some(tag),
+                        _ => None,
+                    })
+                    .partition(|tag| latest_label(tag).is_some());
+                // Modifiers alone narrow every file
+                let files = match (index.intersect(tags), latest.is_empty()) {
+                    (None, false) => Some((0..index.files.len()).collect()),
+                    (files, _) => files,
+                };
+                let Some(files) = files else {
+                    info!(path = debug(path), "failed lookup");
+                    return HashSet::new();
+                };
+                let live = files
+                    .into_iter()
+                    .filter(|idx| !index.is_deleted(*idx))
+                    .collect::<HashSet<_>>();
+                latest
+                    .into_iter()
+                    .filter_map(latest_label)
+                    .fold(live, |live, label| {
+                        latest_per_value(&index.tags, &index.files, live, label)
+                    })
+            };
+            let names = index.dir_names(dir, listed, self.collision_format);
+            let entry = folded_name
+                .as_deref()
+                .or(path.file_name())
+                .and_then(|name| names.get(name))
+                .map(|idx| (*idx, &index.files[*idx]));
+            match entry {
+                None => Missing,
+                Some((idx, e)) => match &e.member {
+                    Some(member) => Member(index.source(e), member.clone(), idx),
+                    None => File(index.source(e), idx),
+                },
+            }
+        };
+        match found {
+            Missing if tag_dir => Directory,
+            found => found,
+        }
+    }
+}
+
+/// `name` with case and accents folded away, for matching `cafe` to `Café`;
+/// `None` if it isn't UTF-8.
+fn fold(name: &OsStr) -> Option<String> {
+    let name = name.to_str()?;
+    Some(
+        name.nfd()
+            .filter(|c| !is_combining_mark(*c))
+            .flat_map(char::to_lowercase)
+            .collect(),
+    )
+}
+
+/// Ids of the files carrying a tag displayed as `display`. A path component
+/// names every tag with that display form, so a value emitted as a singleton
+/// by one tagger and as one of many by another matches both sets of files.
+fn files_displayed_as(tags: &HashMap<Tag, HashSet<usize>>, display: &OsStr) -> HashSet<usize> {
+    tags.iter()
+        .filter(|(tag, _)| tag.as_os_str() == display)
+        .flat_map(|(_, file_ids)| file_ids.iter().copied())
+        .collect()
+}
+
+/// The label named by a `+latest:<label>` path component.
+fn latest_label(component: &OsStr) -> Option<&OsStr> {
+    component
+        .as_bytes()
+        .strip_prefix(LATEST_PREFIX.as_bytes())
+        .filter(|label| !label.is_empty())
+        .map(OsStr::from_bytes)
+}
+
+/// Of `file_ids`, the most recently modified file carrying each value of
+/// `label`. Files without the label are dropped; ties go to the file added
+/// last.
+fn latest_per_value(
+    tags: &HashMap<Tag, HashSet<usize>>,
+    files: &[Entry],
+    file_ids: HashSet<usize>,
+    label: &OsStr,
+) -> HashSet<usize> {
+    let newness = |file_id: usize| (files.get(file_id).and_then(|e| e.mtime), file_id);
+    let mut latest = HashMap::<&OsStr, usize>::new();
+    for (tag, ids) in tags
+        .iter()
+        .filter(|(tag, _)| tag.has_label() && tag.label() == label)
+    {
+        for file_id in ids.iter().copied().filter(|id| file_ids.contains(id)) {
+            latest
+                .entry(tag.value())
+                .and_modify(|newest| *newest = cmp::max_by_key(*newest, file_id, |id| newness(*id)))
+                .or_insert(file_id);
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// The names `file_ids` are listed under, paired with their ids. Files
+/// sharing a name are ranked by source path, and all but the first renamed
+/// in `collision` format. A renaming that's already taken, by another file's
+/// own name or an earlier renaming, is skipped for the next rank's.
+fn file_names(
+    files: &[Entry],
+    file_ids: impl IntoIterator<Item = usize>,
+    collision: CollisionFormat,
+) -> Vec<(usize, Cow<'_, OsStr>)> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for file_id in file_ids {
+        if let Some(name) = files.get(file_id).and_then(Entry::file_name) {
+            groups.entry(name).or_default().push(file_id);
+        }
+    }
+    let mut taken = groups
+        .keys()
+        .map(|name| Cow::Borrowed(*name))
+        .collect::<HashSet<_>>();
+    let mut names = Vec::new();
+    for (name, mut group) in groups {
+        group.sort_by_cached_key(|file_id| (files[*file_id].listed_path(), *file_id));
+        let mut rank = 0;
+        for (nth, file_id) in group.into_iter().enumerate() {
+            if nth == 0 {
+                names.push((file_id, Cow::Borrowed(name)));
+                continue;
+            }
+            let path = files[file_id].listed_path();
+            let dir = path.parent().unwrap_or(Path::new(""));
+            // Formats ignoring the rank can't get round a clash, so give up
+            // once every taken name has been tried
+            let listed = (rank + 1..=rank + 1 + taken.len())
+                .map(|next| {
+                    rank = next;
+                    collision.disambiguate(name, next, dir)
+                })
+                .find(|listed| !taken.contains(listed))
+                .unwrap_or_else(|| collision.disambiguate(name, rank, dir));
+            taken.insert(listed.clone());
+            names.push((file_id, listed));
+        }
+    }
+    names
+}
+
+#[instrument(skip_all)]
+/// Tags and files listed in the directory `root`, with only the tags whose
+/// label, or whole name when unlabelled, is in `labels` when given.
+fn get_children<'a, 'b, 'c, 'd, F>(
+    root: &Path,
+    tags: &'a HashMap<Tag, HashSet<usize>>,
+    files: &'b [Entry],
+    is_deleted: F,
+    labels: Option<&'d HashSet<OsString>>,
+    view: View,
+    collision: CollisionFormat,
+) -> impl Iterator<Item = (FileType, Cow<'c, OsStr>)>
+where
+    'a: 'c,
+    'b: 'c,
+    'd: 'c,
+    F: Fn(usize) -> bool + 'c,
+{
+    // TODO Filter out intrinsic tags NOT represented by residual files
+
+    let (latest, root_tags): (Vec<_>, Vec<_>) = root
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(p) => Some(p.to_os_string()),
+            _ => None,
+        })
+        .partition(|p| latest_label(p).is_some());
+    let root_tags = root_tags.into_iter().collect::<HashSet<_>>();
 
     // Collect ids of files with ALL tags in path
-    let file_ids = if root_tags.is_empty() {
-        HashSet::new()
-    } else {
-        tags.iter()
-            .filter(|(tag, _file_ids)| root_tags.contains(tag.as_os_str()))
-            .map(|(_tag, file_ids)| file_ids)
-            .fold(None, |acc, v| match acc {
-                None => Some(v.clone()),
-                Some(a) => Some(a.intersection(v).cloned().collect()),
+    let file_ids = match (view.shows_files(), root_tags.is_empty()) {
+        (false, _) => HashSet::new(),
+        // Every file matches an empty intersection, so the root only lists
+        // files when its view asks for them, whatever tags they carry
+        (true, true) => (0..files.len()).collect(),
+        (true, false) => root_tags
+            .iter()
+            .map(|root_tag| files_displayed_as(tags, root_tag))
+            .reduce(|a, b| a.intersection(&b).copied().collect())
+            .unwrap_or_default(),
+    };
+    let file_ids = file_ids
+        .into_iter()
+        .filter(|file_id| !is_deleted(*file_id))
+        .collect::<HashSet<_>>();
+    let file_ids = latest
+        .iter()
+        .filter_map(|p| latest_label(p))
+        .fold(file_ids, |file_ids, label| {
+            latest_per_value(tags, files, file_ids, label)
+        });
+
+    debug!(?file_ids, ?root_tags, ?root, "residue");
+
+    // TODO Deal with name collision between 2x tags NOT in root, one intrinsic, one extrinsic
+
+    let mut singleton_labels = HashSet::new();
+    for tag in tags.keys() {
+        debug!(?tag, ?root_tags, "detect singletons");
+        if root_tags.contains(tag.as_os_str()) && tag.is_singleton() {
+            singleton_labels.insert(tag.label());
+        }
+    }
+
+    let show_tags = view.shows_tags();
+    tags.iter()
+        .filter(move |_| show_tags)
+        // An empty name can't be a path component
+        .filter(|(t, _)| !t.as_os_str().is_empty())
+        // Filter out tags already in path
+        .filter(move |(t, _)| {
+            debug!(?t, ?root_tags, "visited filter tag");
+            !root_tags.contains(t.as_os_str())
+        })
+        // Filter out already seen filter tags
+        .filter(move |(t, _)| {
+            debug!(?t, ?singleton_labels, "singleton filter tag");
+            !t.is_singleton() || !singleton_labels.contains(t.label())
+        })
+        .filter(move |(t, _)| {
+            labels.is_none_or(|labels| match t.has_label() {
+                true => labels.contains(t.label()),
+                false => labels.contains(t.as_os_str()),
+            })
+        })
+        // Remaining tags become directory entries
+        .map(|(t, _)| (FileType::Directory, Cow::Borrowed(t.as_os_str())))
+        .chain(
+            // File ids become Regular File entries
+            file_names(files, file_ids, collision)
+                .into_iter()
+                .map(|(_, file_name)| (FileType::RegularFile, file_name)),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeSet, HashMap, HashSet},
+        env,
+        ffi::{OsStr, OsString},
+        fs, io,
+        mem::MaybeUninit,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use clap::ValueEnum as _;
+    use fuse_mt::{FileType, FilesystemMT as _, RequestInfo, Xattr};
+    use itertools::Itertools as _;
+    use libc::{
+        EBADF, EINVAL, EIO, EISDIR, ENODATA, ENOENT, ENOSYS, ENOTDIR, ENOTSUP, EPERM, ERANGE, EROFS,
+    };
+    use tracing_test::traced_test;
+
+    use crate::{
+        archive::test::write_zip,
+        file_updater::{FileUpdater, Provenance},
+        filesystem::{
+            collision::CollisionFormat,
+            libc_wrappers::{
+                InMemoryLibcWrapper, LibcWrapper as _, LibcWrapperReal, MockLibcWrapper,
+            },
+            tagfs::{
+                get_children, ListingView, TagFS, View, INFO_FILE, NEW_TAG, PROVENANCE_XATTR,
+                RECENT_TAG, SIZE_OUTLIER_TAG, SOURCE_XATTR, TAG_CONTROL_XATTR,
+            },
+        },
+        tagger::{Error, Tag, Tagger, TAG_SEPARATOR},
+    };
+
+    use super::{Entry, Index, LookupResult, OpenFile, DIR_NAMES_CACHED};
+
+    // Mutex to ensure only one test at a time is accessing the global context for construction
+    static MTX: Mutex<()> = Mutex::new(());
+
+    /// A mock statting sources as the real filesystem does, as adding files
+    /// to the index does.
+    fn mock() -> MockLibcWrapper {
+        let mut mock = MockLibcWrapper::default();
+        mock.expect_lstat()
+            .returning(|path| LibcWrapperReal.lstat(path));
+        mock
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_root() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        tags.insert(Tag::from("tag2"), HashSet::new());
+        tags.insert(Tag::from("tag3"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Tags,
+            CollisionFormat::default(),
+        );
+        assert_eq!(3, children.count());
+    }
+
+    #[test]
+    fn get_children_root_lists_every_tag_and_no_files() {
+        let files = vec![
+            Entry::from("/fake/source/plain.txt"),
+            Entry::from("/fake/source/labelled.txt"),
+        ];
+        let tags = HashMap::from([
+            (Tag::from("plain"), HashSet::from([0])),
+            (Tag::new("size", true, "1"), HashSet::from([1])),
+            (Tag::new("size", true, "2"), HashSet::from([1])),
+            (Tag::new("colour", false, "red"), HashSet::from([1])),
+            (Tag::from(""), HashSet::from([0])),
+        ]);
+
+        let mut children = get_children(
+            Path::new("/"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Tags,
+            CollisionFormat::default(),
+        )
+        .map(|(kind, name)| (kind, name.to_str().unwrap().to_owned()))
+        .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            vec![
+                (FileType::Directory, "colour:red".to_owned()),
+                (FileType::Directory, "plain".to_owned()),
+                (FileType::Directory, "size:1".to_owned()),
+                (FileType::Directory, "size:2".to_owned()),
+            ],
+            children
+        );
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_tag2() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        tags.insert(Tag::from("tag2"), HashSet::new());
+        tags.insert(Tag::from("tag3"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/tag2"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Both,
+            CollisionFormat::default(),
+        );
+        assert_eq!(2, children.count());
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_tag2_tag1() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        tags.insert(Tag::from("tag2"), HashSet::new());
+        tags.insert(Tag::from("tag3"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/tag2/tag1"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Both,
+            CollisionFormat::default(),
+        );
+        assert_eq!(1, children.count());
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_singleton_root() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::new("singleton", true, "v1"), HashSet::new());
+        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Tags,
+            CollisionFormat::default(),
+        )
+        .map(|(kind, name)| (kind, name.into_owned()))
+        .collect::<HashSet<_>>();
+        // Root shows all tags, no files
+        assert_eq!(3, children.len());
+        assert!(children.contains(&(
+            fuse_mt::FileType::Directory,
+            OsString::from("singleton".to_owned() + TAG_SEPARATOR + "v1")
+        )));
+        assert!(children.contains(&(
+            fuse_mt::FileType::Directory,
+            OsString::from("singleton".to_owned() + TAG_SEPARATOR + "v2")
+        )));
+        assert!(children.contains(&(fuse_mt::FileType::Directory, OsString::from("tag1"))));
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_singleton_child_file() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
+        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v1"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Both,
+            CollisionFormat::default(),
+        )
+        .map(|(kind, name)| (kind, name.into_owned()))
+        .collect::<HashSet<_>>();
+        // Inner shows only non-singleton collisions, and related files
+        assert_eq!(2, children.len());
+        assert!(children.contains(&(fuse_mt::FileType::Directory, OsString::from("tag1"))));
+        assert!(children.contains(&(fuse_mt::FileType::RegularFile, OsString::from("file1.txt"))));
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_singleton_child_file_deleted() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
+        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v1"),
+            &tags,
+            &files,
+            |file_id| file_id == 0,
+            None,
+            View::Both,
+            CollisionFormat::default(),
+        )
+        .map(|(kind, name)| (kind, name.into_owned()))
+        .collect::<HashSet<_>>();
+        // Inner shows only non-singleton collisions, and related files
+        assert_eq!(1, children.len());
+        assert!(children.contains(&(fuse_mt::FileType::Directory, OsString::from("tag1"))));
+    }
+
+    #[traced_test]
+    #[test]
+    fn get_children_singleton_child_nofile() {
+        let mut tags = HashMap::new();
+        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
+        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
+        tags.insert(Tag::from("tag1"), HashSet::new());
+        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+
+        let children = get_children(
+            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v2"),
+            &tags,
+            &files,
+            |_| false,
+            None,
+            View::Both,
+            CollisionFormat::default(),
+        )
+        .map(|(kind, name)| (kind, name.into_owned()))
+        .collect::<HashSet<_>>();
+        // Inner shows only non-singleton collisions, and related files
+        assert_eq!(1, children.len());
+        assert!(children.contains(&(fuse_mt::FileType::Directory, OsString::from("tag1"))));
+        assert!(!children.contains(&(fuse_mt::FileType::RegularFile, OsString::from("file1.txt"))));
+    }
+
+    #[traced_test]
+    #[test]
+    fn unlink_present_file() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_unlink().times(1).returning(|_path| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let mut tags = HashSet::new();
+        tags.insert(Tag::from("tag"));
+        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
+        let r = fs.unlink(
+            RequestInfo {
+                unique: 0,
+                uid: 0,
+                gid: 0,
+                pid: 0,
+            },
+            &PathBuf::from("/tag"),
+            &OsString::from("present.txt"),
+        );
+        assert!(r.is_ok());
+        assert!(fs.is_deleted(0));
+    }
+
+    #[traced_test]
+    #[test]
+    fn unlink_missing_file() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let mut tags = HashSet::new();
+        tags.insert(Tag::from("tag"));
+        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
+        let r = fs.unlink(
+            RequestInfo {
+                unique: 0,
+                uid: 0,
+                gid: 0,
+                pid: 0,
+            },
+            &PathBuf::from("/tag"),
+            &OsString::from("missing.txt"),
+        );
+        assert!(r.is_err());
+        assert_eq!(ENOENT, r.unwrap_err());
+        assert!(!fs.is_deleted(0));
+    }
+
+    #[traced_test]
+    #[test]
+    fn unlink_forbidden_file() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_unlink().times(1).returning(|path| {
+                if path == Path::new("/fake/source/present.txt") {
+                    Err(std::io::Error::from_raw_os_error(EPERM))
+                } else {
+                    Err(std::io::Error::from_raw_os_error(ENOENT))
+                }
+            });
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let mut tags = HashSet::new();
+        tags.insert(Tag::from("tag"));
+        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
+        let r = fs.unlink(
+            RequestInfo {
+                unique: 0,
+                uid: 0,
+                gid: 0,
+                pid: 0,
+            },
+            &PathBuf::from("/tag"),
+            &OsString::from("present.txt"),
+        );
+        assert!(r.is_err());
+        assert_eq!(EPERM, r.unwrap_err());
+        assert!(!fs.is_deleted(0));
+        let r = fs.unlink(
+            RequestInfo {
+                unique: 0,
+                uid: 0,
+                gid: 0,
+                pid: 0,
+            },
+            &PathBuf::from("/tag"),
+            &OsString::from("missing.txt"),
+        );
+        assert!(r.is_err());
+        assert_eq!(ENOENT, r.unwrap_err());
+    }
+
+    fn request() -> RequestInfo {
+        RequestInfo {
+            unique: 0,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        }
+    }
+
+    fn zeroed_stat() -> libc::stat {
+        unsafe { MaybeUninit::<libc::stat>::zeroed().assume_init() }
+    }
+
+    #[traced_test]
+    #[test]
+    fn open_concurrent_distinct_handles() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().times(2).returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().times(2).returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = 42;
+                Ok(stat)
+            });
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+
+        let fs = &fs;
+        let (first, second) = thread::scope(|s| {
+            let first = s.spawn(|| fs.open(request(), &PathBuf::from("/tag/present.txt"), 0));
+            let second = s.spawn(|| fs.open(request(), &PathBuf::from("/tag/present.txt"), 0));
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        let (first, _) = first.unwrap();
+        let (second, _) = second.unwrap();
+        assert_ne!(first, second);
+
+        let handles = fs.handles.read().unwrap();
+        assert_eq!(2, handles.len());
+        assert!(handles.values().all(|open_file| open_file.fd == 7
+            && open_file.size == 42
+            && open_file.source == Path::new("/fake/source/present.txt")));
+    }
+
+    #[traced_test]
+    #[test]
+    fn release_removes_handle() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().times(2).returning(|_path, _flags| Ok(7));
+            mock.expect_fstat()
+                .times(2)
+                .returning(|_fd| Ok(zeroed_stat()));
+            mock.expect_close()
+                .withf(|fd| *fd == 7)
+                .times(2)
+                .returning(|_fd| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/present.txt");
+        let (first, _) = fs.open(request(), &path, 0).unwrap();
+        let (second, _) = fs.open(request(), &path, 0).unwrap();
+
+        assert!(fs.release(request(), &path, first, 0, 0, false).is_ok());
+        assert!(!fs.handles.read().unwrap().contains_key(&first));
+        assert!(fs.handles.read().unwrap().contains_key(&second));
+
+        assert!(fs.release(request(), &path, second, 0, 0, false).is_ok());
+        assert!(fs.handles.read().unwrap().is_empty());
+
+        // Releasing an unknown handle is rejected without touching the wrapper
+        assert_eq!(Err(EBADF), fs.release(request(), &path, first, 0, 0, false));
+    }
+
+    #[traced_test]
+    #[test]
+    fn info_file_read() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_taggers(["mime", "metadata"]);
+        fs.add_file(
+            &PathBuf::from("/fake/source/first.txt"),
+            HashSet::from([Tag::from("tag1"), Tag::from("tag2")]),
+        );
+        fs.add_file(
+            &PathBuf::from("/fake/source/second.txt"),
+            HashSet::from([Tag::from("tag2"), Tag::from(INFO_FILE)]),
+        );
+
+        let path = PathBuf::from("/").join(INFO_FILE);
+        let (_ttl, attr) = fs.getattr(request(), &path, None).unwrap();
+        assert_eq!(fuse_mt::FileType::RegularFile, attr.kind);
+        assert_eq!(0o444, attr.perm);
+
+        let (fh, _flags) = fs.open(request(), &path, 0).unwrap();
+        let content = String::from_utf8(fs.read_handle(fh, 0, 4096).unwrap()).unwrap();
+        assert!(content.starts_with(r#"{"files":2,"tags":2,"uptime_secs":"#));
+        assert!(content.ends_with("\"taggers\":[\"mime\",\"metadata\"]}\n"));
+        assert_eq!(attr.size, content.len() as u64);
+        assert_eq!(
+            content.as_bytes()[1..6].to_vec(),
+            fs.read_handle(fh, 1, 5).unwrap()
+        );
+        assert!(fs.release(request(), &path, fh, 0, 0, false).is_ok());
+        assert_eq!(Err(EBADF), fs.read_handle(fh, 0, 4096));
+
+        let children = fs.readdir(request(), &PathBuf::from("/"), 0).unwrap();
+        assert_eq!(
+            1,
+            children
+                .iter()
+                .filter(|entry| entry.name == INFO_FILE)
+                .count()
+        );
+        assert_eq!(
+            Err(EPERM),
+            fs.unlink(request(), &PathBuf::from("/"), &OsString::from(INFO_FILE))
+        );
+    }
+
+    #[traced_test]
+    #[test]
+    fn lookup_stops_at_empty_intersection() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/first.txt"),
+            HashSet::from([Tag::from("tag1"), Tag::from("tag3")]),
+        );
+        fs.add_file(
+            &PathBuf::from("/fake/source/second.txt"),
+            HashSet::from([Tag::from("tag2"), Tag::from("tag3")]),
+        );
+
+        let result = fs.lookup(&PathBuf::from("/tag1/tag2/tag3/first.txt"));
+        assert!(matches!(result, super::LookupResult::Missing));
+        assert!(logs_contain("empty intersection"));
+        assert!(logs_contain("tag=\"tag2\""));
+        assert!(!logs_contain("tag=\"tag3\""));
+    }
+
+    #[traced_test]
+    #[test]
+    fn opendir_snapshots_are_independent() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/b.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag");
+        let (first, _) = fs.opendir(request(), &path, 0).unwrap();
+
+        fs.add_file(
+            &PathBuf::from("/fake/source/a.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let (second, _) = fs.opendir(request(), &path, 0).unwrap();
+        assert_ne!(first, second);
+
+        let names = |fh| {
+            fs.readdir(request(), &path, fh)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![".", "..", "b.txt"], names(first));
+        assert_eq!(vec![".", "..", "a.txt", "b.txt"], names(second));
+        // Repeated pages on the same handle are served from the same snapshot
+        assert_eq!(names(first), names(first));
+
+        assert!(fs.releasedir(request(), &path, first, 0).is_ok());
+        assert_eq!(Err(EBADF), fs.releasedir(request(), &path, first, 0));
+        assert_eq!(vec![".", "..", "a.txt", "b.txt"], names(second));
+        assert!(fs.releasedir(request(), &path, second, 0).is_ok());
+        assert!(fs.directories.read().unwrap().is_empty());
+    }
+
+    #[traced_test]
+    #[test]
+    fn query_intersection() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/first.txt"),
+            HashSet::from([Tag::from("tag1"), Tag::from("tag2")]),
+        );
+        fs.add_file(
+            &PathBuf::from("/fake/source/second.txt"),
+            HashSet::from([Tag::from("tag2")]),
+        );
+
+        let tag1 = OsString::from("tag1");
+        let tag2 = OsString::from("tag2");
+        let unknown = OsString::from("unknown");
+        assert_eq!(
+            vec![Path::new("/fake/source/first.txt")],
+            fs.query(&[&tag1])
+        );
+        assert_eq!(
+            vec![
+                Path::new("/fake/source/first.txt"),
+                Path::new("/fake/source/second.txt")
+            ],
+            fs.query(&[&tag2])
+        );
+        assert_eq!(
+            vec![Path::new("/fake/source/first.txt")],
+            fs.query(&[&tag2, &tag1])
+        );
+        assert!(fs.query(&[&unknown]).is_empty());
+        assert!(fs.query(&[&tag2, &unknown]).is_empty());
+        assert_eq!(2, fs.query(&[]).len());
+
+        fs.delete_file(0);
+        assert!(fs.query(&[&tag1]).is_empty());
+    }
+
+    #[traced_test]
+    #[test]
+    fn getxattr_source() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/first.txt"),
+            HashSet::from([Tag::from("tag1")]),
+        );
+        let name = OsString::from(SOURCE_XATTR);
+        let file = PathBuf::from("/tag1/first.txt");
+
+        match fs.getxattr(request(), &file, &name, 0) {
+            Ok(Xattr::Size(size)) => assert_eq!("/fake/source/first.txt".len() as u32, size),
+            other => panic!("unexpected {other:?}"),
+        }
+        match fs.getxattr(request(), &file, &name, 4096) {
+            Ok(Xattr::Data(data)) => assert_eq!(b"/fake/source/first.txt".to_vec(), data),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(matches!(
+            fs.getxattr(request(), &file, &name, 4),
+            Err(ERANGE)
+        ));
+        assert!(matches!(
+            fs.getxattr(request(), &file, &OsString::from("user.other"), 4096),
+            Err(ENODATA)
+        ));
+        assert!(matches!(
+            fs.getxattr(request(), &PathBuf::from("/tag1"), &name, 4096),
+            Err(ENODATA)
+        ));
+        assert!(matches!(
+            fs.getxattr(request(), &PathBuf::from("/tag1/missing.txt"), &name, 4096),
+            Err(ENOENT)
+        ));
+        match fs.listxattr(request(), &file, 4096) {
+            Ok(Xattr::Data(data)) => assert_eq!(
+                format!("{SOURCE_XATTR}\0{PROVENANCE_XATTR}\0").into_bytes(),
+                data
+            ),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[derive(Debug)]
+    struct SwitchTagger(Arc<Mutex<HashSet<Tag>>>);
+    impl Tagger for SwitchTagger {
+        fn name(&self) -> &str {
+            "switch"
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    /// Clears a running flag when dropped, so threads polling it stop even
+    /// when the thread holding it panics.
+    struct ClearOnDrop<'a>(&'a std::sync::atomic::AtomicBool);
+    impl Drop for ClearOnDrop<'_> {
+        fn drop(&mut self) {
+            self.0.store(false, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[traced_test]
+    #[test]
+    fn retag_swaps_tags() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let fs = TagFS::<MockLibcWrapper>::new();
+
+        let source = env::temp_dir().join("tagfs-retag-swaps-tags.txt");
+        fs::write(&source, b"content").unwrap();
+        let output = Arc::new(Mutex::new(HashSet::from([
+            Tag::from("old"),
+            Tag::from("kept"),
+        ])));
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(output.clone()));
+
+        let old = OsString::from("old");
+        let new = OsString::from("new");
+        let kept = OsString::from("kept");
+        fs.retag(&source, &updater);
+        assert_eq!(vec![source.clone()], fs.query(&[&old, &kept]));
+
+        *output.lock().unwrap() = HashSet::from([Tag::from("new"), Tag::from("kept")]);
+        fs.retag(&source, &updater);
+        assert!(fs.query(&[&old]).is_empty());
+        assert_eq!(vec![source.clone()], fs.query(&[&new, &kept]));
+        assert_eq!(1, fs.query(&[]).len());
+        let root = fs.readdir(request(), &PathBuf::from("/"), 0).unwrap();
+        assert!(root.iter().all(|entry| entry.name != old));
+        assert!(root.iter().any(|entry| entry.name == new));
+
+        fs::remove_file(&source).unwrap();
+        fs.retag(&source, &updater);
+        assert!(fs.query(&[]).is_empty());
+        assert!(fs.query(&[&new]).is_empty());
+        let root = fs.readdir(request(), &PathBuf::from("/"), 0).unwrap();
+        assert!(root
+            .iter()
+            .all(|entry| entry.name != new && entry.name != kept));
+    }
+
+    #[test]
+    fn dir_names_cache_bounded() {
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        for i in 0..DIR_NAMES_CACHED * 2 {
+            fs.add_file(
+                &PathBuf::from(format!("/fake/source/file{i}.txt")),
+                HashSet::from([Tag::from(format!("tag{i}").as_str())]),
+            );
+        }
+        let cached = |fs: &TagFS<_>| {
+            fs.index
+                .read()
+                .unwrap()
+                .dir_names
+                .lock()
+                .unwrap()
+                .entries
+                .len()
+        };
+        // Missing directories list nothing, so aren't kept
+        for i in 0..DIR_NAMES_CACHED * 2 {
+            let path = PathBuf::from(format!("/typo{i}/file.txt"));
+            assert!(matches!(fs.lookup(&path), LookupResult::Missing));
+        }
+        assert_eq!(0, cached(&fs));
+        for i in 0..DIR_NAMES_CACHED * 2 {
+            let path = PathBuf::from(format!("/tag{i}/file{i}.txt"));
+            assert!(matches!(fs.lookup(&path), LookupResult::File(_, _)));
+        }
+        assert_eq!(DIR_NAMES_CACHED, cached(&fs));
+        // The least recently used directories went first
+        let index = fs.index.read().unwrap();
+        let dir_names = index.dir_names.lock().unwrap();
+        assert!(dir_names
+            .entries
+            .contains_key(Path::new(&format!("/tag{}", DIR_NAMES_CACHED * 2 - 1))));
+        assert!(!dir_names.entries.contains_key(Path::new("/tag0")));
+    }
+
+    #[test]
+    fn retag_judges_existence_by_lstat() {
+        let mut mock = MockLibcWrapper::default();
+        mock.expect_lstat().returning(|path| {
+            let mut stat = zeroed_stat();
+            stat.st_mode = match path.to_str().unwrap() {
+                "/fake/fifo" => libc::S_IFIFO,
+                "/fake/dangling" => libc::S_IFLNK,
+                "/fake/denied" => return Err(std::io::Error::from_raw_os_error(libc::EACCES)),
+                _ => return Err(std::io::Error::from_raw_os_error(ENOENT)),
+            };
+            stat.st_size = 3;
+            Ok(stat)
+        });
+        let mut fs = TagFS::with_libc_wrapper(mock);
+        fs.add_file(Path::new("/fake/denied"), HashSet::from([Tag::from("old")]));
+        fs.add_file(Path::new("/fake/gone"), HashSet::from([Tag::from("old")]));
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(Arc::new(Mutex::new(HashSet::from([
+            Tag::from("new"),
+        ])))));
+        for source in ["/fake/fifo", "/fake/dangling", "/fake/denied", "/fake/gone"] {
+            fs.retag(Path::new(source), &updater);
+        }
+
+        // Non-regular files are tagged as in a scan, with their stat
+        assert_eq!(
+            vec![PathBuf::from("/fake/fifo"), PathBuf::from("/fake/dangling")],
+            fs.query(&[OsStr::new("new")])
+        );
+        let index = fs.index.read().unwrap();
+        let fifo = index.find(Path::new("/fake/fifo")).unwrap();
+        assert_eq!(Some(3), index.files[fifo].size);
+        drop(index);
+        // A file that can't be stat'ed keeps its tags; only a missing one goes
+        assert_eq!(
+            vec![PathBuf::from("/fake/denied")],
+            fs.query(&[OsStr::new("old")])
+        );
+    }
+
+    #[traced_test]
+    #[test]
+    fn read_empty_file() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| Ok(zeroed_stat()));
+            mock.expect_read().never();
+            mock.expect_close().returning(|_fd| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/empty.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/empty.txt");
+        let (fh, _) = fs.open(request(), &path, 0).unwrap();
+
+        assert_eq!(Ok(Vec::new()), fs.read_handle(fh, 0, 4096));
+        assert_eq!(Ok(Vec::new()), fs.read_handle(fh, 10, 4096));
+        assert!(fs.release(request(), &path, fh, 0, 0, false).is_ok());
+    }
+
+    #[test]
+    fn read_past_u32_boundary() {
+        let _m = MTX.lock();
+
+        const SIZE: u64 = u32::MAX as u64 + 10;
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = SIZE as i64;
+                Ok(stat)
+            });
+            // Straddling the boundary, only the tail is asked for
+            mock.expect_read()
+                .withf(|fd, offset, count| (*fd, *offset, *count) == (7, u32::MAX as i64 - 5, 15))
+                .times(1)
+                .returning(|_fd, _offset, count| Ok(vec![b'x'; count as usize]));
+            mock.expect_read()
+                .withf(|fd, offset, count| (*fd, *offset, *count) == (7, 1 << 32, 9))
+                .times(1)
+                .returning(|_fd, _offset, count| Ok(vec![b'y'; count as usize]));
+            // Far from the end, the whole request is passed on untruncated
+            mock.expect_read()
+                .withf(|fd, offset, count| (*fd, *offset, *count) == (7, 0, u32::MAX))
+                .times(1)
+                .returning(|_fd, _offset, _count| Ok(Vec::new()));
+            mock.expect_close().returning(|_fd| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/huge.img"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/huge.img");
+        let (fh, _) = fs.open(request(), &path, 0).unwrap();
+
+        assert_eq!(
+            15,
+            fs.read_handle(fh, u32::MAX as u64 - 5, 4096).unwrap().len()
+        );
+        assert_eq!(9, fs.read_handle(fh, 1 << 32, 4096).unwrap().len());
+        assert_eq!(Ok(Vec::new()), fs.read_handle(fh, 0, u32::MAX));
+        assert_eq!(Ok(Vec::new()), fs.read_handle(fh, SIZE, u32::MAX));
+        assert_eq!(Ok(Vec::new()), fs.read_handle(fh, u64::MAX, u32::MAX));
+        assert!(fs.release(request(), &path, fh, 0, 0, false).is_ok());
+    }
+
+    #[traced_test]
+    #[test]
+    fn getxattr_provenance() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let output = Arc::new(Mutex::new(HashSet::from([Tag::new("colour", true, "red")])));
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(output));
+        let source = Path::new("/fake/source/first.txt");
+        let mut tags = updater.tag_with_provenance(source);
+        tags.insert(Tag::from("plain"), BTreeSet::new());
+        fs.add_file_with_provenance(source, tags);
+
+        let name = OsString::from(PROVENANCE_XATTR);
+        match fs.getxattr(request(), &PathBuf::from("/plain/first.txt"), &name, 4096) {
+            Ok(Xattr::Data(data)) => assert_eq!(b"colour:red\tswitch\nplain\t\n".to_vec(), data),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[traced_test]
+    #[test]
+    fn release_tolerates_closed_fd() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| Ok(zeroed_stat()));
+            mock.expect_close().times(2).returning(|fd| match fd {
+                7 => Err(io::Error::from_raw_os_error(EBADF)),
+                _ => Err(io::Error::from_raw_os_error(EIO)),
+            });
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/present.txt");
+        let (fh, _) = fs.open(request(), &path, 0).unwrap();
+        assert_eq!(Ok(()), fs.release(request(), &path, fh, 0, 0, false));
+        assert!(logs_contain("fd already closed"));
+
+        // Other close errors still surface
+        let fh = fs.insert_handle(OpenFile::new(
+            8,
+            PathBuf::from("/fake/source/present.txt"),
+            0,
+            false,
+        ));
+        assert_eq!(Err(EIO), fs.release(request(), &path, fh, 0, 0, false));
+    }
+
+    #[test]
+    fn source_root_interned() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let root = Path::new("/a/fairly/long/common/source/root/shared/by/every/file");
+        let sources = (0..10_000)
+            .map(|i| root.join(format!("dir{}/file{i}.txt", i % 10)))
+            .chain([PathBuf::from("/elsewhere/outside.txt")])
+            .collect::<Vec<_>>();
+        let build = |root: Option<&Path>| {
+            let mut fs = TagFS::<MockLibcWrapper>::new();
+            if let Some(root) = root {
+                fs.set_source_root(root);
+            }
+            for source in &sources {
+                fs.add_file(source, HashSet::from([Tag::from("tag")]));
+            }
+            fs
+        };
+        let stored_bytes = |fs: &TagFS<MockLibcWrapper>| -> usize {
+            let index = fs.index.read().unwrap();
+            index
+                .files
+                .iter()
+                .map(|entry| entry.source.as_os_str().len())
+                .sum()
+        };
+        let plain = build(None);
+        let interned = build(Some(root));
+
+        assert_eq!(sources, interned.files_for_tags(&[]));
+        assert_eq!(plain.files_for_tags(&[]), interned.files_for_tags(&[]));
+        assert!(stored_bytes(&interned) * 3 < stored_bytes(&plain));
+
+        let path = PathBuf::from("/tag/file7.txt");
+        match interned.lookup(&path) {
+            LookupResult::File(source, 7) => assert_eq!(root.join("dir7/file7.txt"), source),
+            other => panic!("unexpected {other:?}"),
+        }
+        match interned.lookup(&PathBuf::from("/tag/outside.txt")) {
+            LookupResult::File(source, _) => {
+                assert_eq!(PathBuf::from("/elsewhere/outside.txt"), source)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[traced_test]
+    #[test]
+    fn read_cache_serves_repeat_reads() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = 100;
+                Ok(stat)
+            });
+            mock.expect_read()
+                .withf(|fd, offset, count| (*fd, *offset, *count) == (7, 0, 10))
+                .times(2)
+                .returning(|_fd, _offset, count| Ok(vec![b'x'; count as usize]));
+            mock.expect_unlink().returning(|_path| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_read_cache_bytes(1024 * 1024);
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        fs.add_file(
+            &PathBuf::from("/fake/source/other.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/present.txt");
+        let (first, _) = fs.open(request(), &path, 0).unwrap();
+        let (second, _) = fs.open(request(), &path, 0).unwrap();
+
+        assert_eq!(Ok(vec![b'x'; 10]), fs.read_handle(first, 0, 10));
+        assert_eq!(Ok(vec![b'x'; 10]), fs.read_handle(second, 0, 10));
+        assert!(logs_contain("read cache hit"));
+
+        // Unlinking invalidates, so the next read goes to the source again
+        assert!(fs
+            .unlink(request(), Path::new("/tag"), &OsString::from("present.txt"))
+            .is_ok());
+        assert_eq!(Ok(vec![b'x'; 10]), fs.read_handle(first, 0, 10));
+    }
+
+    #[traced_test]
+    #[test]
+    fn sequential_reads_coalesce() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = 100;
+                Ok(stat)
+            });
+            // The first read, then two chunks, then a seek back
+            for (offset, count) in [(0, 8), (8, 32), (40, 32), (0, 8)] {
+                mock.expect_read()
+                    .withf(move |fd, o, c| (*fd, *o, *c) == (7, offset, count))
+                    .times(1)
+                    .returning(|_fd, offset, count| {
+                        Ok((offset..offset + count as i64).map(|b| b as u8).collect())
+                    });
+            }
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_readahead_bytes(32);
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let (fh, _) = fs
+            .open(request(), Path::new("/tag/present.txt"), 0)
+            .unwrap();
+
+        for offset in (0..64).step_by(8) {
+            assert_eq!(
+                Ok((offset..offset + 8).collect::<Vec<u8>>()),
+                fs.read_handle(fh, offset as u64, 8)
+            );
+        }
+        assert!(logs_contain("readahead hit"));
+        assert_eq!(Ok((0..8).collect::<Vec<u8>>()), fs.read_handle(fh, 0, 8));
+    }
+
+    #[traced_test]
+    #[test]
+    fn archive_members_listed_and_read() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = MockLibcWrapper::default();
+            mock.expect_lstat().returning(|_path| {
+                let mut stat = zeroed_stat();
+                stat.st_mode = libc::S_IFREG | 0o644;
+                Ok(stat)
+            });
+            mock.expect_open().never();
+            mock
+        });
+        let archive = env::temp_dir().join("tagfs_archive_members.zip");
+        write_zip(&archive, &[("a.txt", b"alpha"), ("dir/b.txt", b"bravo!")]);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        assert_eq!(2, fs.add_archive_members(&archive).unwrap());
+
+        let dir = PathBuf::from("/archive-member-of:tagfs_archive_members.zip");
+        let names = fs
+            .readdir(request(), &dir, 0)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![".", "..", "a.txt", "b.txt"], names);
+        assert_eq!(
+            vec![archive.join("a.txt"), archive.join("dir/b.txt")],
+            fs.files_for_tags(&[Tag::new(
+                "archive-member-of",
+                false,
+                "tagfs_archive_members.zip"
+            )])
+        );
+
+        let member = dir.join("b.txt");
+        let (_ttl, attr) = fs.getattr(request(), &member, None).unwrap();
+        assert_eq!(6, attr.size);
+        assert_eq!(0o444, attr.perm);
+        let (fh, _) = fs.open(request(), &member, libc::O_RDONLY as u32).unwrap();
+        assert_eq!(Ok(b"bravo!".to_vec()), fs.read_handle(fh, 0, 4096));
+        assert_eq!(Ok(b"avo".to_vec()), fs.read_handle(fh, 2, 3));
+        assert!(fs.release(request(), &member, fh, 0, 0, false).is_ok());
+
+        assert_eq!(Err(EROFS), fs.open(request(), &member, libc::O_RDWR as u32));
+        assert_eq!(
+            Err(EROFS),
+            fs.unlink(request(), &dir, &OsString::from("b.txt"))
+        );
+        fs::remove_file(&archive).unwrap();
+    }
+
+    #[test]
+    fn deterministic_ids_ignore_walk_order() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let files = ["/src/c.txt", "/src/a.txt", "/src/sub/b.txt", "/src/d.txt"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let tags = Provenance::from([
+                    (Tag::from("all"), BTreeSet::new()),
+                    (
+                        Tag::new("parity", true, (i % 2).to_string()),
+                        BTreeSet::new(),
+                    ),
+                ]);
+                (PathBuf::from(source), tags)
+            })
+            .collect::<Vec<_>>();
+        let build = |files: Vec<(PathBuf, Provenance)>| {
+            let mut fs = TagFS::<MockLibcWrapper>::new();
+            fs.set_deterministic_ids(true);
+            fs.add_files(files);
+            fs
+        };
+        let forward = build(files.clone());
+        let reversed = build(files.into_iter().rev().collect());
+
+        let forward_index = forward.index.read().unwrap();
+        let reversed_index = reversed.index.read().unwrap();
+        let sources = |index: &Index| {
+            index
+                .files
+                .iter()
+                .map(|entry| entry.source.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sources(&forward_index), sources(&reversed_index));
+        assert_eq!(forward_index.tags, reversed_index.tags);
+        assert_eq!(
+            vec![
+                PathBuf::from("/src/a.txt"),
+                PathBuf::from("/src/c.txt"),
+                PathBuf::from("/src/d.txt"),
+                PathBuf::from("/src/sub/b.txt"),
+            ],
+            sources(&forward_index)
+        );
+    }
+
+    #[test]
+    fn listing_views() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let build = |listing_view: ListingView| {
+            let mut fs = TagFS::<MockLibcWrapper>::new();
+            fs.set_listing_view(listing_view);
+            fs.add_file(
+                Path::new("/fake/source/first.txt"),
+                HashSet::from([Tag::from("tag1"), Tag::from("tag2")]),
+            );
+            fs.add_file(
+                Path::new("/fake/source/second.txt"),
+                HashSet::from([Tag::from("tag2")]),
+            );
+            fs
+        };
+        let names = |fs: &TagFS<MockLibcWrapper>, path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != ".." && name != INFO_FILE)
+                .collect::<Vec<_>>()
+        };
+
+        let fs = build(ListingView::default());
+        assert_eq!(vec!["tag1", "tag2"], names(&fs, "/"));
+        assert_eq!(vec!["first.txt", "second.txt", "tag1"], names(&fs, "/tag2"));
+
+        let fs = build(ListingView::new(View::Files));
+        assert_eq!(vec!["first.txt", "second.txt"], names(&fs, "/"));
+        assert_eq!(vec!["first.txt", "second.txt", "tag1"], names(&fs, "/tag2"));
+
+        let fs = build(ListingView::new(View::Both));
+        assert_eq!(
+            vec!["first.txt", "second.txt", "tag1", "tag2"],
+            names(&fs, "/")
+        );
+
+        let fs = build(ListingView::default().with_depth(1, View::Tags));
+        assert_eq!(vec!["tag1"], names(&fs, "/tag2"));
+        assert_eq!(vec!["first.txt"], names(&fs, "/tag2/tag1"));
+
+        let fs = build(ListingView::default().with_depth(1, View::Files));
+        assert_eq!(vec!["first.txt", "second.txt"], names(&fs, "/tag2"));
+    }
+
+    #[test]
+    fn max_tag_depth_lists_only_files() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_listing_view(ListingView::default().with_max_tag_depth(1));
+        fs.add_file(
+            Path::new("/fake/source/first.txt"),
+            HashSet::from([Tag::from("tag1"), Tag::from("tag2")]),
+        );
+        fs.add_file(
+            Path::new("/fake/source/second.txt"),
+            HashSet::from([Tag::from("tag2")]),
+        );
+        let entries = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .filter(|entry| entry.name != "." && entry.name != "..")
+                .map(|entry| (entry.kind, entry.name.into_string().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(entries("/")
+            .iter()
+            .any(|(kind, name)| *kind == FileType::Directory && name == "tag2"));
+        assert_eq!(
+            vec![
+                (FileType::RegularFile, "first.txt".to_string()),
+                (FileType::RegularFile, "second.txt".to_string())
+            ],
+            entries("/tag2")
+        );
+        // Deeper paths typed by hand still resolve, but offer no further tags
+        assert_eq!(
+            vec![(FileType::RegularFile, "first.txt".to_string())],
+            entries("/tag2/tag1")
+        );
+    }
+
+    #[traced_test]
+    #[test]
+    fn unlinked_open_file_keeps_working() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = MockLibcWrapper::default();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().withf(|fd| *fd == 7).returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_mode = libc::S_IFREG | 0o644;
+                stat.st_size = 5;
+                stat.st_nlink = 0;
+                Ok(stat)
+            });
+            mock.expect_unlink().times(1).returning(|_path| Ok(()));
+            // Only when the file is added, to cache its size and mtime
+            mock.expect_lstat()
+                .times(1)
+                .returning(|_path| Err(std::io::Error::from_raw_os_error(ENOENT)));
+            mock.expect_read()
+                .withf(|fd, offset, count| (*fd, *offset, *count) == (7, 0, 5))
+                .returning(|_fd, _offset, _count| Ok(b"hello".to_vec()));
+            mock.expect_close().returning(|_fd| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/present.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/present.txt");
+        let (fh, _) = fs.open(request(), &path, 0).unwrap();
+        assert!(fs
+            .unlink(request(), Path::new("/tag"), &OsString::from("present.txt"))
+            .is_ok());
+
+        // The path is gone, but the open handle is still served via fstat
+        assert_eq!(ENOENT, fs.getattr(request(), &path, None).unwrap_err());
+        let (_ttl, attr) = fs.getattr(request(), &path, Some(fh)).unwrap();
+        assert_eq!(5, attr.size);
+        assert_eq!(0, attr.nlink);
+        assert_eq!(Ok(b"hello".to_vec()), fs.read_handle(fh, 0, 4096));
+        assert!(fs.release(request(), &path, fh, 0, 0, false).is_ok());
+    }
+
+    #[test]
+    fn recent_holds_newest() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let dir = env::temp_dir().join("tagfs_recent_holds_newest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let sources = (0..5)
+            .map(|i| {
+                let source = dir.join(format!("file{i}.txt"));
+                let file = fs::File::create(&source).unwrap();
+                file.set_modified(epoch + Duration::from_secs(i * 60))
+                    .unwrap();
+                source
             })
-            .unwrap_or_default()
-    };
+            .collect::<Vec<_>>();
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_recent_limit(3);
+        for source in &sources {
+            fs.add_file(source, HashSet::from([Tag::from("all")]));
+        }
+        fs.refresh_recent();
 
-    debug!(?file_ids, ?root_tags, ?root, "residue");
+        let recent = OsString::from(RECENT_TAG);
+        assert_eq!(sources[2..].to_vec(), fs.query(&[&recent]));
 
-    // TODO Deal with name collision between 2x tags NOT in root, one intrinsic, one extrinsic
+        // Touching an old file moves it into the set on retag
+        fs::File::options()
+            .write(true)
+            .open(&sources[0])
+            .unwrap()
+            .set_modified(epoch + Duration::from_secs(3600))
+            .unwrap();
+        fs.retag(&sources[0], &FileUpdater::new());
+        assert_eq!(
+            vec![sources[0].clone(), sources[3].clone(), sources[4].clone()],
+            fs.query(&[&recent])
+        );
 
-    let mut singleton_labels = HashSet::new();
-    for tag in tags.keys() {
-        debug!(?tag, ?root_tags, "detect singletons");
-        if root_tags.contains(tag.as_os_str()) && tag.is_singleton() {
-            singleton_labels.insert(tag.label());
+        // Marked recent by hand, so kept when no longer among the newest
+        fs.setxattr(
+            request(),
+            &Path::new("/recent").join(sources[3].file_name().unwrap()),
+            OsStr::new(TAG_CONTROL_XATTR),
+            b"+recent",
+            0,
+            0,
+        )
+        .unwrap();
+        fs.set_recent_limit(1);
+        fs.refresh_recent();
+        assert_eq!(
+            vec![sources[0].clone(), sources[3].clone()],
+            fs.query(&[&recent])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_cache_stat() {
+        let source = env::temp_dir().join("tagfs_entries_cache_stat.txt");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        fs::write(&source, "twelve bytes").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        fs.add_file(&source, HashSet::from([Tag::from("all")]));
+        fs.add_file(
+            Path::new("/fake/source/missing.txt"),
+            HashSet::from([Tag::from("all")]),
+        );
+
+        let metadata = fs::metadata(&source).unwrap();
+        {
+            let index = fs.index.read().unwrap();
+            assert_eq!(Some(metadata.len()), index.files[0].size);
+            assert_eq!(Some(metadata.modified().unwrap()), index.files[0].mtime);
+            assert_eq!(Some(mtime), index.files[0].mtime);
+            assert_eq!((None, None), (index.files[1].size, index.files[1].mtime));
         }
+
+        // Retagging picks up changes
+        fs::write(&source, "now eighteen bytes").unwrap();
+        fs.retag(&source, &FileUpdater::new());
+        assert_eq!(Some(18), fs.index.read().unwrap().files[0].size);
+        fs::remove_file(&source).unwrap();
     }
 
-    tags.iter()
-        // Filter out tags already in path
-        .filter(move |(t, _)| {
-            debug!(?t, ?root_tags, "visited filter tag");
-            !root_tags.contains(t.as_os_str())
-        })
-        // Filter out already seen filter tags
-        .filter(move |(t, _)| {
-            debug!(?t, ?singleton_labels, "singleton filter tag");
-            !t.is_singleton() || !singleton_labels.contains(t.label())
-        })
-        // Remaining tags become directory entries
-        .map(|(t, _)| (FileType::Directory, t.as_os_str()))
-        .chain(
-            // File ids become Regular File entries
-            file_ids
+    #[test]
+    fn fold_accents_resolves_tags() {
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        fs.add_file(
+            Path::new("/fake/source/menu.txt"),
+            HashSet::from([Tag::from("café"), Tag::new("place", true, "Zürich")]),
+        );
+        let is_dir = |fs: &TagFS<MockLibcWrapper>, path: &str| {
+            matches!(fs.lookup(Path::new(path)), LookupResult::Directory)
+        };
+        assert!(is_dir(&fs, "/café"));
+        assert!(!is_dir(&fs, "/cafe"));
+
+        fs.set_fold_accents(true);
+        for path in ["/cafe", "/CAFÉ", "/place:zurich/cafe"] {
+            assert!(is_dir(&fs, path), "{path}");
+        }
+        assert!(matches!(
+            fs.lookup(Path::new("/cafe/menu.txt")),
+            LookupResult::File(..)
+        ));
+        // Listings keep the names as tagged
+        assert_eq!(
+            vec![OsString::from("menu.txt"), "place:Zürich".into()],
+            fs.list_directory(Path::new("/cafe"))
                 .into_iter()
-                .filter(move |file_id| !is_deleted(*file_id))
-                .filter_map(|file_id| files.get(file_id))
-                .filter_map(|file| file.source.file_name())
-                .unique()
-                .map(|file_name| (FileType::RegularFile, file_name)),
-        )
-}
+                .map(|entry| entry.name)
+                .filter(|name| name != "." && name != "..")
+                .collect::<Vec<_>>()
+        );
 
-#[cfg(test)]
-mod test {
-    use std::{
-        collections::{HashMap, HashSet},
-        ffi::OsString,
-        path::PathBuf,
-        sync::Mutex,
-    };
+        // File names aren't folded, nor hidden by tags that match once folded
+        fs.add_file(
+            Path::new("/fake/source/cafe"),
+            HashSet::from([Tag::from("café")]),
+        );
+        assert!(matches!(
+            fs.lookup(Path::new("/café/cafe")),
+            LookupResult::File(source, _) if source == Path::new("/fake/source/cafe")
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/café/Menu.txt")),
+            LookupResult::Missing
+        ));
 
-    use fuse_mt::{FilesystemMT as _, RequestInfo};
-    use libc::{ENOENT, EPERM};
-    use tracing_test::traced_test;
+        fs.set_flatten_singletons(true);
+        assert!(is_dir(&fs, "/place/zurich/cafe"));
+    }
 
-    use crate::{
-        filesystem::{
-            libc_wrappers::MockLibcWrapper,
-            tagfs::{get_children, TagFS},
-        },
-        tagger::{Tag, TAG_SEPARATOR},
-    };
+    #[test]
+    fn latest_per_folder() {
+        let dir = env::temp_dir().join("tagfs_latest_per_folder");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        for (name, folder, minutes) in [
+            ("old-work.txt", Some("work"), 0),
+            ("new-work.txt", Some("work"), 20),
+            ("mid-work.txt", Some("work"), 10),
+            ("new-home.txt", Some("home"), 5),
+            ("old-home.txt", Some("home"), 1),
+            ("loose.txt", None, 30),
+        ] {
+            let source = dir.join(name);
+            fs::File::create(&source)
+                .unwrap()
+                .set_modified(epoch + Duration::from_secs(minutes * 60))
+                .unwrap();
+            let mut tags = HashSet::from([Tag::from("all")]);
+            tags.extend(folder.map(|folder| Tag::new("folder", true, folder)));
+            fs.add_file(&source, tags);
+        }
+
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .filter(|entry| entry.kind == FileType::RegularFile)
+                .map(|entry| entry.name)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let newest = vec![OsString::from("new-home.txt"), "new-work.txt".into()];
+        assert_eq!(newest, names("/all/+latest:folder"));
+        assert_eq!(newest, names("/+latest:folder"));
+        assert_eq!(
+            vec![OsString::from("new-work.txt")],
+            names("/folder:work/+latest:folder")
+        );
+        assert!(matches!(
+            fs.lookup(Path::new("/all/+latest:folder/new-work.txt")),
+            LookupResult::File(..)
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/all/+latest:folder/old-work.txt")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/+latest:nolabel")),
+            LookupResult::Missing
+        ));
 
-    use super::Entry;
+        // Lookups follow a newer file being retagged into the folder
+        let mid = dir.join("mid-work.txt");
+        fs::File::options()
+            .write(true)
+            .open(&mid)
+            .unwrap()
+            .set_modified(epoch + Duration::from_secs(3600))
+            .unwrap();
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(Arc::new(Mutex::new(HashSet::from([
+            Tag::from("all"),
+            Tag::new("folder", true, "work"),
+        ])))));
+        fs.retag(&mid, &updater);
+        assert!(matches!(
+            fs.lookup(Path::new("/all/+latest:folder/new-work.txt")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/all/+latest:folder/mid-work.txt")),
+            LookupResult::File(..)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
-    // Mutex to ensure only one test at a time is accessing the global context for construction
-    static MTX: Mutex<()> = Mutex::new(());
+    #[test]
+    fn opendir_distinguishes_files() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/a.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+
+        let (fh, _) = fs.opendir(request(), Path::new("/tag"), 0).unwrap();
+        assert!(fs.releasedir(request(), Path::new("/tag"), fh, 0).is_ok());
+        assert_eq!(
+            Err(ENOTDIR),
+            fs.opendir(request(), Path::new("/tag/a.txt"), 0)
+        );
+        assert_eq!(
+            Err(ENOTDIR),
+            fs.opendir(request(), &Path::new("/").join(INFO_FILE), 0)
+        );
+        assert_eq!(
+            Err(ENOENT),
+            fs.opendir(request(), Path::new("/tag/missing.txt"), 0)
+        );
+        assert_eq!(Err(ENOENT), fs.opendir(request(), Path::new("/nope"), 0));
+    }
+
+    #[test]
+    fn concurrent_reads_during_retag() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let fs = TagFS::<MockLibcWrapper>::new();
+        let dir = env::temp_dir().join("tagfs_concurrent_reads_during_retag");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let sources = (0..4)
+            .map(|i| {
+                let source = dir.join(format!("file{i}.txt"));
+                fs::write(&source, "content").unwrap();
+                source
+            })
+            .collect::<Vec<_>>();
+        let output = Arc::new(Mutex::new(HashSet::from([Tag::from("red")])));
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(output.clone()));
+        for source in &sources {
+            fs.retag(source, &updater);
+        }
+
+        let writing = std::sync::atomic::AtomicBool::new(true);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while writing.load(std::sync::atomic::Ordering::Relaxed) {
+                        // Every file carries one colour in any single snapshot
+                        let root = fs.list_directory(Path::new("/"));
+                        assert!(root
+                            .iter()
+                            .any(|entry| entry.name == "red" || entry.name == "blue"));
+                        let _ = fs.lookup(Path::new("/red/file0.txt"));
+                        let _ = fs.query(&[OsStr::new("blue")]);
+                        let _ = fs.readdir(request(), Path::new("/red"), 0);
+                    }
+                });
+            }
+            scope.spawn(|| {
+                let _writing = ClearOnDrop(&writing);
+                // Taggers needn't be Send, so the writer builds its own
+                let mut updater = FileUpdater::new();
+                updater.add_tagger(SwitchTagger(output.clone()));
+                for round in 0..200 {
+                    let colour = if round % 2 == 0 { "blue" } else { "red" };
+                    *output.lock().unwrap() = HashSet::from([Tag::from(colour)]);
+                    for source in &sources {
+                        fs.retag(source, &updater);
+                    }
+                    fs.delete_file(round % sources.len());
+                }
+            });
+        });
+
+        // The final retag of each file undid any earlier deletion bar the last
+        assert_eq!(3, fs.query(&[OsStr::new("red")]).len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_swap_is_atomic() {
+        let sources = (0..4)
+            .map(|i| PathBuf::from(format!("/fake/source/file{i}.txt")))
+            .collect::<Vec<_>>();
+        let rebuilt = |colour: &str, count: usize| {
+            let mut fs = TagFS::with_libc_wrapper(mock());
+            for source in &sources[..count] {
+                fs.add_file(source, HashSet::from([Tag::from(colour)]));
+            }
+            fs
+        };
+        let fs = rebuilt("red", 4);
+        let handle = fs.index_handle();
+
+        let swapping = std::sync::atomic::AtomicBool::new(true);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while swapping.load(std::sync::atomic::Ordering::Relaxed) {
+                        // Each listing comes wholly from one index or the other
+                        let root = fs
+                            .list_directory(Path::new("/"))
+                            .into_iter()
+                            .map(|entry| entry.name)
+                            .filter(|name| name == "red" || name == "blue")
+                            .collect::<Vec<_>>();
+                        assert!(root == ["red"] || root == ["blue"], "{root:?}");
+                        let red = fs.query(&[OsStr::new("red")]).len();
+                        assert!(red == 0 || red == 4, "{red} red files");
+                    }
+                });
+            }
+            scope.spawn(|| {
+                let _swapping = ClearOnDrop(&swapping);
+                for round in 0..200 {
+                    let colour = if round % 2 == 0 { "blue" } else { "red" };
+                    handle.swap_from(rebuilt(colour, 4));
+                }
+            });
+        });
+
+        handle.swap_from(rebuilt("blue", 3));
+        assert_eq!(3, fs.query(&[OsStr::new("blue")]).len());
+        assert_eq!(3, fs.metrics().index_files());
+        assert!(matches!(
+            fs.lookup(Path::new("/blue/file2.txt")),
+            LookupResult::File(_, 2)
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/blue/file3.txt")),
+            LookupResult::Missing
+        ));
+    }
+
+    #[test]
+    fn generations_tag_new_files() {
+        let source = |name: &str| PathBuf::from(format!("/fake/source/{name}.txt"));
+        let tagged = || HashSet::from([Tag::from("tag")]);
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        fs.add_file(&source("first"), tagged());
+        // A single generation isn't worth tagging
+        assert!(fs.query(&[OsStr::new("generation:1")]).is_empty());
+
+        assert_eq!(2, fs.next_generation());
+        fs.add_file(&source("second"), tagged());
+        let query = |tag: &str| fs.query(&[OsStr::new(tag)]);
+        assert_eq!(vec![source("first")], query("generation:1"));
+        assert_eq!(vec![source("second")], query("generation:2"));
+        assert_eq!(vec![source("second")], query(NEW_TAG));
+
+        fs.next_generation();
+        assert!(query(NEW_TAG).is_empty());
+
+        // A rebuild is a generation of its own, keeping known files' ones
+        let mut rebuilt = TagFS::with_libc_wrapper(mock());
+        rebuilt.add_file(&source("first"), tagged());
+        rebuilt.add_file(&source("fourth"), tagged());
+        fs.index_handle().swap_from(rebuilt);
+        assert_eq!(vec![source("first")], query("generation:1"));
+        assert!(query("generation:2").is_empty());
+        assert_eq!(vec![source("fourth")], query("generation:4"));
+        assert_eq!(vec![source("fourth")], query(NEW_TAG));
+    }
+
+    #[test]
+    fn truncate_resizes_open_files() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open().returning(|_path, _flags| Ok(7));
+            mock.expect_fstat().returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = 10;
+                Ok(stat)
+            });
+            // Growing preallocates just the new tail
+            mock.expect_fallocate()
+                .withf(|fd, offset, len, mode| (*fd, *offset, *len, *mode) == (7, 10, 4990, 0))
+                .times(1)
+                .returning(|_fd, _offset, _len, _mode| Ok(()));
+            mock.expect_ftruncate()
+                .withf(|fd, len| (*fd, *len) == (7, 4))
+                .times(1)
+                .returning(|_fd, _len| Ok(()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            &PathBuf::from("/fake/source/data.db"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = PathBuf::from("/tag/data.db");
+        let (fh, _) = fs.open(request(), &path, libc::O_RDWR as u32).unwrap();
+        let size = |fs: &TagFS<_>| fs.with_handle(fh, |open_file| open_file.size);
+        assert_eq!(Ok(()), fs.truncate(request(), &path, Some(fh), 5000));
+        // Reads see the new size
+        assert_eq!(Some(5000), size(&fs));
+        assert_eq!(Ok(()), fs.truncate(request(), &path, Some(fh), 5000));
+        assert_eq!(Ok(()), fs.truncate(request(), &path, Some(fh), 4));
+        assert_eq!(Some(4), size(&fs));
+
+        assert_eq!(
+            Err(EISDIR),
+            fs.truncate(request(), Path::new("/tag"), Some(fh), 0)
+        );
+        assert_eq!(
+            Err(ENOENT),
+            fs.truncate(request(), Path::new("/tag/missing.db"), Some(fh), 0)
+        );
+        assert_eq!(Err(EBADF), fs.truncate(request(), &path, Some(fh + 100), 0));
+        assert_eq!(Err(ENOSYS), fs.truncate(request(), &path, None, 6000));
+    }
+
+    #[test]
+    fn flattened_singletons() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_flatten_singletons(true);
+        fs.add_file(
+            Path::new("/fake/source/a.c"),
+            HashSet::from([Tag::new("mime", true, "text|x-c"), Tag::from("src")]),
+        );
+        fs.add_file(
+            Path::new("/fake/source/b.txt"),
+            HashSet::from([Tag::new("mime", true, "text|plain"), Tag::from("src")]),
+        );
+        fs.add_file(
+            Path::new("/fake/source/c.txt"),
+            HashSet::from([Tag::new("mime", true, "text|plain")]),
+        );
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != ".." && name != INFO_FILE)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["mime", "src"], names("/"));
+        assert_eq!(vec!["text|plain", "text|x-c"], names("/mime"));
+        assert_eq!(vec!["text|plain", "text|x-c"], names("/src/mime"));
+        assert!(matches!(
+            fs.lookup(Path::new("/mime")),
+            LookupResult::Directory
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/mime/text|x-c")),
+            LookupResult::Directory
+        ));
+        assert_eq!(vec!["a.c", "src"], names("/mime/text|x-c"));
+        assert_eq!(vec!["a.c"], names("/mime/text|x-c/src"));
+        assert!(matches!(
+            fs.lookup(Path::new("/mime/text|x-c/a.c")),
+            LookupResult::File(_, 0)
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/mime/nope")),
+            LookupResult::Missing
+        ));
+        // A label only names a directory under other tags
+        assert!(matches!(
+            fs.lookup(Path::new("/nonexistent/mime")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/src/nonexistent/mime")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/mime/text|x-c/a.c/mime")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/src/mime")),
+            LookupResult::Directory
+        ));
+        // Only values of files under the path are offered
+        fs.delete_file(0);
+        assert_eq!(vec!["text|plain"], names("/src/mime"));
+    }
 
     #[traced_test]
     #[test]
-    fn get_children_root() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        tags.insert(Tag::from("tag2"), HashSet::new());
-        tags.insert(Tag::from("tag3"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn readdir_skips_unlistable_entries() {
+        let _m = MTX.lock();
 
-        let children = get_children(&PathBuf::from("/"), &tags, &files, |_| false);
-        assert_eq!(3, children.count());
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            Path::new("/fake/source/a.txt"),
+            HashSet::from([
+                Tag::from("good"),
+                Tag::from("bad/tag"),
+                Tag::from("nul\0tag"),
+                Tag::from(".."),
+            ]),
+        );
+        let names = fs
+            .readdir(request(), Path::new("/"), 0)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![".", "..", INFO_FILE, "good"], names);
+        assert!(logs_contain("skipping unlistable entry"));
+    }
+
+    #[test]
+    fn root_labels_restrict_root() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_root_labels(["category", "starred"]);
+        fs.add_file(
+            Path::new("/fake/source/a.txt"),
+            HashSet::from([
+                Tag::new("category", false, "docs"),
+                Tag::new("folder", false, "work"),
+                Tag::new("mime", true, "text|plain"),
+                Tag::from("starred"),
+                Tag::from("draft"),
+            ]),
+        );
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != ".." && name != INFO_FILE)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["category:docs", "starred"], names("/"));
+        assert_eq!(
+            vec![
+                "a.txt",
+                "draft",
+                "folder:work",
+                "mime:text|plain",
+                "starred"
+            ],
+            names("/category:docs")
+        );
+        // Hidden tags can still be entered by name
+        assert_eq!(
+            vec![
+                "a.txt",
+                "category:docs",
+                "draft",
+                "mime:text|plain",
+                "starred"
+            ],
+            names("/folder:work")
+        );
+    }
+
+    #[test]
+    fn all_dir_lists_every_file() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.set_all_dir("everything");
+        fs.add_file(
+            Path::new("/fake/source/a.txt"),
+            HashSet::from([Tag::from("red")]),
+        );
+        fs.add_file(
+            Path::new("/fake/source/b.txt"),
+            HashSet::from([Tag::from("blue")]),
+        );
+        fs.add_file(Path::new("/fake/source/untagged.txt"), HashSet::new());
+        fs.add_file(
+            Path::new("/fake/source/everything"),
+            HashSet::from([Tag::from("everything")]),
+        );
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != ".." && name != INFO_FILE)
+                .collect::<Vec<_>>()
+        };
+
+        // The tag of the same name is shadowed
+        assert_eq!(vec!["blue", "everything", "red"], names("/"));
+        assert_eq!(
+            vec!["a.txt", "b.txt", "everything", "untagged.txt"],
+            names("/everything")
+        );
+        assert!(matches!(
+            fs.lookup(Path::new("/everything")),
+            LookupResult::Directory
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/everything/untagged.txt")),
+            LookupResult::File(_, 2)
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/everything/missing.txt")),
+            LookupResult::Missing
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/everything/a.txt/b.txt")),
+            LookupResult::Missing
+        ));
+        fs.delete_file(0);
+        assert_eq!(
+            vec!["b.txt", "everything", "untagged.txt"],
+            names("/everything")
+        );
+
+        fs.set_all_dir("");
+        assert!(matches!(
+            fs.lookup(Path::new("/everything/untagged.txt")),
+            LookupResult::Missing
+        ));
+    }
+
+    #[test]
+    fn size_outliers_per_directory() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let dir = env::temp_dir().join("tagfs_size_outliers_per_directory");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("few")).unwrap();
+        let mut sources = Vec::new();
+        for i in 0..12 {
+            let source = dir.join(format!("similar{i}.txt"));
+            fs::write(&source, vec![b'x'; 100 + i]).unwrap();
+            sources.push(source);
+        }
+        let huge = dir.join("huge.bin");
+        fs::write(&huge, vec![0u8; 100_000]).unwrap();
+        sources.push(huge.clone());
+        // Too few files to call any an outlier
+        for (i, size) in [10, 12, 100_000].into_iter().enumerate() {
+            let source = dir.join("few").join(format!("file{i}"));
+            fs::write(&source, vec![b'x'; size]).unwrap();
+            sources.push(source);
+        }
+        // One file is tagged an outlier by its tagger too
+        fs.add_file(&sources[0], HashSet::from([Tag::from(SIZE_OUTLIER_TAG)]));
+        for source in &sources[1..] {
+            fs.add_file(source, HashSet::new());
+        }
+
+        fs.refresh_size_outliers();
+        let outlier = OsString::from(SIZE_OUTLIER_TAG);
+        assert_eq!(vec![sources[0].clone(), huge], fs.query(&[&outlier]));
+
+        fs.delete_file(12);
+        fs.refresh_size_outliers();
+        assert_eq!(vec![sources[0].clone()], fs.query(&[&outlier]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chmod_chown_pass_through() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_chmod()
+                .withf(|path, mode| (path, *mode) == (Path::new("/fake/source/a.sh"), 0o755))
+                .times(1)
+                .returning(|_path, _mode| Ok(()));
+            mock.expect_chown()
+                .withf(|path, uid, gid| {
+                    (path, *uid, *gid) == (Path::new("/fake/source/a.sh"), Some(1000), None)
+                })
+                .times(1)
+                .returning(|_path, _uid, _gid| Ok(()));
+            mock.expect_chown()
+                .withf(|path, uid, gid| {
+                    (path, *uid, *gid) == (Path::new("/fake/source/a.sh"), None, Some(100))
+                })
+                .times(1)
+                .returning(|_path, _uid, _gid| Err(io::Error::from_raw_os_error(EPERM)));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            Path::new("/fake/source/a.sh"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = Path::new("/tag/a.sh");
+
+        assert_eq!(Ok(()), fs.chmod(request(), path, None, 0o755));
+        assert_eq!(Ok(()), fs.chown(request(), path, None, Some(1000), None));
+        assert_eq!(Err(EPERM), fs.chown(request(), path, None, None, Some(100)));
+        assert_eq!(
+            Err(EPERM),
+            fs.chmod(request(), Path::new("/tag"), None, 0o700)
+        );
+        assert_eq!(
+            Err(EPERM),
+            fs.chown(request(), Path::new("/"), None, Some(0), Some(0))
+        );
+        assert_eq!(
+            Err(ENOENT),
+            fs.chmod(request(), Path::new("/tag/missing.sh"), None, 0o755)
+        );
     }
 
     #[traced_test]
     #[test]
-    fn get_children_tag2() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        tags.insert(Tag::from("tag2"), HashSet::new());
-        tags.insert(Tag::from("tag3"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn audit_events_per_tag() {
+        let _m = MTX.lock();
+
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            Path::new("/fake/source/quiet.txt"),
+            HashSet::from([Tag::from("hushed")]),
+        );
+        assert!(!logs_contain("tag assigned"));
+
+        fs.set_audit_tags(true);
+        fs.add_file_with_provenance(
+            Path::new("/fake/source/a.txt"),
+            Provenance::from([
+                (
+                    Tag::new("mime", true, "text|plain"),
+                    BTreeSet::from(["mime".to_string()]),
+                ),
+                (
+                    Tag::from("src"),
+                    BTreeSet::from(["rules".to_string(), "kind".to_string()]),
+                ),
+            ]),
+        );
+        logs_assert(|lines| {
+            let events = lines
+                .iter()
+                .filter(|line| line.contains("tag assigned"))
+                .collect::<Vec<_>>();
+            let expected = [
+                "file=/fake/source/a.txt label=mime value=text|plain tagger=\"mime\"",
+                "file=/fake/source/a.txt label= value=src tagger=\"kind\"",
+                "file=/fake/source/a.txt label= value=src tagger=\"rules\"",
+            ];
+            match events.len() == expected.len()
+                && expected
+                    .iter()
+                    .all(|fields| events.iter().any(|event| event.contains(fields)))
+            {
+                true => Ok(()),
+                false => Err(format!("unexpected audit events {events:?}")),
+            }
+        });
+    }
 
-        let children = get_children(&PathBuf::from("/tag2"), &tags, &files, |_| false);
-        assert_eq!(2, children.count());
+    #[test]
+    fn in_memory_source_read() {
+        let libc_wrapper = InMemoryLibcWrapper::new();
+        libc_wrapper.insert("/mem/notes.txt", "remember the milk");
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        fs.add_file(
+            Path::new("/mem/notes.txt"),
+            HashSet::from([Tag::from("todo")]),
+        );
+        let path = Path::new("/todo/notes.txt");
+
+        let (_ttl, attr) = fs.getattr(request(), path, None).unwrap();
+        assert_eq!(FileType::RegularFile, attr.kind);
+        assert_eq!(17, attr.size);
+        let (fh, _) = fs.open(request(), path, libc::O_RDONLY as u32).unwrap();
+        assert_eq!(Ok(b"the milk".to_vec()), fs.read_handle(fh, 9, 4096));
+        assert!(fs.release(request(), path, fh, 0, 0, false).is_ok());
+
+        assert_eq!(
+            Ok(()),
+            fs.unlink(request(), Path::new("/todo"), OsStr::new("notes.txt"))
+        );
+        assert_eq!(Err(ENOENT), fs.getattr(request(), path, None).map(|_| ()));
     }
 
-    #[traced_test]
     #[test]
-    fn get_children_tag2_tag1() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        tags.insert(Tag::from("tag2"), HashSet::new());
-        tags.insert(Tag::from("tag3"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn append_handle_ignores_offset() {
+        let _m = MTX.lock();
 
-        let children = get_children(&PathBuf::from("/tag2/tag1"), &tags, &files, |_| false);
-        assert_eq!(1, children.count());
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(|| {
+            let mut mock = mock();
+            mock.expect_open()
+                .returning(|_path, flags| match flags & libc::O_APPEND {
+                    0 => Ok(7),
+                    _ => Ok(8),
+                });
+            mock.expect_fstat().returning(|_fd| {
+                let mut stat = zeroed_stat();
+                stat.st_size = 10;
+                Ok(stat)
+            });
+            mock.expect_write()
+                .withf(|fd, data| (*fd, data) == (8, b"tail".as_slice()))
+                .times(2)
+                .returning(|_fd, data| Ok(data.len()));
+            mock.expect_pwrite()
+                .withf(|fd, offset, data| (*fd, *offset, data) == (7, 2, b"mid".as_slice()))
+                .times(1)
+                .returning(|_fd, _offset, data| Ok(data.len()));
+            mock
+        });
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        fs.add_file(
+            Path::new("/fake/source/log.txt"),
+            HashSet::from([Tag::from("tag")]),
+        );
+        let path = Path::new("/tag/log.txt");
+        let flags = (libc::O_WRONLY | libc::O_APPEND) as u32;
+        let (append_fh, _) = fs.open(request(), path, flags).unwrap();
+        let (fh, _) = fs.open(request(), path, libc::O_WRONLY as u32).unwrap();
+
+        assert_eq!(
+            Ok(4),
+            fs.write(request(), path, append_fh, 0, b"tail".to_vec(), 0)
+        );
+        assert_eq!(
+            Ok(4),
+            fs.write(request(), path, append_fh, 3, b"tail".to_vec(), 0)
+        );
+        assert_eq!(
+            Some(18),
+            fs.with_handle(append_fh, |open_file| open_file.size)
+        );
+        assert_eq!(Ok(3), fs.write(request(), path, fh, 2, b"mid".to_vec(), 0));
+        assert_eq!(Some(10), fs.with_handle(fh, |open_file| open_file.size));
     }
 
-    #[traced_test]
     #[test]
-    fn get_children_singleton_root() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::new("singleton", true, "v1"), HashSet::new());
-        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn append_writes_at_eof_in_memory() {
+        let libc_wrapper = InMemoryLibcWrapper::new();
+        libc_wrapper.insert("/mem/log.txt", "one\n");
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        fs.add_file(Path::new("/mem/log.txt"), HashSet::from([Tag::from("log")]));
+        let path = Path::new("/log/log.txt");
+        let flags = (libc::O_RDWR | libc::O_APPEND) as u32;
+        let (fh, _) = fs.open(request(), path, flags).unwrap();
 
-        let children =
-            get_children(&PathBuf::from("/"), &tags, &files, |_| false).collect::<HashSet<_>>();
-        // Root shows all tags, no files
-        assert_eq!(3, children.len());
-        assert!(children.contains(&(
-            fuse_mt::FileType::Directory,
-            &OsString::from("singleton".to_owned() + TAG_SEPARATOR + "v1")
-        )));
-        assert!(children.contains(&(
-            fuse_mt::FileType::Directory,
-            &OsString::from("singleton".to_owned() + TAG_SEPARATOR + "v2")
-        )));
-        assert!(children.contains(&(fuse_mt::FileType::Directory, &OsString::from("tag1"))));
+        assert_eq!(
+            Ok(4),
+            fs.write(request(), path, fh, 0, b"two\n".to_vec(), 0)
+        );
+        assert_eq!(
+            Ok(6),
+            fs.write(request(), path, fh, 1, b"three\n".to_vec(), 0)
+        );
+        assert_eq!(
+            Ok(b"one\ntwo\nthree\n".to_vec()),
+            fs.read_handle(fh, 0, 4096)
+        );
+        assert!(fs.release(request(), path, fh, 0, 0, false).is_ok());
     }
 
     #[traced_test]
     #[test]
-    fn get_children_singleton_child_file() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
-        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn setxattr_adds_and_removes_tags() {
+        let _m = MTX.lock();
 
-        let children = get_children(
-            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v1"),
-            &tags,
-            &files,
-            |_| false,
-        )
-        .collect::<HashSet<_>>();
-        // Inner shows only non-singleton collisions, and related files
-        assert_eq!(2, children.len());
-        assert!(children.contains(&(fuse_mt::FileType::Directory, &OsString::from("tag1"))));
-        assert!(children.contains(&(fuse_mt::FileType::RegularFile, &OsString::from("file1.txt"))));
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let source = Path::new("/fake/source/first.txt");
+        fs.add_file(
+            source,
+            HashSet::from([Tag::new("colour", true, "red"), Tag::from("plain")]),
+        );
+
+        let name = OsString::from(TAG_CONTROL_XATTR);
+        let file = PathBuf::from("/plain/first.txt");
+        fs.setxattr(request(), &file, &name, b"+reviewed\n-plain\n", 0, 0)
+            .unwrap();
+        let reviewed = OsString::from("reviewed");
+        let plain = OsString::from("plain");
+        assert_eq!(vec![source.to_path_buf()], fs.query(&[&reviewed]));
+        assert!(fs.query(&[&plain]).is_empty());
+
+        // Singleton labels stay single-valued
+        let file = PathBuf::from("/reviewed/first.txt");
+        fs.setxattr(request(), &file, &name, b"+colour:blue", 0, 0)
+            .unwrap();
+        let red = OsString::from("colour:red");
+        let blue = OsString::from("colour:blue");
+        assert!(fs.query(&[&red]).is_empty());
+        assert_eq!(vec![source.to_path_buf()], fs.query(&[&blue]));
+        match fs.getxattr(request(), &file, &OsString::from(PROVENANCE_XATTR), 4096) {
+            Ok(Xattr::Data(data)) => {
+                assert_eq!(b"colour:blue\tmanual\nreviewed\tmanual\n".to_vec(), data)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        for (value, errno) in [
+            (&b"reviewed"[..], EINVAL),
+            (b"+", EINVAL),
+            (b"+a/b", EINVAL),
+            (b"", EINVAL),
+            (b"+ok\n*bad", EINVAL),
+        ] {
+            assert_eq!(
+                Err(errno),
+                fs.setxattr(request(), &file, &name, value, 0, 0)
+            );
+        }
+        // A rejected batch changes nothing
+        let ok = OsString::from("ok");
+        assert!(fs.query(&[&ok]).is_empty());
+        assert_eq!(
+            Err(ENOTSUP),
+            fs.setxattr(request(), &file, &OsString::from("user.other"), b"+x", 0, 0)
+        );
+        assert_eq!(
+            Err(EPERM),
+            fs.setxattr(request(), &PathBuf::from("/reviewed"), &name, b"+x", 0, 0)
+        );
+        assert_eq!(
+            Err(ENOENT),
+            fs.setxattr(
+                request(),
+                &PathBuf::from("/reviewed/nope"),
+                &name,
+                b"+x",
+                0,
+                0
+            )
+        );
     }
 
-    #[traced_test]
     #[test]
-    fn get_children_singleton_child_file_deleted() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
-        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn metrics_count_operations() {
+        let libc_wrapper = InMemoryLibcWrapper::new();
+        libc_wrapper.insert("/mem/notes.txt", "remember the milk");
+        libc_wrapper.insert("/mem/list.txt", "eggs");
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        fs.add_file(
+            Path::new("/mem/notes.txt"),
+            HashSet::from([Tag::from("todo")]),
+        );
+        fs.add_file(
+            Path::new("/mem/list.txt"),
+            HashSet::from([Tag::from("todo")]),
+        );
+        let metrics = fs.metrics();
+        assert_eq!(2, metrics.index_files());
 
-        let children = get_children(
-            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v1"),
-            &tags,
-            &files,
-            |file_id| file_id == 0,
-        )
-        .collect::<HashSet<_>>();
-        // Inner shows only non-singleton collisions, and related files
-        assert_eq!(1, children.len());
-        assert!(children.contains(&(fuse_mt::FileType::Directory, &OsString::from("tag1"))));
+        let path = Path::new("/todo/notes.txt");
+        fs.getattr(request(), path, None).unwrap();
+        fs.getattr(request(), Path::new("/todo"), None).unwrap();
+        fs.readdir(request(), Path::new("/todo"), 0).unwrap();
+        let (fh, _) = fs.open(request(), path, libc::O_RDONLY as u32).unwrap();
+        fs.read_handle(fh, 0, 8).unwrap();
+        fs.read_handle(fh, 9, 4096).unwrap();
+        assert_eq!(Err(EBADF), fs.read_handle(fh + 100, 0, 4096));
+        fs.unlink(request(), Path::new("/todo"), OsStr::new("list.txt"))
+            .unwrap();
+
+        assert_eq!(2, metrics.getattr_calls());
+        assert_eq!(1, metrics.readdir_calls());
+        assert_eq!(3, metrics.read_calls());
+        assert_eq!(16, metrics.read_bytes());
+        assert_eq!(1, metrics.index_files());
+        let rendered = metrics.render();
+        assert!(rendered.contains("tagfs_read_bytes_total 16\n"));
+        assert!(rendered.contains("# TYPE tagfs_index_files gauge\n"));
     }
 
-    #[traced_test]
     #[test]
-    fn get_children_singleton_child_nofile() {
-        let mut tags = HashMap::new();
-        tags.insert(Tag::new("singleton", true, "v1"), HashSet::from([0]));
-        tags.insert(Tag::new("singleton", true, "v2"), HashSet::new());
-        tags.insert(Tag::from("tag1"), HashSet::new());
-        let files = vec![Entry::from("/fake/dir/where/file/exists/file1.txt")];
+    fn collect_matches_over_tag_paths() {
+        let _m = MTX.lock();
 
-        let children = get_children(
-            &PathBuf::from("/singleton".to_owned() + TAG_SEPARATOR + "v2"),
-            &tags,
-            &files,
-            |_| false,
-        )
-        .collect::<HashSet<_>>();
-        // Inner shows only non-singleton collisions, and related files
-        assert_eq!(1, children.len());
-        assert!(children.contains(&(fuse_mt::FileType::Directory, &OsString::from("tag1"))));
-        assert!(!children.contains(&(fuse_mt::FileType::RegularFile, &OsString::from("file1.txt"))));
+        let ctx = MockLibcWrapper::new_context();
+        ctx.expect().returning(mock);
+        let mut fs = TagFS::<MockLibcWrapper>::new();
+        let (a, b, c) = (
+            Path::new("/fake/one/a.txt"),
+            Path::new("/fake/two/a.txt"),
+            Path::new("/fake/one/c.txt"),
+        );
+        fs.add_file(
+            a,
+            HashSet::from([
+                Tag::from("red"),
+                Tag::from("round"),
+                Tag::new("size", true, "1"),
+            ]),
+        );
+        fs.add_file(b, HashSet::from([Tag::from("red"), Tag::from("round")]));
+        fs.add_file(c, HashSet::from([Tag::from("red")]));
+
+        let paths = |fs: &TagFS<_>, path: &str| fs.collect_matches(Path::new(path)).unwrap();
+        assert_eq!(vec![a, b], paths(&fs, "/red/round"));
+        assert_eq!(vec![a, b, c], paths(&fs, "/red"));
+        assert_eq!(vec![a, b, c], paths(&fs, "/"));
+        assert_eq!(vec![c], paths(&fs, "/red/c.txt"));
+        // Nothing to export from what isn't a tag directory or file
+        assert_eq!(None, fs.collect_matches(Path::new("/red/blue")));
+        assert_eq!(None, fs.collect_matches(Path::new("/blue")));
+        assert_eq!(None, fs.collect_matches(&Path::new("/").join(INFO_FILE)));
+        fs.delete_file(1);
+        assert_eq!(vec![a], paths(&fs, "/red/round"));
+
+        // A bare singleton label covers each of its values
+        fs.set_flatten_singletons(true);
+        assert_eq!(vec![a], paths(&fs, "/red/size"));
+        assert_eq!(vec![a], paths(&fs, "/size/1"));
     }
 
-    #[traced_test]
     #[test]
-    fn unlink_present_file() {
+    fn vanished_sources_pruned() {
         let _m = MTX.lock();
 
         let ctx = MockLibcWrapper::new_context();
         ctx.expect().returning(|| {
             let mut mock = MockLibcWrapper::default();
-            mock.expect_unlink().times(1).returning(|_path| Ok(()));
+            mock.expect_lstat()
+                .returning(|_path| Err(std::io::Error::from_raw_os_error(ENOENT)));
+            mock.expect_open()
+                .returning(|_path, _flags| Err(std::io::Error::from_raw_os_error(ENOENT)));
             mock
         });
         let mut fs = TagFS::<MockLibcWrapper>::new();
-        let mut tags = HashSet::new();
-        tags.insert(Tag::from("tag"));
-        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
-        let r = fs.unlink(
-            RequestInfo {
-                unique: 0,
-                uid: 0,
-                gid: 0,
-                pid: 0,
-            },
-            &PathBuf::from("/tag"),
-            &OsString::from("present.txt"),
+        fs.add_file(
+            Path::new("/fake/source/gone.txt"),
+            HashSet::from([Tag::from("red")]),
         );
-        assert!(r.is_ok());
+        fs.add_file(
+            Path::new("/fake/source/also-gone.txt"),
+            HashSet::from([Tag::from("red")]),
+        );
+        let red = OsString::from("red");
+        let gone = Path::new("/red/gone.txt");
+        let also_gone = Path::new("/red/also-gone.txt");
+
+        // Left listed unless pruning
+        assert_eq!(Err(ENOENT), fs.getattr(request(), gone, None).map(|_| ()));
+        assert_eq!(2, fs.query(&[&red]).len());
+
+        fs.set_prune_stale(true);
+        assert_eq!(Err(ENOENT), fs.getattr(request(), gone, None).map(|_| ()));
         assert!(fs.is_deleted(0));
+        assert!(matches!(fs.lookup(gone), LookupResult::Missing));
+        assert_eq!(
+            Err(ENOENT),
+            fs.open(request(), also_gone, libc::O_RDONLY as u32)
+                .map(|_| ())
+        );
+        assert!(fs.query(&[&red]).is_empty());
+        assert_eq!(0, fs.metrics().index_files());
     }
 
-    #[traced_test]
     #[test]
-    fn unlink_missing_file() {
+    fn saved_search_lists_matches() {
         let _m = MTX.lock();
 
         let ctx = MockLibcWrapper::new_context();
-        ctx.expect().returning(MockLibcWrapper::default);
+        ctx.expect().returning(mock);
         let mut fs = TagFS::<MockLibcWrapper>::new();
-        let mut tags = HashSet::new();
-        tags.insert(Tag::from("tag"));
-        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
-        let r = fs.unlink(
-            RequestInfo {
-                unique: 0,
-                uid: 0,
-                gid: 0,
-                pid: 0,
-            },
-            &PathBuf::from("/tag"),
-            &OsString::from("missing.txt"),
+        fs.add_file(
+            Path::new("/fake/invoices/march.pdf"),
+            HashSet::from([
+                Tag::new("folder", true, "Invoices"),
+                Tag::new("doctype", true, "invoice"),
+            ]),
         );
-        assert!(r.is_err());
-        assert_eq!(ENOENT, r.unwrap_err());
-        assert!(!fs.is_deleted(0));
+        fs.add_file(
+            Path::new("/fake/invoices/notes.txt"),
+            HashSet::from([Tag::new("folder", true, "Invoices")]),
+        );
+        fs.add_file(
+            Path::new("/fake/other/april.pdf"),
+            HashSet::from([Tag::new("doctype", true, "invoice"), Tag::from("paid")]),
+        );
+        fs.add_saved_search(
+            "work-invoices",
+            "folder:Invoices AND doctype:invoice OR (doctype:invoice AND NOT paid)"
+                .parse()
+                .unwrap(),
+        );
+        fs.add_saved_search("unpaid", "NOT paid".parse().unwrap());
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != "..")
+                .collect::<Vec<_>>()
+        };
+
+        let root = names("/");
+        assert!(root.contains(&"work-invoices".to_string()));
+        assert!(root.contains(&"unpaid".to_string()));
+        assert_eq!(vec!["march.pdf"], names("/work-invoices"));
+        assert_eq!(vec!["march.pdf", "notes.txt"], names("/unpaid"));
+        assert!(matches!(
+            fs.lookup(Path::new("/work-invoices/march.pdf")),
+            LookupResult::File(_, 0)
+        ));
+        assert!(matches!(
+            fs.lookup(Path::new("/work-invoices/april.pdf")),
+            LookupResult::Missing
+        ));
+        assert_eq!(
+            Some(vec![PathBuf::from("/fake/invoices/march.pdf")]),
+            fs.collect_matches(Path::new("/work-invoices"))
+        );
+
+        // A name matches a tag emitted as one of many values too
+        fs.add_file(
+            Path::new("/fake/invoices/may.pdf"),
+            HashSet::from([
+                Tag::new("folder", true, "Invoices"),
+                Tag::new("doctype", false, "invoice"),
+                Tag::from("paid"),
+            ]),
+        );
+        assert!(matches!(
+            fs.lookup(Path::new("/work-invoices/may.pdf")),
+            LookupResult::File(_, 3)
+        ));
     }
 
-    #[traced_test]
     #[test]
-    fn unlink_forbidden_file() {
-        let _m = MTX.lock();
+    fn tags_companion_listed_and_read() {
+        let libc_wrapper = InMemoryLibcWrapper::new();
+        libc_wrapper.insert("/mem/notes.txt", "remember the milk");
+        libc_wrapper.insert("/mem/todo.txt", "milk");
+        libc_wrapper.insert("/mem/todo.txt.tags", "a real file");
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        fs.set_tags_suffix(".tags");
+        fs.add_file(
+            Path::new("/mem/notes.txt"),
+            HashSet::from([Tag::from("todo"), Tag::new("kind", true, "text")]),
+        );
+        fs.add_file(
+            Path::new("/mem/todo.txt"),
+            HashSet::from([Tag::from("todo")]),
+        );
+        fs.add_file(
+            Path::new("/mem/todo.txt.tags"),
+            HashSet::from([Tag::from("todo")]),
+        );
+        let names = |path: &str| {
+            fs.list_directory(Path::new(path))
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .filter(|name| name != "." && name != "..")
+                .collect::<Vec<_>>()
+        };
 
-        let ctx = MockLibcWrapper::new_context();
-        ctx.expect().returning(|| {
-            let mut mock = MockLibcWrapper::default();
-            mock.expect_unlink().times(1).returning(|path| {
-                if path == PathBuf::from("/fake/source/present.txt") {
-                    Err(std::io::Error::from_raw_os_error(EPERM))
-                } else {
-                    Err(std::io::Error::from_raw_os_error(ENOENT))
-                }
-            });
-            mock
-        });
-        let mut fs = TagFS::<MockLibcWrapper>::new();
-        let mut tags = HashSet::new();
-        tags.insert(Tag::from("tag"));
-        fs.add_file(&PathBuf::from("/fake/source/present.txt"), tags);
-        let r = fs.unlink(
-            RequestInfo {
-                unique: 0,
-                uid: 0,
-                gid: 0,
-                pid: 0,
-            },
-            &PathBuf::from("/tag"),
-            &OsString::from("present.txt"),
+        assert_eq!(
+            vec![
+                "kind:text",
+                "notes.txt",
+                "notes.txt.tags",
+                "todo.txt",
+                "todo.txt.tags",
+                "todo.txt.tags.tags"
+            ],
+            names("/todo")
         );
-        assert!(r.is_err());
-        assert_eq!(EPERM, r.unwrap_err());
-        assert!(!fs.is_deleted(0));
-        let r = fs.unlink(
-            RequestInfo {
-                unique: 0,
-                uid: 0,
-                gid: 0,
-                pid: 0,
-            },
-            &PathBuf::from("/tag"),
-            &OsString::from("missing.txt"),
+        let path = Path::new("/todo/notes.txt.tags");
+        let (_ttl, attr) = fs.getattr(request(), path, None).unwrap();
+        assert_eq!(FileType::RegularFile, attr.kind);
+        assert_eq!(15, attr.size);
+        let (fh, _) = fs.open(request(), path, libc::O_RDONLY as u32).unwrap();
+        assert_eq!(
+            Ok(b"kind:text\ntodo\n".to_vec()),
+            fs.read_handle(fh, 0, 4096)
         );
-        assert!(r.is_err());
-        assert_eq!(ENOENT, r.unwrap_err());
+        assert!(fs.release(request(), path, fh, 0, 0, false).is_ok());
+        assert_eq!(
+            Err(EPERM),
+            fs.unlink(request(), Path::new("/todo"), OsStr::new("notes.txt.tags"))
+        );
+
+        // The real file isn't shadowed by the companion of todo.txt
+        let path = Path::new("/todo/todo.txt.tags");
+        let (fh, _) = fs.open(request(), path, libc::O_RDONLY as u32).unwrap();
+        assert_eq!(Ok(b"a real file".to_vec()), fs.read_handle(fh, 0, 4096));
+        assert!(fs.release(request(), path, fh, 0, 0, false).is_ok());
+        assert!(matches!(
+            fs.lookup(Path::new("/todo/missing.txt.tags")),
+            LookupResult::Missing
+        ));
+    }
+
+    #[test]
+    fn multi_valued_label_siblings() {
+        let files = vec![
+            Entry::from("/fake/source/both.txt"),
+            Entry::from("/fake/source/other.txt"),
+            Entry::from("/fake/source/manual.txt"),
+        ];
+        let tags = HashMap::from([
+            (Tag::new("folder", false, "A"), HashSet::from([0, 2])),
+            (Tag::new("folder", false, "B"), HashSet::from([0])),
+            (Tag::new("folder", false, "C"), HashSet::from([1])),
+            (Tag::new("kind", true, "text"), HashSet::from([0])),
+            (Tag::new("kind", true, "bin"), HashSet::from([1])),
+            // The same value as a singleton from another tagger
+            (Tag::new("folder", true, "A"), HashSet::from([1])),
+        ]);
+        let children = |path: &str| {
+            get_children(
+                Path::new(path),
+                &tags,
+                &files,
+                |_| false,
+                None,
+                View::Both,
+                CollisionFormat::default(),
+            )
+            .map(|(kind, name)| (kind, name.to_str().unwrap().to_owned()))
+            .sorted_by(|a, b| a.1.cmp(&b.1))
+            .dedup()
+            .collect::<Vec<_>>()
+        };
+
+        // Other values of the label stay siblings of the one entered
+        assert_eq!(
+            vec![
+                (FileType::RegularFile, "both.txt".to_owned()),
+                (FileType::Directory, "folder:B".to_owned()),
+                (FileType::Directory, "folder:C".to_owned()),
+                (FileType::Directory, "kind:bin".to_owned()),
+                (FileType::Directory, "kind:text".to_owned()),
+                (FileType::RegularFile, "manual.txt".to_owned()),
+                (FileType::RegularFile, "other.txt".to_owned()),
+            ],
+            children("/folder:A")
+        );
+        assert_eq!(
+            vec![
+                (FileType::RegularFile, "both.txt".to_owned()),
+                (FileType::Directory, "folder:C".to_owned()),
+                (FileType::Directory, "kind:bin".to_owned()),
+                (FileType::Directory, "kind:text".to_owned()),
+            ],
+            children("/folder:A/folder:B")
+        );
+        // Entering a singleton value hides the label's other values only
+        assert_eq!(
+            vec![
+                (FileType::RegularFile, "both.txt".to_owned()),
+                (FileType::Directory, "folder:A".to_owned()),
+                (FileType::Directory, "folder:B".to_owned()),
+                (FileType::Directory, "folder:C".to_owned()),
+            ],
+            children("/kind:text")
+        );
+    }
+
+    #[test]
+    fn collision_formats_round_trip() {
+        for format in CollisionFormat::value_variants() {
+            let mut fs = TagFS::with_libc_wrapper(mock());
+            fs.set_collision_format(*format);
+            fs.set_all_dir("all");
+            for dir in ["b", "a"] {
+                fs.add_file(
+                    &PathBuf::from(format!("/fake/{dir}/report.txt")),
+                    HashSet::from([Tag::from("docs")]),
+                );
+            }
+            for dir in ["/docs", "/all"] {
+                let names = fs
+                    .list_directory(Path::new(dir))
+                    .into_iter()
+                    .filter(|entry| entry.kind == FileType::RegularFile)
+                    .map(|entry| entry.name)
+                    .collect::<Vec<_>>();
+                assert_eq!(2, names.len(), "{format:?} {names:?}");
+                // The first by source path keeps the shared name
+                let sources = names
+                    .iter()
+                    .map(|name| match fs.lookup(&Path::new(dir).join(name)) {
+                        LookupResult::File(source, _) => (name.clone(), source),
+                        found => panic!("{format:?} {name:?} found {found:?}"),
+                    })
+                    .collect::<HashMap<_, _>>();
+                assert_eq!(
+                    Some(&PathBuf::from("/fake/a/report.txt")),
+                    sources.get(OsStr::new("report.txt")),
+                    "{format:?}"
+                );
+                assert_eq!(
+                    HashSet::from([
+                        PathBuf::from("/fake/a/report.txt"),
+                        PathBuf::from("/fake/b/report.txt")
+                    ]),
+                    sources.into_values().collect::<HashSet<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn collision_names_skip_real_names() {
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        for source in ["/fake/a/x.txt", "/fake/b/x.txt", "/fake/c/x (2).txt"] {
+            fs.add_file(Path::new(source), HashSet::from([Tag::from("docs")]));
+        }
+        let listed = fs
+            .list_directory(Path::new("/docs"))
+            .into_iter()
+            .filter(|entry| entry.kind == FileType::RegularFile)
+            .map(|entry| {
+                let source = match fs.lookup(&Path::new("/docs").join(&entry.name)) {
+                    LookupResult::File(source, _) => source,
+                    found => panic!("{:?} found {found:?}", entry.name),
+                };
+                (entry.name, source)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (
+                    OsString::from("x (2).txt"),
+                    PathBuf::from("/fake/c/x (2).txt")
+                ),
+                ("x (3).txt".into(), "/fake/b/x.txt".into()),
+                ("x.txt".into(), "/fake/a/x.txt".into()),
+            ],
+            listed
+        );
+    }
+
+    #[test]
+    fn associations_borrow_index() {
+        let mut fs = TagFS::with_libc_wrapper(mock());
+        fs.set_source_root("/fake/source");
+        fs.add_file(
+            Path::new("/fake/source/a.txt"),
+            HashSet::from([Tag::from("draft"), Tag::new("colour", false, "red")]),
+        );
+        fs.add_file(
+            Path::new("/elsewhere/b.txt"),
+            HashSet::from([Tag::new("colour", false, "red")]),
+        );
+        fs.add_file(
+            Path::new("/fake/source/gone.txt"),
+            HashSet::from([Tag::from("draft")]),
+        );
+        fs.delete_file(2);
+
+        let associations = fs.associations();
+        assert_eq!(Some(Path::new("/fake/source")), associations.root());
+        let pairs = associations
+            .iter()
+            .map(|(tag, path)| (tag.clone(), path))
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            HashSet::from([
+                (Tag::from("draft"), Path::new("a.txt")),
+                (Tag::new("colour", false, "red"), Path::new("a.txt")),
+                (
+                    Tag::new("colour", false, "red"),
+                    Path::new("/elsewhere/b.txt")
+                ),
+            ]),
+            pairs
+        );
+    }
+
+    #[test]
+    fn tag_commands_survive_retag_and_rescan() {
+        let source = env::temp_dir().join("tagfs-tag-commands-survive.txt");
+        fs::write(&source, b"content").unwrap();
+        let output = Arc::new(Mutex::new(HashSet::from([Tag::from("draft")])));
+        let mut updater = FileUpdater::new();
+        updater.add_tagger(SwitchTagger(output.clone()));
+        let fs = TagFS::with_libc_wrapper(mock());
+        fs.retag(&source, &updater);
+
+        let file = Path::new("/draft").join(source.file_name().unwrap());
+        fs.setxattr(
+            request(),
+            &file,
+            OsStr::new(TAG_CONTROL_XATTR),
+            b"+reviewed\n-draft",
+            0,
+            0,
+        )
+        .unwrap();
+        let (reviewed, draft) = (OsString::from("reviewed"), OsString::from("draft"));
+        *output.lock().unwrap() = HashSet::from([Tag::from("draft"), Tag::from("final")]);
+        fs.retag(&source, &updater);
+        assert_eq!(vec![source.clone()], fs.query(&[&reviewed]));
+        assert!(fs.query(&[&draft]).is_empty());
+
+        let rebuilt = TagFS::with_libc_wrapper(mock());
+        rebuilt.retag(&source, &updater);
+        fs.index_handle().swap_from(rebuilt);
+        assert_eq!(vec![source.clone()], fs.query(&[&reviewed]));
+        assert!(fs.query(&[&draft]).is_empty());
+        fs::remove_file(&source).unwrap();
+    }
+
+    #[test]
+    fn vanished_archive_prunes_every_member() {
+        let archive = env::temp_dir().join("tagfs_vanished_archive_members.zip");
+        write_zip(&archive, &[("a.txt", b"alpha"), ("b.txt", b"bravo")]);
+        let mut libc_wrapper = MockLibcWrapper::default();
+        libc_wrapper
+            .expect_lstat()
+            .returning(|_path| Err(std::io::Error::from_raw_os_error(ENOENT)));
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        assert_eq!(2, fs.add_archive_members(&archive).unwrap());
+        fs::remove_file(&archive).unwrap();
+        fs.set_prune_stale(true);
+
+        let dir = Path::new("/archive-member-of:tagfs_vanished_archive_members.zip");
+        assert_eq!(
+            Err(ENOENT),
+            fs.getattr(request(), &dir.join("a.txt"), None).map(|_| ())
+        );
+        assert!(fs.is_deleted(0));
+        assert!(fs.is_deleted(1));
+        assert_eq!(0, fs.metrics().index_files());
+    }
+
+    #[test]
+    fn errors_without_errno_not_pruned() {
+        let mut libc_wrapper = MockLibcWrapper::default();
+        libc_wrapper
+            .expect_lstat()
+            .returning(|_path| Err(std::io::Error::other("no errno")));
+        let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+        fs.add_file(
+            Path::new("/fake/source/flaky.txt"),
+            HashSet::from([Tag::from("red")]),
+        );
+        fs.set_prune_stale(true);
+
+        assert_eq!(
+            Err(EIO),
+            fs.getattr(request(), Path::new("/red/flaky.txt"), None)
+                .map(|_| ())
+        );
+        assert!(!fs.is_deleted(0));
     }
 }
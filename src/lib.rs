@@ -0,0 +1,16 @@
+//! Tag-based filesystem, with directory hierarchy based on intrinsic file
+//! properties.
+//!
+//! The tagging and index logic can be used without mounting anything: run a
+//! [`FileUpdater`] over source files, feed the results to
+//! [`filesystem::tagfs::TagFS::add_file`], then query the index.
+pub mod archive;
+pub mod config;
+pub mod daemon;
+pub mod file_updater;
+pub mod filesystem;
+pub mod paths;
+pub mod scan_cache;
+pub mod tagger;
+
+pub use file_updater::{BudgetExceeded, ErrorBudget, FileUpdater, NonUtf8Policy, Provenance};
@@ -1,106 +1,1150 @@
-use anyhow::{Context as _, Result};
-use clap::Parser;
-use filesystem::tagfs;
-use magic::{cookie::Load, Cookie};
-use std::collections::HashSet;
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::{Parser, Subcommand, ValueEnum as _};
+use itertools::Itertools as _;
+use reimagined_octo_train::{
+    config::with_config_args,
+    daemon::{self, Detached},
+    filesystem::{
+        collation::Collation,
+        collision::CollisionFormat,
+        search::Expr,
+        tagfs::{self, IndexHandle, ListingView, TagFS, View},
+        InMemoryLibcWrapper, LibcWrapper,
+    },
+    paths::{check_not_nested, resolve_mountpoint, resolve_source},
+    scan_cache::{self, Validation},
+    tagger::{ArchiveTagger, SlashEscape, TaggerConfig, TaggerRegistry, DEFAULT_SNIFF_BYTES},
+    ErrorBudget, FileUpdater, NonUtf8Policy,
+};
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use std::any::Any;
 use std::env;
-use std::ffi::OsStr;
-use std::path::Path;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
-use tagger::{MetadataTagger, MimeTagger, Tag, Tagger};
-use tracing::{debug, info, Level};
-use tracing_subscriber::fmt::format::FmtSpan;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan, EnvFilter};
 
-mod filesystem;
-mod tagger;
+/// Wait before the first remount under `--supervise`, doubling for each after.
+const REMOUNT_BACKOFF: Duration = Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 #[command(
     version,
     about("Tag-based filesystem"),
-    after_help = "Tag-based filesystem, with directory hierarchy based on intrinsic file properties."
+    after_help = "Tag-based filesystem, with directory hierarchy based on intrinsic file properties.",
+    args_override_self = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
-    /// Mount point
-    mountpoint: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Mount point. One named like a subcommand, such as `inspect`, goes
+    /// after `--`
+    #[arg(required = true)]
+    mountpoint: Option<String>,
 
     /// Source folder
-    source: String,
+    #[arg(required = true)]
+    source: Option<String>,
+
+    /// TOML file of default options, in place of
+    /// `$XDG_CONFIG_HOME/tagfs/config.toml`
+    #[arg(long)]
+    config: Option<String>,
 
     /// Number of threads
     #[arg(short, long, default_value_t = 1)]
     num_threads: usize,
+
+    /// Name the mount is listed under by `mount` and `df`, in place of the
+    /// source path
+    #[arg(long)]
+    volume_name: Option<String>,
+
+    #[command(flatten)]
+    taggers: TaggerArgs,
+
+    /// Store indexed paths relative to the source folder, saving memory on
+    /// large trees
+    #[arg(long)]
+    relative_sources: bool,
+
+    /// Memory for caching small recently read slices, in MiB; 0 disables
+    #[arg(long, default_value_t = 0)]
+    read_cache_mb: usize,
+
+    /// Once a file is read sequentially, read this many KiB from the source
+    /// at a time and serve the following reads from it; 0 disables
+    #[arg(long, default_value_t = 0)]
+    readahead_kb: u32,
+
+    /// Most memory, in MiB, buffered by reads in flight at once; further
+    /// reads wait for it. 0 leaves reads unbounded
+    #[arg(long, default_value_t = 0)]
+    max_read_mem: u64,
+
+    /// Serve the members of zip archives as read-only files, tagged with
+    /// the archive they belong to
+    #[arg(long)]
+    expand_archives: bool,
+
+    /// Number files in source path order rather than discovery order, so
+    /// repeated scans build identical indexes
+    #[arg(long)]
+    deterministic_ids: bool,
+
+    /// What the root directory lists
+    #[arg(long, value_enum, default_value_t = View::Tags)]
+    root_view: View,
+
+    /// List only tags with these labels at the root, e.g.
+    /// `category,folder`; deeper directories still list every tag
+    #[arg(long, value_delimiter = ',')]
+    root_labels: Vec<String>,
+
+    /// What directories DEPTH tags deep list, as DEPTH=VIEW; repeatable
+    #[arg(long, value_parser = parse_depth_view)]
+    depth_view: Vec<(usize, View)>,
+
+    /// List only files, no further tags, in directories this many tags deep
+    #[arg(long)]
+    max_tag_depth: Option<usize>,
+
+    /// Browse singleton tags such as `mime:text|plain` as a directory per
+    /// label holding a directory per value, `mime/text|plain`
+    #[arg(long)]
+    flatten_singletons: bool,
+
+    /// Resolve tag directories ignoring case and accents, so `/cafe` finds a
+    /// `Café` tag
+    #[arg(long)]
+    fold_accents: bool,
+
+    /// Order of entries within directory listings
+    #[arg(long, value_enum, default_value_t)]
+    sort: Collation,
+
+    /// How files listed in one directory under the same name are told apart
+    #[arg(long, value_enum, default_value_t)]
+    collision_format: CollisionFormat,
+
+    /// Number of most recently modified files listed under `recent`; 0
+    /// disables it
+    #[arg(long, default_value_t = 100)]
+    recent: usize,
+
+    /// Log a debug event for every tag given to a file, with its label,
+    /// value and tagger, for auditing; needs `RUST_LOG=debug`
+    #[arg(long)]
+    audit_tags: bool,
+
+    /// Copy the source's files into memory at startup and serve reads from
+    /// there; changes to the source after mounting aren't seen, even on a
+    /// SIGHUP rescan
+    #[arg(long)]
+    memory: bool,
+
+    /// Name of the top-level directory listing every file, shadowing any tag
+    /// of the same name; empty disables it
+    #[arg(long, default_value = "all")]
+    all_dir: String,
+
+    /// Abort the scan once more than this many files fail tagging
+    #[arg(long)]
+    max_tag_errors: Option<usize>,
+
+    /// Abort if more than this percentage of scanned files fail tagging
+    #[arg(long)]
+    max_tag_error_percent: Option<f64>,
+
+    /// File caching the scan's tags between mounts; keep it outside the
+    /// source, or its own writes make it stale
+    #[arg(long)]
+    cache: Option<PathBuf>,
+
+    /// How the cache is checked against the source before reuse
+    #[arg(long, value_enum, default_value_t)]
+    cache_validation: Validation,
+
+    /// Return once mounted, leaving a background process serving the
+    /// filesystem; its PID is printed
+    #[arg(long)]
+    background: bool,
+
+    /// Unmount and remount should serving the filesystem panic, rather than
+    /// leaving a stale mountpoint
+    #[arg(long)]
+    supervise: bool,
+
+    /// Times `--supervise` remounts before giving up, waiting twice as long
+    /// before each
+    #[arg(long, default_value_t = 3)]
+    max_remounts: u32,
+
+    /// Serve operation counters over HTTP on this address, e.g.
+    /// `127.0.0.1:9100`, for scraping by Prometheus
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Drop files from the index when their source is found to have been
+    /// deleted since the scan
+    #[arg(long)]
+    prune_stale: bool,
+
+    /// Top-level directory of the files matching a tag expression, as
+    /// NAME=EXPR, e.g. `invoices=folder:Invoices AND NOT paid`; repeatable
+    #[arg(long, value_parser = parse_saved_search)]
+    saved_search: Vec<(String, Expr)>,
+
+    /// Suffix of the read-only file beside each file listing its tags, one
+    /// per line, e.g. `notes.txt.tags`; empty disables them
+    #[arg(long, default_value = ".tags")]
+    tags_suffix: String,
+}
+
+/// Options configuring the taggers, shared by mounting and `inspect`.
+#[derive(clap::Args, Debug)]
+struct TaggerArgs {
+    /// Namespace prefixed to labels emitted by the mime tagger
+    #[arg(long, global = true)]
+    mime_namespace: Option<String>,
+
+    /// Namespace prefixed to labels emitted by the metadata tagger
+    #[arg(long, global = true)]
+    metadata_namespace: Option<String>,
+
+    /// TOML table of `"mime/type" = "name"` overrides for the `type` tag
+    #[arg(long, global = true)]
+    friendly_types: Option<PathBuf>,
+
+    /// TOML table of `"glob" = ["tag", ...]` rules tagging matching paths
+    #[arg(long, global = true)]
+    rules: Option<PathBuf>,
+
+    /// TOML table of `"regex" = ["tag", ...]` rules tagging text files whose
+    /// content matches
+    #[arg(long, global = true)]
+    content_rules: Option<PathBuf>,
+
+    /// TOML document of `[[category]]` tables filing matching files into a
+    /// category tree, tagged a level at a time as `cat-l1`, `cat-l2`, ...
+    #[arg(long, global = true)]
+    taxonomy: Option<PathBuf>,
+
+    /// Taggers to run, by name, in order; defaults to every built-in tagger
+    #[arg(long = "taggers", global = true, value_delimiter = ',')]
+    enabled: Vec<String>,
+
+    /// How `/` in mime types and document properties is made path-safe
+    #[arg(long, global = true, value_enum, default_value_t)]
+    slash_escape: SlashEscape,
+
+    /// Handling of tags that aren't valid UTF-8
+    #[arg(long, global = true, value_enum, default_value_t)]
+    non_utf8: NonUtf8Policy,
+
+    /// Drop tags with this label, e.g. `size`, before indexing; repeatable
+    #[arg(long, global = true)]
+    suppress_label: Vec<String>,
+
+    /// Bytes read once from the start of each file and shared by the
+    /// taggers that only look at a prefix
+    #[arg(long, global = true, default_value_t = DEFAULT_SNIFF_BYTES)]
+    sniff_bytes: u64,
+
+    /// Days since last access under which files are `accessed-age:recent`
+    #[arg(long, global = true, default_value_t = 30)]
+    accessed_recent_days: u64,
+
+    /// Days since last access over which files are `accessed-age:ancient`
+    #[arg(long, global = true, default_value_t = 365)]
+    accessed_ancient_days: u64,
+
+    /// Fraction by which an image's sides may differ and still count as
+    /// `orientation:square`
+    #[arg(long, global = true, default_value_t = 0.05)]
+    square_tolerance: f64,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the tags of one file, and the taggers emitting each, without
+    /// mounting anything
+    Inspect {
+        /// File to tag
+        path: PathBuf,
+    },
+    /// Copy the files under a tag path, such as `red/round`, out of the
+    /// scanned source into a directory, renaming any whose names collide
+    Export {
+        /// Source folder to scan
+        #[arg(long)]
+        source: PathBuf,
+        /// Path within the filesystem, as it would be browsed when mounted
+        tag_path: PathBuf,
+        /// Directory to copy into, created if missing
+        dest: PathBuf,
+    },
+    /// Check a scan cache against the live source, listing files missing,
+    /// changed in size or modification time, or whose tags would differ on
+    /// re-tagging; exits non-zero if there are any
+    Verify {
+        /// Cache file, as given to `--cache`
+        cache: PathBuf,
+    },
+}
+
+fn parse_saved_search(s: &str) -> Result<(String, Expr), String> {
+    let (name, expression) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=EXPR, got {s:?}"))?;
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("invalid saved search name {name:?}"));
+    }
+    let expr = expression
+        .parse()
+        .map_err(|e| format!("invalid expression {expression:?}: {e}"))?;
+    Ok((name.to_string(), expr))
+}
+
+fn parse_depth_view(s: &str) -> Result<(usize, View), String> {
+    let (depth, view) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected DEPTH=VIEW, got {s:?}"))?;
+    let depth = depth.parse().map_err(|e| format!("invalid depth: {e}"))?;
+    let view = View::from_str(view, true)?;
+    Ok((depth, view))
+}
+
+/// The configured set of taggers.
+fn file_updater(args: &TaggerArgs) -> Result<FileUpdater> {
+    let mut file_updater = FileUpdater::new();
+    file_updater.set_non_utf8_policy(args.non_utf8);
+    file_updater.set_sniff_bytes(args.sniff_bytes);
+    for label in &args.suppress_label {
+        file_updater.suppress_label(label);
+    }
+    let config = TaggerConfig {
+        mime_namespace: args.mime_namespace.clone(),
+        metadata_namespace: args.metadata_namespace.clone(),
+        friendly_types: args.friendly_types.clone(),
+        rules: args.rules.clone(),
+        content_rules: args.content_rules.clone(),
+        taxonomy: args.taxonomy.clone(),
+        slash_escape: args.slash_escape,
+        accessed_thresholds: Some((
+            Duration::from_secs(args.accessed_recent_days.saturating_mul(24 * 60 * 60)),
+            Duration::from_secs(args.accessed_ancient_days.saturating_mul(24 * 60 * 60)),
+        )),
+        square_tolerance: Some(args.square_tolerance),
+    };
+    let registry = TaggerRegistry::with_builtins();
+    let names = match args.enabled.is_empty() {
+        true => registry.names().map(str::to_string).collect(),
+        false => args.enabled.clone(),
+    };
+    for name in &names {
+        match registry.build(name, &config)? {
+            Some(tagger) => file_updater.add_boxed_tagger(tagger),
+            None => debug!(name, "tagger not configured, skipping"),
+        }
+    }
+    Ok(file_updater)
+}
+
+/// Everything about the tagger configuration that can change the tags a scan
+/// gives, for keying the scan cache: the options, the contents of the files
+/// they name, and the taggers enabled.
+fn tagger_fingerprint(args: &TaggerArgs, file_updater: &FileUpdater) -> Vec<u8> {
+    let taggers = file_updater.tagger_names().join(",");
+    let mut config = format!("{args:?}\n{taggers}\n").into_bytes();
+    for file in [
+        &args.friendly_types,
+        &args.rules,
+        &args.content_rules,
+        &args.taxonomy,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        config.extend(fs::read(file).unwrap_or_default());
+        config.push(b'\n');
+    }
+    config
+}
+
+/// Write `tag<TAB>tagger,...` lines for `path`, sorted by tag.
+fn inspect(args: &TaggerArgs, path: &Path, out: &mut impl Write) -> Result<()> {
+    let (tags, failures) = file_updater(args)?.tag_counting_failures(path);
+    for (tag, taggers) in tags
+        .iter()
+        .sorted_by(|(a, _), (b, _)| a.as_os_str().cmp(b.as_os_str()))
+    {
+        writeln!(
+            out,
+            "{}\t{}",
+            tag.as_os_str().to_string_lossy(),
+            taggers.iter().join(",")
+        )?;
+    }
+    if failures > 0 {
+        writeln!(out, "({failures} taggers failed, see log)")?;
+    }
+    Ok(())
+}
+
+/// Write a line for each way the files cached at `cache` differ from their
+/// sources, returning how many there are.
+fn verify(args: &TaggerArgs, cache: &Path, out: &mut impl Write) -> Result<usize> {
+    let discrepancies = scan_cache::verify(cache, &file_updater(args)?)
+        .with_context(|| format!("verify {cache:?}"))?;
+    for discrepancy in &discrepancies {
+        writeln!(out, "{discrepancy}")?;
+    }
+    Ok(discrepancies.len())
+}
+
+/// Filter for a `RUST_LOG` value: either a bare level, as `debug` or `3`, or
+/// `EnvFilter` directives such as `reimagined_octo_train::filesystem=debug`.
+/// Unset, empty or invalid values log at INFO.
+fn log_filter(rust_log: Option<&str>) -> EnvFilter {
+    let at_level = |level| EnvFilter::default().add_directive(level);
+    let Some(rust_log) = rust_log.filter(|v| !v.is_empty()) else {
+        return at_level(LevelFilter::INFO.into());
+    };
+    if let Ok(level) = Level::from_str(rust_log) {
+        return at_level(LevelFilter::from_level(level).into());
+    }
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(rust_log)
+        .unwrap_or_else(|e| {
+            eprintln!("ignoring RUST_LOG {rust_log:?}: {e}");
+            at_level(LevelFilter::INFO.into())
+        })
 }
 
 fn setup_logger() {
     // install global collector configured based on RUST_LOG env var.
-    let level =
-        env::var("RUST_LOG").map_or(Level::INFO, |v| Level::from_str(&v).unwrap_or(Level::INFO));
+    let filter = log_filter(env::var("RUST_LOG").ok().as_deref());
     tracing_subscriber::fmt()
         .with_span_events(FmtSpan::ACTIVE)
         .with_thread_ids(true)
         .with_thread_names(true)
         .with_file(true)
         .with_line_number(true)
-        .with_max_level(level)
+        .with_env_filter(filter)
         .with_ansi(false)
         .init();
 }
 
-#[derive(Debug)]
-struct FileUpdater {
-    taggers: Vec<Box<dyn Tagger>>,
+fn main() -> Result<()> {
+    setup_logger();
+    let args = Args::parse_from(with_config_args(env::args_os())?);
+    match &args.command {
+        Some(Command::Inspect { path }) => {
+            return inspect(&args.taggers, path, &mut io::stdout().lock())
+        }
+        Some(Command::Export {
+            source,
+            tag_path,
+            dest,
+        }) => return export(&args, source, tag_path, dest, &mut io::stdout().lock()),
+        Some(Command::Verify { cache }) => {
+            let discrepancies = verify(&args.taggers, cache, &mut io::stdout().lock())?;
+            if discrepancies > 0 {
+                bail!("{discrepancies} discrepancies between {cache:?} and its source");
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    // Required unless there's a subcommand
+    let (Some(source), Some(mountpoint)) = (&args.source, &args.mountpoint) else {
+        unreachable!("clap enforces mount arguments");
+    };
+    let source = resolve_source(source)?;
+    let mountpoint = resolve_mountpoint(mountpoint)?;
+    check_not_nested(&source, &mountpoint)?;
+    // Before anything spawns threads
+    let detached = args.background.then(daemon::detach).transpose()?;
+
+    match args.memory {
+        true => {
+            let new_fs = || {
+                let libc_wrapper =
+                    InMemoryLibcWrapper::from_dir(&source).context("load source into memory")?;
+                Ok(TagFS::with_libc_wrapper(libc_wrapper))
+            };
+            serve(&args, &source, &mountpoint, detached, new_fs)
+        }
+        false => serve(&args, &source, &mountpoint, detached, || Ok(tagfs::new())),
+    }
+}
+
+/// Scan `source` and copy the files under `tag_path` into `dest`, writing a
+/// `source<TAB>copy` line for each.
+fn export(
+    args: &Args,
+    source: &Path,
+    tag_path: &Path,
+    dest: &Path,
+    out: &mut impl Write,
+) -> Result<()> {
+    let source = resolve_source(source)?;
+    let mut target_fs = tagfs::new();
+    populate(args, &source, &mut target_fs, true)?;
+    let matches = target_fs
+        .collect_matches(&Path::new("/").join(tag_path))
+        .with_context(|| format!("{tag_path:?} is not a tag directory or file"))?;
+    info!(?tag_path, count = matches.len(), "exporting");
+    for (from, to) in matches.iter().zip(copy_out(&matches, dest)?) {
+        writeln!(out, "{}\t{}", from.display(), to.display())?;
+    }
+    Ok(())
 }
-impl FileUpdater {
-    fn new() -> Self {
-        Self {
-            taggers: Vec::new(),
+
+/// Copy each of `sources` into `dest` under its own name, numbering names
+/// already taken as `name (1).ext`, `name (2).ext` and so on. Returns where
+/// each was copied to.
+fn copy_out(sources: &[PathBuf], dest: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dest).with_context(|| format!("create {dest:?}"))?;
+    sources
+        .iter()
+        .map(|source| {
+            let target = reserve_name(source, dest)?;
+            fs::copy(source, &target).with_context(|| format!("copy {source:?}"))?;
+            Ok(target)
+        })
+        .collect()
+}
+
+/// Create an empty file in `dest` named after `source`, or numbered when
+/// that name is taken, so concurrent copies can't claim the same name.
+fn reserve_name(source: &Path, dest: &Path) -> Result<PathBuf> {
+    let name = source
+        .file_name()
+        .with_context(|| format!("no file name in {source:?}"))?;
+    let stem = Path::new(name).file_stem().unwrap_or(name);
+    let extension = Path::new(name).extension();
+    let candidates = std::iter::once(dest.join(name)).chain((1..).map(|n| {
+        let mut numbered = stem.to_os_string();
+        numbered.push(format!(" ({n})"));
+        if let Some(extension) = extension {
+            numbered.push(".");
+            numbered.push(extension);
+        }
+        dest.join(numbered)
+    }));
+    for candidate in candidates {
+        match fs::File::create_new(&candidate) {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("create {candidate:?}")),
         }
     }
+    unreachable!("candidate names are unbounded")
+}
 
-    fn add_tagger(&mut self, tagger: impl Tagger + 'static) {
-        self.taggers.push(Box::new(tagger));
+/// Apply the options to `target_fs`, returning the taggers it's indexed with.
+fn configure<T>(args: &Args, source: &Path, target_fs: &mut TagFS<T>) -> Result<FileUpdater>
+where
+    T: LibcWrapper,
+{
+    target_fs.set_read_cache_bytes(args.read_cache_mb * 1024 * 1024);
+    target_fs.set_readahead_bytes(args.readahead_kb.saturating_mul(1024));
+    target_fs.set_max_read_bytes(args.max_read_mem.saturating_mul(1024 * 1024));
+    target_fs.set_deterministic_ids(args.deterministic_ids);
+    target_fs.set_recent_limit(args.recent);
+    target_fs.set_all_dir(&args.all_dir);
+    for (name, expr) in &args.saved_search {
+        target_fs.add_saved_search(name, expr.clone());
+    }
+    target_fs.set_tags_suffix(&args.tags_suffix);
+    target_fs.set_audit_tags(args.audit_tags);
+    target_fs.set_prune_stale(args.prune_stale);
+    target_fs.set_collation(args.sort);
+    target_fs.set_collision_format(args.collision_format);
+    target_fs.set_flatten_singletons(args.flatten_singletons);
+    target_fs.set_fold_accents(args.fold_accents);
+    target_fs.set_root_labels(&args.root_labels);
+    let listing_view = args.depth_view.iter().fold(
+        ListingView::new(args.root_view),
+        |view, (depth, depth_view)| view.with_depth(*depth, *depth_view),
+    );
+    target_fs.set_listing_view(match args.max_tag_depth {
+        Some(max_tag_depth) => listing_view.with_max_tag_depth(max_tag_depth),
+        None => listing_view,
+    });
+    if args.relative_sources {
+        target_fs.set_source_root(source);
     }
+    let file_updater = file_updater(&args.taggers)?;
+    target_fs.set_taggers(file_updater.tagger_names());
+    Ok(file_updater)
+}
 
-    fn tag(&self, path: &Path) -> HashSet<Tag> {
-        self.taggers.iter().fold(HashSet::new(), |mut acc, tagger| {
-            match tagger.tag(path) {
-                Ok(tags) => acc.extend(tags),
-                Err(_) => todo!(),
+/// Configure `target_fs` and index the tagged files of `source` into it,
+/// from the scan cache when enabled, valid and `reuse_cache` is set.
+fn populate<T>(
+    args: &Args,
+    source: &Path,
+    target_fs: &mut TagFS<T>,
+    reuse_cache: bool,
+) -> Result<()>
+where
+    T: LibcWrapper,
+{
+    let file_updater = configure(args, source, target_fs)?;
+    let config = tagger_fingerprint(&args.taggers, &file_updater);
+    let cached = args
+        .cache
+        .as_ref()
+        .filter(|_| reuse_cache)
+        .and_then(|cache| {
+            let loaded = scan_cache::load(cache, source, args.cache_validation, &config);
+            match loaded {
+                Ok(None) => info!(?cache, "scan cache missing or stale, rescanning"),
+                Err(ref error) => warn!(?cache, ?error, "load scan cache"),
+                Ok(Some(_)) => {}
             }
-            acc
+            loaded.ok().flatten()
+        });
+    let files = match cached {
+        Some(files) => {
+            info!(count = files.len(), "reusing cached scan");
+            files
+        }
+        None => {
+            // Taken before tagging, so edits made during the scan leave the
+            // cache stale
+            let fingerprint = args
+                .cache
+                .as_ref()
+                .map(|cache| (cache, args.cache_validation.fingerprint(source)));
+            let budget = ErrorBudget::new(args.max_tag_errors, args.max_tag_error_percent);
+            let files = file_updater.scan(source, budget).context("scan aborted")?;
+            if let Some((cache, fingerprint)) = fingerprint {
+                let saved = fingerprint.and_then(|fingerprint| {
+                    scan_cache::save(cache, args.cache_validation, fingerprint, &config, &files)
+                });
+                if let Err(error) = saved {
+                    warn!(?cache, ?error, "save scan cache");
+                }
+            }
+            files
+                .into_iter()
+                .map(|file| (file.path, file.provenance))
+                .collect()
+        }
+    };
+    let mut archives = files
+        .iter()
+        .filter(|(_path, tags)| {
+            args.expand_archives && tags.contains_key(&ArchiveTagger::tag_for())
         })
+        .map(|(path, _tags)| path.clone())
+        .collect::<Vec<_>>();
+    target_fs.add_files(files);
+    archives.sort();
+    for archive in &archives {
+        match target_fs.add_archive_members(archive) {
+            Ok(count) => info!(?archive, count, "expanded"),
+            Err(error) => warn!(?archive, ?error, "expand archive"),
+        }
     }
+    target_fs.refresh_recent();
+    target_fs.refresh_size_outliers();
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    setup_logger();
-    let args = Args::parse();
+/// Configure, populate and mount a filesystem from `new_fs`. Remounts under
+/// `--supervise` take another, serving the same index.
+fn serve<T>(
+    args: &Args,
+    source: &Path,
+    mountpoint: &Path,
+    detached: Option<Detached>,
+    new_fs: impl Fn() -> Result<TagFS<T>> + Sync,
+) -> Result<()>
+where
+    T: LibcWrapper + Send + Sync + 'static,
+{
+    let mut target_fs = new_fs()?;
+    populate(args, source, &mut target_fs, true)?;
+    info!(?target_fs, "scanned");
+    if let Some(addr) = args.metrics_addr {
+        target_fs.metrics().serve(addr).context("serving metrics")?;
+    }
+    let index = target_fs.index_handle();
+    let signals = Signals::new([SIGHUP]).context("install SIGHUP handler")?;
+    let signals_handle = signals.handle();
 
-    let mut target_fs = tagfs::new();
-    let mut file_updater = FileUpdater::new();
-    file_updater.add_tagger(MimeTagger::<Cookie<Load>>::new());
-    file_updater.add_tagger(MetadataTagger::new());
+    let fuse_args = fuse_args(args, source);
+    let fuse_args = fuse_args
+        .iter()
+        .map(OsString::as_os_str)
+        .collect::<Vec<_>>();
+    let (mut target_fs, mut detached) = (Some(target_fs), detached);
+    let mut mount_once = || {
+        let target_fs = match target_fs.take() {
+            Some(target_fs) => target_fs,
+            None => {
+                let mut target_fs = new_fs()?;
+                configure(args, source, &mut target_fs)?;
+                target_fs.use_index(&index);
+                target_fs
+            }
+        };
+        let fuse_fs = fuse_mt::FuseMT::new(target_fs, args.num_threads);
+        mount(mountpoint, detached.take(), fuse_fs, &fuse_args)
+    };
+    thread::scope(|scope| {
+        // Files served from memory can't follow a rescan
+        if !args.memory {
+            scope.spawn(|| rescan_on_hangup(args, source, signals, &index));
+        }
+        let served = match args.supervise {
+            true => {
+                let mountpoint = mountpoint.to_owned();
+                supervise(args.max_remounts, REMOUNT_BACKOFF, mount_once, move || {
+                    unmount(&mountpoint)
+                })
+            }
+            false => mount_once(),
+        };
+        signals_handle.close();
+        served
+    })
+}
+
+/// Run `mount_once` until it returns, remounting up to `max_remounts` times
+/// when the mount panics. Each panic is followed by `end_session` and a
+/// wait, starting at `backoff` and doubling.
+///
+/// Panics on any thread serving the mount count, not only the one running
+/// `mount_once`: the filesystem's worker threads aren't joined by it, so
+/// while supervising, a panic hook calls `end_session` to make `mount_once`
+/// return. Panics elsewhere, as in a rescan, are left to the previous hook.
+fn supervise(
+    max_remounts: u32,
+    mut backoff: Duration,
+    mut mount_once: impl FnMut() -> Result<()> + Send,
+    end_session: impl Fn() + Send + Sync + 'static,
+) -> Result<()> {
+    let end_session = Arc::new(end_session);
+    let worker_panic = Arc::new(Mutex::new(None));
+    let previous_hook = Arc::new(panic::take_hook());
+    panic::set_hook(Box::new({
+        let (end_session, worker_panic, previous_hook) = (
+            end_session.clone(),
+            worker_panic.clone(),
+            previous_hook.clone(),
+        );
+        move |info| {
+            previous_hook(info);
+            if !tagfs::is_serving_thread() {
+                return;
+            }
+            let first = {
+                let mut worker_panic = worker_panic.lock().unwrap_or_else(PoisonError::into_inner);
+                let first = worker_panic.is_none();
+                worker_panic.get_or_insert_with(|| panic_message(info.payload()).to_owned());
+                first
+            };
+            // Once per session, however many of its threads panic
+            if first {
+                end_session();
+            }
+        }
+    }));
+
+    let mut remounts = 0;
+    let served = loop {
+        // A panic ends the thread it happens on, leaving this one to recover
+        let joined = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tagfs::mark_serving_thread();
+                    mount_once()
+                })
+                .join()
+        });
+        let worker_panic = worker_panic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let message = match (joined, worker_panic) {
+            (_, Some(message)) => message,
+            (Ok(served), None) => break served,
+            (Err(panic), None) => panic_message(&*panic).to_owned(),
+        };
+        error!(message, remounts, "mount panicked");
+        if remounts == max_remounts {
+            break Err(anyhow!("mount panicked {} times: {message}", remounts + 1));
+        }
+        remounts += 1;
+        warn!(?backoff, remounts, "remounting");
+        thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2);
+    };
+    drop(panic::take_hook());
+    panic::set_hook(Box::new(move |info| previous_hook(info)));
+    served
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown")
+}
 
-    for e in walkdir::WalkDir::new(args.source)
-        .same_file_system(true)
-        .into_iter()
-        .flatten()
+/// Detach whatever is left mounted at `mountpoint`.
+fn unmount(mountpoint: &Path) {
+    match process::Command::new("fusermount")
+        .arg("-u")
+        .arg(mountpoint)
+        .status()
     {
-        debug!(entry = debug(&e), "walkdir");
-        if e.file_type().is_file() {
-            target_fs.add_file(e.path(), file_updater.tag(e.path()));
-            info!(filename = ?e.path(), "file");
+        Ok(status) if status.success() => info!(?mountpoint, "unmounted"),
+        // Usually already gone, through auto_unmount
+        Ok(status) => debug!(?mountpoint, ?status, "fusermount -u"),
+        Err(error) => warn!(?mountpoint, ?error, "run fusermount"),
+    }
+}
+
+/// Mount options: the mount is listed as the volume name, or else `source`,
+/// of type `fuse.tagfs`.
+fn fuse_args(args: &Args, source: &Path) -> Vec<OsString> {
+    let name = match &args.volume_name {
+        Some(name) => OsStr::new(name),
+        None => source.as_os_str(),
+    };
+    let mut fsname = b"fsname=".to_vec();
+    // Commas separate options, so those in the name are escaped
+    for byte in name.as_bytes() {
+        if matches!(byte, b',' | b'\\') {
+            fsname.push(b'\\');
         }
+        fsname.push(*byte);
     }
+    vec![
+        "-o".into(),
+        "auto_unmount".into(),
+        "-o".into(),
+        OsString::from_vec(fsname),
+        "-o".into(),
+        "subtype=tagfs".into(),
+    ]
+}
 
-    info!(?target_fs, "scanned");
+/// Rescan `source` on every SIGHUP, swapping the rebuilt index in through
+/// `index`, until `signals` is closed. A failed rescan keeps the old index.
+fn rescan_on_hangup(args: &Args, source: &Path, mut signals: Signals, index: &IndexHandle) {
+    for _ in signals.forever() {
+        info!(?source, "SIGHUP, rescanning");
+        let mut rebuilt = tagfs::new();
+        match populate(args, source, &mut rebuilt, false) {
+            Ok(()) => index.swap_from(rebuilt),
+            Err(error) => warn!(?error, "rescan failed, keeping the current index"),
+        }
+    }
+}
+
+/// Serve `fuse_fs` at `mountpoint` until unmounted, in a background session
+/// when `detached`.
+fn mount<T>(
+    mountpoint: &Path,
+    detached: Option<Detached>,
+    fuse_fs: fuse_mt::FuseMT<TagFS<T>>,
+    fuse_args: &[&OsStr],
+) -> Result<()>
+where
+    T: LibcWrapper + Send + Sync + 'static,
+{
+    match detached {
+        Some(detached) => {
+            let session = fuse_mt::spawn_mount(fuse_fs, mountpoint, fuse_args)
+                .context("mounting filesystem")?;
+            info!(
+                ?mountpoint,
+                pid = std::process::id(),
+                "mounted in background"
+            );
+            detached.ready()?;
+            // Holding the session keeps the filesystem served until unmounted
+            session.join();
+            Ok(())
+        }
+        None => fuse_mt::mount(fuse_fs, mountpoint, fuse_args).context("running filesystem"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use clap::Parser as _;
+
+    use tracing::{enabled, subscriber, Level};
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    use super::{copy_out, fuse_args, inspect, log_filter, supervise, tagfs, Args, Command};
+
+    #[test]
+    fn inspect_prints_tags() {
+        let path = env::temp_dir().join("tagfs_main_inspect_prints_tags.txt");
+        fs::write(&path, "one\n\ttwo\n").unwrap();
+        let args = Args::parse_from([
+            "tagfs".as_ref(),
+            "inspect".as_ref(),
+            "--rules=/nonexistent/rules.toml".as_ref(),
+            path.as_os_str(),
+        ]);
+        assert!(args.mountpoint.is_none());
+        let Some(Command::Inspect { path: inspected }) = &args.command else {
+            panic!("expected inspect, got {args:?}");
+        };
+        assert_eq!(&path, inspected);
+        // The shared tagger options apply to inspect too
+        assert!(inspect(&args.taggers, &path, &mut Vec::new()).is_err());
 
-    let fuse_args: Vec<&OsStr> = vec![OsStr::new("-o"), OsStr::new("auto_unmount")];
-    fuse_mt::mount(
-        fuse_mt::FuseMT::new(target_fs, args.num_threads),
-        &args.mountpoint,
-        &fuse_args,
-    )
-    .context("running filesystem")
+        let args = Args::parse_from(["tagfs".as_ref(), "inspect".as_ref(), path.as_os_str()]);
+        let mut out = Vec::new();
+        inspect(&args.taggers, &path, &mut out).unwrap();
+        fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().any(|line| line == "lines:2\tline-count"));
+        assert!(out.lines().any(|line| line == "indent:tabs\tindent"));
+    }
+
+    #[test]
+    fn mount_positionals_still_parse() {
+        for argv in [&["tagfs", "mnt", "src"][..], &["tagfs", "--", "mnt", "src"]] {
+            let args = Args::parse_from(argv);
+            assert!(args.command.is_none());
+            assert_eq!(Some("mnt"), args.mountpoint.as_deref());
+            assert_eq!(Some("src"), args.source.as_deref());
+        }
+        // A mount point named after a subcommand goes after `--`
+        let args = Args::parse_from(["tagfs", "--", "inspect", "src"]);
+        assert!(args.command.is_none());
+        assert_eq!(Some("inspect"), args.mountpoint.as_deref());
+        assert!(Args::try_parse_from(["tagfs", "mnt"]).is_err());
+    }
+
+    #[test]
+    fn volume_name_labels_mount() {
+        let source = Path::new("/data/photos");
+        let options = |argv: &[&str]| {
+            fuse_args(&Args::parse_from(argv), source)
+                .into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![
+                "-o",
+                "auto_unmount",
+                "-o",
+                "fsname=holiday\\,2024",
+                "-o",
+                "subtype=tagfs"
+            ],
+            options(&["tagfs", "--volume-name=holiday,2024", "mnt", "src"])
+        );
+        assert_eq!("fsname=/data/photos", options(&["tagfs", "mnt", "src"])[3]);
+    }
+
+    #[test]
+    fn export_numbers_colliding_names() {
+        let dir = env::temp_dir().join("tagfs_main_export_numbers_colliding_names");
+        let _ = fs::remove_dir_all(&dir);
+        let (one, two, dest) = (dir.join("one"), dir.join("two"), dir.join("dest"));
+        fs::create_dir_all(&one).unwrap();
+        fs::create_dir_all(&two).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(one.join("notes.txt"), "first").unwrap();
+        fs::write(two.join("notes.txt"), "second").unwrap();
+        fs::write(one.join("README"), "readme").unwrap();
+        fs::write(dest.join("README"), "already here").unwrap();
+
+        let copied = copy_out(
+            &[
+                one.join("notes.txt"),
+                two.join("notes.txt"),
+                one.join("README"),
+            ],
+            &dest,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                dest.join("notes.txt"),
+                dest.join("notes (1).txt"),
+                dest.join("README (1)"),
+            ],
+            copied
+        );
+        assert_eq!("first", fs::read_to_string(&copied[0]).unwrap());
+        assert_eq!("second", fs::read_to_string(&copied[1]).unwrap());
+        assert_eq!(
+            "already here",
+            fs::read_to_string(dest.join("README")).unwrap()
+        );
+        assert_eq!("readme", fs::read_to_string(&copied[2]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Run `f` under a subscriber filtered as `RUST_LOG` set to `rust_log`.
+    fn with_log_filter<T>(rust_log: Option<&str>, f: impl FnOnce() -> T) -> T {
+        subscriber::with_default(tracing_subscriber::registry().with(log_filter(rust_log)), f)
+    }
+
+    #[test]
+    fn log_filter_directives() {
+        // Bare levels apply everywhere, as before
+        with_log_filter(Some("debug"), || {
+            assert!(enabled!(target: "tagfs::filesystem", Level::DEBUG));
+            assert!(!enabled!(target: "tagfs::filesystem", Level::TRACE));
+            assert!(enabled!(target: "fuse", Level::DEBUG));
+        });
+        with_log_filter(None, || {
+            assert!(enabled!(target: "fuse", Level::INFO));
+            assert!(!enabled!(target: "fuse", Level::DEBUG));
+        });
+        // Directives target modules
+        with_log_filter(Some("info,tagfs::filesystem=trace,fuse=warn"), || {
+            assert!(enabled!(target: "tagfs::filesystem", Level::TRACE));
+            assert!(enabled!(target: "tagfs::tagger", Level::INFO));
+            assert!(!enabled!(target: "tagfs::tagger", Level::DEBUG));
+            assert!(enabled!(target: "fuse", Level::WARN));
+            assert!(!enabled!(target: "fuse", Level::INFO));
+        });
+        // Invalid values fall back to INFO
+        with_log_filter(Some("fuse=loud"), || {
+            assert!(enabled!(target: "fuse", Level::INFO));
+            assert!(!enabled!(target: "fuse", Level::DEBUG));
+        });
+    }
+
+    #[test]
+    fn supervisor_remounts_after_panic() {
+        let mut mounts = 0;
+        let cleanups = Arc::new(AtomicUsize::new(0));
+        let served = supervise(
+            3,
+            Duration::ZERO,
+            || {
+                mounts += 1;
+                if mounts == 1 {
+                    panic!("session lost");
+                }
+                Ok(())
+            },
+            {
+                let cleanups = cleanups.clone();
+                move || {
+                    cleanups.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        );
+        assert!(served.is_ok());
+        assert_eq!(2, mounts);
+        // Once, from the panic hook
+        assert_eq!(1, cleanups.load(Ordering::SeqCst));
+
+        // A panic on a worker thread ends the session the mount is serving
+        let mut mounts = 0;
+        let (end_session, session_ended) = mpsc::channel();
+        let session_ended = Mutex::new(session_ended);
+        let served = supervise(
+            3,
+            Duration::ZERO,
+            || {
+                mounts += 1;
+                if mounts == 1 {
+                    thread::spawn(|| {
+                        tagfs::mark_serving_thread();
+                        panic!("worker lost")
+                    })
+                    .join()
+                    .unwrap_err();
+                    session_ended.lock().unwrap().recv().unwrap();
+                }
+                Ok(())
+            },
+            move || {
+                let _ = end_session.send(());
+            },
+        );
+        assert!(served.is_ok());
+        assert_eq!(2, mounts);
+
+        let mut mounts = 0;
+        let served = supervise(
+            2,
+            Duration::ZERO,
+            || -> anyhow::Result<()> {
+                mounts += 1;
+                panic!("session lost")
+            },
+            || {},
+        );
+        assert!(served.unwrap_err().to_string().contains("session lost"));
+        assert_eq!(3, mounts);
+
+        // Nor does a panic on a thread not serving the mount, as a rescan
+        let mut mounts = 0;
+        let served = supervise(
+            2,
+            Duration::ZERO,
+            || {
+                mounts += 1;
+                thread::spawn(|| panic!("tagger failed"))
+                    .join()
+                    .unwrap_err();
+                Ok(())
+            },
+            || {},
+        );
+        assert!(served.is_ok());
+        assert_eq!(1, mounts);
+
+        // Errors aren't panics, so aren't retried
+        let mut mounts = 0;
+        let served = supervise(
+            2,
+            Duration::ZERO,
+            || {
+                mounts += 1;
+                anyhow::bail!("no such device")
+            },
+            || {},
+        );
+        assert!(served.is_err());
+        assert_eq!(1, mounts);
+    }
 }
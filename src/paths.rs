@@ -0,0 +1,112 @@
+//! Validation of the command line's source and mountpoint paths.
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+
+/// Canonical absolute form of the source folder, so stored entries stay valid
+/// whatever the working directory of the thread later opening them.
+pub fn resolve_source(source: impl AsRef<Path>) -> Result<PathBuf> {
+    let source = source.as_ref();
+    let resolved = source
+        .canonicalize()
+        .with_context(|| format!("source {source:?} not found"))?;
+    if !resolved.is_dir() {
+        bail!("source {source:?} is not a directory");
+    }
+    Ok(resolved)
+}
+
+/// Canonical absolute form of the mountpoint, which must be an existing
+/// directory.
+pub fn resolve_mountpoint(mountpoint: impl AsRef<Path>) -> Result<PathBuf> {
+    let mountpoint = mountpoint.as_ref();
+    let resolved = mountpoint
+        .canonicalize()
+        .with_context(|| format!("mountpoint {mountpoint:?} not found"))?;
+    if !resolved.is_dir() {
+        bail!("mountpoint {mountpoint:?} is not a directory");
+    }
+    Ok(resolved)
+}
+
+/// Refuse a source and mountpoint that are the same directory or nested in
+/// each other, as the scan or later reads would recurse into the mount.
+/// Both paths must already be canonical.
+pub fn check_not_nested(source: &Path, mountpoint: &Path) -> Result<()> {
+    if source == mountpoint {
+        bail!("source and mountpoint are the same directory {source:?}");
+    }
+    if source.starts_with(mountpoint) {
+        bail!("source {source:?} is inside mountpoint {mountpoint:?}");
+    }
+    if mountpoint.starts_with(source) {
+        bail!("mountpoint {mountpoint:?} is inside source {source:?}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs, path::Path};
+
+    use crate::filesystem::tagfs;
+
+    use super::{check_not_nested, resolve_mountpoint, resolve_source};
+
+    #[test]
+    fn relative_source_is_absolute() {
+        let source = resolve_source("src/../src").unwrap();
+        assert!(source.is_absolute());
+        assert_eq!(Path::new("src").canonicalize().unwrap(), source);
+
+        let mut index = tagfs::new();
+        for e in walkdir::WalkDir::new(&source).into_iter().flatten() {
+            if e.file_type().is_file() {
+                index.add_file(e.path(), HashSet::new());
+            }
+        }
+        let files = index.files_for_tags(&[]);
+        assert!(!files.is_empty());
+        assert!(files.iter().all(|path| path.is_absolute()));
+    }
+
+    #[test]
+    fn rejects_missing_and_non_directories() {
+        let missing = env::temp_dir().join("tagfs-paths-missing");
+        let err = resolve_mountpoint(&missing).unwrap_err();
+        assert!(err.to_string().contains("not found"));
+
+        let file = env::temp_dir().join("tagfs-paths-file");
+        fs::write(&file, b"").unwrap();
+        let err = resolve_mountpoint(&file).unwrap_err();
+        assert!(err.to_string().contains("is not a directory"));
+        let err = resolve_source(&file).unwrap_err();
+        assert!(err.to_string().contains("is not a directory"));
+        fs::remove_file(&file).unwrap();
+
+        assert!(resolve_mountpoint(Path::new(".")).unwrap().is_absolute());
+    }
+
+    #[test]
+    fn rejects_nesting() {
+        let dir = env::temp_dir().join("tagfs-paths-nesting");
+        fs::create_dir_all(dir.join("inner")).unwrap();
+        fs::create_dir_all(dir.join("sibling")).unwrap();
+        let outer = resolve_source(&dir).unwrap();
+        // A non-canonical spelling of the same directory
+        let same = resolve_mountpoint(dir.join("inner/..")).unwrap();
+        let inner = resolve_mountpoint(dir.join("inner")).unwrap();
+        let sibling = resolve_mountpoint(dir.join("sibling")).unwrap();
+
+        let err = check_not_nested(&outer, &same).unwrap_err();
+        assert!(err.to_string().contains("same directory"));
+        let err = check_not_nested(&inner, &outer).unwrap_err();
+        assert!(err.to_string().contains("is inside mountpoint"));
+        let err = check_not_nested(&outer, &inner).unwrap_err();
+        assert!(err.to_string().contains("is inside source"));
+        assert!(check_not_nested(&inner, &sibling).is_ok());
+        // Sharing a name prefix isn't nesting
+        assert!(check_not_nested(&inner, &dir.join("inner2")).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
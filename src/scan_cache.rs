@@ -0,0 +1,672 @@
+//! Persisting the tags of a scan, so a later mount of an unchanged source can
+//! skip running the taggers.
+//!
+//! The cache header records a fingerprint of the source taken before it was
+//! scanned, and a hash of the tagger configuration the tags came from. On load both
+//! are recomputed, and a mismatch makes the cache stale, so the caller
+//! rescans. Each file's size and modification time from when it was tagged
+//! are kept too, so [`verify`] can say what changed.
+use std::{
+    collections::BTreeSet,
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    io::{self, BufRead as _, BufReader, BufWriter, Write as _},
+    os::unix::ffi::{OsStrExt as _, OsStringExt as _},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use itertools::Itertools as _;
+
+use crate::{
+    file_updater::{FileUpdater, Provenance},
+    tagger::{Tag, ACCESSED_AGE_LABEL, NAMESPACE_SEPARATOR},
+};
+
+const MAGIC: &str = "tagfs-cache 3";
+
+/// How a cache is checked against the source it was built from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Validation {
+    /// Only the source root's modification time. Cheap, but misses edits to
+    /// files within existing directories
+    Mtime,
+    /// A checksum of every entry's path, size and modification time
+    #[default]
+    Checksum,
+}
+impl Validation {
+    fn name(self) -> &'static str {
+        match self {
+            Validation::Mtime => "mtime",
+            Validation::Checksum => "checksum",
+        }
+    }
+
+    /// Fingerprint of `source` under this validation.
+    pub fn fingerprint(self, source: &Path) -> io::Result<u64> {
+        match self {
+            Validation::Mtime => {
+                let mut hash = Fnv::new();
+                hash.write_mtime(&fs::metadata(source)?)?;
+                Ok(hash.0)
+            }
+            Validation::Checksum => {
+                let mut hash = Fnv::new();
+                for e in walkdir::WalkDir::new(source)
+                    .same_file_system(true)
+                    .sort_by_file_name()
+                {
+                    let e = e?;
+                    let metadata = e.metadata()?;
+                    hash.write(e.path().as_os_str().as_bytes());
+                    hash.write(&metadata.len().to_le_bytes());
+                    hash.write_mtime(&metadata)?;
+                }
+                Ok(hash.0)
+            }
+        }
+    }
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is stable across builds.
+struct Fnv(u64);
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_mtime(&mut self, metadata: &fs::Metadata) -> io::Result<()> {
+        self.write(&mtime_nanos(metadata)?.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Modification time in nanoseconds since the epoch.
+fn mtime_nanos(metadata: &fs::Metadata) -> io::Result<u128> {
+    Ok(metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos())
+}
+
+/// A cached file with its tags, and its size and modification time when it
+/// was tagged, if it could be read then.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    pub mtime: Option<u128>,
+    pub provenance: Provenance,
+}
+impl CachedFile {
+    /// `path`, not yet tagged, with its size and modification time now.
+    /// Taken before tagging, so an edit while or after it's tagged shows up.
+    pub fn stat(path: PathBuf) -> Self {
+        let metadata = fs::symlink_metadata(&path).ok();
+        Self {
+            size: metadata.as_ref().map(fs::Metadata::len),
+            mtime: metadata.and_then(|metadata| mtime_nanos(&metadata).ok()),
+            path,
+            provenance: Provenance::new(),
+        }
+    }
+}
+
+/// Header identifying a cache of a source with `fingerprint` under
+/// `validation`, tagged under `config`.
+fn header(validation: Validation, fingerprint: u64, config: &[u8]) -> String {
+    let mut config_hash = Fnv::new();
+    config_hash.write(config);
+    format!(
+        "{MAGIC} {} {fingerprint:016x} {:016x}",
+        validation.name(),
+        config_hash.0
+    )
+}
+
+/// Write the scanned `files` of a source, tagged under the tagger
+/// configuration `config`, to `path`. `fingerprint` is the source's under
+/// `validation`, taken before the scan so edits made during it leave the
+/// cache stale. The cache is written alongside and renamed into place, so a
+/// crash part way leaves any old one intact.
+pub fn save(
+    path: &Path,
+    validation: Validation,
+    fingerprint: u64,
+    config: &[u8],
+    files: &[CachedFile],
+) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let written = write(&partial, &header(validation, fingerprint, config), files)
+        .and_then(|()| fs::rename(&partial, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    written
+}
+
+fn write(path: &Path, header: &str, files: &[CachedFile]) -> io::Result<()> {
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    writeln!(out, "{header}")?;
+    for file in files {
+        out.write_all(b"F\t")?;
+        out.write_all(&escape(file.path.as_os_str()))?;
+        // Left empty when the file couldn't be stat'ed
+        let size = file.size.map(|size| size.to_string()).unwrap_or_default();
+        let mtime = file
+            .mtime
+            .map(|mtime| mtime.to_string())
+            .unwrap_or_default();
+        writeln!(out, "\t{size}\t{mtime}")?;
+        for (tag, taggers) in &file.provenance {
+            let (kind, label) = match tag.has_label() {
+                true if tag.is_singleton() => ("s", tag.label()),
+                true => ("m", tag.label()),
+                false => ("u", OsStr::new("")),
+            };
+            write!(out, "T\t{kind}\t")?;
+            out.write_all(&escape(label))?;
+            out.write_all(b"\t")?;
+            out.write_all(&escape(tag.value()))?;
+            for tagger in taggers {
+                out.write_all(b"\t")?;
+                out.write_all(&escape(OsStr::new(tagger)))?;
+            }
+            out.write_all(b"\n")?;
+        }
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// The files cached at `path`, or `None` when there is no cache or it is
+/// stale for `source` or the tagger configuration `config`.
+pub fn load(
+    path: &Path,
+    source: &Path,
+    validation: Validation,
+    config: &[u8],
+) -> io::Result<Option<Vec<(PathBuf, Provenance)>>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = BufReader::new(file).split(b'\n');
+    let found = lines.next().transpose()?.unwrap_or_default();
+    if found != header(validation, validation.fingerprint(source)?, config).as_bytes() {
+        return Ok(None);
+    }
+    let files = read_files(lines)?
+        .into_iter()
+        .map(|file| (file.path, file.provenance))
+        .collect();
+    Ok(Some(files))
+}
+
+/// Every file cached at `path`, however stale.
+pub fn read(path: &Path) -> io::Result<Vec<CachedFile>> {
+    let mut lines = BufReader::new(fs::File::open(path)?).split(b'\n');
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if !header.starts_with(format!("{MAGIC} ").as_bytes()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a cache, or from an older version",
+        ));
+    }
+    read_files(lines)
+}
+
+fn read_files(lines: impl Iterator<Item = io::Result<Vec<u8>>>) -> io::Result<Vec<CachedFile>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed cache entry");
+    let mut files: Vec<CachedFile> = Vec::new();
+    for line in lines {
+        let line = line?;
+        let mut fields = line.split(|b| *b == b'\t').map(unescape);
+        match fields.next().as_deref().map(OsStr::as_bytes) {
+            Some(b"F") => {
+                let file = fields.next().ok_or_else(invalid)?;
+                let size = fields.next().and_then(|n| n.to_str()?.parse().ok());
+                let mtime = fields.next().and_then(|n| n.to_str()?.parse().ok());
+                files.push(CachedFile {
+                    path: PathBuf::from(file),
+                    size,
+                    mtime,
+                    provenance: Provenance::new(),
+                });
+            }
+            Some(b"T") => {
+                let provenance = &mut files.last_mut().ok_or_else(invalid)?.provenance;
+                let kind = fields.next().ok_or_else(invalid)?;
+                let label = fields.next().ok_or_else(invalid)?;
+                let value = fields.next().ok_or_else(invalid)?;
+                let tag = match kind.as_bytes() {
+                    b"s" => Tag::new(label, true, value),
+                    b"m" => Tag::new(label, false, value),
+                    b"u" => Tag::from(value),
+                    _ => return Err(invalid()),
+                };
+                let taggers = fields
+                    .map(|tagger| tagger.to_string_lossy().into_owned())
+                    .collect::<BTreeSet<_>>();
+                provenance.insert(tag, taggers);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(files)
+}
+
+/// A way a cached file no longer matches its source.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Discrepancy {
+    Missing(PathBuf),
+    /// The file couldn't be stat'ed, for a reason other than being missing
+    Unreadable {
+        path: PathBuf,
+        error: String,
+    },
+    Size {
+        path: PathBuf,
+        cached: u64,
+        live: u64,
+    },
+    /// Modification times, in nanoseconds since the epoch
+    Mtime {
+        path: PathBuf,
+        cached: u128,
+        live: u128,
+    },
+    /// Tags a re-tag would add to and remove from the cached ones
+    Tags {
+        path: PathBuf,
+        added: Vec<OsString>,
+        removed: Vec<OsString>,
+    },
+}
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = |nanos: u128| format!("{}.{:09}", nanos / 1_000_000_000, nanos % 1_000_000_000);
+        match self {
+            Discrepancy::Missing(path) => write!(f, "missing\t{}", path.display()),
+            Discrepancy::Unreadable { path, error } => {
+                write!(f, "unreadable\t{}\t{error}", path.display())
+            }
+            Discrepancy::Size { path, cached, live } => {
+                write!(f, "size\t{}\t{cached} -> {live}", path.display())
+            }
+            Discrepancy::Mtime { path, cached, live } => write!(
+                f,
+                "mtime\t{}\t{} -> {}",
+                path.display(),
+                secs(*cached),
+                secs(*live)
+            ),
+            Discrepancy::Tags {
+                path,
+                added,
+                removed,
+            } => write!(
+                f,
+                "tags\t{}\t{}",
+                path.display(),
+                added
+                    .iter()
+                    .map(|tag| format!("+{}", tag.to_string_lossy()))
+                    .chain(
+                        removed
+                            .iter()
+                            .map(|tag| format!("-{}", tag.to_string_lossy()))
+                    )
+                    .join(" ")
+            ),
+        }
+    }
+}
+
+/// Check every file cached at `path` against its source, re-stating it and
+/// re-tagging it with `file_updater`. Files are reported in cache order, and
+/// those that can't be stat'ed as [`Discrepancy::Unreadable`].
+pub fn verify(path: &Path, file_updater: &FileUpdater) -> io::Result<Vec<Discrepancy>> {
+    let mut discrepancies = Vec::new();
+    for file in read(path)? {
+        let stat = fs::symlink_metadata(&file.path)
+            .and_then(|metadata| Ok((metadata.len(), mtime_nanos(&metadata)?)));
+        let (size, mtime) = match stat {
+            Ok(stat) => stat,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                discrepancies.push(Discrepancy::Missing(file.path));
+                continue;
+            }
+            Err(e) => {
+                discrepancies.push(Discrepancy::Unreadable {
+                    path: file.path,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if let Some(cached) = file.size.filter(|cached| *cached != size) {
+            discrepancies.push(Discrepancy::Size {
+                path: file.path.clone(),
+                cached,
+                live: size,
+            });
+        }
+        if let Some(cached) = file.mtime.filter(|cached| *cached != mtime) {
+            discrepancies.push(Discrepancy::Mtime {
+                path: file.path.clone(),
+                cached,
+                live: mtime,
+            });
+        }
+        let cached = file
+            .provenance
+            .keys()
+            .filter(|tag| !is_volatile(tag))
+            .map(|tag| tag.as_os_str().to_os_string())
+            .collect::<BTreeSet<_>>();
+        let retagged = file_updater
+            .tag_with_provenance(&file.path)
+            .keys()
+            .filter(|tag| !is_volatile(tag))
+            .map(|tag| tag.as_os_str().to_os_string())
+            .collect::<BTreeSet<_>>();
+        if cached != retagged {
+            discrepancies.push(Discrepancy::Tags {
+                path: file.path,
+                added: retagged.difference(&cached).cloned().collect(),
+                removed: cached.difference(&retagged).cloned().collect(),
+            });
+        }
+    }
+    Ok(discrepancies)
+}
+
+/// Whether `tag` changes without its file changing, so isn't verified.
+/// Access ages move as time passes, and with the reads tagging itself does.
+fn is_volatile(tag: &Tag) -> bool {
+    if !tag.has_label() {
+        return false;
+    }
+    // Under any namespace
+    let label = tag.label().as_bytes();
+    let unnamespaced = label
+        .rsplit(|byte| NAMESPACE_SEPARATOR.as_bytes().contains(byte))
+        .next()
+        .unwrap_or(label);
+    unnamespaced == ACCESSED_AGE_LABEL.as_bytes()
+}
+
+/// Percent-escape the bytes that delimit fields and lines.
+fn escape(s: &OsStr) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(s.len());
+    for byte in s.as_bytes() {
+        match byte {
+            b'%' | b'\t' | b'\n' => escaped.extend(format!("%{byte:02X}").bytes()),
+            byte => escaped.push(*byte),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &[u8]) -> OsString {
+    let mut unescaped = Vec::with_capacity(s.len());
+    let mut bytes = s.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = bytes.by_ref().take(2).copied().collect::<Vec<_>>();
+                let decoded = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match decoded {
+                    Some(decoded) => unescaped.push(decoded),
+                    None => unescaped.extend(b"%".iter().chain(&hex)),
+                }
+            }
+            byte => unescaped.push(*byte),
+        }
+    }
+    OsString::from_vec(unescaped)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeSet, HashMap, HashSet},
+        env, fs,
+        io::Write as _,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use itertools::Itertools as _;
+
+    use crate::{
+        file_updater::FileUpdater,
+        tagger::{Error, Tag, Tagger},
+    };
+
+    use super::{is_volatile, load, save, verify, CachedFile, Discrepancy, Validation};
+
+    /// Tags files over 8 bytes `big`.
+    #[derive(Debug)]
+    struct SizeTagger;
+    impl Tagger for SizeTagger {
+        fn name(&self) -> &str {
+            "size"
+        }
+        fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+            let big = fs::metadata(path).map_err(|_| Error::Illegible)?.len() > 8;
+            Ok(big.then(|| Tag::from("big")).into_iter().collect())
+        }
+    }
+
+    #[test]
+    fn size_change_invalidates_checksum() {
+        let dir = env::temp_dir().join("scan_cache_size_change");
+        let _ = fs::remove_dir_all(&dir);
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        let file = source.join("sub/a\tb.txt");
+        fs::write(&file, "alpha").unwrap();
+        let cache = dir.join("cache");
+
+        let taggers = BTreeSet::from(["stub".to_string()]);
+        let files = vec![CachedFile {
+            provenance: HashMap::from([
+                (Tag::new("size", true, "5"), taggers.clone()),
+                (Tag::new("folder", false, "50%\nx"), taggers.clone()),
+                (Tag::from("plain:colon"), taggers.clone()),
+            ]),
+            ..CachedFile::stat(file.clone())
+        }];
+        let save = |validation: Validation| {
+            let fingerprint = validation.fingerprint(&source).unwrap();
+            save(&cache, validation, fingerprint, b"config", &files).unwrap();
+        };
+        for validation in [Validation::Checksum, Validation::Mtime] {
+            save(validation);
+            assert_eq!(
+                Some(vec![(file.clone(), files[0].provenance.clone())]),
+                load(&cache, &source, validation, b"config").unwrap()
+            );
+        }
+        // Tags from other taggers can't be reused
+        assert_eq!(
+            None,
+            load(&cache, &source, Validation::Mtime, b"other config").unwrap()
+        );
+
+        save(Validation::Checksum);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&file)
+            .unwrap()
+            .write_all(b" and more")
+            .unwrap();
+        // A rescan is needed: the edit changed the file's size
+        assert_eq!(
+            None,
+            load(&cache, &source, Validation::Checksum, b"config").unwrap()
+        );
+        // Root mtime alone can't see an edit inside a subdirectory
+        save(Validation::Mtime);
+        fs::write(&file, "longer still").unwrap();
+        assert!(load(&cache, &source, Validation::Mtime, b"config")
+            .unwrap()
+            .is_some());
+
+        assert_eq!(
+            None,
+            load(
+                &dir.join("missing"),
+                &source,
+                Validation::Checksum,
+                b"config"
+            )
+            .unwrap()
+        );
+        // Nothing is left over from writing
+        assert_eq!(
+            vec![cache.clone(), source.clone()],
+            fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .sorted()
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_reports_stale_entries() {
+        let dir = env::temp_dir().join("scan_cache_verify");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (kept, grown, removed) = (dir.join("kept"), dir.join("grown"), dir.join("removed"));
+        let saved = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for file in [&kept, &grown, &removed] {
+            fs::write(file, "alpha").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(file)
+                .unwrap()
+                .set_modified(saved)
+                .unwrap();
+        }
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(SizeTagger);
+        let files = [&kept, &grown, &removed].map(|file| CachedFile {
+            provenance: file_updater.tag_with_provenance(file),
+            ..CachedFile::stat((*file).clone())
+        });
+        let cache = dir.join("cache");
+        save(&cache, Validation::Mtime, 0, b"", &files).unwrap();
+        assert_eq!(
+            Vec::<Discrepancy>::new(),
+            verify(&cache, &file_updater).unwrap()
+        );
+
+        let mut file = fs::File::options().append(true).open(&grown).unwrap();
+        file.write_all(b" and more").unwrap();
+        file.set_modified(saved + Duration::from_millis(1500))
+            .unwrap();
+        fs::remove_file(&removed).unwrap();
+        let discrepancies = verify(&cache, &file_updater).unwrap();
+        assert_eq!(
+            vec![
+                Discrepancy::Size {
+                    path: grown.clone(),
+                    cached: 5,
+                    live: 14
+                },
+                Discrepancy::Mtime {
+                    path: grown.clone(),
+                    cached: 1_700_000_000_000_000_000,
+                    live: 1_700_000_001_500_000_000
+                },
+                Discrepancy::Tags {
+                    path: grown.clone(),
+                    added: vec!["big".into()],
+                    removed: vec![]
+                },
+                Discrepancy::Missing(removed.clone()),
+            ],
+            discrepancies
+        );
+        assert_eq!(
+            format!(
+                "mtime\t{}\t1700000000.000000000 -> 1700000001.500000000",
+                grown.display()
+            ),
+            discrepancies[1].to_string()
+        );
+        assert_eq!(
+            format!("tags\t{}\t+big", grown.display()),
+            discrepancies[2].to_string()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_against_tag_time() {
+        let dir = env::temp_dir().join("scan_cache_tag_time");
+        let _ = fs::remove_dir_all(&dir);
+        let source = dir.join("source");
+        fs::create_dir_all(&source).unwrap();
+        let edited = source.join("edited");
+        fs::write(&edited, "alpha").unwrap();
+        let fingerprint = Validation::Checksum.fingerprint(&source).unwrap();
+        let mut file_updater = FileUpdater::new();
+        file_updater.add_tagger(SizeTagger);
+        // Not a directory, so can't be stat'ed
+        let unreadable = edited.join("inner");
+        let files = [&edited, &unreadable].map(|file| CachedFile {
+            provenance: file_updater.tag_with_provenance(file),
+            ..CachedFile::stat((*file).clone())
+        });
+        // Edited after it was tagged but before the cache is saved
+        fs::write(&edited, "alpha and more").unwrap();
+        let cache = dir.join("cache");
+        save(&cache, Validation::Checksum, fingerprint, b"", &files).unwrap();
+
+        assert_eq!(
+            None,
+            load(&cache, &source, Validation::Checksum, b"").unwrap()
+        );
+        let discrepancies = verify(&cache, &file_updater).unwrap();
+        assert_eq!(
+            Some(&Discrepancy::Size {
+                path: edited.clone(),
+                cached: 5,
+                live: 14
+            }),
+            discrepancies.first()
+        );
+        assert!(matches!(
+            discrepancies.last(),
+            Some(Discrepancy::Unreadable { path, .. }) if *path == unreadable
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn access_ages_not_verified() {
+        assert!(is_volatile(&Tag::new("accessed-age", true, "recent")));
+        assert!(is_volatile(&Tag::new("meta.accessed-age", true, "never")));
+        assert!(!is_volatile(&Tag::new("accessed", true, "recent")));
+        assert!(!is_volatile(&Tag::from("accessed-age")));
+    }
+}
@@ -0,0 +1,201 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use tracing::debug;
+
+use crate::archive::{ArchiveReader, ZipReader};
+
+use super::{Error, ResourceLimits, Tag, Tagger, PASSWORD_PROTECTED_TAG};
+
+/// Tags readable zip archives with `archive:zip`, adding
+/// [`PASSWORD_PROTECTED_TAG`] when any member is encrypted.
+///
+/// How well an archive is compressed is tagged too, as `compression-ratio`
+/// `low`, `medium` or `high`: the total uncompressed size its members list
+/// over its size on disk. Only the listing is read, not the member data, so
+/// archives whose members don't report a size get no ratio.
+///
+/// With archive expansion enabled, files carrying this tag also have their
+/// members added to the index, see
+/// [`TagFS::add_archive_members`](crate::filesystem::tagfs::TagFS::add_archive_members).
+/// Archives exceeding the [`ResourceLimits`] are refused as illegible, so are
+/// never expanded.
+#[derive(Debug, Default)]
+pub struct ArchiveTagger<R = ZipReader> {
+    reader: R,
+    limits: ResourceLimits,
+}
+impl ArchiveTagger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The tag emitted for readable archives.
+    pub fn tag_for() -> Tag {
+        Tag::new("archive", true, "zip")
+    }
+}
+impl<R: ArchiveReader> ArchiveTagger<R> {
+    pub fn with_reader(reader: R) -> Self {
+        Self {
+            reader,
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    pub fn with_limits(self, limits: ResourceLimits) -> Self {
+        Self { limits, ..self }
+    }
+}
+/// Bucket the ratio of `expanded` uncompressed bytes to an archive of
+/// `archive_size`; stored and already-compressed content stays near 1.
+fn compression_ratio(expanded: u64, archive_size: u64) -> Option<&'static str> {
+    if expanded == 0 || archive_size == 0 {
+        return None;
+    }
+    let ratio = expanded as f64 / archive_size as f64;
+    Some(match ratio {
+        ..1.5 => "low",
+        ..4.0 => "medium",
+        _ => "high",
+    })
+}
+
+impl<R: ArchiveReader> Tagger for ArchiveTagger<R> {
+    fn name(&self) -> &str {
+        "archive"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        match self.reader.members(path) {
+            Ok(members) => {
+                let archive_size = fs::metadata(path).map_err(|_| Error::Illegible)?.len();
+                self.limits.check_archive(path, archive_size, &members)?;
+                let mut tags = HashSet::from([ArchiveTagger::tag_for()]);
+                if members.iter().any(|member| member.encrypted) {
+                    tags.insert(Tag::from(PASSWORD_PROTECTED_TAG));
+                }
+                let expanded = members
+                    .iter()
+                    .fold(0u64, |acc, member| acc.saturating_add(member.size));
+                match compression_ratio(expanded, archive_size) {
+                    Some(ratio) => {
+                        tags.insert(Tag::new("compression-ratio", true, ratio));
+                    }
+                    None => debug!(?path, "no sizes for compression ratio"),
+                }
+                Ok(tags)
+            }
+            Err(e) => {
+                debug!(?path, error = ?e, "not an archive");
+                Ok(HashSet::new())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs};
+
+    use crate::{
+        archive::test::{write_encrypted_zip, write_zip},
+        tagger::{Error, ResourceLimits, Tag, Tagger, PASSWORD_PROTECTED_TAG},
+    };
+
+    use super::{compression_ratio, ArchiveTagger};
+
+    #[test]
+    fn tags_zips_only() {
+        let zip = env::temp_dir().join("archive_tagger_tags_zips_only.zip");
+        write_zip(&zip, &[("a.txt", b"alpha")]);
+        let plain = env::temp_dir().join("archive_tagger_tags_zips_only.txt");
+        fs::write(&plain, "plain").unwrap();
+
+        let tagger = ArchiveTagger::new();
+        assert!(tagger
+            .tag(&zip)
+            .unwrap()
+            .contains(&Tag::new("archive", true, "zip")));
+        assert!(tagger.tag(&plain).unwrap().is_empty());
+        fs::remove_file(&zip).unwrap();
+        fs::remove_file(&plain).unwrap();
+    }
+
+    #[test]
+    fn refuses_bombs() {
+        let bomb = env::temp_dir().join("archive_tagger_refuses_bombs.zip");
+        write_zip(&bomb, &[("zeros", &vec![0u8; 1024 * 1024])]);
+        let tagger = ArchiveTagger::new();
+        assert_eq!(Err(Error::Illegible), tagger.tag(&bomb));
+
+        let many = env::temp_dir().join("archive_tagger_refuses_bombs_many.zip");
+        write_zip(&many, &[("a", b"a"), ("b", b"b"), ("c", b"c")]);
+        let limits = ResourceLimits {
+            max_archive_entries: 2,
+            ..ResourceLimits::default()
+        };
+        assert!(tagger.tag(&many).is_ok());
+        let tagger = ArchiveTagger::new().with_limits(limits);
+        assert_eq!(Err(Error::Illegible), tagger.tag(&many));
+        fs::remove_file(&bomb).unwrap();
+        fs::remove_file(&many).unwrap();
+    }
+
+    #[test]
+    fn tags_encrypted_zips() {
+        let encrypted = env::temp_dir().join("archive_tagger_tags_encrypted_zips.zip");
+        write_encrypted_zip(&encrypted, &[("secret.txt", b"hush")], "hunter2");
+        let plain = env::temp_dir().join("archive_tagger_tags_encrypted_zips_plain.zip");
+        write_zip(&plain, &[("open.txt", b"hello")]);
+
+        let tagger = ArchiveTagger::new();
+        let low = Tag::new("compression-ratio", true, "low");
+        assert_eq!(
+            HashSet::from([
+                ArchiveTagger::tag_for(),
+                Tag::from(PASSWORD_PROTECTED_TAG),
+                low.clone()
+            ]),
+            tagger.tag(&encrypted).unwrap()
+        );
+        assert_eq!(
+            HashSet::from([ArchiveTagger::tag_for(), low]),
+            tagger.tag(&plain).unwrap()
+        );
+        fs::remove_file(&encrypted).unwrap();
+        fs::remove_file(&plain).unwrap();
+    }
+
+    #[test]
+    fn tags_compression_ratio() {
+        let text = env::temp_dir().join("archive_tagger_tags_compression_ratio.zip");
+        write_zip(
+            &text,
+            &[("words.txt", "hello world ".repeat(800).as_bytes())],
+        );
+        // A simple LCG, so the noise doesn't compress
+        let noise = (0..8192u32)
+            .scan(12345u32, |state, _| {
+                *state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                Some((*state >> 16) as u8)
+            })
+            .collect::<Vec<_>>();
+        let random = env::temp_dir().join("archive_tagger_tags_compression_ratio_random.zip");
+        write_zip(&random, &[("noise.bin", &noise)]);
+
+        let tagger = ArchiveTagger::new();
+        assert!(tagger
+            .tag(&text)
+            .unwrap()
+            .contains(&Tag::new("compression-ratio", true, "high")));
+        assert!(tagger
+            .tag(&random)
+            .unwrap()
+            .contains(&Tag::new("compression-ratio", true, "low")));
+        fs::remove_file(&text).unwrap();
+        fs::remove_file(&random).unwrap();
+
+        assert_eq!(Some("medium"), compression_ratio(3000, 1000));
+        // Sizes not reported
+        assert_eq!(None, compression_ratio(0, 1000));
+    }
+}
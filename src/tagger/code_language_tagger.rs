@@ -0,0 +1,351 @@
+use std::{cmp::Reverse, collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 16 * 1024;
+/// Distinct signatures content must show to override the extension.
+const CONTENT_EVIDENCE: usize = 2;
+/// Distinct signatures files without a code extension must show, so prose
+/// mentioning `import` isn't taken for Python.
+const CONTENT_ONLY_EVIDENCE: usize = 3;
+
+/// Languages each extension suggests, the likeliest first.
+const EXTENSIONS: &[(&str, &[&str])] = &[
+    ("rs", &["rust"]),
+    ("py", &["python"]),
+    ("pyw", &["python"]),
+    ("rb", &["ruby"]),
+    ("pl", &["perl"]),
+    ("pm", &["perl"]),
+    ("sh", &["shell"]),
+    ("bash", &["shell"]),
+    ("zsh", &["shell"]),
+    ("js", &["javascript"]),
+    ("mjs", &["javascript"]),
+    ("cjs", &["javascript"]),
+    ("ts", &["typescript"]),
+    ("go", &["go"]),
+    ("c", &["c"]),
+    ("h", &["c", "cpp", "objective-c"]),
+    ("cc", &["cpp"]),
+    ("cpp", &["cpp"]),
+    ("cxx", &["cpp"]),
+    ("hpp", &["cpp"]),
+    ("m", &["objective-c", "matlab"]),
+    ("java", &["java"]),
+    ("kt", &["kotlin"]),
+    ("swift", &["swift"]),
+    ("php", &["php"]),
+    ("lua", &["lua"]),
+    ("hs", &["haskell"]),
+    ("cs", &["csharp"]),
+];
+
+/// Interpreters named by shebangs, version suffixes removed.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("pypy", "python"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+    ("sh", "shell"),
+    ("bash", "shell"),
+    ("dash", "shell"),
+    ("ksh", "shell"),
+    ("zsh", "shell"),
+    ("node", "javascript"),
+    ("deno", "typescript"),
+    ("ts-node", "typescript"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("runghc", "haskell"),
+];
+
+/// Snippets characteristic of each language; the more distinct ones a file
+/// shows, the stronger the evidence.
+const SIGNATURES: &[(&str, &[&str])] = &[
+    (
+        "rust",
+        &[
+            "fn main(",
+            "use std::",
+            "pub fn ",
+            "impl ",
+            "let mut ",
+            "#[derive(",
+            "-> Result<",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "def ",
+            "import ",
+            "if __name__ ==",
+            "elif ",
+            "self.",
+            "print(",
+            "    return ",
+        ],
+    ),
+    ("go", &["package ", "func ", ":= ", "fmt."]),
+    (
+        "c",
+        &[
+            "#include <stdio.h>",
+            "#include <stdlib.h>",
+            "int main(",
+            "printf(",
+            "malloc(",
+        ],
+    ),
+    (
+        "cpp",
+        &[
+            "#include <iostream>",
+            "std::",
+            "namespace ",
+            "template <",
+            "template<",
+            "public:",
+        ],
+    ),
+    (
+        "objective-c",
+        &["@interface", "@implementation", "@end", "#import "],
+    ),
+    (
+        "javascript",
+        &[
+            "function ",
+            "const ",
+            "=> ",
+            "require(",
+            "console.log(",
+            "module.exports",
+        ],
+    ),
+    (
+        "java",
+        &[
+            "public class ",
+            "import java.",
+            "System.out.",
+            "public static void main",
+        ],
+    ),
+    (
+        "ruby",
+        &["require '", "def ", "end\n", "puts ", "attr_accessor"],
+    ),
+    ("php", &["<?php", "$this->", "echo "]),
+    ("shell", &["#!/bin/sh", "fi\n", "then\n", "esac", "echo "]),
+];
+
+/// Tags source code with its `language`, e.g. `language:rust`, weighing a
+/// shebang first, then snippets characteristic of each language, then the
+/// extension.
+///
+/// A shebang settles it. Otherwise content showing enough signatures of one
+/// language outweighs the extension, and among the languages an ambiguous
+/// extension such as `.h` suggests, the content picks. Files whose extension
+/// isn't code need stronger content evidence, so prose is left untagged.
+/// Only the [text sample](Sniff::text_sample) is looked at.
+#[derive(Debug)]
+pub struct CodeLanguageTagger {
+    limits: SampleLimits,
+}
+impl Default for CodeLanguageTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl CodeLanguageTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_limits(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, sample_size),
+        }
+    }
+
+    fn language(path: &Path, text: &str) -> Option<&'static str> {
+        if let Some(language) = shebang(text) {
+            return Some(language);
+        }
+        let candidates = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .and_then(|extension| {
+                EXTENSIONS
+                    .iter()
+                    .find(|(known, _)| *known == extension)
+                    .map(|(_, languages)| *languages)
+            });
+        let scores = SIGNATURES
+            .iter()
+            .map(|(language, signatures)| {
+                let score = signatures
+                    .iter()
+                    .filter(|signature| text.contains(**signature))
+                    .count();
+                (*language, score)
+            })
+            .collect::<Vec<_>>();
+        let score_of = |language: &str| {
+            scores
+                .iter()
+                .find(|(scored, _)| *scored == language)
+                .map_or(0, |(_, score)| *score)
+        };
+        // Only a clear winner counts as content evidence
+        let best = scores.iter().max_by_key(|(_, score)| *score).copied();
+        let winner = best.filter(|(language, score)| {
+            scores
+                .iter()
+                .all(|(other, other_score)| other == language || other_score < score)
+        });
+        match candidates {
+            None => winner
+                .filter(|(_, score)| *score >= CONTENT_ONLY_EVIDENCE)
+                .map(|(language, _)| language),
+            Some(candidates) => {
+                // Content picks among the extension's languages, ties going
+                // to the likelier
+                let (_, expected) = candidates
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by_key(|(idx, candidate)| (score_of(candidate), Reverse(*idx)))?;
+                match winner {
+                    Some((language, score))
+                        if score >= CONTENT_EVIDENCE && score > score_of(expected) =>
+                    {
+                        Some(language)
+                    }
+                    _ => Some(expected),
+                }
+            }
+        }
+    }
+}
+
+/// Language of the interpreter a `#!` first line names, looking through
+/// `env` and its options.
+fn shebang(text: &str) -> Option<&'static str> {
+    let line = text.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(interpreter, _)| *interpreter == program)
+        .map(|(_, language)| *language)
+}
+
+impl Tagger for CodeLanguageTagger {
+    fn name(&self) -> &str {
+        "language"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(sample) = sniff.text_sample(self.limits).filter(|s| !s.is_empty()) else {
+            debug!(?path, "skip language");
+            return Ok(HashSet::new());
+        };
+        let text = String::from_utf8_lossy(sample);
+        Ok(Self::language(path, &text)
+            .map(|language| Tag::new("language", true, language))
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::CodeLanguageTagger;
+
+    fn language(value: &str) -> HashSet<Tag> {
+        HashSet::from([Tag::new("language", true, value)])
+    }
+
+    #[test]
+    fn shebang_and_extension() -> io::Result<()> {
+        assert_eq!(
+            language("python"),
+            tags_of(
+                &CodeLanguageTagger::new(),
+                "deploy",
+                "#!/usr/bin/env python3\nprint('deploying')\n"
+            )?
+        );
+        assert_eq!(
+            language("shell"),
+            tags_of(
+                &CodeLanguageTagger::new(),
+                "build.py",
+                "#!/bin/bash\nset -e\ncargo build\n"
+            )?
+        );
+        assert_eq!(
+            language("rust"),
+            tags_of(
+                &CodeLanguageTagger::new(),
+                "lib.rs",
+                "pub fn add(a: u32, b: u32) -> u32 {\n    a + b\n}\n"
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn content_settles_ambiguity() -> io::Result<()> {
+        assert_eq!(
+            language("cpp"),
+            tags_of(&CodeLanguageTagger::new(), "vector.h", "#pragma once\nnamespace geo {\ntemplate <typename T>\nstruct Vec { T x, y; };\n}\n")?
+        );
+        assert_eq!(
+            language("c"),
+            tags_of(
+                &CodeLanguageTagger::new(),
+                "point.h",
+                "#pragma once\nstruct point { int x, y; };\n"
+            )?
+        );
+        // Content outweighs a misleading extension
+        assert_eq!(
+            language("python"),
+            tags_of(&CodeLanguageTagger::new(), "script.js", "import sys\n\ndef main():\n    print(sys.argv)\n\nif __name__ == '__main__':\n    main()\n")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_non_code() -> io::Result<()> {
+        assert!(tags_of(
+            &CodeLanguageTagger::new(),
+            "notes.txt",
+            "Remember to import the photos and print the def list.\n"
+        )?
+        .is_empty());
+        assert!(tags_of(&CodeLanguageTagger::new(), "blob", "\0\x01fn main(")?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::error;
+
+use super::{Error, Sniff, Tag, Tagger};
+
+/// Leading bytes identifying each compression format.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x1f\x8b", "gzip"),
+    (b"\x28\xb5\x2f\xfd", "zstd"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"BZh", "bzip2"),
+];
+const SNIFF_LEN: u64 = 6;
+
+/// Sniffs compression magic bytes at the start of a file, emitting
+/// `compression:<format>`, or `compression:none` when none match.
+#[derive(Debug, Default)]
+pub struct CompressionTagger;
+impl CompressionTagger {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn detect(header: &[u8]) -> &'static str {
+        SIGNATURES
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map_or("none", |(_, format)| format)
+    }
+}
+impl Tagger for CompressionTagger {
+    fn name(&self) -> &str {
+        "compression"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let sniff = Sniff::read(path, SNIFF_LEN).map_err(|e| {
+            error!(error = ?e, "read for compression");
+            Error::Illegible
+        })?;
+        self.tag_sniffed(path, &sniff)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, _path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        Ok(HashSet::from([Tag::new(
+            "compression",
+            true,
+            Self::detect(&sniff.prefix),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs, io};
+
+    use crate::tagger::{Tag, Tagger};
+
+    use super::CompressionTagger;
+
+    #[test]
+    fn detects_signatures() -> io::Result<()> {
+        let path = env::temp_dir().join("compression_tagger_detects_signatures");
+        for (content, expected) in [
+            (&b"\x1f\x8b\x08\x00rest"[..], "gzip"),
+            (b"\x28\xb5\x2f\xfd\x00", "zstd"),
+            (b"\xfd7zXZ\x00\x00", "xz"),
+            (b"BZh91AY&SY", "bzip2"),
+            (b"plain text\n", "none"),
+            (b"", "none"),
+        ] {
+            fs::write(&path, content)?;
+            assert_eq!(
+                HashSet::from([Tag::new("compression", true, expected)]),
+                CompressionTagger::new().tag(&path).unwrap()
+            );
+        }
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
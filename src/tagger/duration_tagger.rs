@@ -0,0 +1,88 @@
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use tracing::{debug, error};
+
+use super::{probe_duration, Error, Tag, Tagger};
+
+/// Buckets audio and video by playing time, emitting a `duration` singleton
+/// of `<1min`, `1-5min`, `5-30min` or `>30min`.
+///
+/// The duration comes from [`probe_duration`], so one container parse serves
+/// audio and video alike. Files whose duration can't be determined, including
+/// everything that isn't media, get no tag.
+#[derive(Debug, Default)]
+pub struct DurationTagger {}
+impl DurationTagger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn bucket(duration: Duration) -> &'static str {
+    match duration.as_secs() {
+        0..60 => "<1min",
+        60..300 => "1-5min",
+        300..1800 => "5-30min",
+        _ => ">30min",
+    }
+}
+
+impl Tagger for DurationTagger {
+    fn name(&self) -> &str {
+        "duration"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let duration = probe_duration(path).map_err(|e| {
+            error!(error = ?e, "probe duration");
+            Error::Illegible
+        })?;
+        debug!(?path, ?duration, "probed duration");
+        Ok(duration
+            .map(|duration| Tag::new("duration", true, bucket(duration)))
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io, time::Duration};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::{bucket, DurationTagger};
+
+    /// Header of a WAV whose data chunk claims `seconds` of 8kHz mono 8-bit
+    /// audio; the samples themselves aren't needed.
+    fn wav(seconds: u32) -> Vec<u8> {
+        let mut wav =
+            b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x40\x1f\0\0\x40\x1f\0\0\x01\0\x08\0data"
+                .to_vec();
+        wav.extend_from_slice(&(seconds * 8000).to_le_bytes());
+        wav
+    }
+
+    #[test]
+    fn short_and_long_media() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("duration", true, "<1min")]),
+            tags_of(&DurationTagger::new(), "short.wav", wav(12))?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("duration", true, ">30min")]),
+            tags_of(&DurationTagger::new(), "long.wav", wav(3600))?
+        );
+        assert!(tags_of(&DurationTagger::new(), "text.txt", b"no duration here")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bucket_boundaries() {
+        let bucket = |secs| bucket(Duration::from_secs(secs));
+        assert_eq!("<1min", bucket(59));
+        assert_eq!("1-5min", bucket(60));
+        assert_eq!("5-30min", bucket(300));
+        assert_eq!("5-30min", bucket(1799));
+        assert_eq!(">30min", bucket(1800));
+    }
+}
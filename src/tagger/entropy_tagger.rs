@@ -0,0 +1,137 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::error;
+
+use super::{Error, Sniff, Tag, Tagger};
+
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+/// Bits per byte above which content is likely encrypted or compressed.
+const HIGH_ENTROPY: f64 = 7.5;
+/// Bits per byte below which content is likely plain text or sparse data.
+const LOW_ENTROPY: f64 = 5.0;
+
+/// Estimates the Shannon entropy of the start of a file, emitting
+/// `entropy:high` for likely encrypted or packed content, otherwise
+/// `entropy:medium` or `entropy:low`.
+///
+/// Only the first sample-size bytes are read, or the shared sniff prefix when
+/// run by a [`FileUpdater`](crate::FileUpdater); empty files aren't tagged.
+#[derive(Debug)]
+pub struct EntropyTagger {
+    sample_size: u64,
+}
+impl Default for EntropyTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl EntropyTagger {
+    pub fn new() -> Self {
+        Self::with_sample_size(DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_sample_size(sample_size: u64) -> Self {
+        Self { sample_size }
+    }
+
+    /// Shannon entropy of `sample`, in bits per byte.
+    fn entropy(sample: &[u8]) -> f64 {
+        let mut counts = [0usize; 256];
+        for byte in sample {
+            counts[*byte as usize] += 1;
+        }
+        let len = sample.len() as f64;
+        counts
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f64 / len;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    fn bucket(entropy: f64) -> &'static str {
+        if entropy > HIGH_ENTROPY {
+            "high"
+        } else if entropy < LOW_ENTROPY {
+            "low"
+        } else {
+            "medium"
+        }
+    }
+}
+impl Tagger for EntropyTagger {
+    fn name(&self) -> &str {
+        "entropy"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let sniff = Sniff::read(path, self.sample_size).map_err(|e| {
+            error!(error = ?e, "read for entropy");
+            Error::Illegible
+        })?;
+        self.tag_sniffed(path, &sniff)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, _path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let sample = &sniff.prefix;
+        if sample.is_empty() {
+            return Ok(HashSet::new());
+        }
+        Ok(HashSet::from([Tag::new(
+            "entropy",
+            true,
+            Self::bucket(Self::entropy(sample)),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs, io};
+
+    use crate::tagger::{Tag, Tagger};
+
+    use super::EntropyTagger;
+
+    /// Deterministic pseudo-random bytes, from a xorshift generator.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_is_high() -> io::Result<()> {
+        let path = env::temp_dir().join("entropy_tagger_random_is_high");
+        fs::write(&path, random_bytes(256 * 1024))?;
+        let tags = EntropyTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert_eq!(HashSet::from([Tag::new("entropy", true, "high")]), tags);
+        Ok(())
+    }
+
+    #[test]
+    fn repetitive_text_is_low() -> io::Result<()> {
+        let path = env::temp_dir().join("entropy_tagger_repetitive_text_is_low");
+        fs::write(
+            &path,
+            "all work and no play makes jack a dull boy\n".repeat(1000),
+        )?;
+        let tags = EntropyTagger::new().tag(&path).unwrap();
+        fs::write(&path, "")?;
+        let empty = EntropyTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert_eq!(HashSet::from([Tag::new("entropy", true, "low")]), tags);
+        assert!(empty.is_empty());
+        Ok(())
+    }
+}
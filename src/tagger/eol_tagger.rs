@@ -0,0 +1,104 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Detects the newline convention of text files, emitting `eol:lf`,
+/// `eol:crlf` or `eol:mixed` from their [text sample](Sniff::text_sample).
+/// Samples with no line breaks aren't tagged.
+#[derive(Debug)]
+pub struct EolTagger {
+    limits: SampleLimits,
+}
+impl Default for EolTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl EolTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_limits(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, sample_size),
+        }
+    }
+}
+impl Tagger for EolTagger {
+    fn name(&self) -> &str {
+        "eol"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(sample) = sniff.text_sample(self.limits) else {
+            debug!(?path, "skip eol");
+            return Ok(HashSet::new());
+        };
+        let (mut lf, mut crlf) = (false, false);
+        for (idx, _) in sample.iter().enumerate().filter(|(_, b)| **b == b'\n') {
+            match idx > 0 && sample[idx - 1] == b'\r' {
+                true => crlf = true,
+                false => lf = true,
+            }
+        }
+
+        let eol = match (lf, crlf) {
+            (true, true) => "mixed",
+            (true, false) => "lf",
+            (false, true) => "crlf",
+            (false, false) => return Ok(HashSet::new()),
+        };
+        Ok(HashSet::from([Tag::new("eol", true, eol)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::EolTagger;
+
+    #[test]
+    fn detects_convention() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("eol", true, "lf")]),
+            tags_of(&EolTagger::new(), "lf", b"one\ntwo\nthree")?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("eol", true, "crlf")]),
+            tags_of(&EolTagger::new(), "crlf", b"one\r\ntwo\r\n")?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("eol", true, "mixed")]),
+            tags_of(&EolTagger::new(), "mixed", b"one\r\ntwo\nthree\r\n")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_binary_and_single_lines() -> io::Result<()> {
+        assert!(tags_of(&EolTagger::new(), "binary", b"\x00\x01\r\n\x02\n")?.is_empty());
+        assert!(tags_of(&EolTagger::new(), "single", b"no newline")?.is_empty());
+
+        let tags = tags_of(
+            &EolTagger::with_limits(1024, 12),
+            "sampled",
+            b"first line\r\nsecond\n",
+        )?;
+        assert_eq!(HashSet::from([Tag::new("eol", true, "crlf")]), tags);
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Reversible escaping of `/` in tag values, which can't appear in a path
+//! component.
+
+/// How `/` in a tag value is made path-safe. Both schemes percent-escape
+/// whatever would otherwise be ambiguous, so [`SlashEscape::unescape`]
+/// always recovers the original value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SlashEscape {
+    /// `/` becomes `|`, so `text/plain` reads `text|plain`; a literal `|` is
+    /// escaped as `%7C`
+    #[default]
+    Pipe,
+    /// `/` becomes `%2F`
+    Percent,
+}
+impl SlashEscape {
+    pub fn escape(self, value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match (self, c) {
+                (_, '%') => escaped.push_str("%25"),
+                (SlashEscape::Pipe, '|') => escaped.push_str("%7C"),
+                (SlashEscape::Pipe, '/') => escaped.push('|'),
+                (SlashEscape::Percent, '/') => escaped.push_str("%2F"),
+                (_, c) => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// The value [`SlashEscape::escape`] was given. Malformed escapes are
+    /// kept as-is.
+    pub fn unescape(self, escaped: &str) -> String {
+        let mut value = Vec::with_capacity(escaped.len());
+        let mut bytes = escaped.bytes();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'|' if self == SlashEscape::Pipe => value.push(b'/'),
+                b'%' => {
+                    let hex = bytes.clone().take(2).collect::<Vec<_>>();
+                    match std::str::from_utf8(&hex)
+                        .ok()
+                        .filter(|hex| hex.len() == 2)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    {
+                        Some(decoded) => {
+                            value.push(decoded);
+                            bytes.nth(1);
+                        }
+                        None => value.push(b),
+                    }
+                }
+                b => value.push(b),
+            }
+        }
+        String::from_utf8_lossy(&value).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SlashEscape;
+
+    #[test]
+    fn round_trips_pipes() {
+        for value in ["application/vnd.x|y", "a%7Cb/c", "plain", "%", "x|/%2F"] {
+            for escape in [SlashEscape::Pipe, SlashEscape::Percent] {
+                let escaped = escape.escape(value);
+                assert!(!escaped.contains('/'));
+                assert_eq!(value, escape.unescape(&escaped), "{escape:?}");
+            }
+        }
+        assert_eq!(
+            "application|vnd.x%7Cy",
+            SlashEscape::Pipe.escape("application/vnd.x|y")
+        );
+        assert_eq!(
+            "application%2Fvnd.x|y",
+            SlashEscape::Percent.escape("application/vnd.x|y")
+        );
+        assert_eq!("text|plain", SlashEscape::Pipe.escape("text/plain"));
+        assert_eq!("100%zz", SlashEscape::Pipe.unescape("100%zz"));
+    }
+}
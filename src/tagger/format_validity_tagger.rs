@@ -0,0 +1,231 @@
+use std::{collections::HashSet, fs::File, io::Read as _, path::Path};
+
+use saphyr_parser::Parser;
+use serde::de::IgnoredAny;
+use tracing::{debug, error};
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+use super::{Error, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Serialization formats whose files are checked, each tagged under its own
+/// label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Xml,
+}
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            "xml" => Some(Self::Xml),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Xml => "xml",
+        }
+    }
+
+    fn is_valid(self, content: &[u8]) -> bool {
+        match self {
+            Self::Json => serde_json::from_slice::<IgnoredAny>(content).is_ok(),
+            // Every document of a stream has to parse
+            Self::Yaml => std::str::from_utf8(content)
+                .is_ok_and(|content| Parser::new_from_str(content).all(|event| event.is_ok())),
+            Self::Toml => std::str::from_utf8(content)
+                .is_ok_and(|content| content.parse::<toml::Table>().is_ok()),
+            Self::Xml => std::str::from_utf8(content).is_ok_and(is_well_formed_xml),
+        }
+    }
+}
+
+/// Whether `xml` is one root element, with every element closed by its own
+/// name.
+fn is_well_formed_xml(xml: &str) -> bool {
+    let mut open = Vec::new();
+    let mut roots = 0;
+    for token in Tokenizer::from(xml) {
+        match token {
+            Err(_) => return false,
+            Ok(Token::ElementStart { prefix, local, .. }) => {
+                if open.is_empty() {
+                    roots += 1;
+                }
+                open.push((prefix.as_str(), local.as_str()));
+            }
+            Ok(Token::ElementEnd { end, .. }) => match end {
+                ElementEnd::Open => {}
+                ElementEnd::Empty => {
+                    open.pop();
+                }
+                ElementEnd::Close(prefix, local) => {
+                    if open.pop() != Some((prefix.as_str(), local.as_str())) {
+                        return false;
+                    }
+                }
+            },
+            Ok(_) => {}
+        }
+    }
+    open.is_empty() && roots == 1
+}
+
+/// Separates well-formed JSON, YAML, TOML and XML files from broken ones,
+/// emitting e.g. `json:valid` or `json:invalid`.
+///
+/// The format comes from the extension. Files are parsed whole, so ones over
+/// the size cap, which a truncated parse would call invalid, aren't tagged.
+#[derive(Debug)]
+pub struct FormatValidityTagger {
+    max_size: u64,
+}
+impl Default for FormatValidityTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl FormatValidityTagger {
+    pub fn new() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Parse only files of at most `max_size` bytes.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl Tagger for FormatValidityTagger {
+    fn name(&self) -> &str {
+        "format-validity"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let Some(format) = Format::from_path(path) else {
+            return Ok(HashSet::new());
+        };
+        let file = File::open(path).map_err(|e| {
+            error!(error = ?e, "open for format validity");
+            Error::Illegible
+        })?;
+        match file.metadata() {
+            Ok(metadata) if !metadata.is_file() || metadata.len() > self.max_size => {
+                debug!(?path, "skip format validity");
+                return Ok(HashSet::new());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!(error = ?e, "get file metadata");
+                return Err(Error::Illegible);
+            }
+        }
+        let mut content = Vec::new();
+        file.take(self.max_size)
+            .read_to_end(&mut content)
+            .map_err(|e| {
+                error!(error = ?e, "read for format validity");
+                Error::Illegible
+            })?;
+        let validity = match format.is_valid(&content) {
+            true => "valid",
+            false => "invalid",
+        };
+        Ok(HashSet::from([Tag::new(format.label(), true, validity)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::FormatValidityTagger;
+
+    fn validity(label: &str, value: &str) -> HashSet<Tag> {
+        HashSet::from([Tag::new(label, true, value)])
+    }
+
+    #[test]
+    fn valid_and_malformed_json() -> io::Result<()> {
+        let tagger = FormatValidityTagger::new();
+        assert_eq!(
+            validity("json", "valid"),
+            tags_of(
+                &tagger,
+                "good.json",
+                br#"{"name": "octo", "tags": ["a", "b"], "depth": 2}"#
+            )?
+        );
+        assert_eq!(
+            validity("json", "invalid"),
+            tags_of(&tagger, "trailing.json", br#"{"name": "octo",}"#)?
+        );
+        assert_eq!(
+            validity("json", "invalid"),
+            tags_of(&tagger, "truncated.JSON", br#"{"tags": ["a", "#)?
+        );
+        // Too big to parse whole
+        assert!(tags_of(
+            &FormatValidityTagger::new().with_max_size(4),
+            "big.json",
+            b"[1, 2, 3]"
+        )?
+        .is_empty());
+        assert!(tags_of(&tagger, "notes.txt", b"{")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn other_formats() -> io::Result<()> {
+        let tagger = FormatValidityTagger::new();
+        assert_eq!(
+            validity("yaml", "valid"),
+            tags_of(&tagger, "good.yml", b"a: 1\n---\nb: [2, 3]\n")?
+        );
+        assert_eq!(
+            validity("yaml", "invalid"),
+            tags_of(&tagger, "bad.yaml", b"a: 1\n---\nb: [2, 3\n")?
+        );
+        assert_eq!(
+            validity("toml", "valid"),
+            tags_of(&tagger, "good.toml", b"[package]\nname = \"octo\"\n")?
+        );
+        assert_eq!(
+            validity("toml", "invalid"),
+            tags_of(&tagger, "bad.toml", b"name = \n")?
+        );
+        assert_eq!(
+            validity("xml", "valid"),
+            tags_of(
+                &tagger,
+                "good.xml",
+                b"<?xml version=\"1.0\"?>\n<a x=\"1\"><b/><c>text</c></a>\n"
+            )?
+        );
+        assert_eq!(
+            validity("xml", "invalid"),
+            tags_of(&tagger, "mismatched.xml", b"<a><b></a></b>")?
+        );
+        assert_eq!(
+            validity("xml", "invalid"),
+            tags_of(&tagger, "unclosed.xml", b"<a><b/>")?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use tracing::error;
+
+use super::{mime_tagger::MimeExtractor, Error, Tag, Tagger};
+
+const DEFAULT_TYPES: &str = include_str!("friendly_types.toml");
+
+/// Maps the detected MIME type to a short friendly name, emitted as a `type`
+/// singleton alongside the raw `mime` tag.
+///
+/// The mapping is a TOML table of `"mime/type" = "name"` pairs. MIME types
+/// missing from the table fall back to their top-level category.
+#[derive(Debug)]
+pub struct FriendlyTypeTagger<T> {
+    mime_extractor: T,
+    types: HashMap<String, String>,
+}
+impl<T: MimeExtractor> Default for FriendlyTypeTagger<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: MimeExtractor> FriendlyTypeTagger<T> {
+    pub fn new() -> Self {
+        Self {
+            mime_extractor: T::new(),
+            types: toml::from_str(DEFAULT_TYPES).expect("default friendly type table"),
+        }
+    }
+
+    /// Layer a user-supplied TOML table over the defaults.
+    pub fn with_overrides(mut self, table: &str) -> Result<Self, toml::de::Error> {
+        let overrides: HashMap<String, String> = toml::from_str(table)?;
+        self.types.extend(overrides);
+        Ok(self)
+    }
+
+    fn friendly_name<'a>(&'a self, mime: &'a str) -> &'a str {
+        match self.types.get(mime) {
+            Some(name) => name,
+            None => mime.split_once('/').map_or(mime, |(category, _)| category),
+        }
+    }
+}
+impl<T: MimeExtractor + std::fmt::Debug> Tagger for FriendlyTypeTagger<T> {
+    fn name(&self) -> &str {
+        "friendly-type"
+    }
+
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.mime_extractor
+            .file(path)
+            .map(|mime| HashSet::from([Tag::new("type", true, self.friendly_name(&mime))]))
+            .map_err(|e| {
+                error!(error = ?e, "get mime type");
+                Error::Illegible
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+
+    use crate::tagger::{mime_tagger::MimeExtractor, Tag, Tagger as _};
+
+    use super::FriendlyTypeTagger;
+
+    static MIME: Mutex<&str> = Mutex::new("");
+
+    #[derive(Debug)]
+    struct TestExtractor {}
+    impl MimeExtractor for TestExtractor {
+        fn new() -> Self {
+            Self {}
+        }
+        fn file(&self, _filename: &Path) -> Result<String, anyhow::Error> {
+            Ok(MIME.lock().unwrap().to_string())
+        }
+    }
+
+    fn tag_as(tagger: &FriendlyTypeTagger<TestExtractor>, mime: &'static str) -> HashSet<Tag> {
+        let mut guard = MIME.lock().unwrap();
+        *guard = mime;
+        drop(guard);
+        tagger.tag(&PathBuf::from("bob")).unwrap()
+    }
+
+    #[test]
+    fn mapped_type() {
+        let tagger = FriendlyTypeTagger::<TestExtractor>::new();
+        assert_eq!(
+            HashSet::from([Tag::new("type", true, "pdf")]),
+            tag_as(&tagger, "application/pdf")
+        );
+    }
+
+    #[test]
+    fn unmapped_type_falls_back_to_category() {
+        let tagger = FriendlyTypeTagger::<TestExtractor>::new();
+        assert_eq!(
+            HashSet::from([Tag::new("type", true, "chemical")]),
+            tag_as(&tagger, "chemical/x-pdb")
+        );
+    }
+
+    #[test]
+    fn overrides_extend_defaults() {
+        let tagger = FriendlyTypeTagger::<TestExtractor>::new()
+            .with_overrides(r#""chemical/x-pdb" = "protein""#)
+            .unwrap();
+        assert_eq!(
+            HashSet::from([Tag::new("type", true, "protein")]),
+            tag_as(&tagger, "chemical/x-pdb")
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("type", true, "pdf")]),
+            tag_as(&tagger, "application/pdf")
+        );
+        assert!(FriendlyTypeTagger::<TestExtractor>::new()
+            .with_overrides("not toml")
+            .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+const DEFAULT_MAX_LINES: usize = 1000;
+
+/// Detects how text files indent their lines, emitting `indent:tabs`,
+/// `indent:spaces` or `indent:mixed`.
+///
+/// Only the first max-lines lines of the [text sample](Sniff::text_sample)
+/// are looked at. Files with no indented lines aren't tagged.
+#[derive(Debug)]
+pub struct IndentTagger {
+    limits: SampleLimits,
+    max_lines: usize,
+}
+impl Default for IndentTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl IndentTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_MAX_LINES)
+    }
+
+    pub fn with_limits(max_size: u64, max_lines: usize) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, DEFAULT_SAMPLE_SIZE),
+            max_lines,
+        }
+    }
+}
+impl Tagger for IndentTagger {
+    fn name(&self) -> &str {
+        "indent"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(sample) = sniff.text_sample(self.limits) else {
+            debug!(?path, "skip indent");
+            return Ok(HashSet::new());
+        };
+        let (mut tabs, mut spaces) = (false, false);
+        for line in sample.split(|b| *b == b'\n').take(self.max_lines) {
+            // Whitespace-only lines say nothing about the style
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let indent = line.iter().take_while(|b| **b == b'\t' || **b == b' ');
+            for b in indent {
+                tabs |= *b == b'\t';
+                spaces |= *b == b' ';
+            }
+        }
+
+        let style = match (tabs, spaces) {
+            (true, true) => "mixed",
+            (true, false) => "tabs",
+            (false, true) => "spaces",
+            (false, false) => return Ok(HashSet::new()),
+        };
+        Ok(HashSet::from([Tag::new("indent", true, style)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::IndentTagger;
+
+    #[test]
+    fn detects_style() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("indent", true, "tabs")]),
+            tags_of(
+                &IndentTagger::new(),
+                "tabs",
+                "fn main() {\n\tlet x = 1;\n\t\n\t\tx\n}\n"
+            )?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("indent", true, "spaces")]),
+            tags_of(
+                &IndentTagger::new(),
+                "spaces",
+                "fn main() {\n    let x = 1;\n\t\n        x\n}\n"
+            )?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("indent", true, "mixed")]),
+            tags_of(
+                &IndentTagger::new(),
+                "mixed",
+                "fn main() {\n\tlet x = 1;\n    x\n}\n"
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_unindented_binary_and_sampled_out() -> io::Result<()> {
+        assert!(tags_of(&IndentTagger::new(), "flat", "a\nb\n")?.is_empty());
+        assert!(tags_of(&IndentTagger::new(), "binary", "\0\n\tx\n")?.is_empty());
+
+        let tags = tags_of(
+            &IndentTagger::with_limits(1024, 2),
+            "sampled_out",
+            "a\nb\n\tc\n",
+        )?;
+        assert!(tags.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashSet, fs, os::unix::fs::FileTypeExt as _, path::Path};
+
+use tracing::error;
+
+use super::{Error, Tag, Tagger};
+
+/// Tags each entry with its kind: `kind:regular`, `kind:symlink`,
+/// `kind:fifo`, and so on. Symlinks are reported as such, not followed.
+#[derive(Debug, Default)]
+pub struct KindTagger;
+impl KindTagger {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn kind(file_type: fs::FileType) -> &'static str {
+        if file_type.is_symlink() {
+            "symlink"
+        } else if file_type.is_dir() {
+            "directory"
+        } else if file_type.is_fifo() {
+            "fifo"
+        } else if file_type.is_socket() {
+            "socket"
+        } else if file_type.is_block_device() {
+            "block-device"
+        } else if file_type.is_char_device() {
+            "char-device"
+        } else {
+            "regular"
+        }
+    }
+}
+impl Tagger for KindTagger {
+    fn name(&self) -> &str {
+        "kind"
+    }
+    fn tags_special_files(&self) -> bool {
+        true
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let metadata = fs::symlink_metadata(path).map_err(|e| {
+            error!(error = ?e, "get file kind");
+            Error::Illegible
+        })?;
+        Ok(HashSet::from([Tag::new(
+            "kind",
+            true,
+            Self::kind(metadata.file_type()),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, ffi::CString, fs, io, os::unix::ffi::OsStrExt as _};
+
+    use crate::tagger::{Tag, Tagger};
+
+    use super::KindTagger;
+
+    #[test]
+    fn kinds() -> io::Result<()> {
+        let dir = env::temp_dir().join("kind_tagger_kinds");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let regular = dir.join("regular");
+        fs::write(&regular, "x")?;
+        let symlink = dir.join("symlink");
+        std::os::unix::fs::symlink(&regular, &symlink)?;
+        let fifo = dir.join("fifo");
+        let fifo_c = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(fifo_c.as_ptr(), 0o644) });
+
+        let tagger = KindTagger::new();
+        let kind = |value| HashSet::from([Tag::new("kind", true, value)]);
+        assert_eq!(kind("regular"), tagger.tag(&regular).unwrap());
+        assert_eq!(kind("symlink"), tagger.tag(&symlink).unwrap());
+        assert_eq!(kind("fifo"), tagger.tag(&fifo).unwrap());
+        assert_eq!(kind("directory"), tagger.tag(&dir).unwrap());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::{debug, error};
+
+use super::{Error, Sniff, Tag, Tagger};
+
+const EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cs", "css", "go", "h", "hpp", "java", "js", "jsx", "kt", "lua", "m", "mjs",
+    "php", "pl", "py", "rb", "rs", "scala", "sh", "swift", "ts", "tsx",
+];
+/// Bytes at the start of a file searched for a license header.
+const HEADER_SIZE: usize = 2048;
+const SPDX_MARKER: &str = "SPDX-License-Identifier:";
+/// Header phrases, lowercased and with comment markers stripped, and the
+/// SPDX identifier each implies. Earlier entries win, so more specific
+/// phrases come first.
+const PHRASES: &[(&[&str], &str)] = &[
+    (&["apache license", "version 2.0"], "Apache-2.0"),
+    (
+        &["gnu lesser general public license", "version 3"],
+        "LGPL-3.0",
+    ),
+    (
+        &["gnu lesser general public license", "version 2.1"],
+        "LGPL-2.1",
+    ),
+    (
+        &["gnu affero general public license", "version 3"],
+        "AGPL-3.0",
+    ),
+    (&["gnu general public license", "version 3"], "GPL-3.0"),
+    (&["gnu general public license", "version 2"], "GPL-2.0"),
+    (&["mozilla public license, v. 2.0"], "MPL-2.0"),
+    (&["permission is hereby granted, free of charge"], "MIT"),
+    (
+        &["permission to use, copy, modify, and/or distribute this software"],
+        "ISC",
+    ),
+    (
+        &[
+            "redistribution and use in source and binary forms",
+            "neither the name",
+        ],
+        "BSD-3-Clause",
+    ),
+    (
+        &["redistribution and use in source and binary forms"],
+        "BSD-2-Clause",
+    ),
+    (
+        &["this is free and unencumbered software released into the public domain"],
+        "Unlicense",
+    ),
+];
+
+/// Tags source files with the license declared in their header, emitting
+/// `license:<SPDX id>` from an `SPDX-License-Identifier:` line or a
+/// well-known license phrase, and `license:unknown` when there's neither.
+///
+/// Only the first 2KB of files with a source extension are read; other files
+/// and binary content aren't tagged.
+#[derive(Debug, Default)]
+pub struct LicenseTagger;
+impl LicenseTagger {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn is_source(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+    }
+
+    /// The expression following an SPDX marker, without any comment closer.
+    fn spdx(header: &str) -> Option<&str> {
+        let (_, rest) = header.split_once(SPDX_MARKER)?;
+        let line = rest.lines().next().unwrap_or_default();
+        let expression = line
+            .trim()
+            .trim_end_matches("*/")
+            .trim_end_matches("-->")
+            .trim();
+        (!expression.is_empty()).then_some(expression)
+    }
+
+    /// The header as lowercase prose, with comment markers stripped and
+    /// whitespace collapsed so phrases match across wrapped lines.
+    fn prose(header: &str) -> String {
+        header
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_start_matches(['/', '*', '#', ';', '-', '!', '<'])
+            })
+            .flat_map(str::split_whitespace)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    }
+
+    fn detect(header: &str) -> &str {
+        if let Some(expression) = Self::spdx(header) {
+            return expression;
+        }
+        let prose = Self::prose(header);
+        PHRASES
+            .iter()
+            .find(|(phrases, _)| phrases.iter().all(|phrase| prose.contains(phrase)))
+            .map_or("unknown", |(_, license)| license)
+    }
+}
+impl Tagger for LicenseTagger {
+    fn name(&self) -> &str {
+        "license"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        if !Self::is_source(path) {
+            return Ok(HashSet::new());
+        }
+        let sniff = Sniff::read(path, HEADER_SIZE as u64).map_err(|e| {
+            error!(error = ?e, "read for license");
+            Error::Illegible
+        })?;
+        self.tag_sniffed(path, &sniff)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        if !sniff.is_file || !Self::is_source(path) {
+            return Ok(HashSet::new());
+        }
+        let header = &sniff.prefix[..sniff.prefix.len().min(HEADER_SIZE)];
+        if header.contains(&0) {
+            debug!(?path, "binary content, skip license");
+            return Ok(HashSet::new());
+        }
+        let header = String::from_utf8_lossy(header);
+        Ok(HashSet::from([Tag::new(
+            "license",
+            true,
+            Self::detect(&header),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::LicenseTagger;
+
+    #[test]
+    fn spdx_identifiers() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("license", true, "MIT")]),
+            tags_of(
+                &LicenseTagger::new(),
+                "spdx.rs",
+                "// SPDX-License-Identifier: MIT\nfn main() {}\n"
+            )?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("license", true, "Apache-2.0 OR MIT")]),
+            tags_of(
+                &LicenseTagger::new(),
+                "spdx.c",
+                "/* SPDX-License-Identifier: Apache-2.0 OR MIT */\nint x;\n"
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn header_phrases() -> io::Result<()> {
+        let apache = "# Licensed under the Apache License,\n# Version 2.0 (the \"License\");\n# you may not use this file except in compliance with the License.\n";
+        assert_eq!(
+            HashSet::from([Tag::new("license", true, "Apache-2.0")]),
+            tags_of(&LicenseTagger::new(), "apache.py", apache)?
+        );
+        let bsd = " * Redistribution and use in source and binary forms, with or without\n * modification, are permitted provided that the following conditions\n * are met: ... Neither the name of the copyright holder ...\n";
+        assert_eq!(
+            HashSet::from([Tag::new("license", true, "BSD-3-Clause")]),
+            tags_of(&LicenseTagger::new(), "bsd.java", bsd)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_and_skipped() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("license", true, "unknown")]),
+            tags_of(&LicenseTagger::new(), "plain.go", "package main\n")?
+        );
+        assert!(tags_of(
+            &LicenseTagger::new(),
+            "notes.txt",
+            "SPDX-License-Identifier: MIT\n"
+        )?
+        .is_empty());
+        Ok(())
+    }
+}
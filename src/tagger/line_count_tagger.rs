@@ -0,0 +1,161 @@
+use std::{collections::HashSet, fs::File, io::Read, path::Path};
+
+use tracing::{debug, error};
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Counts lines in text files, emitting both the exact count and a coarse
+/// bucket under the `lines` label.
+///
+/// Files without a [text sample](Sniff::text_sample) are skipped; the rest
+/// are read whole.
+#[derive(Debug)]
+pub struct LineCountTagger {
+    limits: SampleLimits,
+}
+impl Default for LineCountTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl LineCountTagger {
+    pub fn new() -> Self {
+        Self::with_max_size(DEFAULT_MAX_SIZE)
+    }
+
+    pub fn with_max_size(max_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, CHUNK_SIZE as u64),
+        }
+    }
+
+    /// Newlines in `reader`, and its last byte.
+    fn count(mut reader: impl Read) -> Result<(u64, Option<u8>), Error> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut lines = 0;
+        let mut last = None;
+        loop {
+            let count = reader.read(&mut buf).map_err(|e| {
+                error!(error = ?e, "read for line count");
+                Error::Illegible
+            })?;
+            if count == 0 {
+                break;
+            }
+            let chunk = &buf[..count];
+            lines += chunk.iter().filter(|b| **b == b'\n').count() as u64;
+            last = chunk.last().copied();
+        }
+        Ok((lines, last))
+    }
+
+    fn bucket(lines: u64) -> &'static str {
+        match lines {
+            0..100 => "small",
+            100..1000 => "medium",
+            _ => "large",
+        }
+    }
+}
+impl Tagger for LineCountTagger {
+    fn name(&self) -> &str {
+        "line-count"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        if sniff.text_sample(self.limits).is_none() {
+            debug!(?path, "skip line count");
+            return Ok(HashSet::new());
+        }
+        // Only read the file again when the sniff didn't hold all of it
+        let (mut lines, last) = match sniff.prefix.len() as u64 == sniff.size {
+            true => Self::count(sniff.prefix.as_slice())?,
+            false => Self::count(File::open(path).map_err(|e| {
+                error!(error = ?e, "open for line count");
+                Error::Illegible
+            })?)?,
+        };
+        // A final line without a trailing newline still counts
+        if last.is_some_and(|b| b != b'\n') {
+            lines += 1;
+        }
+
+        Ok(HashSet::from([
+            Tag::new("lines", false, lines.to_string()),
+            Tag::new("lines", false, Self::bucket(lines)),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs, io};
+
+    use crate::tagger::{Tag, Tagger};
+
+    use super::LineCountTagger;
+
+    #[test]
+    fn counts_lines() -> io::Result<()> {
+        let path = env::temp_dir().join("line_count_tagger_counts_lines");
+        fs::write(&path, "one\ntwo\nthree\n")?;
+        let tags = LineCountTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert_eq!(
+            HashSet::from([
+                Tag::new("lines", false, "3"),
+                Tag::new("lines", false, "small")
+            ]),
+            tags
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn counts_without_trailing_newline() -> io::Result<()> {
+        let path = env::temp_dir().join("line_count_tagger_no_trailing_newline");
+        fs::write(&path, "x\n".repeat(149) + "last")?;
+        let tags = LineCountTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert_eq!(
+            HashSet::from([
+                Tag::new("lines", false, "150"),
+                Tag::new("lines", false, "medium")
+            ]),
+            tags
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_binary_and_large() -> io::Result<()> {
+        let path = env::temp_dir().join("line_count_tagger_skips");
+        fs::write(&path, b"\x00\x01\n\x02")?;
+        assert!(LineCountTagger::new().tag(&path).unwrap().is_empty());
+        fs::write(&path, "a\nb\n")?;
+        assert!(LineCountTagger::with_max_size(1)
+            .tag(&path)
+            .unwrap()
+            .is_empty());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn empty_file() -> io::Result<()> {
+        let path = env::temp_dir().join("line_count_tagger_empty");
+        fs::write(&path, "")?;
+        let tags = LineCountTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert!(tags.contains(&Tag::new("lines", false, "0")));
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+const DEFAULT_THRESHOLD: usize = 200;
+
+/// Flags text files with overlong lines, emitting `long-lines:yes` or
+/// `long-lines:no` against a threshold, and the longest line as a
+/// `max-line` bucket of `<80`, `80-120`, `120-200`, `200-1000` or `>1000`
+/// characters. Minified and generated files stand out this way.
+///
+/// Only the [text sample](Sniff::text_sample) is looked at, so a line running
+/// past it counts as long as the part sampled. Empty files aren't tagged.
+#[derive(Debug)]
+pub struct LineLengthTagger {
+    limits: SampleLimits,
+    threshold: usize,
+}
+impl Default for LineLengthTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl LineLengthTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_limits(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, sample_size),
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Count lines of more than `threshold` characters as long.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+}
+
+/// Characters in a line of UTF-8, not counting continuation bytes.
+fn chars(line: &[u8]) -> usize {
+    line.iter().filter(|b| **b & 0xc0 != 0x80).count()
+}
+
+/// Line lengths of a text sample, which both this and the
+/// [`MinifiedTagger`](super::MinifiedTagger) judge files by.
+#[derive(Debug, Default)]
+pub(super) struct LineStats {
+    /// Lines with any characters
+    pub(super) lines: usize,
+    /// Characters on all lines, line breaks excluded
+    pub(super) chars: usize,
+    /// Characters on the longest line
+    pub(super) longest: usize,
+}
+impl LineStats {
+    pub(super) fn of(sample: &[u8]) -> Self {
+        sample
+            .split(|b| *b == b'\n')
+            .map(|line| chars(line.strip_suffix(b"\r").unwrap_or(line)))
+            .filter(|length| *length > 0)
+            .fold(Self::default(), |stats, length| Self {
+                lines: stats.lines + 1,
+                chars: stats.chars + length,
+                longest: stats.longest.max(length),
+            })
+    }
+
+    /// Mean characters per line, not counting empty ones.
+    pub(super) fn average(&self) -> f64 {
+        self.chars as f64 / self.lines.max(1) as f64
+    }
+}
+
+fn bucket(length: usize) -> &'static str {
+    match length {
+        0..80 => "<80",
+        80..120 => "80-120",
+        120..200 => "120-200",
+        200..1000 => "200-1000",
+        _ => ">1000",
+    }
+}
+
+impl Tagger for LineLengthTagger {
+    fn name(&self) -> &str {
+        "line-length"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(sample) = sniff.text_sample(self.limits).filter(|s| !s.is_empty()) else {
+            debug!(?path, "skip line length");
+            return Ok(HashSet::new());
+        };
+        let longest = LineStats::of(sample).longest;
+        let long = match longest > self.threshold {
+            true => "yes",
+            false => "no",
+        };
+        Ok(HashSet::from([
+            Tag::new("long-lines", true, long),
+            Tag::new("max-line", true, bucket(longest)),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::LineLengthTagger;
+
+    fn line_length(long: &str, max: &str) -> HashSet<Tag> {
+        HashSet::from([
+            Tag::new("long-lines", true, long),
+            Tag::new("max-line", true, max),
+        ])
+    }
+
+    #[test]
+    fn short_and_long_lines() -> io::Result<()> {
+        let tagger = LineLengthTagger::new();
+        assert_eq!(
+            line_length("no", "<80"),
+            tags_of(
+                &tagger,
+                "short.rs",
+                b"fn main() {\r\n    println!(\"hi\");\r\n}\r\n"
+            )?
+        );
+        let mut generated = b"// generated\nconst TABLE: &[u8] = &[".to_vec();
+        generated.extend("0, ".repeat(400).as_bytes());
+        generated.extend_from_slice(b"];\n");
+        assert_eq!(
+            line_length("yes", ">1000"),
+            tags_of(&tagger, "generated.rs", &generated)?
+        );
+        // Characters count, not bytes
+        assert_eq!(
+            line_length("no", "80-120"),
+            tags_of(&tagger, "accents.txt", "é".repeat(100).as_bytes())?
+        );
+        assert_eq!(
+            line_length("yes", "80-120"),
+            tags_of(
+                &LineLengthTagger::new().with_threshold(80),
+                "strict.txt",
+                "é".repeat(100).as_bytes()
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_empty_and_binary() -> io::Result<()> {
+        let tagger = LineLengthTagger::new();
+        assert!(tags_of(&tagger, "empty.txt", b"")?.is_empty());
+        assert!(tags_of(&tagger, "binary.bin", b"\0\x01\x02\n")?.is_empty());
+        Ok(())
+    }
+}
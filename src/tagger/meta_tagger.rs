@@ -1,23 +1,115 @@
-use std::{collections::HashSet, os::unix::fs::MetadataExt as _, path::Path};
+use std::{
+    collections::HashSet,
+    os::unix::fs::MetadataExt as _,
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
 use time::OffsetDateTime;
 use tracing::error;
 
-use super::{Error, Tag, Tagger};
+use super::{Error, Tag, Tagger, ACCESSED_AGE_LABEL};
 
+/// Unallocated bytes a file needs before it counts as sparse, so tail
+/// packing and preallocation rounding on small files don't.
+const SPARSE_MIN_HOLE: u64 = 1024 * 1024;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_ACCESSED_RECENT: Duration = DAY.saturating_mul(30);
+const DEFAULT_ACCESSED_ANCIENT: Duration = DAY.saturating_mul(365);
+
+/// Tags files with their `size` and `modified` time, plus `modified-weekday`
+/// (e.g. `Mon`) and `modified-hour` (`00` to `23`) facets for time-of-day
+/// browsing.
+///
+/// Files with more than one hard link also get a `hardlink-group:<dev>:<ino>`
+/// facet, shared by every name of the same inode. Files with at least a MiB
+/// of holes, allocating under half their apparent size, get a label-less
+/// `sparse` tag.
+///
+/// Every file gets an `accessed-age` of `never` when it hasn't been read
+/// since last modified, otherwise `recent`, `stale` or `ancient` by how long
+/// before it was tagged it was last read. This is best-effort: with `noatime`
+/// mounts access times aren't kept, so files read since written still show
+/// `never`, and `relatime` only updates them about daily. Taggers reading
+/// content count as accesses too, so should run after this one.
+///
+/// Times are always UTC, so the facets don't shift with the mounting host's
+/// timezone.
 #[derive(Debug)]
-pub struct MetadataTagger {}
+pub struct MetadataTagger {
+    /// Access ages under this are `recent`
+    accessed_recent: Duration,
+    /// Access ages over this are `ancient`, and between the two `stale`
+    accessed_ancient: Duration,
+}
+impl Default for MetadataTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl MetadataTagger {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            accessed_recent: DEFAULT_ACCESSED_RECENT,
+            accessed_ancient: DEFAULT_ACCESSED_ANCIENT,
+        }
+    }
+
+    /// Bucket access ages under `recent` as `recent` and over `ancient` as
+    /// `ancient`.
+    pub fn with_access_thresholds(mut self, recent: Duration, ancient: Duration) -> Self {
+        self.accessed_recent = recent;
+        self.accessed_ancient = ancient.max(recent);
+        self
+    }
+
+    /// Bucket at `now` for a file last accessed at `atime` and modified at
+    /// `mtime`, both in seconds since the epoch.
+    fn accessed_age(&self, now: SystemTime, atime: i64, mtime: i64) -> &'static str {
+        if atime <= mtime {
+            return "never";
+        }
+        let now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let age = Duration::from_secs(
+            u64::try_from(atime).map_or(now, |atime| now.saturating_sub(atime)),
+        );
+        if age < self.accessed_recent {
+            "recent"
+        } else if age <= self.accessed_ancient {
+            "stale"
+        } else {
+            "ancient"
+        }
     }
 }
 impl Tagger for MetadataTagger {
+    fn name(&self) -> &str {
+        "metadata"
+    }
     fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
         let mut tags = HashSet::new();
         match path.metadata() {
             Ok(metadata) if metadata.is_file() => {
                 tags.insert(Tag::new("size", true, metadata.size().to_string()));
+                if metadata.nlink() > 1 {
+                    tags.insert(Tag::new(
+                        "hardlink-group",
+                        true,
+                        format!("{}:{}", metadata.dev(), metadata.ino()),
+                    ));
+                }
+                let allocated = metadata.blocks().saturating_mul(512);
+                if allocated < metadata.size() / 2 && metadata.size() - allocated >= SPARSE_MIN_HOLE
+                {
+                    tags.insert(Tag::from("sparse"));
+                }
+                tags.insert(Tag::new(
+                    ACCESSED_AGE_LABEL,
+                    true,
+                    self.accessed_age(SystemTime::now(), metadata.atime(), metadata.mtime()),
+                ));
                 if let Ok(date) = metadata.modified() {
                     let t: OffsetDateTime = date.into();
                     tags.insert(Tag::new(
@@ -33,6 +125,12 @@ impl Tagger for MetadataTagger {
                             t.second()
                         ),
                     ));
+                    tags.insert(Tag::new(
+                        "modified-weekday",
+                        true,
+                        &t.weekday().to_string()[..3],
+                    ));
+                    tags.insert(Tag::new("modified-hour", true, format!("{:0>2}", t.hour())));
                 }
             }
             Ok(_) => error!("non-file for metadata"),
@@ -71,13 +169,121 @@ mod test {
 
         let tagger = MetadataTagger::new();
         let tags = tagger.tag(&path).unwrap();
-        assert_eq!(2, tags.len());
+        assert_eq!(5, tags.len());
         assert!(tags.contains(&Tag::new("size", true, "1234")));
         assert!(tags.contains(&Tag::new("modified", true, "1970-01-02 00:00:00")));
+        assert!(tags.contains(&Tag::new("modified-weekday", true, "Fri")));
+        assert!(tags.contains(&Tag::new("modified-hour", true, "00")));
         fs::remove_file(path)?;
         Ok(())
     }
 
+    #[test]
+    fn tags_accessed_age() -> io::Result<()> {
+        let path = std::env::temp_dir().join("meta_tagger_accessed_age");
+        let file = fs::File::create(&path)?;
+        let now = SystemTime::now();
+        let days = |n: u64| Duration::from_secs(n * 24 * 60 * 60);
+        let tagger = MetadataTagger::new();
+        let age = |accessed, modified| -> io::Result<Vec<Tag>> {
+            file.set_times(
+                fs::FileTimes::new()
+                    .set_accessed(now - days(accessed))
+                    .set_modified(now - days(modified)),
+            )?;
+            Ok(tagger
+                .tag(&path)
+                .unwrap()
+                .into_iter()
+                .filter(|tag| tag.has_label() && tag.label() == "accessed-age")
+                .collect())
+        };
+        assert_eq!(vec![Tag::new("accessed-age", true, "recent")], age(2, 40)?);
+        assert_eq!(
+            vec![Tag::new("accessed-age", true, "stale")],
+            age(100, 400)?
+        );
+        assert_eq!(
+            vec![Tag::new("accessed-age", true, "ancient")],
+            age(800, 900)?
+        );
+        // Not read since last written
+        assert_eq!(vec![Tag::new("accessed-age", true, "never")], age(20, 10)?);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tags_weekday_and_hour() -> io::Result<()> {
+        let path = std::env::temp_dir().join("meta_tagger_weekday_and_hour");
+        let file = fs::File::create(&path)?;
+        // Saturday 15/Jun/2024 14:30:00 UTC
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_718_461_800))?;
+
+        let tags = MetadataTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path)?;
+        assert!(tags.contains(&Tag::new("modified-weekday", true, "Sat")));
+        assert!(tags.contains(&Tag::new("modified-hour", true, "14")));
+        Ok(())
+    }
+
+    #[test]
+    fn tags_hardlink_group() -> io::Result<()> {
+        let dir = std::env::temp_dir().join("meta_tagger_hardlink_group");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let (first, second, single) = (dir.join("first"), dir.join("second"), dir.join("single"));
+        fs::write(&first, "shared")?;
+        fs::hard_link(&first, &second)?;
+        fs::write(&single, "alone")?;
+
+        let tagger = MetadataTagger::new();
+        let group = |path| {
+            tagger
+                .tag(path)
+                .unwrap()
+                .into_iter()
+                .filter(|tag| tag.label() == "hardlink-group")
+                .collect::<Vec<_>>()
+        };
+        let first_group = group(&first);
+        assert_eq!(1, first_group.len());
+        assert_eq!(first_group, group(&second));
+        assert!(group(&single).is_empty());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tags_sparse() -> io::Result<()> {
+        use std::{
+            io::{Seek as _, SeekFrom, Write as _},
+            os::unix::fs::MetadataExt as _,
+        };
+
+        let dense = std::env::temp_dir().join("meta_tagger_dense");
+        let sparse = std::env::temp_dir().join("meta_tagger_sparse");
+        fs::write(&dense, vec![1u8; 2 * 1024 * 1024])?;
+        let mut file = fs::File::create(&sparse)?;
+        file.seek(SeekFrom::Start(16 * 1024 * 1024))?;
+        file.write_all(b"end")?;
+        drop(file);
+
+        let tagger = MetadataTagger::new();
+        let sparse_tag = Tag::from("sparse");
+        assert!(!tagger.tag(&dense).unwrap().contains(&sparse_tag));
+        // Filesystems without hole support allocate the whole file
+        let metadata = fs::metadata(&sparse)?;
+        let supported = metadata.blocks() * 512 < metadata.size();
+        assert_eq!(
+            supported,
+            tagger.tag(&sparse).unwrap().contains(&sparse_tag)
+        );
+        fs::remove_file(&dense)?;
+        fs::remove_file(&sparse)?;
+        Ok(())
+    }
+
     #[test]
     fn tags_dir() {
         let path = PathBuf::from("src");
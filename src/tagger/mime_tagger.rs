@@ -1,16 +1,33 @@
 use std::{collections::HashSet, path::Path};
 
+#[cfg(feature = "magic")]
 use anyhow::Context;
+#[cfg(feature = "magic")]
 use magic::{cookie::Load, Cookie};
 use tracing::error;
 
-use super::{Error, Tag, Tagger};
+use super::{Error, SlashEscape, Tag, Tagger};
 
-pub(crate) trait MimeExtractor {
+pub trait MimeExtractor {
     fn new() -> Self;
     fn file(&self, filename: &Path) -> Result<String, anyhow::Error>;
 }
 
+/// Reports every file as `application/octet-stream`, as libmagic does for
+/// data it doesn't recognise, standing in for it in builds without the
+/// `magic` feature.
+#[derive(Debug)]
+pub struct UnknownMime;
+impl MimeExtractor for UnknownMime {
+    fn new() -> Self {
+        Self
+    }
+    fn file(&self, _filename: &Path) -> Result<String, anyhow::Error> {
+        Ok(String::from("application/octet-stream"))
+    }
+}
+
+#[cfg(feature = "magic")]
 impl MimeExtractor for Cookie<Load> {
     fn new() -> Self {
         let cookie =
@@ -26,19 +43,35 @@ impl MimeExtractor for Cookie<Load> {
 #[derive(Debug)]
 pub struct MimeTagger<T> {
     mime_extractor: T,
+    escape: SlashEscape,
+}
+impl<T: MimeExtractor> Default for MimeTagger<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl<T: MimeExtractor> MimeTagger<T> {
     pub fn new() -> Self {
         Self {
             mime_extractor: T::new(),
+            escape: SlashEscape::default(),
         }
     }
+
+    /// Make the `/` of MIME types path-safe with `escape`.
+    pub fn with_escape(mut self, escape: SlashEscape) -> Self {
+        self.escape = escape;
+        self
+    }
 }
 impl<T: MimeExtractor + std::fmt::Debug> Tagger for MimeTagger<T> {
+    fn name(&self) -> &str {
+        "mime"
+    }
     fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
         self.mime_extractor
             .file(path)
-            .map(|tag| HashSet::from([Tag::new("mime", true, tag.replace('/', "|"))]))
+            .map(|tag| HashSet::from([Tag::new("mime", true, self.escape.escape(&tag))]))
             .map_err(|e| {
                 error!(error = ?e, "get mime type");
                 Error::Illegible
@@ -49,18 +82,16 @@ impl<T: MimeExtractor + std::fmt::Debug> Tagger for MimeTagger<T> {
 #[cfg(test)]
 mod test {
     use std::{
-        collections::HashSet,
         ffi::OsString,
         path::{Path, PathBuf},
     };
 
     use anyhow::Context;
 
-    use magic::{cookie::Load, Cookie};
     use tracing::debug;
     use tracing_test::traced_test;
 
-    use crate::tagger::{Tag, Tagger as _, TAG_SEPARATOR};
+    use crate::tagger::{SlashEscape, Tagger as _, TAG_SEPARATOR};
 
     use super::{MimeExtractor, MimeTagger};
 
@@ -106,13 +137,43 @@ mod test {
         }));
     }
 
+    #[cfg(feature = "magic")]
     #[traced_test]
     #[test]
     fn mime_extraction_real() {
+        use std::collections::HashSet;
+
+        use magic::{cookie::Load, Cookie};
+
+        use crate::tagger::Tag;
+
         let t = MimeTagger::<Cookie<Load>>::new();
         let t = t.tag(&PathBuf::from("./src/main.rs"));
         assert!(t.is_ok());
         let t = t.unwrap();
         assert_eq!(t, HashSet::from([Tag::new("mime", true, "text|x-c")]));
     }
+
+    #[test]
+    fn mime_with_pipe_round_trips() {
+        #[derive(Debug)]
+        struct TestExtractor {}
+        impl MimeExtractor for TestExtractor {
+            fn new() -> Self {
+                Self {}
+            }
+            fn file(&self, _filename: &Path) -> Result<String, anyhow::Error> {
+                Ok(String::from("application/vnd.x|y"))
+            }
+        }
+        for escape in [SlashEscape::Pipe, SlashEscape::Percent] {
+            let t = MimeTagger::<TestExtractor>::new().with_escape(escape);
+            let tags = t.tag(&PathBuf::from("bob")).unwrap();
+            let tag = tags.iter().next().unwrap();
+            assert_eq!(
+                "application/vnd.x|y",
+                escape.unescape(tag.value().to_str().unwrap())
+            );
+        }
+    }
 }
@@ -0,0 +1,115 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{
+    line_length_tagger::LineStats, read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger,
+};
+
+const EXTENSIONS: &[&str] = &["js", "mjs", "css", "json", "html", "htm"];
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+/// Average line length above which a sample reads as minified.
+const MINIFIED_LINE_LENGTH: f64 = 200.0;
+/// Whitespace fraction below which a sample reads as minified.
+const MINIFIED_WHITESPACE: f64 = 0.08;
+
+/// Classifies web assets as built or source, emitting `minified:yes` or
+/// `minified:no` from the average line length and whitespace ratio of the
+/// start of the file.
+///
+/// Only the [text sample](Sniff::text_sample) of `.js`, `.css`, `.json` and
+/// `.html` style files is looked at; empty files aren't tagged.
+#[derive(Debug)]
+pub struct MinifiedTagger {
+    limits: SampleLimits,
+}
+impl Default for MinifiedTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MinifiedTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_limits(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, sample_size),
+        }
+    }
+
+    fn is_minified(sample: &[u8]) -> bool {
+        let average = LineStats::of(sample).average();
+        let whitespace = sample.iter().filter(|b| b.is_ascii_whitespace()).count();
+        let ratio = whitespace as f64 / sample.len() as f64;
+        average > MINIFIED_LINE_LENGTH || ratio < MINIFIED_WHITESPACE
+    }
+
+    fn is_asset(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+    }
+}
+impl Tagger for MinifiedTagger {
+    fn name(&self) -> &str {
+        "minified"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        if !Self::is_asset(path) {
+            return Ok(HashSet::new());
+        }
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        if !Self::is_asset(path) {
+            return Ok(HashSet::new());
+        }
+        let Some(sample) = sniff.text_sample(self.limits).filter(|s| !s.is_empty()) else {
+            debug!(?path, "skip minified");
+            return Ok(HashSet::new());
+        };
+        let minified = match Self::is_minified(sample) {
+            true => "yes",
+            false => "no",
+        };
+        Ok(HashSet::from([Tag::new("minified", true, minified)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::MinifiedTagger;
+
+    const PRETTY: &str = "function add(first, second) {\n    const total = first + second;\n    return total;\n}\n\n";
+    const MINIFIED: &str = "function add(n,t){const o=n+t;return o}";
+
+    #[test]
+    fn classifies_same_content() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("minified", true, "no")]),
+            tags_of(&MinifiedTagger::new(), "pretty.js", PRETTY.repeat(20))?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("minified", true, "yes")]),
+            tags_of(&MinifiedTagger::new(), "built.min.js", MINIFIED.repeat(20))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_other_extensions_and_empty() -> io::Result<()> {
+        assert!(tags_of(&MinifiedTagger::new(), "built.txt", MINIFIED.repeat(20))?.is_empty());
+        assert!(tags_of(&MinifiedTagger::new(), "empty.css", "")?.is_empty());
+        Ok(())
+    }
+}
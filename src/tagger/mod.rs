@@ -1,29 +1,93 @@
+mod archive_tagger;
+mod code_language_tagger;
+mod compression_tagger;
+mod duration_tagger;
+mod entropy_tagger;
+mod eol_tagger;
+mod escape;
+mod format_validity_tagger;
+mod friendly_type_tagger;
+mod indent_tagger;
+mod kind_tagger;
+mod license_tagger;
+mod line_count_tagger;
+mod line_length_tagger;
 mod meta_tagger;
 mod mime_tagger;
+mod minified_tagger;
+mod namespaced_tagger;
+mod normalize;
+mod office_tagger;
+mod orientation_tagger;
+mod pdf_security;
+mod pdf_tagger;
+mod regex_content_tagger;
+mod registry;
+mod rule_tagger;
+mod script_tagger;
+mod taxonomy_tagger;
 
 use std::{
     collections::HashSet,
     ffi::{OsStr, OsString},
     fmt::Debug,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::ffi::OsStrExt as _,
     path::Path,
+    time::Duration,
 };
 
+use tracing::{error, warn};
+
+use crate::archive::ArchiveMember;
+
+pub use archive_tagger::ArchiveTagger;
+pub use code_language_tagger::CodeLanguageTagger;
+pub use compression_tagger::CompressionTagger;
+pub use duration_tagger::DurationTagger;
+pub use entropy_tagger::EntropyTagger;
+pub use eol_tagger::EolTagger;
+pub use escape::SlashEscape;
+pub use format_validity_tagger::FormatValidityTagger;
+pub use friendly_type_tagger::FriendlyTypeTagger;
+pub use indent_tagger::IndentTagger;
+pub use kind_tagger::KindTagger;
+pub use license_tagger::LicenseTagger;
+pub use line_count_tagger::LineCountTagger;
+pub use line_length_tagger::LineLengthTagger;
 pub use meta_tagger::MetadataTagger;
-pub use mime_tagger::MimeTagger;
+pub use mime_tagger::{MimeExtractor, MimeTagger, UnknownMime};
+pub use minified_tagger::MinifiedTagger;
+pub use namespaced_tagger::NamespacedTagger;
+pub use normalize::Normalizers;
+pub use office_tagger::OfficeTagger;
+pub use orientation_tagger::OrientationTagger;
+pub use pdf_tagger::PdfTagger;
+pub use regex_content_tagger::RegexContentTagger;
+pub use registry::{TaggerConfig, TaggerFactory, TaggerRegistry};
+pub use rule_tagger::{RuleError, RuleTagger};
+pub use script_tagger::ScriptTagger;
+pub use taxonomy_tagger::TaxonomyTagger;
 
 pub(crate) const TAG_SEPARATOR: &str = ":";
+pub(crate) const NAMESPACE_SEPARATOR: &str = ".";
+/// Label of [`MetadataTagger`]'s last access bucket.
+pub const ACCESSED_AGE_LABEL: &str = "accessed-age";
+/// Label-less tag of files whose content can't be read without a password.
+pub const PASSWORD_PROTECTED_TAG: &str = "password-protected";
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     Illegible,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TagLabel {
     label: OsString,
     singleton: bool,
 }
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Tag {
     label: Option<TagLabel>,
     value: OsString,
@@ -42,21 +106,93 @@ impl Tag {
             display,
         }
     }
+
+    /// Rebuild a tag from its display form.
+    ///
+    /// Only the first separator splits label from value, so values may
+    /// themselves contain the separator. Singleton-ness isn't part of the
+    /// display form, so labelled tags are rebuilt as non-singletons.
+    pub fn from_display(display: impl Into<OsString>) -> Self {
+        let display: OsString = display.into();
+        match split_display(&display) {
+            (Some(label), value) => Self::new(label, false, value),
+            (None, _) => Self::from(display),
+        }
+    }
+
     pub fn as_os_str(&self) -> &OsStr {
         self.display.as_os_str()
     }
 
+    pub fn value(&self) -> &OsStr {
+        &self.value
+    }
+
     pub fn is_singleton(&self) -> bool {
         self.label.as_ref().map(|l| l.singleton).unwrap_or(false)
     }
 
+    pub fn has_label(&self) -> bool {
+        self.label.is_some()
+    }
+
     pub fn label(&self) -> &OsStr {
         match &self.label {
             Some(l) => &l.label,
             None => todo!(),
         }
     }
+
+    /// Whether the display form is valid UTF-8 with no NUL bytes, so can be
+    /// used as a path component and passed through libc.
+    pub fn is_representable(&self) -> bool {
+        self.display
+            .to_str()
+            .is_some_and(|display| !display.contains('\0'))
+    }
+
+    /// Copy with invalid UTF-8 and NUL bytes in the label and value replaced
+    /// by U+FFFD.
+    pub fn to_lossy(&self) -> Self {
+        let lossy = |s: &OsStr| OsString::from(s.to_string_lossy().replace('\0', "\u{FFFD}"));
+        match &self.label {
+            Some(label) => Tag::new(lossy(&label.label), label.singleton, lossy(&self.value)),
+            None => Tag::from(lossy(&self.value)),
+        }
+    }
+
+    /// Prefix the label with `namespace`; label-less tags are left as-is.
+    pub fn with_namespace(self, namespace: &OsStr) -> Self {
+        match self.label {
+            Some(label) => {
+                let mut namespaced = namespace.to_os_string();
+                namespaced.push(NAMESPACE_SEPARATOR);
+                namespaced.push(&label.label);
+                Tag::new(namespaced, label.singleton, self.value)
+            }
+            None => self,
+        }
+    }
+}
+/// Split a display string into its label and value on the first separator.
+///
+/// Strings with no separator, or an empty label, are label-less and the whole
+/// string is the value.
+pub fn split_display(display: &OsStr) -> (Option<&OsStr>, &OsStr) {
+    let bytes = display.as_bytes();
+    let separator = TAG_SEPARATOR.as_bytes();
+    match bytes
+        .windows(separator.len())
+        .position(|window| window == separator)
+    {
+        Some(0) | None => (None, display),
+        Some(idx) => (
+            Some(OsStr::from_bytes(&bytes[..idx])),
+            OsStr::from_bytes(&bytes[idx + separator.len()..]),
+        ),
+    }
 }
+
 impl From<OsString> for Tag {
     fn from(value: OsString) -> Self {
         Self {
@@ -73,17 +209,419 @@ impl From<&str> for Tag {
     }
 }
 
+/// Caps on the work content-reading taggers do for one file, so decoding an
+/// untrusted decompression bomb fails with [`Error::Illegible`] rather than
+/// exhausting memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Most bytes decompressed from any one archive member
+    pub max_member_bytes: u64,
+    /// Largest file read whole to parse it, as PDFs are
+    pub max_file_bytes: u64,
+    /// Most members an archive may list
+    pub max_archive_entries: usize,
+    /// Largest ratio of total uncompressed size to archive size
+    pub max_expansion_ratio: u64,
+}
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_member_bytes: 256 * 1024 * 1024,
+            max_file_bytes: 64 * 1024 * 1024,
+            max_archive_entries: 100_000,
+            max_expansion_ratio: 200,
+        }
+    }
+}
+impl ResourceLimits {
+    /// Check an archive of `archive_size` bytes listing `members`.
+    pub fn check_archive(
+        &self,
+        path: &Path,
+        archive_size: u64,
+        members: &[ArchiveMember],
+    ) -> Result<(), Error> {
+        if members.len() > self.max_archive_entries {
+            warn!(?path, entries = members.len(), "too many archive entries");
+            return Err(Error::Illegible);
+        }
+        let expanded = members
+            .iter()
+            .fold(0u64, |acc, member| acc.saturating_add(member.size));
+        if expanded > archive_size.max(1).saturating_mul(self.max_expansion_ratio) {
+            warn!(
+                ?path,
+                archive_size, expanded, "archive expansion ratio too high"
+            );
+            return Err(Error::Illegible);
+        }
+        Ok(())
+    }
+
+    /// Decompress an archive member whose header claims `declared` bytes
+    /// from `member`. Members claiming more than the cap, or inflating past
+    /// their claim, are refused rather than read without bound.
+    pub fn read_member(&self, member: impl Read, declared: u64) -> io::Result<Vec<u8>> {
+        let too_big = || io::Error::new(io::ErrorKind::InvalidData, "archive member too large");
+        if declared > self.max_member_bytes {
+            return Err(too_big());
+        }
+        let mut content = Vec::with_capacity(declared as usize);
+        member.take(declared + 1).read_to_end(&mut content)?;
+        if content.len() as u64 > declared {
+            return Err(too_big());
+        }
+        Ok(content)
+    }
+}
+
+/// Bytes read for sniffing when no budget is configured.
+pub const DEFAULT_SNIFF_BYTES: u64 = 64 * 1024;
+
+/// How much of a file the text taggers look at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleLimits {
+    /// Larger files aren't tagged
+    pub max_size: u64,
+    /// Most leading bytes looked at
+    pub sample_size: u64,
+}
+impl SampleLimits {
+    pub fn new(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            max_size,
+            sample_size,
+        }
+    }
+}
+
+/// Read the start of `path` for a text tagger run without a shared sniff,
+/// to be gated by [`Sniff::text_sample`].
+pub(crate) fn read_text_sample(path: &Path, limits: SampleLimits) -> Result<Sniff, Error> {
+    Sniff::read(path, limits.sample_size).map_err(|e| {
+        error!(error = ?e, ?path, "read text sample");
+        Error::Illegible
+    })
+}
+
+/// Whether `mime` is in the text category: `text/*`, JSON, JavaScript and
+/// XML, and empty files.
+fn is_text_mime(mime: &str) -> bool {
+    let Some((category, subtype)) = mime.split_once('/') else {
+        return false;
+    };
+    category == "text"
+        || matches!(subtype, "json" | "javascript" | "xml" | "x-empty")
+        || subtype.ends_with("+json")
+        || subtype.ends_with("+xml")
+}
+
+/// MIME type libmagic gives `prefix`.
+#[cfg(feature = "magic")]
+fn sniff_mime(prefix: &[u8]) -> Option<String> {
+    use magic::{cookie::Load, Cookie};
+
+    thread_local! {
+        static COOKIE: Cookie<Load> = <Cookie<Load> as MimeExtractor>::new();
+    }
+    COOKIE
+        .with(|cookie| cookie.buffer(prefix))
+        .map_err(|e| warn!(error = ?e, "sniff mime type"))
+        .ok()
+}
+
+#[cfg(not(feature = "magic"))]
+fn sniff_mime(_prefix: &[u8]) -> Option<String> {
+    None
+}
+
+/// The start of a file, read once and shared by every tagger that only
+/// looks at a prefix, rather than each reading it again.
+#[derive(Debug)]
+pub struct Sniff {
+    /// Whether the file is a regular file
+    pub is_file: bool,
+    /// Size of the whole file
+    pub size: u64,
+    /// Up to the sniff budget of leading bytes
+    pub prefix: Vec<u8>,
+    /// MIME type of the prefix, in builds with libmagic
+    pub mime: Option<String>,
+}
+impl Sniff {
+    /// Read at most `max_bytes` from the start of `path`.
+    pub fn read(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mut prefix = Vec::new();
+        let mut mime = None;
+        if metadata.is_file() {
+            file.take(max_bytes).read_to_end(&mut prefix)?;
+            mime = sniff_mime(&prefix);
+        }
+        Ok(Self {
+            is_file: metadata.is_file(),
+            size: metadata.len(),
+            prefix,
+            mime,
+        })
+    }
+
+    /// Up to `limits.sample_size` leading bytes, for the text taggers.
+    ///
+    /// Only regular files within `limits.max_size` whose sniffed MIME type is
+    /// in the text category have a sample: `text/*`, JSON, JavaScript, XML
+    /// and empty files. Builds without libmagic have no MIME type, so take
+    /// files whose prefix has no NUL bytes as text instead.
+    pub fn text_sample(&self, limits: SampleLimits) -> Option<&[u8]> {
+        if !self.is_file || self.size > limits.max_size {
+            return None;
+        }
+        let sample = &self.prefix[..self.prefix.len().min(limits.sample_size as usize)];
+        let text = match &self.mime {
+            Some(mime) => is_text_mime(mime),
+            None => !self.prefix.contains(&0),
+        };
+        text.then_some(sample)
+    }
+}
+
+/// Playing time of an audio or video file, from its container headers.
+///
+/// WAV, FLAC and MP4/QuickTime containers are understood; `None` for other
+/// files, and for those whose headers don't give a duration. Only headers
+/// are read, seeking past media data, so this is cheap on large files.
+pub fn probe_duration(path: &Path) -> io::Result<Option<Duration>> {
+    let mut file = File::open(path)?;
+    if !file.metadata()?.is_file() {
+        return Ok(None);
+    }
+    probe_duration_of(&mut file)
+}
+
+fn probe_duration_of(reader: &mut (impl Read + Seek)) -> io::Result<Option<Duration>> {
+    match probe_container(reader) {
+        // Headers cut short by a truncated file
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result,
+    }
+}
+
+fn probe_container(reader: &mut (impl Read + Seek)) -> io::Result<Option<Duration>> {
+    let mut header = Vec::new();
+    reader.by_ref().take(12).read_to_end(&mut header)?;
+    if header.starts_with(b"fLaC") {
+        reader.seek(SeekFrom::Start(4))?;
+        return flac_duration(reader);
+    }
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE") {
+        return wav_duration(reader);
+    }
+    if header.get(4..8) == Some(b"ftyp") {
+        reader.seek(SeekFrom::Start(0))?;
+        return mp4_duration(reader);
+    }
+    Ok(None)
+}
+
+/// `units` at `per_second`, in integer math so no header value can overflow.
+fn units_duration(units: u64, per_second: u64) -> Duration {
+    let nanos = (units % per_second) as u128 * 1_000_000_000 / per_second as u128;
+    Duration::new(units / per_second, nanos as u32)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Total samples over sample rate, from the STREAMINFO block that must
+/// come first.
+fn flac_duration(reader: &mut impl Read) -> io::Result<Option<Duration>> {
+    let block: [u8; 4] = read_array(reader)?;
+    if block[0] & 0x7f != 0 {
+        return Ok(None);
+    }
+    let info: [u8; 18] = read_array(reader)?;
+    // 20 bits of sample rate, 8 of channels and depth, 36 of total samples
+    let bits = u64::from_be_bytes(info[10..18].try_into().unwrap());
+    let (rate, samples) = (bits >> 44, bits & ((1 << 36) - 1));
+    Ok((rate > 0 && samples > 0).then(|| units_duration(samples, rate)))
+}
+
+/// Size of the `data` chunk over the byte rate of the `fmt ` chunk before
+/// it.
+fn wav_duration(reader: &mut (impl Read + Seek)) -> io::Result<Option<Duration>> {
+    let mut byte_rate = None;
+    loop {
+        let Ok(chunk) = read_array::<8>(reader) else {
+            return Ok(None);
+        };
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        match &chunk[..4] {
+            b"fmt " if size < 12 => return Ok(None),
+            b"fmt " => {
+                let fmt: [u8; 12] = read_array(reader)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..12].try_into().unwrap()));
+                reader.seek(SeekFrom::Current(size as i64 - 12 + (size & 1) as i64))?;
+            }
+            b"data" => {
+                return Ok(byte_rate
+                    .filter(|rate| *rate > 0)
+                    .map(|rate| units_duration(size, rate as u64)));
+            }
+            // Chunks are padded to even lengths
+            _ => {
+                reader.seek(SeekFrom::Current((size + (size & 1)) as i64))?;
+            }
+        }
+    }
+}
+
+/// Payload start and end of the first `kind` box between the reader's
+/// position and `end`.
+fn find_box(
+    reader: &mut (impl Read + Seek),
+    end: u64,
+    kind: &[u8; 4],
+) -> io::Result<Option<(u64, u64)>> {
+    let mut at = reader.stream_position()?;
+    while at + 8 <= end {
+        reader.seek(SeekFrom::Start(at))?;
+        let header: [u8; 8] = read_array(reader)?;
+        let (payload, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // Extends to the end of the enclosing box
+            0 => (at + 8, end - at),
+            // A 64-bit size follows the type
+            1 => (at + 16, u64::from_be_bytes(read_array(reader)?)),
+            size => (at + 8, size as u64),
+        };
+        if size < payload - at {
+            return Ok(None);
+        }
+        let box_end = at.saturating_add(size).min(end);
+        if &header[4..] == kind {
+            return Ok(Some((payload, box_end)));
+        }
+        at = box_end;
+    }
+    Ok(None)
+}
+
+/// Duration over timescale, from the movie header inside `moov`.
+fn mp4_duration(reader: &mut (impl Read + Seek)) -> io::Result<Option<Duration>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let Some((moov, moov_end)) = find_box(reader, end, b"moov")? else {
+        return Ok(None);
+    };
+    reader.seek(SeekFrom::Start(moov))?;
+    let Some((mvhd, _)) = find_box(reader, moov_end, b"mvhd")? else {
+        return Ok(None);
+    };
+    reader.seek(SeekFrom::Start(mvhd))?;
+    let [version, ..] = read_array::<4>(reader)?;
+    let (timescale, duration) = match version {
+        0 => {
+            let header: [u8; 16] = read_array(reader)?;
+            let duration = u32::from_be_bytes(header[12..16].try_into().unwrap());
+            (
+                u32::from_be_bytes(header[8..12].try_into().unwrap()),
+                (duration != u32::MAX).then_some(duration as u64),
+            )
+        }
+        1 => {
+            let header: [u8; 28] = read_array(reader)?;
+            let duration = u64::from_be_bytes(header[20..28].try_into().unwrap());
+            (
+                u32::from_be_bytes(header[16..20].try_into().unwrap()),
+                (duration != u64::MAX).then_some(duration),
+            )
+        }
+        _ => return Ok(None),
+    };
+    Ok(duration
+        .filter(|_| timescale > 0)
+        .map(|duration| units_duration(duration, timescale as u64)))
+}
+
 pub trait Tagger: Debug {
+    /// Short identifier for the tagger, used in logs and mount reporting.
+    fn name(&self) -> &str;
+    /// Whether to run on symlinks, fifos and other non-regular files. Most
+    /// taggers read content, which for a fifo would block, so default to no.
+    fn tags_special_files(&self) -> bool {
+        false
+    }
     fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error>;
+    /// Whether the tagger only reads the start of files, so can be handed a
+    /// shared [`Sniff`] through [`Tagger::tag_sniffed`].
+    fn sniffs(&self) -> bool {
+        false
+    }
+    /// Tag `path` from its already read `sniff`.
+    fn tag_sniffed(&self, path: &Path, _sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        self.tag(path)
+    }
 }
 
 #[cfg(test)]
-mod test {
-    use std::ffi::OsString;
+pub(crate) mod test {
+    use std::{
+        collections::HashSet,
+        env,
+        ffi::{OsStr, OsString},
+        fs,
+        io::{self, Cursor},
+        path::{Path, PathBuf},
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
 
     use crate::tagger::TAG_SEPARATOR;
 
-    use super::Tag;
+    use super::{split_display, SampleLimits, Sniff, Tag, Tagger};
+
+    /// A fresh directory under the system temp directory, removed with
+    /// everything in it on drop.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> io::Result<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = env::temp_dir().join(format!(
+                "tagger-test-{}-{}",
+                process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir(&dir)?;
+            Ok(Self(dir))
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The tags `tagger` gives a file named `name` holding `content`.
+    pub(crate) fn tags_of(
+        tagger: &impl Tagger,
+        name: &str,
+        content: impl AsRef<[u8]>,
+    ) -> io::Result<HashSet<Tag>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(name);
+        fs::write(&path, content)?;
+        Ok(tagger.tag(&path).unwrap())
+    }
 
     #[test]
     fn as_os_str_no_label() {
@@ -97,4 +635,143 @@ mod test {
         let expected: OsString = format!("{}{}{}", "label", TAG_SEPARATOR, "value").into();
         assert_eq!(expected.as_os_str(), tag.as_os_str());
     }
+
+    #[test]
+    fn split_display_first_separator_only() {
+        let display = OsString::from("modified:1970-01-02 00:00:00");
+        assert_eq!(
+            (
+                Some(OsStr::new("modified")),
+                OsStr::new("1970-01-02 00:00:00")
+            ),
+            split_display(&display)
+        );
+    }
+
+    #[test]
+    fn split_display_no_label() {
+        assert_eq!(
+            (None, OsStr::new("plain")),
+            split_display(OsStr::new("plain"))
+        );
+        assert_eq!((None, OsStr::new(":x")), split_display(OsStr::new(":x")));
+    }
+
+    #[test]
+    fn from_display_round_trip() {
+        let tag = Tag::new("modified", true, "1970-01-02 00:00:00");
+        let parsed = Tag::from_display(tag.as_os_str());
+        assert_eq!(tag.label(), parsed.label());
+        assert_eq!(tag.value(), parsed.value());
+        assert_eq!(tag.as_os_str(), parsed.as_os_str());
+        assert_eq!(OsStr::new("1970-01-02 00:00:00"), parsed.value());
+    }
+
+    #[test]
+    fn from_display_value_with_separators_and_spaces() {
+        let tag = Tag::from_display("time:12:34:56 pm");
+        assert_eq!(OsStr::new("time"), tag.label());
+        assert_eq!(OsStr::new("12:34:56 pm"), tag.value());
+        assert_eq!(OsStr::new("time:12:34:56 pm"), tag.as_os_str());
+    }
+
+    #[test]
+    fn from_display_no_label() {
+        let tag = Tag::from_display("plain value");
+        assert_eq!(Tag::from("plain value"), tag);
+        assert_eq!(OsStr::new("plain value"), tag.value());
+    }
+
+    #[test]
+    fn lossy_invalid_utf8() {
+        use std::os::unix::ffi::OsStringExt as _;
+
+        let value = OsString::from_vec(vec![b'a', 0xff, 0, b'b']);
+        let tag = Tag::new("label", true, value.clone());
+        assert!(!tag.is_representable());
+        let lossy = tag.to_lossy();
+        assert!(lossy.is_representable());
+        assert_eq!(Tag::new("label", true, "a\u{FFFD}\u{FFFD}b"), lossy);
+        assert_eq!(Tag::from("a\u{FFFD}\u{FFFD}b"), Tag::from(value).to_lossy());
+        assert!(Tag::new("label", true, "plain").is_representable());
+    }
+
+    fn wav(byte_rate: u32, data_size: u32) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"fmt \x10\0\0\0\x01\0\x01\0\x44\xac\0\0");
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(b"\x02\0\x10\0data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        wav
+    }
+
+    fn flac(rate: u64, samples: u64) -> Vec<u8> {
+        let mut flac = b"fLaC\x80\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0; 10]);
+        flac.extend_from_slice(&(rate << 44 | 0x1f << 36 | samples).to_be_bytes());
+        flac.extend_from_slice(&[0; 16]);
+        flac
+    }
+
+    fn mp4(timescale: u32, duration: u32) -> Vec<u8> {
+        let mut mp4 = b"\0\0\0\x10ftypisom\0\0\x02\0".to_vec();
+        // Media data before the movie header, as most encoders write it
+        mp4.extend_from_slice(b"\0\0\0\x0cmdat\xde\xad\xbe\xef");
+        mp4.extend_from_slice(b"\0\0\0\x30moov\0\0\0\x0cfree\0\0\0\0");
+        mp4.extend_from_slice(b"\0\0\0\x1cmvhd\0\0\0\0\0\0\0\0\0\0\0\0");
+        mp4.extend_from_slice(&timescale.to_be_bytes());
+        mp4.extend_from_slice(&duration.to_be_bytes());
+        mp4
+    }
+
+    #[test]
+    fn probes_container_durations() {
+        let probe = |bytes: Vec<u8>| super::probe_duration_of(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(Some(Duration::from_secs(3)), probe(wav(88_200, 264_600)));
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            probe(flac(44_100, 26_460_000))
+        );
+        assert_eq!(Some(Duration::from_millis(2500)), probe(mp4(1000, 2500)));
+        // Unknown durations and formats
+        assert_eq!(None, probe(flac(44_100, 0)));
+        assert_eq!(None, probe(mp4(1000, u32::MAX)));
+        assert_eq!(None, probe(b"plain text".to_vec()));
+        // Truncated headers
+        assert_eq!(None, probe(flac(44_100, 100)[..20].to_vec()));
+        assert_eq!(None, probe(mp4(1000, 2500)[..60].to_vec()));
+        // Durations too long for floating point seconds
+        assert_eq!(
+            Some(Duration::new(u32::MAX as u64, 0)),
+            probe(wav(1, u32::MAX))
+        );
+        assert_eq!(
+            Duration::new(u64::MAX, 0),
+            super::units_duration(u64::MAX, 1)
+        );
+    }
+
+    #[test]
+    fn text_sample_gates_on_mime() {
+        let sniff = |mime: Option<&str>, prefix: &[u8]| Sniff {
+            is_file: true,
+            size: prefix.len() as u64,
+            prefix: prefix.to_vec(),
+            mime: mime.map(String::from),
+        };
+        let limits = SampleLimits::new(1024, 4);
+        let text = sniff(Some("text/plain"), b"one\ntwo\n");
+        assert_eq!(Some(&b"one\n"[..]), text.text_sample(limits));
+        assert!(text.text_sample(SampleLimits::new(4, 4)).is_none());
+        for mime in ["application/json", "image/svg+xml", "application/x-empty"] {
+            assert!(sniff(Some(mime), b"").text_sample(limits).is_some());
+        }
+        assert!(sniff(Some("image/png"), b"\x89PNG")
+            .text_sample(limits)
+            .is_none());
+        // Without libmagic, NUL bytes mark binary content
+        assert!(sniff(None, b"plain").text_sample(limits).is_some());
+        assert!(sniff(None, b"ab\0c").text_sample(limits).is_none());
+    }
 }
@@ -0,0 +1,114 @@
+use std::{collections::HashSet, ffi::OsString, path::Path};
+
+use super::{Error, Sniff, Tag, Tagger, NAMESPACE_SEPARATOR};
+
+/// Wraps another tagger, prefixing the labels of every tag it emits with a
+/// namespace so taggers with overlapping labels can coexist.
+///
+/// Label-less tags are passed through unprefixed.
+#[derive(Debug)]
+pub struct NamespacedTagger<T> {
+    namespace: OsString,
+    name: String,
+    inner: T,
+}
+impl<T: Tagger> NamespacedTagger<T> {
+    pub fn new(namespace: impl Into<OsString>, inner: T) -> Self {
+        let namespace = namespace.into();
+        let name = format!(
+            "{}{}{}",
+            namespace.to_string_lossy(),
+            NAMESPACE_SEPARATOR,
+            inner.name()
+        );
+        Self {
+            namespace,
+            name,
+            inner,
+        }
+    }
+}
+impl<T: Tagger> NamespacedTagger<T> {
+    fn namespaced(&self, tags: HashSet<Tag>) -> HashSet<Tag> {
+        tags.into_iter()
+            .map(|tag| tag.with_namespace(&self.namespace))
+            .collect()
+    }
+}
+impl<T: Tagger> Tagger for NamespacedTagger<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn tags_special_files(&self) -> bool {
+        self.inner.tags_special_files()
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.inner.tag(path).map(|tags| self.namespaced(tags))
+    }
+    fn sniffs(&self) -> bool {
+        self.inner.sniffs()
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        self.inner
+            .tag_sniffed(path, sniff)
+            .map(|tags| self.namespaced(tags))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, path::Path};
+
+    use crate::tagger::{Error, Tag, Tagger};
+
+    use super::NamespacedTagger;
+
+    #[derive(Debug)]
+    struct StubTagger;
+    impl Tagger for StubTagger {
+        fn name(&self) -> &str {
+            "stub"
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            Ok(HashSet::from([
+                Tag::new("title", true, "A"),
+                Tag::new("keyword", false, "b"),
+                Tag::from("plain"),
+            ]))
+        }
+    }
+
+    #[test]
+    fn rewrites_labels() {
+        let tagger = NamespacedTagger::new("pdf", StubTagger);
+        let tags = tagger.tag(Path::new("any")).unwrap();
+        assert_eq!(
+            HashSet::from([
+                Tag::new("pdf.title", true, "A"),
+                Tag::new("pdf.keyword", false, "b"),
+                Tag::from("plain"),
+            ]),
+            tags
+        );
+        assert!(tags
+            .iter()
+            .any(|t| t.is_singleton() && t.label() == "pdf.title"));
+        assert_eq!("pdf.stub", tagger.name());
+    }
+
+    #[test]
+    fn propagates_errors() {
+        #[derive(Debug)]
+        struct FailingTagger;
+        impl Tagger for FailingTagger {
+            fn name(&self) -> &str {
+                "failing"
+            }
+            fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+                Err(Error::Illegible)
+            }
+        }
+        let tagger = NamespacedTagger::new("pdf", FailingTagger);
+        assert_eq!(Err(Error::Illegible), tagger.tag(Path::new("any")));
+    }
+}
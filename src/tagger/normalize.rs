@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt::Debug,
+};
+
+use super::Tag;
+
+/// Rewrites the value of a tag carrying a given label, so values from
+/// different taggers share one format.
+pub trait Normalizer {
+    fn normalize(&self, label: &OsStr, value: &OsStr) -> OsString;
+}
+impl<F> Normalizer for F
+where
+    F: Fn(&OsStr, &OsStr) -> OsString,
+{
+    fn normalize(&self, label: &OsStr, value: &OsStr) -> OsString {
+        self(label, value)
+    }
+}
+
+/// Normalizers registered by label, applied in registration order.
+#[derive(Default)]
+pub struct Normalizers {
+    by_label: HashMap<OsString, Vec<Box<dyn Normalizer>>>,
+}
+impl Debug for Normalizers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.by_label.keys()).finish()
+    }
+}
+impl Normalizers {
+    /// Built-in normalizers: ISO-8601 `modified` times and IEC `size` units.
+    pub fn with_defaults() -> Self {
+        let mut normalizers = Self::default();
+        normalizers.register("modified", iso8601_datetime);
+        normalizers.register("size", iec_size);
+        normalizers
+    }
+
+    pub fn register(&mut self, label: impl Into<OsString>, normalizer: impl Normalizer + 'static) {
+        self.by_label
+            .entry(label.into())
+            .or_default()
+            .push(Box::new(normalizer));
+    }
+
+    pub fn apply(&self, tag: Tag) -> Tag {
+        let Some(label) = tag.label.as_ref() else {
+            return tag;
+        };
+        match self.by_label.get(&label.label) {
+            Some(normalizers) => {
+                let value = normalizers.iter().fold(tag.value.clone(), |value, n| {
+                    n.normalize(&label.label, &value)
+                });
+                Tag::new(label.label.clone(), label.singleton, value)
+            }
+            None => tag,
+        }
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS` (UTC) to `YYYY-MM-DDTHH:MM:SSZ`; anything else is
+/// passed through unchanged.
+pub fn iso8601_datetime(_label: &OsStr, value: &OsStr) -> OsString {
+    match value.to_str() {
+        Some(v)
+            if v.len() == 19
+                && v.as_bytes()[10] == b' '
+                && v.bytes()
+                    .enumerate()
+                    .all(|(i, b)| matches!(i, 4 | 7 | 10 | 13 | 16) || b.is_ascii_digit()) =>
+        {
+            format!("{}T{}Z", &v[..10], &v[11..]).into()
+        }
+        _ => value.to_os_string(),
+    }
+}
+
+/// Byte counts to IEC units, e.g. `1536` to `1.5 KiB`; non-numeric values are
+/// passed through unchanged.
+pub fn iec_size(_label: &OsStr, value: &OsStr) -> OsString {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let Some(bytes) = value.to_str().and_then(|v| v.parse::<u64>().ok()) else {
+        return value.to_os_string();
+    };
+    if bytes < 1024 {
+        return format!("{} B", bytes).into();
+    }
+    let mut scaled = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while scaled >= 1024.0 && unit < UNITS.len() - 1 {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", scaled, UNITS[unit]).into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{OsStr, OsString};
+
+    use crate::tagger::Tag;
+
+    use super::{iec_size, iso8601_datetime, Normalizers};
+
+    #[test]
+    fn iso8601() {
+        assert_eq!(
+            OsString::from("1970-01-02T00:00:00Z"),
+            iso8601_datetime(OsStr::new("modified"), OsStr::new("1970-01-02 00:00:00"))
+        );
+        assert_eq!(
+            OsString::from("yesterday"),
+            iso8601_datetime(OsStr::new("modified"), OsStr::new("yesterday"))
+        );
+    }
+
+    #[test]
+    fn iec() {
+        let size = |v| iec_size(OsStr::new("size"), OsStr::new(v));
+        assert_eq!(OsString::from("0 B"), size("0"));
+        assert_eq!(OsString::from("1023 B"), size("1023"));
+        assert_eq!(OsString::from("1.5 KiB"), size("1536"));
+        assert_eq!(OsString::from("1.0 GiB"), size("1073741824"));
+        assert_eq!(OsString::from("big"), size("big"));
+    }
+
+    #[test]
+    fn apply_by_label() {
+        let mut normalizers = Normalizers::with_defaults();
+        normalizers.register("title", |_label: &OsStr, value: &OsStr| {
+            value.to_ascii_lowercase()
+        });
+        assert_eq!(
+            Tag::new("size", true, "1.5 KiB"),
+            normalizers.apply(Tag::new("size", true, "1536"))
+        );
+        assert_eq!(
+            Tag::new("title", false, "shout"),
+            normalizers.apply(Tag::new("title", false, "SHOUT"))
+        );
+        assert_eq!(
+            Tag::new("other", true, "1536"),
+            normalizers.apply(Tag::new("other", true, "1536"))
+        );
+        assert_eq!(Tag::from("1536"), normalizers.apply(Tag::from("1536")));
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Read as _, Seek as _, SeekFrom},
+    path::Path,
+};
+
+use tracing::{debug, error};
+use xmlparser::{ElementEnd, Token, Tokenizer};
+use zip::ZipArchive;
+
+use crate::archive::{ArchiveReader as _, ZipReader};
+
+use super::{Error, ResourceLimits, SlashEscape, Tag, Tagger, PASSWORD_PROTECTED_TAG};
+
+const EXTENSIONS: &[&str] = &["docx", "docm", "xlsx", "xlsm", "pptx", "pptm"];
+const CORE_PROPERTIES: &str = "docProps/core.xml";
+const APP_PROPERTIES: &str = "docProps/app.xml";
+/// Largest properties part read; real ones are a few KiB.
+const MAX_PART_SIZE: u64 = 1024 * 1024;
+/// Signature of the OLE compound files password-protected OOXML is wrapped in.
+const CFB_MAGIC: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+
+/// Reads the core properties of Office Open XML documents, emitting
+/// `doc-author`, `doc-title` and `doc-app`.
+///
+/// Only files with an OOXML extension are opened, and those that aren't
+/// readable OOXML containers are [`Error::Illegible`]. Documents are zips, so
+/// also carry the [`ArchiveTagger`](super::ArchiveTagger)'s tag; the labels
+/// don't overlap.
+///
+/// Password-protected documents, either OLE containers holding an
+/// `EncryptedPackage` or zips with encrypted parts, are tagged
+/// [`PASSWORD_PROTECTED_TAG`] alone, as their properties can't be read.
+#[derive(Debug, Default)]
+pub struct OfficeTagger {
+    limits: ResourceLimits,
+    escape: SlashEscape,
+}
+impl OfficeTagger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Make the `/` of property values path-safe with `escape`.
+    pub fn with_escape(mut self, escape: SlashEscape) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    fn read_part(&self, zip: &mut ZipArchive<File>, name: &str) -> Result<Option<String>, Error> {
+        let part = match zip.by_name(name) {
+            Ok(part) => part,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => {
+                error!(error = ?e, name, "open office part");
+                return Err(Error::Illegible);
+            }
+        };
+        if part.size() > MAX_PART_SIZE {
+            error!(name, size = part.size(), "office part too large");
+            return Err(Error::Illegible);
+        }
+        let declared = part.size();
+        let content = self
+            .limits
+            .read_member(part, declared)
+            .and_then(|content| {
+                String::from_utf8(content)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .map_err(|e| {
+                error!(error = ?e, name, "read office part");
+                Error::Illegible
+            })?;
+        Ok(Some(content))
+    }
+}
+
+/// Whether `file` is an OLE compound file whose root directory holds an
+/// `EncryptedPackage` stream, as Office writes password-protected OOXML.
+fn is_encrypted_package(file: &mut File) -> io::Result<bool> {
+    let mut header = [0u8; 512];
+    file.read_exact(&mut header)?;
+    if !header.starts_with(CFB_MAGIC) {
+        return Ok(false);
+    }
+    // 512 byte sectors in version 3 files, 4096 in version 4
+    let sector_shift = u16::from_le_bytes([header[30], header[31]]);
+    if !(9..=12).contains(&sector_shift) {
+        return Ok(false);
+    }
+    let directory = u32::from_le_bytes([header[48], header[49], header[50], header[51]]);
+    file.seek(SeekFrom::Start((u64::from(directory) + 1) << sector_shift))?;
+    let mut sector = Vec::new();
+    file.take(1 << sector_shift).read_to_end(&mut sector)?;
+    let name = "EncryptedPackage"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    // Each 128 byte directory entry starts with its UTF-16 name
+    Ok(sector.chunks(128).any(|entry| entry.starts_with(&name)))
+}
+
+/// Text of the first `<name>` element, `prefix:local` when namespaced, with
+/// entity and character references decoded. `None` when it's missing,
+/// self-closing, or the XML before its end is malformed.
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+    let mut found = false;
+    // Elements open inside the one found, once its start tag has closed
+    let mut depth = None;
+    let mut text = String::new();
+    for token in Tokenizer::from(xml) {
+        match (token.ok()?, depth) {
+            (
+                Token::ElementStart {
+                    prefix: p,
+                    local: l,
+                    ..
+                },
+                None,
+            ) => {
+                found = p.as_str() == prefix && l.as_str() == local;
+            }
+            (Token::ElementEnd { end, .. }, None) if found => match end {
+                ElementEnd::Open => depth = Some(0),
+                _ => return None,
+            },
+            (Token::ElementEnd { end, .. }, Some(open)) => match end {
+                ElementEnd::Open => depth = Some(open + 1),
+                ElementEnd::Close(..) if open == 0 => return Some(text),
+                ElementEnd::Close(..) => depth = Some(open - 1),
+                ElementEnd::Empty => {}
+            },
+            (Token::Text { text: span }, Some(_)) => text.push_str(&unescape(span.as_str())),
+            (Token::Cdata { text: span, .. }, Some(_)) => text.push_str(span.as_str()),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `text` with its entity and character references decoded; references that
+/// aren't understood are kept as written.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .and_then(|end| Some((reference(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Character an entity or character reference, without its `&` and `;`,
+/// stands for.
+fn reference(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Value usable as a path component: `/` is escaped as for mime types, and
+/// control characters are dropped.
+fn path_safe(value: &str, escape: SlashEscape) -> String {
+    let value = value
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    escape.escape(&value)
+}
+
+impl Tagger for OfficeTagger {
+    fn name(&self) -> &str {
+        "office"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let is_office = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()));
+        if !is_office {
+            return Ok(HashSet::new());
+        }
+        let password_protected = || Ok(HashSet::from([Tag::from(PASSWORD_PROTECTED_TAG)]));
+
+        let mut file = File::open(path).map_err(|_| Error::Illegible)?;
+        if is_encrypted_package(&mut file).unwrap_or(false) {
+            debug!(?path, "encrypted office package");
+            return password_protected();
+        }
+        let members = ZipReader::with_limits(self.limits)
+            .members(path)
+            .map_err(|e| {
+                debug!(?path, error = ?e, "not an office container");
+                Error::Illegible
+            })?;
+        let size = fs::metadata(path).map_err(|_| Error::Illegible)?.len();
+        self.limits.check_archive(path, size, &members)?;
+        if members.iter().any(|member| member.encrypted) {
+            debug!(?path, "encrypted office parts");
+            return password_protected();
+        }
+        let mut zip = ZipArchive::new(file).map_err(|_| Error::Illegible)?;
+        let Some(core) = self.read_part(&mut zip, CORE_PROPERTIES)? else {
+            debug!(?path, "no core properties");
+            return Err(Error::Illegible);
+        };
+        let app = self.read_part(&mut zip, APP_PROPERTIES)?;
+
+        let mut tags = HashSet::new();
+        let properties = [
+            ("doc-author", element_text(&core, "dc:creator")),
+            ("doc-title", element_text(&core, "dc:title")),
+            (
+                "doc-app",
+                app.as_deref()
+                    .and_then(|app| element_text(app, "Application")),
+            ),
+        ];
+        for (label, value) in properties {
+            if let Some(value) = value.map(|value| path_safe(&value, self.escape)) {
+                if !value.is_empty() {
+                    tags.insert(Tag::new(label, true, value));
+                }
+            }
+        }
+        Ok(tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs};
+
+    use crate::{
+        archive::test::{write_encrypted_zip, write_zip},
+        tagger::{ArchiveTagger, Error, Tag, Tagger, PASSWORD_PROTECTED_TAG},
+    };
+
+    use super::OfficeTagger;
+
+    const CORE: &[u8] = br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:title>Q3 Report / Draft</dc:title>
+<dc:creator>Ada &amp; Co &#233;ditions &#x2014; &bogus;</dc:creator>
+<cp:lastModifiedBy>Someone Else</cp:lastModifiedBy>
+</cp:coreProperties>"#;
+    const APP: &[u8] = br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Properties><Application>Microsoft Office Word</Application></Properties>"#;
+
+    #[test]
+    fn reads_core_properties() {
+        let path = env::temp_dir().join("office_tagger_reads_core_properties.docx");
+        write_zip(
+            &path,
+            &[
+                ("[Content_Types].xml", b"<Types/>"),
+                ("docProps/core.xml", CORE),
+                ("docProps/app.xml", APP),
+                ("word/document.xml", b"<w:document/>"),
+            ],
+        );
+        let tags = OfficeTagger::new().tag(&path).unwrap();
+        let archive_tags = ArchiveTagger::new().tag(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            HashSet::from([
+                Tag::new("doc-author", true, "Ada & Co éditions — &bogus;"),
+                Tag::new("doc-title", true, "Q3 Report | Draft"),
+                Tag::new("doc-app", true, "Microsoft Office Word"),
+            ]),
+            tags
+        );
+        // Both run on documents without clashing labels
+        assert!(archive_tags.contains(&ArchiveTagger::tag_for()));
+        assert!(tags.is_disjoint(&archive_tags));
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let garbage = env::temp_dir().join("office_tagger_rejects_malformed.xlsx");
+        fs::write(&garbage, b"not a zip").unwrap();
+        let bare_zip = env::temp_dir().join("office_tagger_rejects_malformed.pptx");
+        write_zip(&bare_zip, &[("a.txt", b"alpha")]);
+        let plain = env::temp_dir().join("office_tagger_rejects_malformed.txt");
+        fs::write(&plain, b"not a zip").unwrap();
+
+        let tagger = OfficeTagger::new();
+        assert_eq!(Err(Error::Illegible), tagger.tag(&garbage));
+        assert_eq!(Err(Error::Illegible), tagger.tag(&bare_zip));
+        assert_eq!(Ok(HashSet::new()), tagger.tag(&plain));
+        fs::remove_file(&garbage).unwrap();
+        fs::remove_file(&bare_zip).unwrap();
+        fs::remove_file(&plain).unwrap();
+    }
+
+    #[test]
+    fn password_protected() {
+        // A minimal compound file whose first directory sector, just after
+        // the header, lists the root and an encrypted package
+        let mut cfb = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1".to_vec();
+        cfb.resize(512, 0);
+        cfb[30] = 9;
+        for name in ["Root Entry", "EncryptedPackage"] {
+            let mut entry = name
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>();
+            entry.resize(128, 0);
+            cfb.extend(entry);
+        }
+        let ole = env::temp_dir().join("office_tagger_password_protected.docx");
+        fs::write(&ole, &cfb).unwrap();
+        let zipped = env::temp_dir().join("office_tagger_password_protected.xlsx");
+        write_encrypted_zip(&zipped, &[("docProps/core.xml", CORE)], "hunter2");
+
+        let tagger = OfficeTagger::new();
+        let expected = HashSet::from([Tag::from(PASSWORD_PROTECTED_TAG)]);
+        assert_eq!(Ok(expected.clone()), tagger.tag(&ole));
+        assert_eq!(Ok(expected), tagger.tag(&zipped));
+        fs::remove_file(&ole).unwrap();
+        fs::remove_file(&zipped).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::{debug, error};
+
+use super::{Error, Sniff, Tag, Tagger};
+
+/// Bytes read when run on its own; enough to pass typical JPEG metadata
+/// segments before the frame header.
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+const DEFAULT_SQUARE_TOLERANCE: f64 = 0.05;
+
+/// Tags images with a coarse `orientation` of `portrait`, `landscape` or
+/// `square`, from the dimensions in their header.
+///
+/// PNG, GIF, BMP, JPEG and WebP headers are understood; nothing is decoded.
+/// Images whose longer side is within the square tolerance of the shorter
+/// are `square`. EXIF rotation isn't applied, so a camera's portrait shot
+/// stored sideways reads as `landscape`.
+#[derive(Debug)]
+pub struct OrientationTagger {
+    square_tolerance: f64,
+}
+impl Default for OrientationTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl OrientationTagger {
+    pub fn new() -> Self {
+        Self {
+            square_tolerance: DEFAULT_SQUARE_TOLERANCE,
+        }
+    }
+
+    /// Count images as square when the longer side is at most `tolerance`
+    /// (a fraction, e.g. `0.05`) longer than the shorter.
+    pub fn with_square_tolerance(mut self, tolerance: f64) -> Self {
+        self.square_tolerance = tolerance.max(0.0);
+        self
+    }
+
+    fn orientation(&self, width: u32, height: u32) -> &'static str {
+        let (long, short) = (width.max(height) as f64, width.min(height) as f64);
+        if long <= short * (1.0 + self.square_tolerance) {
+            "square"
+        } else if width > height {
+            "landscape"
+        } else {
+            "portrait"
+        }
+    }
+}
+
+fn u16_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u24_le(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn i32_le(bytes: &[u8], at: usize) -> Option<i32> {
+    Some(i32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Width and height from an image header, if the format is known and the
+/// header lies within `header`.
+fn dimensions(header: &[u8]) -> Option<(u32, u32)> {
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((u32_be(header, 16)?, u32_be(header, 20)?));
+    }
+    if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        return Some((u16_le(header, 6)?, u16_le(header, 8)?));
+    }
+    if header.starts_with(b"BM") {
+        // Only the known DIB header sizes, as `BM` alone also starts text
+        return match u32::from_le_bytes(header.get(14..18)?.try_into().ok()?) {
+            12 => Some((u16_le(header, 18)?, u16_le(header, 20)?)),
+            // Negative heights are top-down bitmaps
+            40 | 52 | 56 | 108 | 124 => Some((
+                i32_le(header, 18)?.unsigned_abs(),
+                i32_le(header, 22)?.unsigned_abs(),
+            )),
+            _ => None,
+        };
+    }
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        return match header.get(12..16)? {
+            b"VP8X" => Some((u24_le(header, 24)? + 1, u24_le(header, 27)? + 1)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(header.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8 " => Some((u16_le(header, 26)? & 0x3fff, u16_le(header, 28)? & 0x3fff)),
+            _ => None,
+        };
+    }
+    if header.starts_with(b"\xff\xd8") {
+        return jpeg_dimensions(header);
+    }
+    None
+}
+
+/// Walk JPEG segments to the first start-of-frame marker.
+fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *jpeg.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *jpeg.get(at + 1)?;
+        match marker {
+            // Fill bytes before a marker
+            0xff => at += 1,
+            // Standalone markers carry no length
+            0x01 | 0xd0..=0xd7 => at += 2,
+            // Start of frame, other than DHT, JPG and DAC
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return Some((u16_be(jpeg, at + 7)?, u16_be(jpeg, at + 5)?));
+            }
+            // Start of scan with no frame header seen
+            0xda => return None,
+            _ => at += 2 + u16_be(jpeg, at + 2)? as usize,
+        }
+    }
+}
+
+impl Tagger for OrientationTagger {
+    fn name(&self) -> &str {
+        "orientation"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let sniff = Sniff::read(path, DEFAULT_SAMPLE_SIZE).map_err(|e| {
+            error!(error = ?e, "read for orientation");
+            Error::Illegible
+        })?;
+        self.tag_sniffed(path, &sniff)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some((width, height)) = dimensions(&sniff.prefix) else {
+            return Ok(HashSet::new());
+        };
+        if width == 0 || height == 0 {
+            debug!(?path, width, height, "empty image, skip orientation");
+            return Ok(HashSet::new());
+        }
+        Ok(HashSet::from([Tag::new(
+            "orientation",
+            true,
+            self.orientation(width, height),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::OrientationTagger;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png.extend_from_slice(b"\x08\x02\0\0\0");
+        png
+    }
+
+    fn orientation(value: &str) -> HashSet<Tag> {
+        HashSet::from([Tag::new("orientation", true, value)])
+    }
+
+    #[test]
+    fn wide_tall_and_square() -> io::Result<()> {
+        let tagger = OrientationTagger::new();
+        assert_eq!(
+            orientation("landscape"),
+            tags_of(&tagger, "wide.png", png(640, 480))?
+        );
+        assert_eq!(
+            orientation("portrait"),
+            tags_of(&tagger, "tall.png", png(480, 640))?
+        );
+        assert_eq!(
+            orientation("square"),
+            tags_of(&tagger, "square.png", png(500, 500))?
+        );
+        // Within 5% is still square
+        assert_eq!(
+            orientation("square"),
+            tags_of(&tagger, "nearly.png", png(500, 524))?
+        );
+        assert_eq!(
+            orientation("portrait"),
+            tags_of(&tagger, "nearly.png", png(500, 526))?
+        );
+        assert_eq!(
+            orientation("square"),
+            tags_of(
+                &OrientationTagger::new().with_square_tolerance(0.1),
+                "tolerant.png",
+                png(500, 526)
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn other_formats() -> io::Result<()> {
+        let tagger = OrientationTagger::new();
+        let gif = b"GIF89a\x20\x03\x58\x02\0\0\0";
+        assert_eq!(orientation("landscape"), tags_of(&tagger, "wide.gif", gif)?);
+        // APP0 segment, then a baseline frame header 300 high and 200 wide
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04\x00\x00\xff\xc0\x00\x11\x08\x01\x2c\x00\xc8\x03";
+        assert_eq!(orientation("portrait"), tags_of(&tagger, "tall.jpg", jpeg)?);
+        // A 40 byte DIB header, 100 wide and 200 high stored top-down
+        let mut bmp = b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0\x64\0\0\0".to_vec();
+        bmp.extend_from_slice(&(-200i32).to_le_bytes());
+        assert_eq!(orientation("portrait"), tags_of(&tagger, "tall.bmp", &bmp)?);
+        // The OS/2 core header holds 16-bit sides
+        let core = b"BM\x1e\0\0\0\0\0\0\0\x1a\0\0\0\x0c\0\0\0\xc8\0\x64\0\x01\0\x18\0";
+        assert_eq!(
+            orientation("landscape"),
+            tags_of(&tagger, "wide.bmp", core)?
+        );
+        assert!(tags_of(&tagger, "text.txt", b"not an image")?.is_empty());
+        assert!(tags_of(&tagger, "bmw.txt", b"BMW service history, 2019 to 2024")?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+//! Whether an encrypted PDF opens without a password. PDFs restricted only
+//! by an owner password are encrypted with an empty user password, which
+//! the standard security handler lets anyone open.
+use aes::{
+    cipher::{BlockEncrypt as _, KeyInit as _},
+    Aes128, Block,
+};
+use md5::{Digest as _, Md5};
+use sha2::{Sha256, Sha384, Sha512};
+
+use super::pdf_tagger::{find, rfind};
+
+/// Pads passwords to 32 bytes for revisions 2 to 4.
+const PADDING: [u8; 32] = [
+    0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+    0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe, 0x64, 0x53, 0x69, 0x7a,
+];
+
+/// Whether the PDF is encrypted and can't be read without a password: it
+/// names an `/Encrypt` dictionary, and the empty user password doesn't open
+/// it. Encryption that can't be checked here, such as public-key security
+/// or a dictionary that can't be found, counts as needing one.
+pub(super) fn needs_password(pdf: &[u8]) -> bool {
+    encrypt_dictionary(pdf)
+        .is_some_and(|value| !opens_without_password(pdf, value).unwrap_or(false))
+}
+
+/// The value of the first `/Encrypt` key, if the PDF names one.
+pub(super) fn encrypt_dictionary(pdf: &[u8]) -> Option<&[u8]> {
+    let mut rest = pdf;
+    while let Some(idx) = find(rest, b"/Encrypt") {
+        let after = &rest[idx + b"/Encrypt".len()..];
+        // Not a longer name such as `/EncryptMetadata`
+        if after.first().is_none_or(|next| !is_regular(*next)) {
+            return Some(skip_space(after));
+        }
+        rest = after;
+    }
+    None
+}
+
+fn opens_without_password(pdf: &[u8], encrypt: &[u8]) -> Option<bool> {
+    let encrypt = resolve(pdf, encrypt)?;
+    let entries = dictionary(encrypt)?;
+    let get = |key: &[u8]| {
+        entries
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+    };
+    if get(b"Filter")? != b"/Standard" {
+        return None;
+    }
+    let revision = integer(get(b"R")?)?;
+    let owner = string(get(b"O")?)?;
+    let user = string(get(b"U")?)?;
+    match revision {
+        2..=4 => {
+            let permissions = integer(get(b"P")?)?;
+            let length = get(b"Length").and_then(integer).unwrap_or(40);
+            let encrypt_metadata = get(b"EncryptMetadata").is_none_or(|value| value != b"false");
+            let id = first_id(pdf).unwrap_or_default();
+            let key = rc4_key(
+                b"",
+                revision,
+                length,
+                &owner,
+                permissions,
+                &id,
+                encrypt_metadata,
+            );
+            let hash = user_hash(&key, revision, &id);
+            Some(user.get(..hash.len())? == hash)
+        }
+        5 | 6 => {
+            let salt = user.get(32..40)?;
+            Some(user.get(..32)? == sha_hash(b"", salt, revision))
+        }
+        _ => None,
+    }
+}
+
+/// Key for revisions 2 to 4 from `password` (algorithm 2 of ISO 32000).
+fn rc4_key(
+    password: &[u8],
+    revision: i64,
+    length: i64,
+    owner: &[u8],
+    permissions: i64,
+    id: &[u8],
+    encrypt_metadata: bool,
+) -> Vec<u8> {
+    let bytes = match revision {
+        2 => 5,
+        _ => (length / 8).clamp(5, 16) as usize,
+    };
+    let mut md5 = Md5::new();
+    md5.update(padded(password));
+    md5.update(&owner[..owner.len().min(32)]);
+    md5.update((permissions as u32).to_le_bytes());
+    md5.update(id);
+    if revision >= 4 && !encrypt_metadata {
+        md5.update([0xff; 4]);
+    }
+    let mut hash = md5.finalize().to_vec();
+    if revision >= 3 {
+        for _ in 0..50 {
+            hash = Md5::digest(&hash[..bytes]).to_vec();
+        }
+    }
+    hash.truncate(bytes);
+    hash
+}
+
+/// The start of the `/U` entry a user password giving `key` matches
+/// (algorithms 4 and 5).
+fn user_hash(key: &[u8], revision: i64, id: &[u8]) -> Vec<u8> {
+    if revision == 2 {
+        return rc4(key, &PADDING);
+    }
+    let mut md5 = Md5::new();
+    md5.update(PADDING);
+    md5.update(id);
+    let mut hash = rc4(key, &md5.finalize());
+    for i in 1..=19 {
+        let key = key.iter().map(|b| b ^ i).collect::<Vec<_>>();
+        hash = rc4(&key, &hash);
+    }
+    hash
+}
+
+/// Hash of `password` with `salt` for revisions 5 and 6 (algorithm 2.B).
+fn sha_hash(password: &[u8], salt: &[u8], revision: i64) -> Vec<u8> {
+    let mut hash = Sha256::new_with_prefix(password)
+        .chain_update(salt)
+        .finalize()
+        .to_vec();
+    if revision == 5 {
+        return hash;
+    }
+    let mut round = 0;
+    loop {
+        let data = [password, &hash].concat().repeat(64);
+        let encrypted = aes128_cbc(&hash[..16], &hash[16..32], &data);
+        let remainder = encrypted[..16].iter().map(|b| *b as usize).sum::<usize>() % 3;
+        hash = match remainder {
+            0 => Sha256::digest(&encrypted).to_vec(),
+            1 => Sha384::digest(&encrypted).to_vec(),
+            _ => Sha512::digest(&encrypted).to_vec(),
+        };
+        round += 1;
+        if round >= 64
+            && encrypted
+                .last()
+                .is_some_and(|last| *last as usize <= round - 32)
+        {
+            break;
+        }
+    }
+    hash.truncate(32);
+    hash
+}
+
+fn padded(password: &[u8]) -> Vec<u8> {
+    let password = &password[..password.len().min(32)];
+    [password, &PADDING[..32 - password.len()]].concat()
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|b| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            b ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// `data`, a whole number of blocks, encrypted without padding.
+fn aes128_cbc(key: &[u8], iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new_from_slice(key).expect("16 byte key");
+    let mut previous = Block::clone_from_slice(iv);
+    let mut encrypted = Vec::with_capacity(data.len());
+    for chunk in data.chunks(16) {
+        let mut block = Block::clone_from_slice(chunk);
+        block
+            .iter_mut()
+            .zip(previous.iter())
+            .for_each(|(b, p)| *b ^= p);
+        cipher.encrypt_block(&mut block);
+        encrypted.extend_from_slice(&block);
+        previous = block;
+    }
+    encrypted
+}
+
+/// The first string of the last `/ID` array, which revisions 2 to 4 mix
+/// into the key.
+fn first_id(pdf: &[u8]) -> Option<Vec<u8>> {
+    let idx = rfind(pdf, b"/ID")?;
+    let array = skip_space(&pdf[idx + b"/ID".len()..]).strip_prefix(b"[")?;
+    let (id, _) = object(skip_space(array))?;
+    string(id)
+}
+
+/// The dictionary `value` is, or refers to with `n g R`.
+fn resolve<'p>(pdf: &'p [u8], value: &'p [u8]) -> Option<&'p [u8]> {
+    if value.starts_with(b"<<") {
+        return object(value).map(|(dictionary, _)| dictionary);
+    }
+    let (number, rest) = object(value)?;
+    let (generation, _) = object(skip_space(rest))?;
+    let header = [number, b" ", generation, b" obj"].concat();
+    // The last definition wins, as incremental updates append
+    let mut rest = pdf;
+    let mut found = None;
+    while let Some(idx) = find(rest, &header) {
+        if idx == 0 || !rest[idx - 1].is_ascii_digit() {
+            found = Some(&rest[idx + header.len()..]);
+        }
+        rest = &rest[idx + header.len()..];
+    }
+    object(skip_space(found?)).map(|(dictionary, _)| dictionary)
+}
+
+/// The key and value of each entry of `dictionary`, keys without their `/`.
+fn dictionary(dictionary: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut rest = skip_space(dictionary.strip_prefix(b"<<")?);
+    let mut entries = Vec::new();
+    while !rest.starts_with(b">>") {
+        let (key, after) = object(rest)?;
+        let key = key.strip_prefix(b"/")?;
+        let start = skip_space(after);
+        let (mut value, mut after) = object(start)?;
+        // An indirect reference is three tokens
+        if let Some((_, rest)) = object(skip_space(after))
+            .filter(|(generation, _)| generation.iter().all(u8::is_ascii_digit))
+            .and_then(|(_, rest)| object(skip_space(rest)))
+            .filter(|(r, _)| *r == b"R")
+        {
+            value = &start[..start.len() - rest.len()];
+            after = rest;
+        }
+        entries.push((key, value));
+        rest = skip_space(after);
+    }
+    Some(entries)
+}
+
+/// The first object of `data`, and what follows it.
+fn object(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = match data.first()? {
+        b'<' if data.starts_with(b"<<") => {
+            let mut rest = skip_space(&data[2..]);
+            while !rest.starts_with(b">>") {
+                rest = skip_space(object(rest)?.1);
+            }
+            data.len() - rest.len() + 2
+        }
+        b'[' => {
+            let mut rest = skip_space(&data[1..]);
+            while !rest.starts_with(b"]") {
+                rest = skip_space(object(rest)?.1);
+            }
+            data.len() - rest.len() + 1
+        }
+        b'<' => data.iter().position(|b| *b == b'>')? + 1,
+        b'(' => {
+            let mut depth = 0;
+            let mut idx = 1;
+            loop {
+                match *data.get(idx)? {
+                    b'\\' => idx += 1,
+                    b'(' => depth += 1,
+                    b')' if depth == 0 => break idx + 1,
+                    b')' => depth -= 1,
+                    _ => {}
+                }
+                idx += 1;
+            }
+        }
+        b'/' => 1 + data[1..].iter().take_while(|b| is_regular(**b)).count(),
+        _ => data.iter().take_while(|b| is_regular(**b)).count(),
+    };
+    (end > 0).then(|| data.split_at(end))
+}
+
+fn integer(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// The bytes of a literal `(...)` or hex `<...>` string.
+fn string(value: &[u8]) -> Option<Vec<u8>> {
+    if let Some(hex) = value.strip_prefix(b"<") {
+        let digits = hex
+            .iter()
+            .take_while(|b| **b != b'>')
+            .filter_map(|b| (*b as char).to_digit(16))
+            .map(|digit| digit as u8)
+            .collect::<Vec<_>>();
+        // A missing final digit is taken as 0
+        return Some(
+            digits
+                .chunks(2)
+                .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+                .collect(),
+        );
+    }
+    let literal = value.strip_prefix(b"(")?.strip_suffix(b")")?;
+    let mut bytes = Vec::with_capacity(literal.len());
+    let mut idx = 0;
+    while let Some(&b) = literal.get(idx) {
+        idx += 1;
+        match b {
+            b'\\' => {
+                let Some(&escaped) = literal.get(idx) else {
+                    break;
+                };
+                idx += 1;
+                match escaped {
+                    b'n' => bytes.push(b'\n'),
+                    b'r' => bytes.push(b'\r'),
+                    b't' => bytes.push(b'\t'),
+                    b'b' => bytes.push(0x08),
+                    b'f' => bytes.push(0x0c),
+                    b'0'..=b'7' => {
+                        let digits = literal[idx - 1..]
+                            .iter()
+                            .take(3)
+                            .take_while(|b| (b'0'..=b'7').contains(*b))
+                            .fold(Vec::new(), |mut digits, digit| {
+                                digits.push(digit - b'0');
+                                digits
+                            });
+                        idx += digits.len() - 1;
+                        bytes.push(digits.iter().fold(0u8, |acc, d| acc.wrapping_mul(8) + d));
+                    }
+                    // A backslash before an end of line continues the string
+                    b'\r' => {
+                        if literal.get(idx) == Some(&b'\n') {
+                            idx += 1;
+                        }
+                    }
+                    b'\n' => {}
+                    other => bytes.push(other),
+                }
+            }
+            // Any unescaped end of line reads as a line feed
+            b'\r' => {
+                if literal.get(idx) == Some(&b'\n') {
+                    idx += 1;
+                }
+                bytes.push(b'\n');
+            }
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+/// Whether `b` may continue a name or other bare token.
+fn is_regular(b: u8) -> bool {
+    !b.is_ascii_whitespace() && b != 0 && !b"()<>[]{}/%".contains(&b)
+}
+
+/// `data` after any leading whitespace and comments.
+fn skip_space(mut data: &[u8]) -> &[u8] {
+    loop {
+        match data.first() {
+            Some(b) if b.is_ascii_whitespace() || *b == 0 => data = &data[1..],
+            Some(b'%') => {
+                let end = data
+                    .iter()
+                    .position(|b| *b == b'\r' || *b == b'\n')
+                    .unwrap_or(data.len());
+                data = &data[end..];
+            }
+            _ => return data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dictionary, string};
+
+    #[test]
+    fn parses_strings_and_dictionaries() {
+        assert_eq!(
+            Some(b"a(b)\nc\x53\x07".to_vec()),
+            string(b"(a\\(b\\)\\nc\\123\\7)")
+        );
+        assert_eq!(
+            Some(b"line\ncontinued".to_vec()),
+            string(b"(line\r\ncon\\\ntinued)")
+        );
+        assert_eq!(Some(vec![0x90, 0x1f, 0xa0]), string(b"<90 1f A>"));
+        assert_eq!(
+            Some(vec![
+                (&b"CF"[..], &b"<</StdCF<</Length 16>>>>"[..]),
+                (b"O", b"(<>)"),
+                (b"Info", b"12 0 R"),
+                (b"P", b"-4"),
+            ]),
+            dictionary(b"<</CF<</StdCF<</Length 16>>>>/O(<>) % comment\n/Info 12 0 R/P -4>>")
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read as _},
+    path::Path,
+};
+
+use flate2::read::ZlibDecoder;
+use tracing::{debug, error};
+
+use super::{
+    pdf_security::{encrypt_dictionary, needs_password},
+    Error, ResourceLimits, Tag, Tagger, PASSWORD_PROTECTED_TAG,
+};
+
+const MAGIC: &[u8] = b"%PDF-";
+const DEFAULT_MAX_PAGES: usize = 20;
+/// Most bytes inflated from any one stream.
+const MAX_INFLATED: u64 = 4 * 1024 * 1024;
+const STREAM: &[u8] = b"stream";
+const END_STREAM: &[u8] = b"endstream";
+
+/// Separates PDFs with extractable text from scanned, image-only ones,
+/// emitting `pdf-text:searchable` or `pdf-text:scanned`.
+///
+/// Streams are read in file order until the page cap is passed, inflating
+/// `FlateDecode` ones, including object streams. A PDF is searchable once a
+/// content stream shows text, and scanned if none does but it has images;
+/// PDFs with neither aren't tagged. Encrypted PDFs that the empty user
+/// password doesn't open are tagged [`PASSWORD_PROTECTED_TAG`] instead; those
+/// it does, restricted only by an owner password, aren't tagged, as their
+/// streams are still encrypted.
+/// Files larger than [`ResourceLimits::max_file_bytes`] are skipped.
+#[derive(Debug)]
+pub struct PdfTagger {
+    limits: ResourceLimits,
+    max_pages: usize,
+}
+impl Default for PdfTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PdfTagger {
+    pub fn new() -> Self {
+        Self::with_limits(ResourceLimits::default(), DEFAULT_MAX_PAGES)
+    }
+
+    pub fn with_limits(limits: ResourceLimits, max_pages: usize) -> Self {
+        Self { limits, max_pages }
+    }
+
+    fn classify(&self, pdf: &[u8]) -> Option<&'static str> {
+        let (mut pages, mut images) = (0, false);
+        let mut rest = pdf;
+        while let Some(start) = find(rest, STREAM) {
+            let (objects, after) = rest.split_at(start);
+            pages += count_pages(objects);
+            if pages > self.max_pages {
+                debug!(pages, "page cap reached");
+                break;
+            }
+            images |= find(objects, b"/Image").is_some();
+
+            let body = after[STREAM.len()..]
+                .strip_prefix(b"\r\n")
+                .or_else(|| after[STREAM.len()..].strip_prefix(b"\n"))
+                .unwrap_or(&after[STREAM.len()..]);
+            let end = find(body, END_STREAM).unwrap_or(body.len());
+            // The dictionary of this stream follows the last `obj`
+            let dictionary = rfind(objects, b"obj").map_or(objects, |idx| &objects[idx..]);
+            let data = match find(dictionary, b"/FlateDecode") {
+                Some(_) => match inflate(&body[..end]) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        debug!(error = ?e, "inflate pdf stream");
+                        Vec::new()
+                    }
+                },
+                None => body[..end].to_vec(),
+            };
+            if shows_text(&data) {
+                return Some("searchable");
+            }
+            // Object streams hold page and image dictionaries too
+            pages += count_pages(&data);
+            images |= find(&data, b"/Image").is_some();
+            rest = &body[(end + END_STREAM.len()).min(body.len())..];
+        }
+        images.then_some("scanned")
+    }
+}
+
+pub(super) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+pub(super) fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_INFLATED)
+        .read_to_end(&mut inflated)?;
+    Ok(inflated)
+}
+
+/// Whether `op` appears as a whole content stream operator.
+fn has_operator(data: &[u8], op: &[u8]) -> bool {
+    data.windows(op.len()).enumerate().any(|(idx, window)| {
+        window == op
+            && (idx == 0 || !data[idx - 1].is_ascii_alphanumeric())
+            && data
+                .get(idx + op.len())
+                .is_none_or(|next| !next.is_ascii_alphanumeric())
+    })
+}
+
+/// Whether a content stream opens a text object and shows text in it.
+fn shows_text(data: &[u8]) -> bool {
+    has_operator(data, b"BT") && (has_operator(data, b"Tj") || has_operator(data, b"TJ"))
+}
+
+/// Number of `/Type /Page` dictionaries, not counting `/Pages` trees.
+fn count_pages(data: &[u8]) -> usize {
+    let mut count = 0;
+    let mut rest = data;
+    while let Some(idx) = find(rest, b"/Type") {
+        let after = &rest[idx + b"/Type".len()..];
+        let value = after.trim_ascii_start();
+        if value.starts_with(b"/Page")
+            && value
+                .get(b"/Page".len())
+                .is_none_or(|next| !next.is_ascii_alphanumeric())
+        {
+            count += 1;
+        }
+        rest = after;
+    }
+    count
+}
+
+impl Tagger for PdfTagger {
+    fn name(&self) -> &str {
+        "pdf"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let mut file = File::open(path).map_err(|e| {
+            error!(error = ?e, "open for pdf");
+            Error::Illegible
+        })?;
+        match file.metadata() {
+            Ok(metadata) if !metadata.is_file() || metadata.len() > self.limits.max_file_bytes => {
+                debug!(?path, "skip pdf");
+                return Ok(HashSet::new());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!(error = ?e, "get file metadata");
+                return Err(Error::Illegible);
+            }
+        }
+        let read_error = |e| {
+            error!(error = ?e, "read for pdf");
+            Error::Illegible
+        };
+        // Only read the whole file once it proves to be a PDF
+        let mut content = Vec::new();
+        (&mut file)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut content)
+            .map_err(read_error)?;
+        if content != MAGIC {
+            return Ok(HashSet::new());
+        }
+        file.take(
+            self.limits
+                .max_file_bytes
+                .saturating_sub(MAGIC.len() as u64),
+        )
+        .read_to_end(&mut content)
+        .map_err(read_error)?;
+        if needs_password(&content) {
+            debug!(?path, "password protected pdf");
+            return Ok(HashSet::from([Tag::from(PASSWORD_PROTECTED_TAG)]));
+        }
+        if encrypt_dictionary(&content).is_some() {
+            debug!(?path, "encrypted pdf opens without password");
+            return Ok(HashSet::new());
+        }
+        Ok(self
+            .classify(&content)
+            .map(|text| Tag::new("pdf-text", true, text))
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io, io::Write as _};
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::tagger::{test::tags_of, ResourceLimits, Tag, PASSWORD_PROTECTED_TAG};
+
+    use super::PdfTagger;
+
+    const IMAGE_PAGE: &[u8] = b"3 0 obj\n<< /Type /Page /Parent 2 0 R /Resources << /XObject << /Im0 5 0 R >> >> /Contents 4 0 R >>\nendobj\n4 0 obj\n<< /Length 30 >>\nstream\nq 612 0 0 792 0 0 cm /Im0 Do Q\nendstream\nendobj\n5 0 obj\n<< /Type /XObject /Subtype /Image /Width 1 /Height 1 /ColorSpace /DeviceGray /BitsPerComponent 8 /Length 1 >>\nstream\n\xff\nendstream\nendobj\n";
+
+    fn pdf(body: &[&[u8]]) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n2 0 obj\n<< /Type /Pages /Kids [3 0 R] /Count 1 >>\nendobj\n".to_vec();
+        for part in body {
+            pdf.extend_from_slice(part);
+        }
+        pdf.extend_from_slice(b"trailer\n<< /Root 1 0 R >>\n%%EOF\n");
+        pdf
+    }
+
+    fn text_page(compressed: bool) -> Vec<u8> {
+        let content = b"BT /F1 24 Tf 72 720 Td (Hello, world) Tj ET";
+        let (filter, data) = match compressed {
+            true => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content).unwrap();
+                (" /Filter /FlateDecode", encoder.finish().unwrap())
+            }
+            false => ("", content.to_vec()),
+        };
+        let mut page = format!(
+            "6 0 obj\n<< /Type /Page /Parent 2 0 R /Resources << /Font << /F1 8 0 R >> >> /Contents 7 0 R >>\nendobj\n7 0 obj\n<< /Length {}{filter} >>\nstream\n",
+            data.len()
+        )
+        .into_bytes();
+        page.extend_from_slice(&data);
+        page.extend_from_slice(b"\nendstream\nendobj\n");
+        page
+    }
+
+    #[test]
+    fn searchable_and_scanned() -> io::Result<()> {
+        let tagger = PdfTagger::new();
+        let searchable = HashSet::from([Tag::new("pdf-text", true, "searchable")]);
+        assert_eq!(
+            searchable,
+            tags_of(&tagger, "plain.pdf", pdf(&[&text_page(false)]))?
+        );
+        assert_eq!(
+            searchable,
+            tags_of(&tagger, "deflated.pdf", pdf(&[&text_page(true)]))?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("pdf-text", true, "scanned")]),
+            tags_of(&tagger, "scanned.pdf", pdf(&[IMAGE_PAGE]))?
+        );
+        assert!(tags_of(&tagger, "not.pdf", b"plain text Tj BT")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn stops_at_page_cap() -> io::Result<()> {
+        // A scanned first page followed by a page of text
+        let content = pdf(&[IMAGE_PAGE, &text_page(true)]);
+        assert_eq!(
+            HashSet::from([Tag::new("pdf-text", true, "scanned")]),
+            tags_of(
+                &PdfTagger::with_limits(ResourceLimits::default(), 1),
+                "capped.pdf",
+                &content
+            )?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("pdf-text", true, "searchable")]),
+            tags_of(&PdfTagger::new(), "uncapped.pdf", &content)?
+        );
+        let small = ResourceLimits {
+            max_file_bytes: content.len() as u64 - 1,
+            ..ResourceLimits::default()
+        };
+        assert!(tags_of(
+            &PdfTagger::with_limits(small, 20),
+            "oversized.pdf",
+            &content
+        )?
+        .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_not_classified() -> io::Result<()> {
+        let mut content = pdf(&[&text_page(false)]);
+        let trailer = content.len() - b"%%EOF\n".len() - b">>\n".len();
+        content.splice(trailer..trailer, b" /Encrypt 9 0 R".iter().copied());
+        assert_eq!(
+            HashSet::from([Tag::from(PASSWORD_PROTECTED_TAG)]),
+            tags_of(&PdfTagger::new(), "encrypted.pdf", &content)?
+        );
+        Ok(())
+    }
+
+    fn encrypted(dictionary: &str) -> Vec<u8> {
+        let encrypt = format!("9 0 obj\n{dictionary}\nendobj\n");
+        let mut content = pdf(&[&text_page(false), encrypt.as_bytes()]);
+        let trailer = content.len() - b"%%EOF\n".len() - b">>\n".len();
+        content.splice(
+            trailer..trailer,
+            b" /Encrypt 9 0 R /ID [<68DE08CBEBEED742812F49E95EF30E10><17D99A88FB2C1047AC62994FDAE46C4F>]"
+                .iter()
+                .copied(),
+        );
+        content
+    }
+
+    #[test]
+    fn empty_user_password_not_protected() -> io::Result<()> {
+        let tagger = PdfTagger::new();
+        let protected = HashSet::from([Tag::from(PASSWORD_PROTECTED_TAG)]);
+        // Revision 4, restricted by an owner password only
+        let owner = "<734614762E793527DB970A3522B3E1D4ADBD9B3CB4A5897515B259F168D9E9F4>";
+        let revision4 = |user: &str| {
+            encrypted(&format!(
+                "<< /CF << /StdCF << /AuthEvent /DocOpen /CFM /AESV2 /Length 16 >> >> /Filter /Standard /Length 128 /O {owner} /P -1052 /R 4 /StmF /StdCF /StrF /StdCF /U <{user}> /V 4 >>"
+            ))
+        };
+        assert!(tags_of(
+            &tagger,
+            "restricted.pdf",
+            revision4("2E3FB1DA2115447CCC6EAC564DAB0BFF00000000000000000000000000000000")
+        )?
+        .is_empty());
+        assert_eq!(
+            protected,
+            tags_of(
+                &tagger,
+                "locked.pdf",
+                revision4("2E3FB1DA2115447CCC6EAC564DAB0B0000000000000000000000000000000000")
+            )?
+        );
+        // Revision 6, with validation and key salts 1 to 8 and 9 to 16
+        let revision6 = |hash: &str| {
+            encrypted(&format!(
+                "<< /Filter /Standard /V 5 /R 6 /Length 256 /P -4 /O <{}> /U <{hash}0102030405060708090A0B0C0D0E0F10> >>",
+                "00".repeat(48)
+            ))
+        };
+        assert!(tags_of(
+            &tagger,
+            "restricted6.pdf",
+            revision6("8D1EFB4F1BDBB651341704C2139DE4F6BE05D6D4609AF56916B21646ED74825C")
+        )?
+        .is_empty());
+        assert_eq!(
+            protected,
+            tags_of(
+                &tagger,
+                "locked6.pdf",
+                revision6("F73C954722FB8E39ECD42D6FBBA64C7B7C9E2066D3D250CCC990BC183B4AB5B8")
+            )?
+        );
+        Ok(())
+    }
+}
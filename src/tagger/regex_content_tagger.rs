@@ -0,0 +1,149 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use regex::bytes::Regex;
+use tracing::debug;
+
+use super::{read_text_sample, Error, RuleError, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Emits declared tags for text files whose content matches regex rules,
+/// e.g. `contains:todo` for files mentioning `TODO`.
+///
+/// Rules are a TOML table of `"pattern" = ["tag", "label:value", ...]` pairs,
+/// compiled once when loaded. Only the [text sample](Sniff::text_sample) is
+/// searched, at most 1MiB and no more than the shared sniff holds. Tags from
+/// every matching rule are combined.
+#[derive(Debug)]
+pub struct RegexContentTagger {
+    rules: Vec<(Regex, Vec<String>)>,
+    limits: SampleLimits,
+}
+impl Default for RegexContentTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RegexContentTagger {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            // Only the sample is read, so the file's size doesn't matter
+            limits: SampleLimits::new(u64::MAX, DEFAULT_MAX_BYTES),
+        }
+    }
+
+    /// Search at most the first `max_bytes` of each file.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.limits.sample_size = max_bytes;
+        self
+    }
+
+    pub fn from_toml(table: &str) -> Result<Self, RuleError> {
+        let table: BTreeMap<String, Vec<String>> =
+            toml::from_str(table).map_err(RuleError::Toml)?;
+        let mut tagger = Self::new();
+        for (pattern, tags) in table {
+            tagger.add_rule(&pattern, tags)?;
+        }
+        Ok(tagger)
+    }
+
+    pub fn add_rule(
+        &mut self,
+        pattern: &str,
+        tags: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<(), RuleError> {
+        let regex = Regex::new(pattern).map_err(RuleError::Regex)?;
+        self.rules
+            .push((regex, tags.into_iter().map(Into::into).collect()));
+        Ok(())
+    }
+}
+impl Tagger for RegexContentTagger {
+    fn name(&self) -> &str {
+        "content"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        if self.rules.is_empty() {
+            return Ok(HashSet::new());
+        }
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(content) = sniff.text_sample(self.limits) else {
+            debug!(?path, "skip content rules");
+            return Ok(HashSet::new());
+        };
+        Ok(self
+            .rules
+            .iter()
+            .filter(|(regex, _)| regex.is_match(content))
+            .flat_map(|(_, tags)| tags.iter().map(Tag::from_display))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, RuleError, Tag};
+
+    use super::RegexContentTagger;
+
+    const RULES: &str = r#"
+"TODO|FIXME" = ["contains:todo"]
+'(?m)^\d{4}-\d{2}-\d{2}T\S+ ERROR ' = ["contains:error", "log"]
+"#;
+
+    #[test]
+    fn matching_and_not() -> io::Result<()> {
+        let tagger = RegexContentTagger::from_toml(RULES).unwrap();
+        assert_eq!(
+            HashSet::from([Tag::new("contains", false, "todo")]),
+            tags_of(
+                &tagger,
+                "todo.rs",
+                b"fn main() {\n    // TODO: parse args\n}\n"
+            )?
+        );
+        assert!(tags_of(&tagger, "done.rs", b"fn main() {}\n")?.is_empty());
+        assert!(tags_of(&tagger, "binary.bin", b"\0TODO")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_patterns_combine() -> io::Result<()> {
+        let tagger = RegexContentTagger::from_toml(RULES).unwrap();
+        let log = b"2024-03-01T10:00:00Z INFO started\n2024-03-01T10:00:01Z ERROR disk full\n# FIXME rotate\n";
+        assert_eq!(
+            HashSet::from([
+                Tag::new("contains", false, "todo"),
+                Tag::new("contains", false, "error"),
+                Tag::from("log"),
+            ]),
+            tags_of(&tagger, "both.log", log)?
+        );
+        // The FIXME lies past the searched prefix
+        assert_eq!(
+            HashSet::from([Tag::new("contains", false, "error"), Tag::from("log")]),
+            tags_of(&tagger.with_max_bytes(71), "capped.log", log)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(matches!(
+            RegexContentTagger::from_toml(r#""(" = ["x"]"#),
+            Err(RuleError::Regex(_))
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+//! Taggers by name, so the set run can be chosen at startup and extended
+//! without changing how the [`FileUpdater`](crate::FileUpdater) is built.
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Context as _};
+#[cfg(feature = "magic")]
+use magic::{cookie::Load, Cookie};
+
+use super::{
+    ArchiveTagger, CodeLanguageTagger, CompressionTagger, DurationTagger, EntropyTagger, EolTagger,
+    FormatValidityTagger, FriendlyTypeTagger, IndentTagger, KindTagger, LicenseTagger,
+    LineCountTagger, LineLengthTagger, MetadataTagger, MimeExtractor, MimeTagger, MinifiedTagger,
+    NamespacedTagger, OfficeTagger, OrientationTagger, PdfTagger, RegexContentTagger, RuleTagger,
+    ScriptTagger, SlashEscape, Tagger, TaxonomyTagger, UnknownMime,
+};
+
+/// Settings factories build their taggers from.
+#[derive(Clone, Debug, Default)]
+pub struct TaggerConfig {
+    /// Namespace prefixed to labels emitted by the mime tagger
+    pub mime_namespace: Option<String>,
+    /// Namespace prefixed to labels emitted by the metadata tagger
+    pub metadata_namespace: Option<String>,
+    /// TOML file of `"mime/type" = "name"` overrides for the `type` tag
+    pub friendly_types: Option<PathBuf>,
+    /// TOML file of `"glob" = ["tag", ...]` rules
+    pub rules: Option<PathBuf>,
+    /// TOML file of `"regex" = ["tag", ...]` rules matched against content
+    pub content_rules: Option<PathBuf>,
+    /// TOML file of `[[category]]` tables filing files into a category tree
+    pub taxonomy: Option<PathBuf>,
+    pub slash_escape: SlashEscape,
+    /// Access ages the metadata tagger counts as `recent` and `ancient`,
+    /// when not its defaults
+    pub accessed_thresholds: Option<(Duration, Duration)>,
+    /// Fraction by which an image's sides may differ and still be
+    /// `orientation:square`, when not the default
+    pub square_tolerance: Option<f64>,
+}
+
+/// Builds a tagger from the config, or `None` when the config leaves it
+/// nothing to do.
+pub type TaggerFactory = Box<dyn Fn(&TaggerConfig) -> anyhow::Result<Option<Box<dyn Tagger>>>>;
+
+/// Factories by tagger name, remembering the order they were registered in.
+#[derive(Default)]
+pub struct TaggerRegistry {
+    factories: HashMap<String, TaggerFactory>,
+    names: Vec<String>,
+}
+impl TaggerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry of every built-in tagger, in the order they run by default.
+    #[cfg(feature = "magic")]
+    pub fn with_builtins() -> Self {
+        Self::builtins::<Cookie<Load>>(true)
+    }
+
+    /// Registry of every built-in tagger, in the order they run by default.
+    #[cfg(not(feature = "magic"))]
+    pub fn with_builtins() -> Self {
+        Self::without_magic()
+    }
+
+    /// The built-in taggers that don't need libmagic, as builds without the
+    /// `magic` feature have: `mime` and `friendly-type` are missing. Those
+    /// builds sniff no MIME types either, so taxonomy `mime` rules never
+    /// match.
+    pub fn without_magic() -> Self {
+        Self::builtins::<UnknownMime>(false)
+    }
+
+    /// Built-in taggers detecting MIME types with `M`, including the ones
+    /// that only report it when `mime` is set.
+    fn builtins<M: MimeExtractor + std::fmt::Debug + 'static>(mime: bool) -> Self {
+        let mut registry = Self::new();
+        registry.register("metadata", |config| {
+            let tagger = match config.accessed_thresholds {
+                Some((recent, ancient)) => {
+                    MetadataTagger::new().with_access_thresholds(recent, ancient)
+                }
+                None => MetadataTagger::new(),
+            };
+            Ok(Some(match &config.metadata_namespace {
+                Some(namespace) => Box::new(NamespacedTagger::new(namespace, tagger)),
+                None => Box::new(tagger),
+            }))
+        });
+        // After metadata, which reads access times before anything else
+        // reads the content
+        if mime {
+            registry.register("mime", |config| {
+                let tagger = MimeTagger::<M>::new().with_escape(config.slash_escape);
+                Ok(Some(match &config.mime_namespace {
+                    Some(namespace) => Box::new(NamespacedTagger::new(namespace, tagger)),
+                    None => Box::new(tagger),
+                }))
+            });
+        }
+        registry.register("line-count", |_| Ok(Some(Box::new(LineCountTagger::new()))));
+        registry.register("compression", |_| {
+            Ok(Some(Box::new(CompressionTagger::new())))
+        });
+        registry.register("archive", |_| Ok(Some(Box::new(ArchiveTagger::new()))));
+        registry.register("entropy", |_| Ok(Some(Box::new(EntropyTagger::new()))));
+        registry.register("kind", |_| Ok(Some(Box::new(KindTagger::new()))));
+        registry.register("indent", |_| Ok(Some(Box::new(IndentTagger::new()))));
+        registry.register("eol", |_| Ok(Some(Box::new(EolTagger::new()))));
+        registry.register("line-length", |_| {
+            Ok(Some(Box::new(LineLengthTagger::new())))
+        });
+        registry.register("office", |config| {
+            Ok(Some(Box::new(
+                OfficeTagger::new().with_escape(config.slash_escape),
+            )))
+        });
+        registry.register("orientation", |config| {
+            Ok(Some(Box::new(match config.square_tolerance {
+                Some(tolerance) => OrientationTagger::new().with_square_tolerance(tolerance),
+                None => OrientationTagger::new(),
+            })))
+        });
+        registry.register("duration", |_| Ok(Some(Box::new(DurationTagger::new()))));
+        registry.register("pdf", |_| Ok(Some(Box::new(PdfTagger::new()))));
+        registry.register("minified", |_| Ok(Some(Box::new(MinifiedTagger::new()))));
+        registry.register("script", |_| Ok(Some(Box::new(ScriptTagger::new()))));
+        registry.register("language", |_| {
+            Ok(Some(Box::new(CodeLanguageTagger::new())))
+        });
+        registry.register("license", |_| Ok(Some(Box::new(LicenseTagger::new()))));
+        registry.register("format-validity", |_| {
+            Ok(Some(Box::new(FormatValidityTagger::new())))
+        });
+        if mime {
+            registry.register("friendly-type", |config| {
+                let tagger = FriendlyTypeTagger::<M>::new();
+                Ok(Some(Box::new(match &config.friendly_types {
+                    Some(path) => tagger
+                        .with_overrides(&fs::read_to_string(path).context("read friendly types")?)
+                        .context("parse friendly types")?,
+                    None => tagger,
+                })))
+            });
+        }
+        registry.register("rules", |config| {
+            let Some(path) = &config.rules else {
+                return Ok(None);
+            };
+            Ok(Some(Box::new(
+                RuleTagger::from_toml(&fs::read_to_string(path).context("read rules")?)
+                    .context("parse rules")?,
+            )))
+        });
+        registry.register("content", |config| {
+            let Some(path) = &config.content_rules else {
+                return Ok(None);
+            };
+            Ok(Some(Box::new(
+                RegexContentTagger::from_toml(
+                    &fs::read_to_string(path).context("read content rules")?,
+                )
+                .context("parse content rules")?,
+            )))
+        });
+        registry.register("taxonomy", |config| {
+            let Some(path) = &config.taxonomy else {
+                return Ok(None);
+            };
+            Ok(Some(Box::new(
+                TaxonomyTagger::from_toml(&fs::read_to_string(path).context("read taxonomy")?)
+                    .context("parse taxonomy")?,
+            )))
+        });
+        registry
+    }
+
+    /// Register `factory` as `name`, replacing any factory already under
+    /// that name but keeping its place in the order.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(&TaggerConfig) -> anyhow::Result<Option<Box<dyn Tagger>>> + 'static,
+    ) {
+        let name = name.into();
+        if !self.factories.contains_key(&name) {
+            self.names.push(name.clone());
+        }
+        self.factories.insert(name, Box::new(factory));
+    }
+
+    /// Registered names, in registration order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    /// Build the tagger registered as `name`.
+    pub fn build(
+        &self,
+        name: &str,
+        config: &TaggerConfig,
+    ) -> anyhow::Result<Option<Box<dyn Tagger>>> {
+        let factory = self.factories.get(name).ok_or_else(|| {
+            anyhow!(
+                "unknown tagger {name:?}, expected one of {}",
+                self.names.join(", ")
+            )
+        })?;
+        factory(config).with_context(|| format!("build tagger {name:?}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, fs, path::Path};
+
+    use crate::{
+        tagger::{Error, Tag, Tagger},
+        FileUpdater,
+    };
+
+    use super::{TaggerConfig, TaggerRegistry};
+
+    #[derive(Debug)]
+    struct StubTagger;
+    impl Tagger for StubTagger {
+        fn name(&self) -> &str {
+            "stub"
+        }
+        fn tag(&self, _path: &Path) -> Result<HashSet<Tag>, Error> {
+            Ok(HashSet::from([Tag::from("stubbed")]))
+        }
+    }
+
+    #[test]
+    fn custom_factory_enabled_by_name() {
+        let mut registry = TaggerRegistry::with_builtins();
+        registry.register("stub", |_| Ok(Some(Box::new(StubTagger))));
+        assert_eq!(Some("stub"), registry.names().last());
+
+        let config = TaggerConfig::default();
+        let mut file_updater = FileUpdater::new();
+        for name in ["stub", "rules"] {
+            if let Some(tagger) = registry.build(name, &config).unwrap() {
+                file_updater.add_boxed_tagger(tagger);
+            }
+        }
+        // Rules are skipped without a rules file
+        assert_eq!(
+            vec!["stub"],
+            file_updater.tagger_names().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            HashSet::from([Tag::from("stubbed")]),
+            file_updater.tag(Path::new("Cargo.toml"))
+        );
+
+        let err = registry.build("nope", &config).err().unwrap();
+        assert!(err.to_string().contains("unknown tagger \"nope\""));
+    }
+
+    #[test]
+    fn builtins_named_as_taggers() {
+        let registry = TaggerRegistry::with_builtins();
+        let config = TaggerConfig::default();
+        // Rule taggers are skipped without a rules file
+        for name in registry
+            .names()
+            .filter(|name| !["rules", "content", "taxonomy"].contains(name))
+        {
+            let tagger = registry.build(name, &config).unwrap().unwrap();
+            assert_eq!(name, tagger.name());
+        }
+    }
+
+    #[test]
+    fn fallback_builtins_without_magic() {
+        let registry = TaggerRegistry::without_magic();
+        let names = registry.names().collect::<Vec<_>>();
+        assert!(!names.contains(&"mime"));
+        assert!(!names.contains(&"friendly-type"));
+        for name in ["metadata", "kind", "line-count", "rules", "taxonomy"] {
+            assert!(names.contains(&name), "{name} missing");
+        }
+
+        // Taxonomy MIME rules only match in builds sniffing MIME types,
+        // otherwise leaving extensions and the default
+        let taxonomy = env::temp_dir().join("registry_fallback_taxonomy.toml");
+        fs::write(
+            &taxonomy,
+            "default = \"misc\"\n[[category]]\npath = \"text\"\nmime = [\"text/*\"]\n",
+        )
+        .unwrap();
+        let config = TaggerConfig {
+            taxonomy: Some(taxonomy.clone()),
+            ..TaggerConfig::default()
+        };
+        let tagger = registry.build("taxonomy", &config).unwrap().unwrap();
+        fs::remove_file(&taxonomy).unwrap();
+        let category = match cfg!(feature = "magic") {
+            true => "text",
+            false => "misc",
+        };
+        assert_eq!(
+            HashSet::from([Tag::new("cat-l1", false, category)]),
+            tagger.tag(Path::new("Cargo.toml")).unwrap()
+        );
+    }
+}
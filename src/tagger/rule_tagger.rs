@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use glob::Pattern;
+
+use super::{Error, Tag, Tagger};
+
+/// Error loading a rules table.
+#[derive(Debug)]
+pub enum RuleError {
+    Toml(toml::de::Error),
+    Pattern(glob::PatternError),
+    Regex(regex::Error),
+    /// Well-formed TOML not laid out as expected
+    Invalid(String),
+}
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Toml(e) => write!(f, "invalid rules table: {e}"),
+            RuleError::Pattern(e) => write!(f, "invalid glob: {e}"),
+            RuleError::Regex(e) => write!(f, "invalid regex: {e}"),
+            RuleError::Invalid(e) => write!(f, "invalid rules: {e}"),
+        }
+    }
+}
+impl std::error::Error for RuleError {}
+
+/// Emits declared tags for paths matching glob rules.
+///
+/// Rules are a TOML table of `"glob" = ["tag", "label:value", ...]` pairs,
+/// matched against the full source path. Tags from every matching rule are
+/// combined.
+#[derive(Debug, Default)]
+pub struct RuleTagger {
+    rules: Vec<(Pattern, Vec<String>)>,
+}
+impl RuleTagger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_toml(table: &str) -> Result<Self, RuleError> {
+        let table: BTreeMap<String, Vec<String>> =
+            toml::from_str(table).map_err(RuleError::Toml)?;
+        let mut tagger = Self::new();
+        for (glob, tags) in table {
+            tagger.add_rule(&glob, tags)?;
+        }
+        Ok(tagger)
+    }
+
+    pub fn add_rule(
+        &mut self,
+        glob: &str,
+        tags: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<(), RuleError> {
+        let pattern = Pattern::new(glob).map_err(RuleError::Pattern)?;
+        self.rules
+            .push((pattern, tags.into_iter().map(Into::into).collect()));
+        Ok(())
+    }
+}
+impl Tagger for RuleTagger {
+    fn name(&self) -> &str {
+        "rules"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        Ok(self
+            .rules
+            .iter()
+            .filter(|(pattern, _)| pattern.matches_path(path))
+            .flat_map(|(_, tags)| tags.iter().map(Tag::from_display))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, path::Path};
+
+    use crate::tagger::{Tag, Tagger};
+
+    use super::{RuleError, RuleTagger};
+
+    const RULES: &str = r#"
+"*.pdf" = ["document"]
+"*.invoice.pdf" = ["doctype:invoice", "finance"]
+"/archive/**/*" = ["archived"]
+"#;
+
+    #[test]
+    fn overlapping_rules_union() {
+        let tagger = RuleTagger::from_toml(RULES).unwrap();
+        assert_eq!(
+            HashSet::from([
+                Tag::from("document"),
+                Tag::new("doctype", false, "invoice"),
+                Tag::from("finance"),
+                Tag::from("archived"),
+            ]),
+            tagger
+                .tag(Path::new("/archive/2024/march.invoice.pdf"))
+                .unwrap()
+        );
+        assert_eq!(
+            HashSet::from([Tag::from("document")]),
+            tagger.tag(Path::new("/docs/notes.pdf")).unwrap()
+        );
+    }
+
+    #[test]
+    fn non_matching_file() {
+        let tagger = RuleTagger::from_toml(RULES).unwrap();
+        assert!(tagger.tag(Path::new("/docs/notes.txt")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(matches!(
+            RuleTagger::from_toml(r#""[" = ["x"]"#),
+            Err(RuleError::Pattern(_))
+        ));
+        assert!(matches!(
+            RuleTagger::from_toml(r#""*" = "x""#),
+            Err(RuleError::Toml(_))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing::debug;
+
+use super::{read_text_sample, Error, SampleLimits, Sniff, Tag, Tagger};
+
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_SAMPLE_SIZE: u64 = 64 * 1024;
+/// Label-less tag on text with any non-ASCII character.
+const NON_ASCII_TAG: &str = "non-ascii";
+
+/// Writing systems recognised, by the `script` value they're tagged with.
+fn script(c: char) -> Option<&'static str> {
+    match c {
+        'A'..='Z'
+        | 'a'..='z'
+        | '\u{00C0}'..='\u{00D6}'
+        | '\u{00D8}'..='\u{00F6}'
+        | '\u{00F8}'..='\u{024F}'
+        | '\u{1E00}'..='\u{1EFF}' => Some("latin"),
+        '\u{0400}'..='\u{052F}'
+        | '\u{1C80}'..='\u{1C8F}'
+        | '\u{2DE0}'..='\u{2DFF}'
+        | '\u{A640}'..='\u{A69F}' => Some("cyrillic"),
+        // Han, kana and hangul
+        '\u{1100}'..='\u{11FF}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3130}'..='\u{318F}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{3134F}' => Some("cjk"),
+        _ => None,
+    }
+}
+
+/// Finds the writing systems used in UTF-8 text files, emitting a `script`
+/// tag for each of Latin, Cyrillic and CJK letters in their
+/// [text sample](Sniff::text_sample), and a label-less `non-ascii` tag for
+/// text with any character outside ASCII. Samples that aren't UTF-8 aren't
+/// tagged.
+#[derive(Debug)]
+pub struct ScriptTagger {
+    limits: SampleLimits,
+}
+impl Default for ScriptTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ScriptTagger {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_SIZE, DEFAULT_SAMPLE_SIZE)
+    }
+
+    pub fn with_limits(max_size: u64, sample_size: u64) -> Self {
+        Self {
+            limits: SampleLimits::new(max_size, sample_size),
+        }
+    }
+}
+impl Tagger for ScriptTagger {
+    fn name(&self) -> &str {
+        "script"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        self.tag_sniffed(path, &read_text_sample(path, self.limits)?)
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        let Some(sample) = sniff.text_sample(self.limits) else {
+            debug!(?path, "skip script");
+            return Ok(HashSet::new());
+        };
+        let text = match std::str::from_utf8(sample) {
+            Ok(text) => text,
+            // The sample may end part way through a character
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&sample[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(_) => {
+                debug!(?path, "not utf-8, skip script");
+                return Ok(HashSet::new());
+            }
+        };
+
+        let mut tags = text
+            .chars()
+            .filter_map(script)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|script| Tag::new("script", false, script))
+            .collect::<HashSet<_>>();
+        if !text.is_ascii() {
+            tags.insert(Tag::from(NON_ASCII_TAG));
+        }
+        Ok(tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    use crate::tagger::{test::tags_of, Tag};
+
+    use super::ScriptTagger;
+
+    #[test]
+    fn ascii_cyrillic_and_cjk() -> io::Result<()> {
+        assert_eq!(
+            HashSet::from([Tag::new("script", false, "latin")]),
+            tags_of(&ScriptTagger::new(), "ascii.txt", b"plain old text\n")?
+        );
+        assert_eq!(
+            HashSet::from([
+                Tag::new("script", false, "cyrillic"),
+                Tag::from("non-ascii")
+            ]),
+            tags_of(
+                &ScriptTagger::new(),
+                "cyrillic.txt",
+                "Привет, мир!\n".as_bytes()
+            )?
+        );
+        assert_eq!(
+            HashSet::from([Tag::new("script", false, "cjk"), Tag::from("non-ascii")]),
+            tags_of(
+                &ScriptTagger::new(),
+                "cjk.txt",
+                "你好，世界。こんにちは\n".as_bytes()
+            )?
+        );
+        assert_eq!(
+            HashSet::from([
+                Tag::new("script", false, "latin"),
+                Tag::new("script", false, "cyrillic"),
+                Tag::from("non-ascii"),
+            ]),
+            tags_of(
+                &ScriptTagger::new(),
+                "mixed.txt",
+                "Moscow — Москва\n".as_bytes()
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_binary_and_non_utf8() -> io::Result<()> {
+        assert!(tags_of(&ScriptTagger::new(), "binary.bin", b"abc\0def")?.is_empty());
+        assert!(tags_of(&ScriptTagger::new(), "latin1.txt", b"caf\xe9 au lait")?.is_empty());
+        // Cut part way through a character by the sample
+        let tags = tags_of(&ScriptTagger::with_limits(1024, 3), "truncated.txt", "Жж")?;
+        assert_eq!(
+            HashSet::from([
+                Tag::new("script", false, "cyrillic"),
+                Tag::from("non-ascii")
+            ]),
+            tags
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use std::{collections::HashSet, path::Path};
+
+use glob::Pattern;
+use toml::{Table, Value};
+use tracing::error;
+
+use super::{Error, RuleError, Sniff, Tag, Tagger, DEFAULT_SNIFF_BYTES};
+
+/// Label of the first level; deeper levels count up from it.
+const LEVEL_LABEL: &str = "cat-l";
+
+/// Which files fall into a category: any of its extensions, MIME types or
+/// path globs matching is enough.
+#[derive(Debug)]
+struct Category {
+    levels: Vec<String>,
+    extensions: Vec<String>,
+    mimes: Vec<String>,
+    globs: Vec<Pattern>,
+}
+impl Category {
+    fn matches_path(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        extension.is_some_and(|extension| self.extensions.contains(&extension))
+            || self.globs.iter().any(|glob| glob.matches_path(path))
+    }
+
+    fn matches_mime(&self, mime: &str) -> bool {
+        self.mimes
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(category) => mime.split_once('/').is_some_and(|(c, _)| c == category),
+                None => pattern == mime,
+            })
+    }
+}
+
+/// Files a category path such as `documents/legal/contracts` into a tree,
+/// emitting each level as its own tag: `cat-l1:documents`, `cat-l2:legal`
+/// and `cat-l3:contracts`, so browsing one level lists the next.
+///
+/// The taxonomy is a TOML document of `[[category]]` tables, each with a
+/// `path` and any of `extensions`, `mime` (exact or `type/*`) and `globs`
+/// matched against the full source path. The first category matching a file
+/// wins; files matching none get the optional top-level `default` path.
+///
+/// MIME types are the ones sniffed from the start of the file, so `mime`
+/// rules never match in builds without libmagic.
+#[derive(Debug)]
+pub struct TaxonomyTagger {
+    categories: Vec<Category>,
+    default: Option<Vec<String>>,
+}
+impl Default for TaxonomyTagger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl TaxonomyTagger {
+    pub fn new() -> Self {
+        Self {
+            categories: Vec::new(),
+            default: None,
+        }
+    }
+
+    pub fn from_toml(taxonomy: &str) -> Result<Self, RuleError> {
+        let mut table: Table = toml::from_str(taxonomy).map_err(RuleError::Toml)?;
+        let mut tagger = Self::new();
+        if let Some(default) = table.remove("default") {
+            let default = default
+                .as_str()
+                .ok_or_else(|| RuleError::Invalid("default isn't a string".into()))?;
+            tagger.default = Some(levels(default)?);
+        }
+        let categories = match table.remove("category") {
+            Some(Value::Array(categories)) => categories,
+            Some(_) => return Err(RuleError::Invalid("category isn't an array".into())),
+            None => Vec::new(),
+        };
+        if let Some(key) = table.keys().next() {
+            return Err(RuleError::Invalid(format!("unknown key {key:?}")));
+        }
+        for category in categories {
+            let Value::Table(mut category) = category else {
+                return Err(RuleError::Invalid("category isn't a table".into()));
+            };
+            let path = category
+                .remove("path")
+                .and_then(|path| path.as_str().map(str::to_owned))
+                .ok_or_else(|| RuleError::Invalid("category without a path".into()))?;
+            let mut take = |key: &str| strings(&path, category.remove(key), key);
+            let extensions = take("extensions")?;
+            let mimes = take("mime")?;
+            let globs = take("globs")?;
+            if let Some(key) = category.keys().next() {
+                return Err(RuleError::Invalid(format!("unknown key {key:?} in {path}")));
+            }
+            tagger.add_category(&path, extensions, mimes, globs)?;
+        }
+        Ok(tagger)
+    }
+
+    /// Add a category below the existing ones, so it's only tried on files
+    /// they don't match.
+    pub fn add_category(
+        &mut self,
+        path: &str,
+        extensions: impl IntoIterator<Item = impl Into<String>>,
+        mimes: impl IntoIterator<Item = impl Into<String>>,
+        globs: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<(), RuleError> {
+        self.categories.push(Category {
+            levels: levels(path)?,
+            extensions: extensions
+                .into_iter()
+                .map(|extension| extension.into().to_ascii_lowercase())
+                .collect(),
+            mimes: mimes.into_iter().map(Into::into).collect(),
+            globs: globs
+                .into_iter()
+                .map(|glob| Pattern::new(glob.as_ref()).map_err(RuleError::Pattern))
+                .collect::<Result<_, _>>()?,
+        });
+        Ok(())
+    }
+
+    /// Category path given to files matching no category.
+    pub fn with_default(mut self, path: &str) -> Result<Self, RuleError> {
+        self.default = Some(levels(path)?);
+        Ok(self)
+    }
+
+    /// Category of `path`, calling `mime` for its MIME type only once a
+    /// category needs it.
+    fn category(
+        &self,
+        path: &Path,
+        mime: impl FnOnce() -> Result<Option<String>, Error>,
+    ) -> Result<Option<&[String]>, Error> {
+        let mut lookup = Some(mime);
+        let mut mime = None;
+        for category in &self.categories {
+            if category.matches_path(path) {
+                return Ok(Some(&category.levels));
+            }
+            if category.mimes.is_empty() {
+                continue;
+            }
+            if let Some(lookup) = lookup.take() {
+                mime = lookup()?;
+            }
+            if mime
+                .as_deref()
+                .is_some_and(|mime| category.matches_mime(mime))
+            {
+                return Ok(Some(&category.levels));
+            }
+        }
+        Ok(self.default.as_deref())
+    }
+
+    fn tags(levels: Option<&[String]>) -> HashSet<Tag> {
+        levels
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| Tag::new(format!("{LEVEL_LABEL}{}", depth + 1), false, level))
+            .collect()
+    }
+}
+
+/// The non-empty `/`-separated levels of a category path.
+fn levels(path: &str) -> Result<Vec<String>, RuleError> {
+    let levels = path
+        .split('/')
+        .filter(|level| !level.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    match levels.is_empty() {
+        true => Err(RuleError::Invalid(format!("empty category path {path:?}"))),
+        false => Ok(levels),
+    }
+}
+
+/// The strings of an optional array `value`, found as `key` in `category`.
+fn strings(category: &str, value: Option<Value>, key: &str) -> Result<Vec<String>, RuleError> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    value
+        .as_array()
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|value| value.as_str().map(str::to_owned))
+                .collect()
+        })
+        .ok_or_else(|| RuleError::Invalid(format!("{key} in {category} isn't a string array")))
+}
+
+impl Tagger for TaxonomyTagger {
+    fn name(&self) -> &str {
+        "taxonomy"
+    }
+    fn tag(&self, path: &Path) -> Result<HashSet<Tag>, Error> {
+        let category = self.category(path, || {
+            Sniff::read(path, DEFAULT_SNIFF_BYTES)
+                .map(|sniff| sniff.mime)
+                .map_err(|e| {
+                    error!(error = ?e, "read for mime type");
+                    Error::Illegible
+                })
+        })?;
+        Ok(Self::tags(category))
+    }
+    fn sniffs(&self) -> bool {
+        true
+    }
+    fn tag_sniffed(&self, path: &Path, sniff: &Sniff) -> Result<HashSet<Tag>, Error> {
+        Ok(Self::tags(self.category(path, || Ok(sniff.mime.clone()))?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, path::Path};
+
+    use crate::tagger::{RuleError, Sniff, Tag, Tagger as _};
+
+    use super::TaxonomyTagger;
+
+    const TAXONOMY: &str = r#"
+default = "misc/unsorted"
+
+[[category]]
+path = "documents/legal/contracts"
+globs = ["**/contracts/**"]
+
+[[category]]
+path = "documents/text"
+extensions = ["TXT", "md"]
+
+[[category]]
+path = "media/images"
+mime = ["image/*"]
+"#;
+
+    /// Tags for `path`, sniffed as a PNG by its extension as libmagic would.
+    fn tag(tagger: &TaxonomyTagger, path: &str) -> HashSet<Tag> {
+        let path = Path::new(path);
+        let mime = match path.extension().is_some_and(|e| e == "png") {
+            true => "image/png",
+            false => "application/octet-stream",
+        };
+        let sniff = Sniff {
+            is_file: true,
+            size: 0,
+            prefix: Vec::new(),
+            mime: Some(mime.to_string()),
+        };
+        tagger.tag_sniffed(path, &sniff).unwrap()
+    }
+
+    fn levels(path: &str) -> HashSet<Tag> {
+        path.split('/')
+            .enumerate()
+            .map(|(depth, level)| Tag::new(format!("cat-l{}", depth + 1), false, level))
+            .collect()
+    }
+
+    #[test]
+    fn three_level_category() {
+        let tagger = TaxonomyTagger::from_toml(TAXONOMY).unwrap();
+        assert_eq!(
+            levels("documents/legal/contracts"),
+            tag(&tagger, "/work/contracts/2024/lease.md")
+        );
+        assert_eq!(levels("documents/text"), tag(&tagger, "/work/notes.txt"));
+        assert_eq!(levels("media/images"), tag(&tagger, "/work/photo.png"));
+        // Path rules settle it before the file would need reading
+        assert_eq!(
+            levels("documents/text"),
+            tagger.tag(Path::new("/work/missing.md")).unwrap()
+        );
+    }
+
+    #[test]
+    fn fallthrough_default() {
+        let tagger = TaxonomyTagger::from_toml(TAXONOMY).unwrap();
+        assert_eq!(levels("misc/unsorted"), tag(&tagger, "/work/data.bin"));
+        assert!(tag(&TaxonomyTagger::new(), "/work/data.bin").is_empty());
+    }
+
+    #[test]
+    fn invalid_taxonomies() {
+        let parse = TaxonomyTagger::from_toml;
+        assert!(matches!(
+            parse("[[category]]\nextensions = [\"txt\"]"),
+            Err(RuleError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse("[[category]]\npath = \"/\""),
+            Err(RuleError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse("[[category]]\npath = \"a\"\nglobs = [\"[\"]"),
+            Err(RuleError::Pattern(_))
+        ));
+        assert!(matches!(
+            parse("[[category]]\npath = \"a\"\nextension = [\"txt\"]"),
+            Err(RuleError::Invalid(_))
+        ));
+    }
+}
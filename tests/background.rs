@@ -0,0 +1,41 @@
+//! Mount in the background through the real binary. Needs a working FUSE
+//! setup, so only runs when `TAGFS_FUSE_TESTS` is set and `/dev/fuse` exists.
+use std::{
+    env, fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn background_mount_returns_once_mounted() {
+    if env::var_os("TAGFS_FUSE_TESTS").is_none() || !Path::new("/dev/fuse").exists() {
+        eprintln!("skipping: FUSE tests not enabled");
+        return;
+    }
+    let dir = env::temp_dir().join("tagfs_background_mount");
+    let _ = fs::remove_dir_all(&dir);
+    let (source, mountpoint) = (dir.join("source"), dir.join("mnt"));
+    fs::create_dir_all(&source).unwrap();
+    fs::create_dir_all(&mountpoint).unwrap();
+    fs::write(source.join("a.txt"), "alpha\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_reimagined-octo-train"))
+        .arg("--background")
+        .arg(&mountpoint)
+        .arg(&source)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // The parent only exits once the mount is serving
+    let listed = fs::read_dir(&mountpoint).unwrap().count();
+
+    Command::new("fusermount")
+        .arg("-u")
+        .arg(&mountpoint)
+        .status()
+        .unwrap();
+    assert!(listed > 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
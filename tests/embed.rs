@@ -0,0 +1,41 @@
+//! Build and query a tag index through the library API, without mounting.
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use reimagined_octo_train::{filesystem::tagfs, tagger::Tag};
+
+#[test]
+fn query_index_without_mounting() {
+    let mut index = tagfs::new();
+    index.add_file(
+        Path::new("/photos/beach.jpg"),
+        HashSet::from([Tag::new("mime", true, "image|jpeg"), Tag::from("holiday")]),
+    );
+    index.add_file(
+        Path::new("/photos/receipt.jpg"),
+        HashSet::from([Tag::new("mime", true, "image|jpeg")]),
+    );
+    index.add_file(
+        Path::new("/docs/itinerary.pdf"),
+        HashSet::from([
+            Tag::new("mime", true, "application|pdf"),
+            Tag::from("holiday"),
+        ]),
+    );
+
+    assert_eq!(
+        vec![
+            PathBuf::from("/photos/beach.jpg"),
+            PathBuf::from("/docs/itinerary.pdf")
+        ],
+        index.files_for_tags(&[Tag::from("holiday")])
+    );
+    assert_eq!(
+        vec![PathBuf::from("/photos/beach.jpg")],
+        index.files_for_tags(&[Tag::from("holiday"), Tag::from_display("mime:image|jpeg")])
+    );
+    assert!(index.files_for_tags(&[Tag::from("unknown")]).is_empty());
+    assert_eq!(3, index.files_for_tags(&[]).len());
+}
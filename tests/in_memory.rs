@@ -0,0 +1,51 @@
+//! Serve an index from an in-memory source, without touching the disk.
+use std::{collections::HashSet, path::Path};
+
+use fuse_mt::{FileType, FilesystemMT as _, RequestInfo};
+use reimagined_octo_train::{
+    filesystem::{tagfs::TagFS, InMemoryLibcWrapper, LibcWrapper as _},
+    tagger::Tag,
+};
+
+fn request() -> RequestInfo {
+    RequestInfo {
+        unique: 0,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    }
+}
+
+#[test]
+fn stat_and_open_in_memory_files() {
+    let libc_wrapper = InMemoryLibcWrapper::new();
+    libc_wrapper.insert("/photos/beach.jpg", vec![0xff; 2048]);
+    libc_wrapper.insert("/docs/itinerary.txt", "fly out monday\n");
+    let mut fs = TagFS::with_libc_wrapper(libc_wrapper);
+    fs.add_file(
+        Path::new("/photos/beach.jpg"),
+        HashSet::from([Tag::from("holiday")]),
+    );
+    fs.add_file(
+        Path::new("/docs/itinerary.txt"),
+        HashSet::from([Tag::from("holiday")]),
+    );
+
+    let (_ttl, attr) = fs
+        .getattr(request(), Path::new("/holiday/beach.jpg"), None)
+        .unwrap();
+    assert_eq!(FileType::RegularFile, attr.kind);
+    assert_eq!(2048, attr.size);
+    assert_eq!(4, attr.blocks);
+
+    let path = Path::new("/holiday/itinerary.txt");
+    let (fh, _) = fs.open(request(), path, libc::O_RDONLY as u32).unwrap();
+    let (_ttl, attr) = fs.getattr(request(), path, Some(fh)).unwrap();
+    assert_eq!(15, attr.size);
+    assert!(fs.release(request(), path, fh, 0, 0, false).is_ok());
+    assert_eq!(
+        Err(libc::ENOENT),
+        fs.open(request(), Path::new("/holiday/missing.txt"), 0)
+            .map(|_| ())
+    );
+}